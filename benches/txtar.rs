@@ -0,0 +1,111 @@
+//! Benchmarks over generated archives
+//!
+//! Run with `cargo bench`, optionally naming benchmarks to run
+//! (`cargo bench -- decode`). With `--features profiling` the internal
+//! counters for one iteration of each benchmark are printed too.
+
+use emx_txtar::{Archive, Decoder, EditBlock, EditOperation, EditRef, EncodingConfig, Encoder, File};
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Minimum time spent measuring each benchmark
+const MEASURE: Duration = Duration::from_millis(300);
+
+/// Deterministic pseudo-random bytes
+fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(2654435761) | 1;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+fn text(lines: usize, seed: usize) -> String {
+    (0..lines).map(|i| format!("line {} of file {}: some ordinary text\n", i, seed)).collect()
+}
+
+/// Archive shapes: many small text files, a few large binaries, and a mix
+fn shapes() -> Vec<(&'static str, Archive)> {
+    let mut small = Archive::with_comment("many small text files");
+    for i in 0..2000 {
+        small.add_file(File::new(format!("src/file{}.txt", i), text(20, i))).unwrap();
+    }
+
+    let mut binary = Archive::with_comment("few large binaries");
+    for i in 0..4 {
+        binary.add_file(File::new(format!("assets/blob{}.bin", i), noise(4 << 20, i))).unwrap();
+    }
+
+    let mut mixed = Archive::with_comment("mixed");
+    for i in 0..200 {
+        let data = if i % 4 == 0 { noise(64 << 10, i as u32) } else { text(200, i).into_bytes() };
+        mixed.add_file(File::new(format!("mixed/{}.dat", i), data)).unwrap();
+    }
+
+    vec![("small-text", small), ("large-binary", binary), ("mixed", mixed)]
+}
+
+/// Time `f`, repeating it until `MEASURE` has passed
+fn bench<R>(name: &str, bytes: usize, filters: &[String], mut f: impl FnMut() -> R) {
+    if !filters.is_empty() && !filters.iter().any(|filter| name.contains(filter.as_str())) {
+        return;
+    }
+
+    #[cfg(feature = "profiling")]
+    let before = emx_txtar::profiling::snapshot();
+    black_box(f());
+    #[cfg(feature = "profiling")]
+    let counters = emx_txtar::profiling::snapshot().since(&before);
+
+    let start = Instant::now();
+    let mut iterations = 0u32;
+    while start.elapsed() < MEASURE {
+        black_box(f());
+        iterations += 1;
+    }
+    let per_iter = start.elapsed() / iterations;
+    let throughput = bytes as f64 / per_iter.as_secs_f64() / (1 << 20) as f64;
+    println!("{:<28} {:>12.3?}/iter {:>10.1} MiB/s", name, per_iter, throughput);
+
+    #[cfg(feature = "profiling")]
+    println!("    {:?}", counters);
+}
+
+fn edit_target() -> (String, EditRef) {
+    let content = text(200_000, 0);
+    let edits = (0..50)
+        .map(|n| {
+            EditBlock::new(
+                EditOperation::Replace,
+                vec![format!("line {} of file 0: some ordinary text", n * 4000 + 7)],
+                vec!["replaced".to_string()],
+            )
+        })
+        .collect();
+    (content, EditRef { command_href: None, start_line: None, edits })
+}
+
+fn main() {
+    let filters: Vec<String> = std::env::args().skip(1).filter(|arg| !arg.starts_with("--")).collect();
+    let encoder = Encoder::new();
+    let decoder = Decoder::new();
+
+    for (shape, archive) in shapes() {
+        let encoded = encoder.encode(&archive).unwrap();
+        bench(&format!("encode/{}", shape), encoded.len(), &filters, || encoder.encode(&archive).unwrap());
+        bench(&format!("decode/{}", shape), encoded.len(), &filters, || decoder.decode(&encoded).unwrap());
+    }
+
+    let (content, edit) = edit_target();
+    bench("edit-apply/200k-lines", content.len(), &filters, || edit.apply(&content).unwrap());
+
+    let config = EncodingConfig::default();
+    let sample = text(50_000, 1).into_bytes();
+    bench("detect/text", sample.len(), &filters, || File::detect_encoding("a.dat", &sample, &config));
+    let sample = noise(4 << 20, 9);
+    bench("detect/binary", sample.len(), &filters, || File::detect_encoding("a.dat", &sample, &config));
+}
@@ -12,7 +12,7 @@ fn main() -> anyhow::Result<()> {
     let mut archive = Archive::with_comment("Content detection test archive\n");
 
     // 1. Normal text file - no encoding needed
-    archive.add_file(File::new("README.md", "# Project\n\nNormal text content."));
+    archive.add_file(File::new("README.md", "# Project\n\nNormal text content."))?;
 
     // 2. File with marker pattern in content - auto-detected as binary
     let tricky_content = r#"This file looks like a txtar archive:
@@ -23,11 +23,11 @@ Some content
 More content
 
 End of file"#;
-    archive.add_file(File::new("tricky.txt", tricky_content));
+    archive.add_file(File::new("tricky.txt", tricky_content))?;
 
     // 3. Actual binary data - auto-detected as binary
     let binary_data = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10, 0x4A, 0x46];
-    archive.add_file(File::new("image.jpg", binary_data));
+    archive.add_file(File::new("image.jpg", binary_data))?;
 
     // Encode the archive
     let encoder = Encoder::new();
@@ -42,14 +42,14 @@ End of file"#;
     println!("-----------------");
     for (i, file) in archive.files.iter().enumerate() {
         let reason_str = if file.is_binary {
-            match file.binary_reason {
-                Some(BinaryReason::ContentConflict) => "Content conflict (has -- filename --)",
-                Some(BinaryReason::InvalidUtf8) => "Invalid UTF-8 (binary data)",
-                Some(BinaryReason::Explicit) => "Explicitly marked",
-                None => "Unknown",
+            match &file.binary_reason {
+                Some(reason @ BinaryReason::ContentConflict { .. }) => format!("Content conflict ({})", reason),
+                Some(reason @ BinaryReason::InvalidUtf8 { .. }) => format!("Binary data ({})", reason),
+                Some(reason) => reason.to_string(),
+                None => "Unknown".to_string(),
             }
         } else {
-            "None (text)"
+            "None (text)".to_string()
         };
 
         println!("{}. {} - {} bytes | Binary: {} | Reason: {}",
@@ -67,14 +67,7 @@ End of file"#;
 
     println!("\nRound-trip verification:");
     for (orig, dec) in archive.files.iter().zip(decoded.files.iter()) {
-        let data_match = if orig.is_binary {
-            orig.data == dec.data
-        } else {
-            // For text, compare normalized (without trailing newlines)
-            let orig_text = String::from_utf8(orig.data.clone()).unwrap();
-            let dec_text = String::from_utf8(dec.data.clone()).unwrap();
-            orig_text.trim_end() == dec_text.trim_end()
-        };
+        let data_match = orig.data == dec.data;
 
         println!("  {}: {}",
             orig.name,
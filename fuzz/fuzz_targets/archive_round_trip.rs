@@ -0,0 +1,14 @@
+//! Build an archive from fuzzer input and check that it survives an
+//! encode/decode round trip
+//!
+//! Run with `cargo fuzz run archive_round_trip` from the repository root.
+
+#![no_main]
+
+use emx_txtar::proptest::check_archive_round_trip;
+use emx_txtar::Archive;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|archive: Archive| {
+    check_archive_round_trip(&archive);
+});
@@ -0,0 +1,12 @@
+//! Decode arbitrary text, then check that encoding and decoding it again
+//! gives the same archive
+//!
+//! Run with `cargo fuzz run decode_round_trip` from the repository root.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    emx_txtar::proptest::check_text_round_trip(data);
+});
@@ -0,0 +1,110 @@
+/* C interface to emx-txtar (built with the `ffi` feature). */
+
+#ifndef EMX_TXTAR_H
+#define EMX_TXTAR_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Opaque archive handle
+ */
+typedef struct EmxTxtarArchive EmxTxtarArchive;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create an empty archive; free it with `emx_txtar_archive_free`
+ */
+EmxTxtarArchive *emx_txtar_archive_new(void);
+
+/**
+ * Decode txtar text, returning null on error (see `emx_txtar_last_error`)
+ *
+ * # Safety
+ * `text` must point to `len` readable bytes.
+ */
+EmxTxtarArchive *emx_txtar_decode(const char *text, size_t len);
+
+/**
+ * Encode an archive as NUL-terminated text, returning null on error
+ *
+ * The length is stored in `out_len` if it is not null. Free the text with
+ * `emx_txtar_string_free`.
+ *
+ * # Safety
+ * `archive` must be a live handle; `out_len` must be null or writable.
+ */
+char *emx_txtar_encode(const EmxTxtarArchive *archive, size_t *out_len);
+
+/**
+ * Add a file, detecting binary content; returns 0 on success and -1 on error
+ *
+ * # Safety
+ * `archive` must be a live handle, `name` a NUL-terminated string and
+ * `data` must point to `len` readable bytes.
+ */
+int emx_txtar_add_file(EmxTxtarArchive *archive, const char *name, const uint8_t *data, size_t len);
+
+/**
+ * Number of entries, including snippet and edit entries
+ *
+ * # Safety
+ * `archive` must be null or a live handle.
+ */
+size_t emx_txtar_file_count(const EmxTxtarArchive *archive);
+
+/**
+ * Name of the entry at `index` as NUL-terminated UTF-8, or null if out of range
+ *
+ * The name stays valid until the archive is modified or freed.
+ *
+ * # Safety
+ * `archive` must be null or a live handle.
+ */
+const char *emx_txtar_file_name(const EmxTxtarArchive *archive, size_t index);
+
+/**
+ * Contents of the named file, or null if there is none
+ *
+ * Snippet and edit entries are excluded. The length is stored in `out_len`,
+ * and the contents stay valid until the archive is modified or freed.
+ *
+ * # Safety
+ * `archive` must be null or a live handle, `name` a NUL-terminated string
+ * and `out_len` null or writable.
+ */
+const uint8_t *emx_txtar_get_file(const EmxTxtarArchive *archive,
+                                  const char *name,
+                                  size_t *out_len);
+
+/**
+ * Message of the last error on this thread, or null; valid until the next call
+ */
+const char *emx_txtar_last_error(void);
+
+/**
+ * Free an archive handle; null is ignored
+ *
+ * # Safety
+ * `archive` must be null or a handle not yet freed.
+ */
+void emx_txtar_archive_free(EmxTxtarArchive *archive);
+
+/**
+ * Free a string returned by `emx_txtar_encode`; null is ignored
+ *
+ * # Safety
+ * `text` must be null or a string from `emx_txtar_encode` not yet freed.
+ */
+void emx_txtar_string_free(char *text);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* EMX_TXTAR_H */
@@ -1,7 +1,18 @@
 //! Archive data structures
 
-use std::borrow::Cow;
-use std::path::Path;
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use crate::condition::Condition;
+use crate::tags::CustomTag;
+use crate::diagnostics::{Diagnostic, Diagnostics};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
 // Txtar format constants
 pub const MARKER_PREFIX: &str = "-- ";
@@ -10,14 +21,357 @@ pub const MARKER_PREFIX_LEN: usize = 3;  // len("-- ")
 pub const MARKER_SUFFIX_LEN: usize = 3;  // len(" --")
 pub const BASE64_SUFFIX: &str = "[.base64]";
 pub const BASE64_SUFFIX_LEN: usize = 9; // len("[.base64]") = 1 + 1 + 6 + 1
+pub const SNIPPET_LANG_PREFIX: &str = ":lang=";
+pub const ENCODING_TAG_PREFIX: &str = "[.enc:";
+pub const ESCAPE_TAG_PREFIX: &str = "[.esc:";
+pub const PATCH_TAG_PREFIX: &str = "[.patch:";
+/// Line naming the file of the next block of an edit entry:
+/// `@@ file: path @@`
+pub const FILE_HEADER_PREFIX: &str = "@@ file: ";
+pub const FILE_HEADER_SUFFIX: &str = " @@";
+pub const MODE_TAG_PREFIX: &str = "[.mode:";
+pub const MTIME_TAG_PREFIX: &str = "[.mtime:";
+/// Tag marking a file whose contents are themselves a txtar archive
+pub const ARCHIVE_TAG: &str = "[.txtar]";
+/// Tag marking a file whose base64 contents are encrypted, as
+/// `[.crypt:<scheme>]`
+pub const CRYPT_TAG_PREFIX: &str = "[.crypt:";
+/// The only supported encryption scheme: XChaCha20-Poly1305 with a random
+/// 24-byte nonce prepended to the ciphertext and the file name as
+/// associated data
+pub const CRYPT_SCHEME: &str = "xchacha20poly1305";
+/// Name of the entry listing every other entry with its size and SHA-256
+pub const MANIFEST_NAME: &str = ".manifest";
+/// Name of the trailing entry holding an archive signature
+pub const SIGNATURE_NAME: &str = ".signature";
+/// Name of the `.gitignore`-style files [`Archive::from_dir`] honors
+pub const IGNORE_FILE_NAME: &str = ".txtarignore";
+/// Character prepended to escaped marker lines (see [`ESCAPE_TAG_PREFIX`])
+/// and to escaped characters in header names
+pub const MARKER_ESCAPE: char = '\\';
+/// Delimiter line starting each archive of a multi-archive stream: `=== archive: name ===`
+pub const STREAM_DELIMITER_PREFIX: &str = "=== archive: ";
+pub const STREAM_DELIMITER_SUFFIX: &str = " ===";
+
+/// Archive name of a stream delimiter line, if the line is one
+pub(crate) fn parse_stream_delimiter(line: &str) -> Option<&str> {
+    let name = line.trim().strip_prefix(STREAM_DELIMITER_PREFIX)?.strip_suffix(STREAM_DELIMITER_SUFFIX)?;
+    let name = name.trim();
+    (!name.is_empty()).then_some(name)
+}
+
+/// Whether a name contains the `-- ` and ` --` of a file marker
+pub(crate) fn has_marker_pattern(name: &str) -> bool {
+    name.contains("-- ") && name.contains(" --")
+}
+
+/// Characters that follow [`MARKER_ESCAPE`] in an escaped header name
+fn is_name_escape(c: char) -> bool {
+    matches!(c, '[' | ']' | '-' | MARKER_ESCAPE)
+}
+
+/// Escape a file name for an entry header
+///
+/// Brackets become `\[` and `\]` so they are not read as tags. In names with
+/// a marker pattern, each `-` following another is written `\-`, leaving no
+/// `--` in the header. A backslash is doubled only where it would otherwise
+/// start an escape, so other names are written unchanged.
+pub(crate) fn escape_name(name: &str) -> Cow<'_, str> {
+    let markers = has_marker_pattern(name);
+    if !markers && !name.contains(['[', ']', MARKER_ESCAPE]) {
+        return Cow::Borrowed(name);
+    }
+
+    let mut escaped = String::with_capacity(name.len() + 4);
+    let mut prev = None;
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        let escape = match c {
+            '[' | ']' => true,
+            MARKER_ESCAPE => chars.peek().is_none_or(|&next| is_name_escape(next)),
+            '-' => markers && prev == Some('-'),
+            _ => false,
+        };
+        if escape {
+            escaped.push(MARKER_ESCAPE);
+        }
+        escaped.push(c);
+        prev = Some(c);
+    }
+    Cow::Owned(escaped)
+}
+
+/// Undo [`escape_name`]; a backslash not starting an escape is kept as is
+pub(crate) fn unescape_name(name: &str) -> Cow<'_, str> {
+    if !name.contains(MARKER_ESCAPE) {
+        return Cow::Borrowed(name);
+    }
+
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(&next) if c == MARKER_ESCAPE && is_name_escape(next) => {
+                unescaped.push(next);
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// Byte offset of the first `[` in a header name that is not escaped
+pub(crate) fn find_tag_start(name: &str) -> Option<usize> {
+    let mut chars = name.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '[' => return Some(i),
+            MARKER_ESCAPE if chars.peek().is_some_and(|&(_, next)| is_name_escape(next)) => {
+                chars.next();
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Minimum fraction of printable bytes for the Latin-1 fallback to accept data as text
+const LATIN1_MIN_PRINTABLE_RATIO: f32 = 0.95;
 
 /// Configuration for encoding detection
+///
+/// Start from [`EncodingConfig::default`] or one of the presets
+/// ([`strict`](EncodingConfig::strict), [`lenient`](EncodingConfig::lenient),
+/// [`go_compat`](EncodingConfig::go_compat)) and adjust with
+/// [`EncodingConfig::builder`] / [`EncodingConfig::into_builder`].
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct EncodingConfig {
     /// Whether to check file content for conflicting marker patterns
     pub check_content_markers: bool,
     /// Whether to validate UTF-8 encoding (if false, treats all non-UTF8 as binary)
     pub validate_utf8: bool,
+    /// Maximum fraction (0.0-1.0) of NUL/control bytes tolerated in text;
+    /// `None` disables the check
+    pub max_control_ratio: Option<f32>,
+    /// Maximum line length in bytes tolerated in text; `None` disables the check
+    pub max_line_length: Option<usize>,
+    /// Number of leading bytes inspected by the control-byte and line-length heuristics
+    pub heuristic_sample_size: usize,
+    /// Forced classifications keyed by glob pattern (e.g. `*.png`), consulted
+    /// in order before any content checks; the first matching pattern wins
+    pub overrides: Vec<(String, EncodingOverride)>,
+    /// Treat non-UTF-8 data that is mostly printable ISO-8859-1 as Latin-1 text
+    pub latin1_fallback: bool,
+    /// Detect non-UTF-8 GBK, Shift_JIS and Big5 text and store it transcoded;
+    /// only takes effect with the `encoding` feature, and is tried before
+    /// [`latin1_fallback`](Self::latin1_fallback)
+    pub cjk_fallback: bool,
+    /// Only inspect parts of large files for markers and UTF-8 validity;
+    /// `None` (the default) always scans the full content
+    pub sampling: Option<DetectionSampling>,
+    /// Custom callback consulted before all other rules; returning `None`
+    /// falls through to the built-in detection
+    pub detector: Option<DetectionHook>,
+}
+
+type DetectionFn = dyn Fn(&str, &[u8]) -> Option<EncodingDetection> + Send + Sync;
+
+/// User-supplied detection callback for [`EncodingConfig::detector`]
+///
+/// Receives the file name and data.
+#[derive(Clone)]
+pub struct DetectionHook(Arc<DetectionFn>);
+
+impl DetectionHook {
+    /// Wrap a closure as a detection hook
+    pub fn new(f: impl Fn(&str, &[u8]) -> Option<EncodingDetection> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(f))
+    }
+
+    /// Run the hook
+    pub fn detect(&self, name: &str, data: &[u8]) -> Option<EncodingDetection> {
+        (self.0)(name, data)
+    }
+}
+
+impl core::fmt::Debug for DetectionHook {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("DetectionHook(..)")
+    }
+}
+
+/// Sampling windows used by the marker and UTF-8 checks on large files
+///
+/// Sampling trades accuracy for speed: a marker line or invalid byte outside
+/// the windows goes unnoticed, and the encoder will then either fail (invalid
+/// UTF-8) or produce an archive that decodes differently (marker line).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectionSampling {
+    /// Files up to this many bytes are always scanned in full
+    pub threshold: usize,
+    /// Number of leading bytes inspected
+    pub prefix: usize,
+    /// Number of trailing bytes inspected
+    pub suffix: usize,
+}
+
+impl Default for DetectionSampling {
+    fn default() -> Self {
+        Self {
+            threshold: 4 * 1024 * 1024,
+            prefix: 64 * 1024,
+            suffix: 16 * 1024,
+        }
+    }
+}
+
+impl EncodingConfig {
+    /// Preset that base64-encodes anything suspicious: any control byte or
+    /// a line over 4096 bytes makes a file binary
+    pub fn strict() -> Self {
+        Self {
+            max_control_ratio: Some(0.0),
+            max_line_length: Some(4096),
+            ..Self::default()
+        }
+    }
+
+    /// Preset that keeps as much as possible readable: no binary heuristics,
+    /// and near-text legacy-encoded files are transcoded instead of
+    /// base64-encoded
+    pub fn lenient() -> Self {
+        Self {
+            latin1_fallback: true,
+            cjk_fallback: true,
+            ..Self::default()
+        }
+    }
+
+    /// Preset that only falls back to base64 when plain txtar cannot represent
+    /// the content (marker lines or invalid UTF-8), so text entries stay
+    /// readable by Go's `txtar` package
+    pub fn go_compat() -> Self {
+        Self {
+            check_content_markers: true,
+            validate_utf8: true,
+            max_control_ratio: None,
+            max_line_length: None,
+            latin1_fallback: false,
+            cjk_fallback: false,
+            overrides: Vec::new(),
+            sampling: None,
+            detector: None,
+            ..Self::default()
+        }
+    }
+
+    /// Start building a config from the defaults
+    pub fn builder() -> EncodingConfigBuilder {
+        Self::default().into_builder()
+    }
+
+    /// Continue adjusting this config with a builder
+    pub fn into_builder(self) -> EncodingConfigBuilder {
+        EncodingConfigBuilder { config: self }
+    }
+
+    /// Find the override for a file name, if any pattern matches
+    pub fn override_for(&self, name: &str) -> Option<(&str, &EncodingOverride)> {
+        self.overrides
+            .iter()
+            .find(|(pattern, _)| crate::glob::matches(pattern, name))
+            .map(|(pattern, ovr)| (pattern.as_str(), ovr))
+    }
+}
+
+/// Builder for [`EncodingConfig`]
+#[derive(Debug, Clone)]
+pub struct EncodingConfigBuilder {
+    config: EncodingConfig,
+}
+
+impl EncodingConfigBuilder {
+    /// Enable or disable the content marker check
+    pub fn check_content_markers(mut self, enabled: bool) -> Self {
+        self.config.check_content_markers = enabled;
+        self
+    }
+
+    /// Enable or disable UTF-8 validation
+    pub fn validate_utf8(mut self, enabled: bool) -> Self {
+        self.config.validate_utf8 = enabled;
+        self
+    }
+
+    /// Set the maximum tolerated fraction of NUL/control bytes (`None` disables)
+    pub fn max_control_ratio(mut self, ratio: Option<f32>) -> Self {
+        self.config.max_control_ratio = ratio;
+        self
+    }
+
+    /// Set the maximum tolerated line length (`None` disables)
+    pub fn max_line_length(mut self, length: Option<usize>) -> Self {
+        self.config.max_line_length = length;
+        self
+    }
+
+    /// Set the number of bytes inspected by the heuristics
+    pub fn heuristic_sample_size(mut self, size: usize) -> Self {
+        self.config.heuristic_sample_size = size;
+        self
+    }
+
+    /// Append an override for file names matching `pattern`
+    pub fn override_pattern(mut self, pattern: impl Into<String>, ovr: EncodingOverride) -> Self {
+        self.config.overrides.push((pattern.into(), ovr));
+        self
+    }
+
+    /// Enable or disable the Latin-1 fallback
+    pub fn latin1_fallback(mut self, enabled: bool) -> Self {
+        self.config.latin1_fallback = enabled;
+        self
+    }
+
+    /// Enable or disable GBK / Shift_JIS / Big5 detection
+    pub fn cjk_fallback(mut self, enabled: bool) -> Self {
+        self.config.cjk_fallback = enabled;
+        self
+    }
+
+    /// Set the sampling windows for large files (`None` scans in full)
+    pub fn sampling(mut self, sampling: Option<DetectionSampling>) -> Self {
+        self.config.sampling = sampling;
+        self
+    }
+
+    /// Set a custom detection callback, consulted before all other rules
+    pub fn detector(
+        mut self,
+        f: impl Fn(&str, &[u8]) -> Option<EncodingDetection> + Send + Sync + 'static,
+    ) -> Self {
+        self.config.detector = Some(DetectionHook::new(f));
+        self
+    }
+
+    /// Finish building
+    pub fn build(self) -> EncodingConfig {
+        self.config
+    }
+}
+
+/// Forced classification for files matching an [`EncodingConfig`] override pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodingOverride {
+    /// Always store as binary, without sniffing the content
+    Binary,
+    /// Always store as text, skipping marker and heuristic checks
+    /// (data that is not valid UTF-8 is still stored as binary)
+    Text,
+    /// Store as text in a specific encoding
+    Encoding(TextEncoding),
 }
 
 impl Default for EncodingConfig {
@@ -25,6 +379,14 @@ impl Default for EncodingConfig {
         Self {
             check_content_markers: true,
             validate_utf8: true,
+            max_control_ratio: None,
+            max_line_length: None,
+            heuristic_sample_size: 8192,
+            overrides: Vec::new(),
+            latin1_fallback: false,
+            cjk_fallback: false,
+            sampling: None,
+            detector: None,
         }
     }
 }
@@ -38,12 +400,121 @@ pub enum EncodingDetection {
     Binary { reason: BinaryReason },
 }
 
+/// Line breaks of text, as found in a file or as written by the decoder
+/// and encoder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LineEnding {
+    /// Line breaks as they are
+    #[default]
+    Preserve,
+    /// `\n`
+    Lf,
+    /// `\r\n`
+    CrLf,
+}
+
+impl LineEnding {
+    /// `text` with its line breaks converted to this ending
+    pub fn apply(self, text: &str) -> Cow<'_, str> {
+        match self {
+            LineEnding::Lf if text.contains("\r\n") => Cow::Owned(text.replace("\r\n", "\n")),
+            LineEnding::CrLf if text.split('\n').rev().skip(1).any(|line| !line.ends_with('\r')) => {
+                let lines: Vec<&str> = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+                Cow::Owned(lines.join("\r\n"))
+            }
+            _ => Cow::Borrowed(text),
+        }
+    }
+}
+
 /// Text encoding type (extensible for i18n)
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TextEncoding {
     /// UTF-8 text
     Utf8,
-    // Future: Utf16Le, Utf16Be, GBK, ShiftJIS, etc.
+    /// Simplified Chinese (GBK / CP936)
+    Gbk,
+    /// Japanese (Shift_JIS / CP932)
+    ShiftJis,
+    /// Traditional Chinese (Big5)
+    Big5,
+    /// Western European (ISO-8859-1)
+    Latin1,
+    // Future: Utf16Le, Utf16Be, etc.
+}
+
+impl TextEncoding {
+    /// Canonical lowercase label used in archive headers (e.g. `gbk`)
+    pub fn label(&self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "utf-8",
+            TextEncoding::Gbk => "gbk",
+            TextEncoding::ShiftJis => "shift_jis",
+            TextEncoding::Big5 => "big5",
+            TextEncoding::Latin1 => "latin1",
+        }
+    }
+
+    /// Look up an encoding by label (case-insensitive, common aliases accepted)
+    pub fn from_label(label: &str) -> Option<Self> {
+        match label.trim().to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(TextEncoding::Utf8),
+            "gbk" | "cp936" | "gb2312" => Some(TextEncoding::Gbk),
+            "shift_jis" | "shift-jis" | "sjis" | "cp932" => Some(TextEncoding::ShiftJis),
+            "big5" | "big5-hkscs" => Some(TextEncoding::Big5),
+            "latin1" | "latin-1" | "iso-8859-1" | "iso8859-1" => Some(TextEncoding::Latin1),
+            _ => None,
+        }
+    }
+
+    /// Decode bytes in this encoding to a UTF-8 string
+    ///
+    /// Returns `None` if the data is not valid in this encoding, or if no
+    /// transcoder is available for it (GBK, Shift_JIS and Big5 need the
+    /// `encoding` feature).
+    pub fn decode(&self, data: &[u8]) -> Option<String> {
+        match self {
+            TextEncoding::Utf8 => core::str::from_utf8(data).ok().map(str::to_string),
+            TextEncoding::Latin1 => Some(data.iter().map(|&b| b as char).collect()),
+            #[cfg(feature = "encoding")]
+            TextEncoding::Gbk | TextEncoding::ShiftJis | TextEncoding::Big5 => self
+                .legacy()?
+                .decode_without_bom_handling_and_without_replacement(data)
+                .map(Cow::into_owned),
+            #[cfg(not(feature = "encoding"))]
+            TextEncoding::Gbk | TextEncoding::ShiftJis | TextEncoding::Big5 => None,
+        }
+    }
+
+    /// Encode a UTF-8 string into this encoding
+    ///
+    /// Returns `None` if the text contains characters this encoding cannot
+    /// represent, or if no transcoder is available for it (GBK, Shift_JIS and
+    /// Big5 need the `encoding` feature).
+    pub fn encode(&self, text: &str) -> Option<Vec<u8>> {
+        match self {
+            TextEncoding::Utf8 => Some(text.as_bytes().to_vec()),
+            TextEncoding::Latin1 => text.chars().map(|c| u8::try_from(c).ok()).collect(),
+            #[cfg(feature = "encoding")]
+            TextEncoding::Gbk | TextEncoding::ShiftJis | TextEncoding::Big5 => {
+                let (bytes, _, unmappable) = self.legacy()?.encode(text);
+                (!unmappable).then(|| bytes.into_owned())
+            }
+            #[cfg(not(feature = "encoding"))]
+            TextEncoding::Gbk | TextEncoding::ShiftJis | TextEncoding::Big5 => None,
+        }
+    }
+
+    /// The `encoding_rs` transcoder for the legacy CJK encodings
+    #[cfg(feature = "encoding")]
+    fn legacy(&self) -> Option<&'static encoding_rs::Encoding> {
+        match self {
+            TextEncoding::Gbk => Some(encoding_rs::GBK),
+            TextEncoding::ShiftJis => Some(encoding_rs::SHIFT_JIS),
+            TextEncoding::Big5 => Some(encoding_rs::BIG5),
+            TextEncoding::Utf8 | TextEncoding::Latin1 => None,
+        }
+    }
 }
 
 /// Reason why data is considered binary
@@ -51,11 +522,164 @@ pub enum TextEncoding {
 pub enum BinaryReason {
     /// Content contains txtar marker pattern (-- filename --)
     /// This is the primary cause for binary encoding
-    ContentConflict,
+    ContentConflict {
+        /// 1-based line number of the first conflicting line
+        line_number: usize,
+        /// The conflicting line, trimmed
+        line: String,
+    },
     /// Invalid UTF-8 encoding (actual binary data)
-    InvalidUtf8,
+    InvalidUtf8 {
+        /// Byte offset of the first invalid sequence
+        offset: usize,
+    },
     /// Explicitly marked as binary by user
     Explicit,
+    /// Forced by an [`EncodingOverride::Binary`] entry in the config
+    Override {
+        /// The pattern that matched the file name
+        pattern: String,
+    },
+    /// Too many NUL/control bytes in the sampled window
+    ControlChars {
+        /// Number of control bytes found
+        count: usize,
+        /// Number of bytes sampled
+        sampled: usize,
+    },
+    /// A line exceeds the configured maximum length
+    LongLine {
+        /// 1-based line number
+        line: usize,
+        /// Length of the line in bytes (within the sampled window)
+        length: usize,
+    },
+}
+
+impl core::fmt::Display for BinaryReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            BinaryReason::ContentConflict { line_number, line } => {
+                write!(f, "line {} looks like a file marker: '{}'", line_number, line)
+            }
+            BinaryReason::InvalidUtf8 { offset } => {
+                write!(f, "invalid UTF-8 at byte offset {}", offset)
+            }
+            BinaryReason::Explicit => write!(f, "explicitly marked as binary"),
+            BinaryReason::Override { pattern } => {
+                write!(f, "forced by override pattern '{}'", pattern)
+            }
+            BinaryReason::ControlChars { count, sampled } => {
+                write!(f, "{} control bytes in the first {} bytes", count, sampled)
+            }
+            BinaryReason::LongLine { line, length } => {
+                write!(f, "line {} is {} bytes long", line, length)
+            }
+        }
+    }
+}
+
+/// Filesystem metadata recorded for a file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// Unix permission bits, tagged as `[.mode:<octal>]`
+    pub mode: Option<u32>,
+    /// Modification time in seconds since the Unix epoch, tagged as `[.mtime:<secs>]`
+    pub mtime: Option<u64>,
+}
+
+impl FileMetadata {
+    /// Capture the permission bits (on Unix) and modification time of a file
+    #[cfg(feature = "std")]
+    pub fn from_fs(metadata: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(metadata.permissions().mode() & 0o7777)
+        };
+        #[cfg(not(unix))]
+        let mode = None;
+
+        let mtime = metadata.modified().ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+
+        Self { mode, mtime }
+    }
+
+    /// Apply the recorded permissions and/or modification time to a file on disk
+    ///
+    /// Permissions are only applied on Unix; missing values are left alone.
+    #[cfg(feature = "std")]
+    pub fn apply(&self, path: &Path, permissions: bool, mtime: bool) -> std::io::Result<()> {
+        #[cfg(unix)]
+        if let (true, Some(mode)) = (permissions, self.mode) {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+        }
+        #[cfg(not(unix))]
+        let _ = permissions;
+
+        if let (true, Some(secs)) = (mtime, self.mtime) {
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+            std::fs::File::options().write(true).open(path)?.set_modified(time)?;
+        }
+        Ok(())
+    }
+
+    /// Header tags for the recorded values
+    fn tags(&self) -> String {
+        let mut tags = String::new();
+        if let Some(mode) = self.mode {
+            tags.push_str(&format!("{}{:04o}]", MODE_TAG_PREFIX, mode));
+        }
+        if let Some(mtime) = self.mtime {
+            tags.push_str(&format!("{}{}]", MTIME_TAG_PREFIX, mtime));
+        }
+        tags
+    }
+}
+
+/// Where the contents of a [`File`] come from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum FileSource {
+    /// The bytes in [`File::data`]
+    #[default]
+    Memory,
+    /// A file on disk, read only when the archive is encoded or extracted.
+    /// `data` is empty and binary detection is deferred until then
+    #[cfg(feature = "std")]
+    Path(PathBuf),
+}
+
+/// Reader over the contents of a [`File`], returned by [`File::reader`]
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub enum FileReader<'a> {
+    /// Contents held in memory
+    Memory(std::io::Cursor<&'a [u8]>),
+    /// Contents read from disk as they are consumed
+    Disk(std::fs::File),
+}
+
+#[cfg(feature = "std")]
+impl std::io::Read for FileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            FileReader::Memory(cursor) => cursor.read(buf),
+            FileReader::Disk(file) => file.read(buf),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::io::Seek for FileReader<'_> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        match self {
+            FileReader::Memory(cursor) => cursor.seek(pos),
+            FileReader::Disk(file) => file.seek(pos),
+        }
+    }
 }
 
 /// Represents a single file in an archive
@@ -63,8 +687,8 @@ pub enum BinaryReason {
 pub struct File {
     /// Name of the file (may include subdirectories)
     pub name: String,
-    /// Contents of the file
-    pub data: Vec<u8>,
+    /// Contents of the file, borrowed for `'static` data such as `include_bytes!`
+    pub data: Cow<'static, [u8]>,
     /// Whether this file is binary encoded
     pub is_binary: bool,
     /// Reason for binary encoding (if applicable)
@@ -73,6 +697,33 @@ pub struct File {
     pub snippet_ref: Option<SnippetRef>,
     /// Edit reference if this file contains edit instructions
     pub edit_ref: Option<EditRef>,
+    /// Source encoding of `data`, tagged as `[.enc:<label>]` when not UTF-8.
+    /// Text files are transcoded to UTF-8 in the archive; for binary files the
+    /// tag is informational only
+    pub encoding: TextEncoding,
+    /// `CrLf` for text decoded from a section whose lines all end with
+    /// `\r\n`, so the encoder writes them back that way even if the decoder
+    /// normalized the contents; `Preserve` writes the contents as they are
+    pub line_ending: LineEnding,
+    /// Recorded permissions and modification time, if any
+    pub metadata: FileMetadata,
+    /// Where the contents come from
+    pub source: FileSource,
+    /// Whether the contents are themselves a txtar archive, tagged `[.txtar]`
+    /// and decoded on demand by [`File::as_archive`]
+    pub is_archive: bool,
+    /// Whether `data` is encrypted, tagged `[.crypt:...]` (see [`CRYPT_SCHEME`])
+    pub is_encrypted: bool,
+    /// Conditions the entry is limited to, tagged `[.if:key=value]` and
+    /// evaluated by [`Archive::resolve`]
+    pub conditions: Vec<Condition>,
+    /// Application-defined tags (see [`tags`](crate::tags))
+    pub custom_tags: Vec<CustomTag>,
+    /// Whether the contents of a decoded text file ended with a newline,
+    /// which `data` leaves out; this tells an empty file from one holding a
+    /// single empty line, and [`content`](File::content) puts it back.
+    /// `None` for constructed files, whose `data` is the whole content
+    pub trailing_newline: Option<bool>,
 }
 
 impl File {
@@ -86,29 +737,117 @@ impl File {
     pub fn with_encoding(name: impl Into<String>, data: impl Into<Vec<u8>>, is_binary: bool) -> Self {
         Self {
             name: name.into(),
-            data: data.into(),
+            data: Cow::Owned(data.into()),
             is_binary,
             binary_reason: if is_binary { Some(BinaryReason::Explicit) } else { None },
             snippet_ref: None,
             edit_ref: None,
+            encoding: TextEncoding::Utf8,
+            line_ending: LineEnding::Preserve,
+            metadata: FileMetadata::default(),
+            source: FileSource::Memory,
+            is_archive: false,
+            is_encrypted: false,
+            conditions: Vec::new(),
+            custom_tags: Vec::new(),
+            trailing_newline: None,
         }
     }
 
+    /// Attach filesystem metadata to the file
+    pub fn with_metadata(mut self, metadata: FileMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
     /// Create a file with custom encoding detection config
     pub fn with_config(name: impl Into<String>, data: impl Into<Vec<u8>>, config: &EncodingConfig) -> Self {
-        let name = name.into();
-        let data = data.into();
+        Self::detect(name.into(), Cow::Owned(data.into()), config)
+    }
+
+    /// Create a file borrowing `'static` data, e.g. from `include_bytes!`,
+    /// so encoding it never copies the contents
+    pub fn from_static(name: impl Into<String>, data: &'static [u8]) -> Self {
+        Self::detect(name.into(), Cow::Borrowed(data), &EncodingConfig::default())
+    }
+
+    /// Create a file from contents read from disk
+    ///
+    /// Like [`File::new`], except that text without a final newline records
+    /// none in [`trailing_newline`](File::trailing_newline), so that it
+    /// can end an archive exactly as it ends on disk.
+    pub fn from_disk(name: impl Into<String>, data: impl Into<Vec<u8>>) -> Self {
+        let mut file = Self::new(name, data);
+        if !file.is_binary && !file.data.ends_with(b"\n") {
+            file.trailing_newline = Some(false);
+        }
+        file
+    }
+
+    /// Create a file backed by `path`, read only when the archive is encoded
+    /// or extracted, so its contents need not fit in memory
+    #[cfg(feature = "std")]
+    pub fn from_path_lazy(name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        let mut file = Self::with_encoding(name, Vec::new(), false);
+        file.binary_reason = None;
+        file.source = FileSource::Path(path.into());
+        file
+    }
 
+    /// The file with its contents in memory: borrowed as is, or read from
+    /// disk and classified with the default config if backed by a path
+    #[cfg(feature = "std")]
+    pub fn load(&self) -> anyhow::Result<Cow<'_, File>> {
+        let FileSource::Path(path) = &self.source else {
+            return Ok(Cow::Borrowed(self));
+        };
+        let data = std::fs::read(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", path.display(), e))?;
+        let mut file = File::from_disk(self.name.clone(), data).with_metadata(self.metadata);
+        file.snippet_ref = self.snippet_ref.clone();
+        file.edit_ref = self.edit_ref.clone();
+        file.is_archive = self.is_archive;
+        file.is_encrypted = self.is_encrypted;
+        file.conditions = self.conditions.clone();
+        file.custom_tags = self.custom_tags.clone();
+        file.trailing_newline = self.trailing_newline.or(file.trailing_newline);
+        file.line_ending = self.line_ending;
+        Ok(Cow::Owned(file))
+    }
+
+    /// A reader over the contents, for APIs that consume `Read + Seek`
+    ///
+    /// Nothing is copied: in-memory data is borrowed and path-backed files
+    /// are opened and read from disk on demand. Like extraction with default
+    /// options, the bytes are the file's own, not transcoded to UTF-8.
+    #[cfg(feature = "std")]
+    pub fn reader(&self) -> std::io::Result<FileReader<'_>> {
+        match &self.source {
+            FileSource::Memory => Ok(FileReader::Memory(std::io::Cursor::new(&self.data))),
+            FileSource::Path(path) => Ok(FileReader::Disk(std::fs::File::open(path)?)),
+        }
+    }
+
+    fn detect(name: String, data: Cow<'static, [u8]>, config: &EncodingConfig) -> Self {
         let detection = Self::detect_encoding(&name, &data, config);
 
         match detection {
-            EncodingDetection::Text { .. } => Self {
+            EncodingDetection::Text { encoding } => Self {
                 name,
                 data,
                 is_binary: false,
                 binary_reason: None,
                 snippet_ref: None,
                 edit_ref: None,
+                encoding,
+                line_ending: LineEnding::Preserve,
+                metadata: FileMetadata::default(),
+                source: FileSource::Memory,
+                is_archive: false,
+                is_encrypted: false,
+                conditions: Vec::new(),
+                custom_tags: Vec::new(),
+                trailing_newline: None,
             },
             EncodingDetection::Binary { reason } => Self {
                 name,
@@ -117,74 +856,396 @@ impl File {
                 binary_reason: Some(reason),
                 snippet_ref: None,
                 edit_ref: None,
+                encoding: TextEncoding::Utf8,
+                line_ending: LineEnding::Preserve,
+                metadata: FileMetadata::default(),
+                source: FileSource::Memory,
+                is_archive: false,
+                is_encrypted: false,
+                conditions: Vec::new(),
+                custom_tags: Vec::new(),
+                trailing_newline: None,
             },
         }
     }
 
     /// Detect the encoding of file data
-    pub fn detect_encoding(_name: &str, data: &[u8], config: &EncodingConfig) -> EncodingDetection {
+    pub fn detect_encoding(name: &str, data: &[u8], config: &EncodingConfig) -> EncodingDetection {
+        profile_count!(detections, 1);
+        // A user callback gets the first say
+        if let Some(detection) = config.detector.as_ref().and_then(|hook| hook.detect(name, data)) {
+            return detection;
+        }
+
+        // Per-pattern overrides take precedence over content checks
+        if let Some((pattern, ovr)) = config.override_for(name) {
+            return match ovr {
+                EncodingOverride::Binary => EncodingDetection::Binary {
+                    reason: BinaryReason::Override { pattern: pattern.to_string() },
+                },
+                EncodingOverride::Text => Self::forced_text(data, TextEncoding::Utf8),
+                EncodingOverride::Encoding(encoding) => Self::forced_text(data, *encoding),
+            };
+        }
+
+        // Large files may only have parts of their content inspected
+        let windows = Self::detection_windows(data, config);
+        let utf8_error = windows.iter().find_map(|w| {
+            core::str::from_utf8(w)
+                .err()
+                .map(|e| Self::offset_in(data, w) + e.valid_up_to())
+        });
+
         // Check content for conflicting marker patterns (if enabled)
         // This is the REAL issue: content containing "-- filename --" patterns
         // will be parsed as new file entries in the archive
-        if config.check_content_markers {
-            if let Ok(text) = std::str::from_utf8(data) {
-                if Self::contains_marker_pattern(text) {
-                    return EncodingDetection::Binary {
-                        reason: BinaryReason::ContentConflict,
-                    };
+        if config.check_content_markers && utf8_error.is_none() {
+            for w in &windows {
+                let text = core::str::from_utf8(w).unwrap_or_default();
+                if let Some(reason) = Self::marker_conflict(data, w, text) {
+                    return EncodingDetection::Binary { reason };
                 }
             }
         }
 
-        // Check UTF-8 encoding (if enabled)
-        if config.validate_utf8 {
-            if std::str::from_utf8(data).is_err() {
+        // Check UTF-8 encoding (if enabled), falling back to a legacy encoding if allowed
+        let mut encoding = TextEncoding::Utf8;
+        if let (true, Some(offset)) = (config.validate_utf8, utf8_error) {
+            let Some(fallback) = Self::legacy_fallback(&windows, config) else {
                 return EncodingDetection::Binary {
-                    reason: BinaryReason::InvalidUtf8,
+                    reason: BinaryReason::InvalidUtf8 { offset },
                 };
+            };
+            // Transcoded text must not contain markers either
+            if config.check_content_markers {
+                for w in &windows {
+                    let text = fallback.decode(w).unwrap_or_default();
+                    if let Some(reason) = Self::marker_conflict(data, w, &text) {
+                        return EncodingDetection::Binary { reason };
+                    }
+                }
             }
+            encoding = fallback;
         }
 
-        // Valid text (currently only UTF-8)
-        EncodingDetection::Text {
-            encoding: TextEncoding::Utf8,
+        // Heuristics for binary formats that happen to be valid UTF-8
+        if let Some(reason) = Self::detect_binary_heuristics(data, config) {
+            return EncodingDetection::Binary { reason };
+        }
+
+        EncodingDetection::Text { encoding }
+    }
+
+    /// Select the parts of the data inspected by the marker and UTF-8 checks
+    ///
+    /// Without sampling (or for data under the threshold) this is the whole
+    /// input. Otherwise the prefix and suffix windows are trimmed to whole
+    /// lines so that multi-byte characters and markers are never split; a
+    /// window with no line break to trim at falls back to the whole input.
+    fn detection_windows<'a>(data: &'a [u8], config: &EncodingConfig) -> Vec<&'a [u8]> {
+        let sampling = match &config.sampling {
+            Some(sampling) if data.len() > sampling.threshold => sampling,
+            _ => return vec![data],
+        };
+        if sampling.prefix + sampling.suffix >= data.len() {
+            return vec![data];
+        }
+
+        let prefix = &data[..sampling.prefix];
+        let suffix = &data[data.len() - sampling.suffix..];
+        match (prefix.iter().rposition(|&b| b == b'\n'), suffix.iter().position(|&b| b == b'\n')) {
+            (Some(end), Some(start)) => vec![&prefix[..end], &suffix[start + 1..]],
+            _ => vec![data],
         }
     }
 
-    /// Check if text contains txtar marker pattern `-- xxx --`
-    fn contains_marker_pattern(text: &str) -> bool {
-        // Look for lines that match the marker pattern
-        for line in text.lines() {
-            let trimmed = line.trim();
-            if trimmed.starts_with(MARKER_PREFIX) && trimmed.ends_with(MARKER_SUFFIX) {
-                // Extract what's between the markers
-                let content = &trimmed[MARKER_PREFIX_LEN..trimmed.len() - MARKER_SUFFIX_LEN];
-                // If it's not empty and looks like a filename (not just spaces)
-                if !content.trim().is_empty() {
-                    return true;
+    /// Byte offset of a detection window within the full data
+    fn offset_in(data: &[u8], window: &[u8]) -> usize {
+        window.as_ptr() as usize - data.as_ptr() as usize
+    }
+
+    /// Build a `ContentConflict` reason for the first marker line in a window
+    fn marker_conflict(data: &[u8], window: &[u8], text: &str) -> Option<BinaryReason> {
+        let (index, line) = Self::find_marker_line(text)?;
+        // Count the lines before the window so the line number is absolute
+        let offset = Self::offset_in(data, window);
+        let lines_before = data[..offset].iter().filter(|&&b| b == b'\n').count();
+        Some(BinaryReason::ContentConflict {
+            line_number: lines_before + index + 1,
+            line: line.to_string(),
+        })
+    }
+
+    /// Classify data as text in the given encoding, or binary if it cannot be decoded
+    fn forced_text(data: &[u8], encoding: TextEncoding) -> EncodingDetection {
+        if encoding.decode(data).is_some() {
+            EncodingDetection::Text { encoding }
+        } else {
+            let offset = core::str::from_utf8(data).err().map_or(0, |e| e.valid_up_to());
+            EncodingDetection::Binary { reason: BinaryReason::InvalidUtf8 { offset } }
+        }
+    }
+
+    /// Pick the legacy encoding enabled in the config that reads all windows as text
+    fn legacy_fallback(windows: &[&[u8]], config: &EncodingConfig) -> Option<TextEncoding> {
+        #[cfg(feature = "encoding")]
+        if config.cjk_fallback {
+            if let Some(encoding) = Self::guess_cjk_encoding(windows) {
+                return Some(encoding);
+            }
+        }
+        (config.latin1_fallback && windows.iter().all(|w| Self::is_mostly_printable_latin1(w)))
+            .then_some(TextEncoding::Latin1)
+    }
+
+    /// Guess GBK, Shift_JIS or Big5 with `chardetng`, accepting the guess only
+    /// if every window decodes without errors or control characters
+    #[cfg(feature = "encoding")]
+    fn guess_cjk_encoding(windows: &[&[u8]]) -> Option<TextEncoding> {
+        let mut detector = chardetng::EncodingDetector::new();
+        for w in windows {
+            detector.feed(w, false);
+        }
+        detector.feed(&[], true);
+        let guess = detector.guess(None, false);
+        let encoding = [TextEncoding::Gbk, TextEncoding::ShiftJis, TextEncoding::Big5]
+            .into_iter()
+            .find(|encoding| encoding.legacy() == Some(guess))?;
+        windows
+            .iter()
+            .all(|w| {
+                encoding.decode(w).is_some_and(|text| {
+                    text.chars().all(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r' | '\x0C'))
+                })
+            })
+            .then_some(encoding)
+    }
+
+    /// Check whether data reads as ISO-8859-1 text with few control bytes
+    fn is_mostly_printable_latin1(data: &[u8]) -> bool {
+        if data.is_empty() {
+            return true;
+        }
+        let printable = data
+            .iter()
+            .filter(|&&b| !Self::is_control_byte(b) && !(0x80..=0x9F).contains(&b))
+            .count();
+        printable as f32 / data.len() as f32 >= LATIN1_MIN_PRINTABLE_RATIO
+    }
+
+    /// Apply the control-byte and line-length heuristics to the sampled prefix
+    fn detect_binary_heuristics(data: &[u8], config: &EncodingConfig) -> Option<BinaryReason> {
+        let sample = &data[..data.len().min(config.heuristic_sample_size)];
+        if sample.is_empty() {
+            return None;
+        }
+
+        if let Some(max_ratio) = config.max_control_ratio {
+            let count = sample.iter().filter(|&&b| Self::is_control_byte(b)).count();
+            if count as f32 / sample.len() as f32 > max_ratio {
+                return Some(BinaryReason::ControlChars { count, sampled: sample.len() });
+            }
+        }
+
+        if let Some(max_len) = config.max_line_length {
+            for (i, line) in sample.split(|&b| b == b'\n').enumerate() {
+                if line.len() > max_len {
+                    return Some(BinaryReason::LongLine { line: i + 1, length: line.len() });
                 }
             }
         }
-        false
+
+        None
+    }
+
+    /// NUL and other C0 control bytes, excluding common text whitespace
+    fn is_control_byte(b: u8) -> bool {
+        (b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0C)) || b == 0x7F
+    }
+
+    /// Find the first line matching the txtar marker pattern `-- xxx --`
+    ///
+    /// Returns the 0-based line index and the trimmed line.
+    fn find_marker_line(text: &str) -> Option<(usize, &str)> {
+        text.lines()
+            .enumerate()
+            .find(|(_, line)| Self::is_marker_line(line))
+            .map(|(index, line)| (index, line.trim()))
+    }
+
+    /// Check if a line would be parsed as a txtar file marker `-- xxx --`
+    pub(crate) fn is_marker_line(line: &str) -> bool {
+        // Extract what's between the markers; in `-- --` they overlap
+        match line.trim().strip_prefix(MARKER_PREFIX).and_then(|rest| rest.strip_suffix(MARKER_SUFFIX)) {
+            // If it's not empty and looks like a filename (not just spaces)
+            Some(content) => !content.trim().is_empty(),
+            None => false,
+        }
     }
 
     /// Get the formatted name for the archive header
-    /// If binary encoding is needed, appends `[.base64]` suffix
+    /// Brackets in the name are escaped as `\[`/`\]`, and `--` as `-\-` in
+    /// names containing a marker pattern. If binary encoding is needed,
+    /// appends `[.base64]` suffix; a non-UTF-8 source encoding adds an
+    /// `[.enc:<label>]` tag, nested archives add `[.txtar]`, encrypted files
+    /// add `[.crypt:...]`, conditions add `[.if:...]`, recorded
+    /// metadata adds `[.mode:...]`/`[.mtime:...]` tags and custom tags
+    /// follow. These are followed by the snippet or edit
+    /// reference tag (if any)
     pub fn archive_name(&self) -> String {
-        if self.is_binary {
-            format!("{}{}", self.name, BASE64_SUFFIX)
-        } else {
-            self.name.clone()
+        self.header_name(self.is_binary, &[])
+    }
+
+    /// Format the header name as [`archive_name`](File::archive_name) does, but
+    /// with an explicit binary flag and extra content tags (`[.esc:...]`,
+    /// `[.patch:...]`) inserted before the reference tags
+    pub(crate) fn header_name(&self, is_binary: bool, content_tags: &[String]) -> String {
+        let mut name = escape_name(&self.name).into_owned();
+        if is_binary {
+            name.push_str(BASE64_SUFFIX);
+        }
+        if self.encoding != TextEncoding::Utf8 {
+            name.push_str(&format!("{}{}]", ENCODING_TAG_PREFIX, self.encoding.label()));
+        }
+        if self.is_archive {
+            name.push_str(ARCHIVE_TAG);
+        }
+        if self.is_encrypted {
+            name.push_str(&format!("{}{}]", CRYPT_TAG_PREFIX, CRYPT_SCHEME));
+        }
+        for condition in &self.conditions {
+            name.push_str(&condition.to_string());
+        }
+        name.push_str(&self.metadata.tags());
+        for tag in &self.custom_tags {
+            name.push_str(&tag.to_string());
         }
+        for tag in content_tags {
+            name.push_str(tag);
+        }
+        if let Some(snippet_ref) = &self.snippet_ref {
+            name.push_str(&snippet_ref.to_string());
+        }
+        if let Some(edit_ref) = &self.edit_ref {
+            name.push_str(&edit_ref.tag());
+        }
+        name
+    }
+
+    /// Create a `[.txtar]` entry holding `archive` encoded with the default encoder
+    pub fn from_archive(name: impl Into<String>, archive: &Archive) -> anyhow::Result<Self> {
+        let mut file = File::new(name, crate::encoder::Encoder::new().encode(archive)?);
+        file.is_archive = true;
+        Ok(file)
+    }
+
+    /// Decode the contents of a `[.txtar]` entry as an archive
+    ///
+    /// Nothing is decoded until this is called. Uses the default decoder;
+    /// see [`Decoder::decode_nested`](crate::Decoder::decode_nested) to
+    /// change the depth limit.
+    pub fn as_archive(&self) -> anyhow::Result<Archive> {
+        crate::decoder::Decoder::new().decode_nested(self)
+    }
+
+    /// MIME type of a binary file inferred from its magic bytes
+    ///
+    /// Text files and unrecognized data return `None`.
+    #[cfg(feature = "mime")]
+    pub fn mime(&self) -> Option<&'static str> {
+        if self.is_binary { crate::mime::sniff(&self.data) } else { None }
+    }
+
+    /// File contents as UTF-8 for text files, transcoding from the source encoding
+    ///
+    /// Binary files and files that cannot be transcoded are returned unchanged.
+    pub fn utf8_data(&self) -> Cow<'_, [u8]> {
+        if self.is_binary || self.encoding == TextEncoding::Utf8 {
+            return Cow::Borrowed(&self.data);
+        }
+        match self.encoding.decode(&self.data) {
+            Some(text) => Cow::Owned(text.into_bytes()),
+            None => Cow::Borrowed(&self.data),
+        }
+    }
+
+    /// Contents with the final newline a decoded text file recorded in
+    /// [`trailing_newline`](File::trailing_newline), as Go's txtar reads them
+    ///
+    /// This is what extraction writes. The newline matches the last line
+    /// break of [`data`](File::data), or [`line_ending`](File::line_ending)
+    /// for a single line.
+    pub fn content(&self) -> Cow<'_, [u8]> {
+        self.with_trailing_newline(Cow::Borrowed(&self.data))
+    }
+
+    /// [`content`](File::content) transcoded to UTF-8 like [`utf8_data`](File::utf8_data)
+    pub fn utf8_content(&self) -> Cow<'_, [u8]> {
+        self.with_trailing_newline(self.utf8_data())
     }
 
-    /// Parse an archive name, extracting the real name and binary flag
+    fn with_trailing_newline<'a>(&self, data: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+        if self.trailing_newline != Some(true) {
+            return data;
+        }
+        // The decoder may have normalized the line breaks of the contents
+        let crlf = match data.iter().rposition(|&b| b == b'\n') {
+            Some(i) => i > 0 && data[i - 1] == b'\r',
+            None => self.line_ending == LineEnding::CrLf,
+        };
+        let line_break: &[u8] = if crlf { b"\r\n" } else { b"\n" };
+        Cow::Owned([&data[..], line_break].concat())
+    }
+
+    /// Convert a snippet entry into an edit entry targeting the same file.
+    ///
+    /// The snippet's current text becomes the SEARCH block and `modified`
+    /// becomes the REPLACE block. The snippet's command href and line are
+    /// carried over to the edit reference.
+    ///
+    /// Returns `None` if this file is not a snippet or is not valid UTF-8.
+    pub fn snippet_to_edit(&self, modified: &str) -> Option<File> {
+        let snippet_ref = self.snippet_ref.as_ref()?;
+        let original = core::str::from_utf8(&self.data).ok()?;
+        let edit_ref = snippet_ref.to_edit_ref(original, modified);
+
+        let mut file = File::with_encoding(self.name.clone(), edit_ref.to_content(), false);
+        file.edit_ref = Some(edit_ref);
+        Some(file)
+    }
+
+    /// Convert an edit entry into a snippet entry showing the edited excerpt.
+    ///
+    /// The REPLACE block becomes the snippet text, positioned at the edit's
+    /// start line. Only edits with a `start_line` and exactly one block can be
+    /// converted, since the position of further blocks is unknown, and only
+    /// if the block edits the file the entry is named after.
+    pub fn edit_to_snippet(&self) -> Option<File> {
+        let edit_ref = self.edit_ref.as_ref()?;
+        let line = edit_ref.start_line?;
+        let [edit] = edit_ref.edits.as_slice() else {
+            return None;
+        };
+        if edit.file.as_ref().is_some_and(|file| *file != self.name) {
+            return None;
+        }
+
+        let mut file = File::with_encoding(self.name.clone(), edit.replacement.join("\n"), false);
+        file.snippet_ref = Some(SnippetRef {
+            command_href: edit_ref.command_href.clone(),
+            line,
+            language: None,
+        });
+        Some(file)
+    }
+
+    /// Parse an archive name, extracting the real (unescaped) name and binary flag
     pub fn parse_archive_name(archive_name: &str) -> (String, bool) {
         if archive_name.ends_with(BASE64_SUFFIX) {
             let name = &archive_name[..archive_name.len() - BASE64_SUFFIX_LEN];
-            (name.to_string(), true)
+            (unescape_name(name).into_owned(), true)
         } else {
-            (archive_name.to_string(), false)
+            (unescape_name(archive_name).into_owned(), false)
         }
     }
 }
@@ -200,13 +1261,130 @@ pub struct Command {
 }
 
 /// A snippet reference for a file
-/// Format: [.snippet:N] or .#href:line
+/// Format: [.snippet:N] or .#href:line, optionally followed by `:lang=<name>`
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SnippetRef {
     /// Optional command reference (if .#href:line format)
     pub command_href: Option<String>,
     /// Line number in the original source
     pub line: usize,
+    /// Optional language hint for syntax highlighting (e.g. `rust`)
+    pub language: Option<String>,
+}
+
+/// How the lines of a SEARCH block are compared with the lines of a file
+///
+/// Edits written by hand or generated by a language model often get the
+/// whitespace of the file wrong; the looser strategies let them apply
+/// anyway. Replacement lines are inserted as written.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MatchStrategy {
+    /// Lines are equal
+    #[default]
+    Exact,
+    /// Lines are equal apart from trailing whitespace
+    TrimWhitespace,
+    /// Lines are equal apart from leading and trailing whitespace, so
+    /// indentation with tabs or spaces, or at another depth, still matches
+    IgnoreIndentation,
+    /// Where no block matches ignoring indentation, the lines most similar
+    /// to the SEARCH block match, if their similarity is at least
+    /// `threshold`: 1 minus the character edits needed to turn one into the
+    /// other, with runs of whitespace counting as one space, over the length
+    /// of the longer
+    Fuzzy { threshold: f32 },
+}
+
+impl MatchStrategy {
+    /// The part of `line` compared, except for fuzzy similarity
+    fn normalize(self, line: &str) -> &str {
+        match self {
+            MatchStrategy::Exact => line,
+            MatchStrategy::TrimWhitespace => line.trim_end(),
+            MatchStrategy::IgnoreIndentation | MatchStrategy::Fuzzy { .. } => line.trim(),
+        }
+    }
+
+    /// Index of the run of `lines` matching `search`: the first, or the one
+    /// starting nearest line index `near`
+    fn find(self, lines: &[Cow<str>], search: &[String], near: Option<usize>) -> Option<usize> {
+        let normalized: Vec<String> = search.iter().map(|line| self.normalize(line).to_string()).collect();
+        let found = find_line_block_near(lines, &normalized, |line, search| self.normalize(line) == search, near);
+        match self {
+            MatchStrategy::Fuzzy { threshold } if found.is_none() => near
+                .and_then(|near| {
+                    // Look around the hint before the whole file
+                    let start = near.saturating_sub(START_LINE_WINDOW);
+                    let end = (near + search.len() + START_LINE_WINDOW).min(lines.len());
+                    Self::most_similar(&lines[start.min(end)..end], search, threshold).map(|found| start + found)
+                })
+                .or_else(|| Self::most_similar(lines, search, threshold)),
+            _ => found,
+        }
+    }
+
+    /// Number of places `search` matches, overlaps included
+    fn count(self, lines: &[Cow<str>], search: &[String]) -> usize {
+        let normalized: Vec<String> = search.iter().map(|line| self.normalize(line).to_string()).collect();
+        let mut count = 0;
+        let mut from = 0;
+        while let Some(start) = find_line_block(&lines[from..], &normalized, |line, search| self.normalize(line) == search) {
+            count += 1;
+            from += start + 1;
+        }
+        count
+    }
+
+    /// Start of the run of `lines` most similar to `search`, the first of
+    /// equally similar ones, if similar enough
+    fn most_similar(lines: &[Cow<str>], search: &[String], threshold: f32) -> Option<usize> {
+        fn words(line: &str) -> Vec<char> {
+            let mut chars = Vec::new();
+            for word in line.split_whitespace() {
+                if !chars.is_empty() {
+                    chars.push(' ');
+                }
+                chars.extend(word.chars());
+            }
+            chars
+        }
+
+        let search: Vec<Vec<char>> = search.iter().map(|line| words(line)).collect();
+        let lines: Vec<Vec<char>> = lines.iter().map(|line| words(line)).collect();
+        let mut best: Option<(usize, f32)> = None;
+        for start in 0..(lines.len() + 1).saturating_sub(search.len()) {
+            let (mut edits, mut len) = (0, 0);
+            for (line, search) in lines[start..].iter().zip(&search) {
+                edits += edit_distance(line, search);
+                len += line.len().max(search.len());
+            }
+            let similarity = if len == 0 { 1.0 } else { 1.0 - edits as f32 / len as f32 };
+            if similarity >= threshold && best.is_none_or(|(_, best)| similarity > best) {
+                best = Some((start, similarity));
+            }
+        }
+        best.map(|(start, _)| start)
+    }
+}
+
+/// Lines on either side of an edit's start line that fuzzy matching
+/// searches before the rest of the file
+const START_LINE_WINDOW: usize = 20;
+
+/// Number of characters to insert, delete or substitute to turn `a` into `b`
+fn edit_distance(a: &[char], b: &[char]) -> usize {
+    // Distances from the prefixes of `a` to the prefix of `b` so far
+    let mut row: Vec<usize> = (0..=a.len()).collect();
+    for (j, cb) in b.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = j + 1;
+        for (i, ca) in a.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[i + 1];
+            row[i + 1] = substituted.min(row[i] + 1).min(diagonal + 1);
+        }
+    }
+    row[a.len()]
 }
 
 /// Operation type for an edit block
@@ -216,12 +1394,22 @@ pub enum EditOperation {
     Replace,
     /// Delete content (only SEARCH present)
     Delete,
-    /// Insert content (only REPLACE present)
+    /// Insert content (only REPLACE present) at the start of the file
     Insert,
+    /// Insert the REPLACE lines after the lines matching SEARCH, which stay
+    /// (ends with `>>>>>>> INSERT AFTER`)
+    InsertAfter,
+    /// Insert the REPLACE lines before the lines matching SEARCH, which stay
+    /// (ends with `>>>>>>> INSERT BEFORE`)
+    InsertBefore,
 }
 
 /// A single edit block (SEARCH/REPLACE pair)
+///
+/// Build one with [`EditBlock::new`], adding a target with
+/// [`with_file`](EditBlock::with_file).
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct EditBlock {
     /// Original content (SEARCH block), lines trimmed for trailing whitespace
     pub search: Vec<String>,
@@ -229,6 +1417,26 @@ pub struct EditBlock {
     pub replacement: Vec<String>,
     /// Operation type
     pub operation: EditOperation,
+    /// File the block edits, from a `@@ file: path @@` line before it;
+    /// `None` for the file the edit entry is named after
+    ///
+    /// Only [`Archive`] methods applying edit entries route blocks by file:
+    /// [`EditRef::apply`] and the like apply every block to the content
+    /// they are given.
+    pub file: Option<String>,
+}
+
+impl EditBlock {
+    /// Create a block for the file the edit entry is named after
+    pub fn new(operation: EditOperation, search: Vec<String>, replacement: Vec<String>) -> Self {
+        Self { search, replacement, operation, file: None }
+    }
+
+    /// Target the block at the file `file` instead
+    pub fn with_file(mut self, file: impl Into<String>) -> Self {
+        self.file = Some(file.into());
+        self
+    }
 }
 
 /// Edit reference for applying changes to files
@@ -237,7 +1445,12 @@ pub struct EditBlock {
 pub struct EditRef {
     /// Optional command reference (metadata about where this edit came from)
     pub command_href: Option<String>,
-    /// Optional starting line number (for information only, not used for application)
+    /// Optional 1-based line of the file where the edit starts
+    ///
+    /// When a SEARCH block matches in several places, the match starting
+    /// nearest this line is edited rather than the first, and
+    /// [fuzzy](MatchStrategy::Fuzzy) matching looks around it before the
+    /// rest of the file.
     pub start_line: Option<usize>,
     /// Edit blocks to apply (typically one, but multiple allowed)
     pub edits: Vec<EditBlock>,
@@ -256,8 +1469,8 @@ pub enum SnippetParseError {
     InvalidLineNumber { input: String },
 }
 
-impl std::fmt::Display for SnippetParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for SnippetParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             SnippetParseError::InvalidFormat => {
                 write!(f, "Invalid snippet format. Expected [.snippet:N], [.snippet#href:line], or [.#href:line]")
@@ -275,7 +1488,7 @@ impl std::fmt::Display for SnippetParseError {
     }
 }
 
-impl std::error::Error for SnippetParseError {}
+impl core::error::Error for SnippetParseError {}
 
 /// Error type for edit block parsing
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -306,10 +1519,13 @@ pub enum EditParseError {
 
     /// Missing closing bracket in tag format
     MissingClosingBracket,
+
+    /// Insert-after or insert-before block with an empty SEARCH anchor
+    MissingAnchor,
 }
 
-impl std::fmt::Display for EditParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for EditParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             EditParseError::UnterminatedBlock => {
                 write!(f, "Unterminated edit block (missing >>>>>>> marker)")
@@ -338,11 +1554,14 @@ impl std::fmt::Display for EditParseError {
             EditParseError::MissingClosingBracket => {
                 write!(f, "Missing closing bracket ']' in tag format")
             }
+            EditParseError::MissingAnchor => {
+                write!(f, "INSERT AFTER and INSERT BEFORE blocks need SEARCH lines to anchor them")
+            }
         }
     }
 }
 
-impl std::error::Error for EditParseError {}
+impl core::error::Error for EditParseError {}
 
 /// Error type for edit application
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -367,10 +1586,16 @@ pub enum EditApplyError {
 
     /// I/O error during file operations
     IoError(String),
+
+    /// An edit entry has no file to apply to
+    TargetNotFound { name: String },
+
+    /// An edit could not be applied to the named file
+    InFile { name: String, error: Box<EditApplyError> },
 }
 
-impl std::fmt::Display for EditApplyError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl core::fmt::Display for EditApplyError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             EditApplyError::SearchNotFound { search } => {
                 write!(f, "Search pattern not found: '{}'", search)
@@ -393,22 +1618,103 @@ impl std::fmt::Display for EditApplyError {
             EditApplyError::IoError(msg) => {
                 write!(f, "I/O error: {}", msg)
             }
+            EditApplyError::TargetNotFound { name } => {
+                write!(f, "Edit target not found: {}", name)
+            }
+            EditApplyError::InFile { name, error } => {
+                write!(f, "Failed to apply edit to {}: {}", name, error)
+            }
+        }
+    }
+}
+
+impl core::error::Error for EditApplyError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            EditApplyError::InFile { error, .. } => Some(error.as_ref()),
+            _ => None,
         }
     }
 }
 
-impl std::error::Error for EditApplyError {}
+impl EditApplyError {
+    /// Attribute this error to the file `name`
+    fn in_file(self, name: &str) -> Self {
+        EditApplyError::InFile { name: name.to_string(), error: Box::new(self) }
+    }
+}
 
+#[cfg(feature = "std")]
 impl From<std::io::Error> for EditApplyError {
     fn from(err: std::io::Error) -> Self {
         EditApplyError::IoError(err.to_string())
     }
 }
 
+/// What [`Archive::apply_edits`] changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AppliedReport {
+    /// Files that were edited, in the order of their first edit
+    pub files: Vec<String>,
+    /// Number of edit entries applied and removed
+    pub edits: usize,
+    /// Number of SEARCH/REPLACE and line blocks applied
+    pub blocks: usize,
+}
+
+/// Error type for building and editing archives
+#[derive(Debug)]
+pub enum ArchiveError {
+    /// A normal file with this name is already in the archive
+    DuplicateFile { name: String },
+
+    /// The name cannot be written as an entry header
+    InvalidName { name: String, reason: &'static str },
+
+    /// No entry has this name
+    FileNotFound { name: String },
+
+    /// Reading a file or directory failed
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+impl core::fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ArchiveError::DuplicateFile { name } => write!(f, "Duplicate file: {}", name),
+            ArchiveError::InvalidName { name, reason } => write!(f, "Invalid file name {:?}: {}", name, reason),
+            ArchiveError::FileNotFound { name } => write!(f, "File not found: {}", name),
+            #[cfg(feature = "std")]
+            ArchiveError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl core::error::Error for ArchiveError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            #[cfg(feature = "std")]
+            ArchiveError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for ArchiveError {
+    fn from(err: std::io::Error) -> Self {
+        ArchiveError::Io(err)
+    }
+}
+
 impl SnippetRef {
     /// Parse a snippet reference from format: [.snippet:N], [.snippet#href:line], or [.#href:line]
     /// Note: [.#href:line] is shorthand for [.snippet#href:line]
     ///
+    /// Any of the formats may carry a trailing language annotation,
+    /// e.g. `[.snippet:N:lang=rust]` or `[.#href:line:lang=rust]`.
+    ///
     /// Returns Ok(SnippetRef) if successful, Err(SnippetParseError) if format is invalid
     pub fn parse(input: &str) -> Result<Self, SnippetParseError> {
         let input = input.trim();
@@ -433,6 +1739,18 @@ impl SnippetRef {
             return Err(SnippetParseError::InvalidFormat);
         };
 
+        // Split off the optional language annotation
+        let (inner, language) = match inner.rfind(SNIPPET_LANG_PREFIX) {
+            Some(pos) => {
+                let lang = inner[pos + SNIPPET_LANG_PREFIX.len()..].trim();
+                if lang.is_empty() {
+                    return Err(SnippetParseError::InvalidFormat);
+                }
+                (&inner[..pos], Some(lang.to_string()))
+            }
+            None => (inner, None),
+        };
+
         // Parse based on whether we have an href marker
         if has_href_marker {
             // Format: href:line
@@ -442,13 +1760,57 @@ impl SnippetRef {
             let line_str = &inner[colon_pos + 1..];
             let line = line_str.trim().parse::<usize>()
                 .map_err(|_| SnippetParseError::InvalidLineNumber { input: line_str.to_string() })?;
-            Ok(SnippetRef { command_href: Some(href), line })
+            Ok(SnippetRef { command_href: Some(href), line, language })
         } else {
             // Format: just line number
             let line = inner.trim().parse::<usize>()
                 .map_err(|_| SnippetParseError::InvalidLineNumber { input: inner.to_string() })?;
-            Ok(SnippetRef { command_href: None, line })
+            Ok(SnippetRef { command_href: None, line, language })
+        }
+    }
+
+    /// Build an edit reference that turns the snippet's `original` text into `modified`.
+    ///
+    /// The edit keeps the snippet's command href and uses its line as the
+    /// start line. If both texts are empty, the edit has no blocks.
+    pub fn to_edit_ref(&self, original: &str, modified: &str) -> EditRef {
+        let search: Vec<String> = original.lines().map(String::from).collect();
+        let replacement: Vec<String> = modified.lines().map(String::from).collect();
+
+        let operation = if search.is_empty() {
+            EditOperation::Insert
+        } else if replacement.is_empty() {
+            EditOperation::Delete
+        } else {
+            EditOperation::Replace
+        };
+
+        let edits = if search.is_empty() && replacement.is_empty() {
+            Vec::new()
+        } else {
+            vec![EditBlock { search, replacement, operation, file: None }]
+        };
+
+        EditRef {
+            command_href: self.command_href.clone(),
+            start_line: Some(self.line),
+            edits,
+        }
+    }
+}
+
+impl core::fmt::Display for SnippetRef {
+    /// Format as a header tag: `[.snippet:N]` or `[.snippet#href:line]`,
+    /// with `:lang=<name>` appended when a language is set
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match &self.command_href {
+            Some(href) => write!(f, "[.snippet#{}:{}", href, self.line)?,
+            None => write!(f, "[.snippet:{}", self.line)?,
+        }
+        if let Some(lang) = &self.language {
+            write!(f, "{}{}", SNIPPET_LANG_PREFIX, lang)?;
         }
+        write!(f, "]")
     }
 }
 
@@ -490,6 +1852,199 @@ impl EditRef {
         parser.finish()
     }
 
+    /// Format the header tag: `[.edit]`, `[.edit:line]` or `[.edit#href:line]`
+    ///
+    /// A command href without a start line cannot be represented and is omitted.
+    pub fn tag(&self) -> String {
+        match (&self.command_href, self.start_line) {
+            (Some(href), Some(line)) => format!("[.edit#{}:{}]", href, line),
+            (None, Some(line)) => format!("[.edit:{}]", line),
+            _ => "[.edit]".to_string(),
+        }
+    }
+
+    /// Render the edit blocks in SEARCH/REPLACE format, suitable as file
+    /// content for an `[.edit]` entry (the inverse of [`EditRef::parse_content`]).
+    pub fn to_content(&self) -> String {
+        let mut out = String::new();
+        for edit in &self.edits {
+            if let Some(file) = &edit.file {
+                out.push_str(&format!("{}{}{}\n", FILE_HEADER_PREFIX, file, FILE_HEADER_SUFFIX));
+            }
+            out.push_str("<<<<<<< SEARCH\n");
+            for line in &edit.search {
+                out.push_str(line);
+                out.push('\n');
+            }
+            if edit.operation == EditOperation::Delete {
+                out.push_str(">>>>>>> DELETE\n");
+                continue;
+            }
+            out.push_str("=======\n");
+            for line in &edit.replacement {
+                out.push_str(line);
+                out.push('\n');
+            }
+            out.push_str(match edit.operation {
+                EditOperation::InsertAfter => ">>>>>>> INSERT AFTER\n",
+                EditOperation::InsertBefore => ">>>>>>> INSERT BEFORE\n",
+                _ => ">>>>>>> REPLACE\n",
+            });
+        }
+        out
+    }
+
+    /// Edit blocks turning `original` into `modified`, line by line
+    ///
+    /// The lines that change are found from a longest common subsequence of
+    /// the lines of both, and each run of changed lines becomes one block.
+    /// Blocks carry just enough preceding lines to match only where they
+    /// belong when applied in order; pure insertions become
+    /// [`InsertAfter`](EditOperation::InsertAfter) blocks anchored on the
+    /// lines before them. As with [`apply`](Self::apply), the final line
+    /// break of either text is not compared.
+    ///
+    /// ```rust
+    /// use emx_txtar::EditRef;
+    ///
+    /// let original = "fn a() {}\nfn b() {}\nfn c() {}";
+    /// let modified = "fn a() {}\nfn b2() {}\nfn c() {}\nfn d() {}";
+    /// let edit = EditRef::diff(original, modified);
+    /// assert_eq!(edit.edits.len(), 2);
+    /// assert_eq!(edit.apply(original)?, modified);
+    /// # Ok::<(), emx_txtar::EditApplyError>(())
+    /// ```
+    pub fn diff(original: &str, modified: &str) -> EditRef {
+        let old: Vec<&str> = original.lines().collect();
+        let new: Vec<&str> = modified.lines().collect();
+        // The text as edited by the blocks so far
+        let mut current = old.clone();
+        let mut edits = Vec::new();
+        // How far the blocks so far moved the lines after them
+        let mut shift = 0isize;
+
+        for (removed, added) in changed_runs(&old, &new) {
+            let start = removed.start.wrapping_add_signed(shift);
+            let end = start + removed.len();
+            edits.push(change_block(&current, start..end, &new[added.clone()]));
+            current.splice(start..end, new[added.clone()].iter().copied());
+            shift += added.len() as isize - removed.len() as isize;
+        }
+
+        EditRef { command_href: None, start_line: None, edits }
+    }
+
+    /// Edit undoing this one: applied to the result of applying this edit to
+    /// `original_content`, it gives back `original_content`
+    ///
+    /// Each block becomes the block restoring the lines it changed, found
+    /// while applying the edit: replacements are searched for and replaced
+    /// by the lines they replaced, deletions become insertions of the
+    /// deleted lines and insertions become deletions. The inverse blocks
+    /// come in reverse order and carry the same context as
+    /// [`diff`](Self::diff) blocks. Fails like [`apply`](Self::apply) if the
+    /// edit does not apply to `original_content`.
+    ///
+    /// Edits see text as lines, so an edit whose result ends with a blank
+    /// line cannot always be undone: the blank line is taken for a final
+    /// line break when the result is split into lines again.
+    ///
+    /// ```rust
+    /// use emx_txtar::EditRef;
+    ///
+    /// let blocks = "<<<<<<< SEARCH\nb\n>>>>>>> DELETE\n<<<<<<< SEARCH\nc\n=======\nC\n>>>>>>> REPLACE\n";
+    /// let edit = EditRef { command_href: None, start_line: None, edits: EditRef::parse_content(blocks).unwrap() };
+    /// let original = "a\nb\nc";
+    /// let edited = edit.apply(original)?;
+    /// assert_eq!(edited, "a\nC");
+    /// assert_eq!(edit.invert(original)?.apply(&edited)?, original);
+    /// # Ok::<(), emx_txtar::EditApplyError>(())
+    /// ```
+    pub fn invert(&self, original_content: &str) -> Result<EditRef, EditApplyError> {
+        self.invert_with(original_content, MatchStrategy::Exact)
+    }
+
+    /// Edit undoing this one like [`invert`](Self::invert), finding SEARCH
+    /// blocks with `strategy`
+    ///
+    /// The inverse restores the lines as they were in `original_content`,
+    /// so it is always applied with [`MatchStrategy::Exact`].
+    pub fn invert_with(&self, original_content: &str, strategy: MatchStrategy) -> Result<EditRef, EditApplyError> {
+        self.check_content(original_content)?;
+        let mut lines: Vec<Cow<str>> = original_content.lines().map(Cow::Borrowed).collect();
+        let mut edits = Vec::new();
+
+        for edit in &self.edits {
+            let before = lines.clone();
+            let removed = self.apply_edit_to_lines(&mut lines, edit, strategy)?;
+            let inserted = lines.len() + removed.len() - before.len();
+            if removed.is_empty() && inserted == 0 {
+                continue;
+            }
+            let after: Vec<&str> = lines.iter().map(|line| line.as_ref()).collect();
+            let restored: Vec<&str> = before[removed.clone()].iter().map(|line| line.as_ref()).collect();
+            let block = change_block(&after, removed.start..removed.start + inserted, &restored);
+            edits.push(EditBlock { file: edit.file.clone(), ..block });
+        }
+
+        edits.reverse();
+        Ok(EditRef { command_href: self.command_href.clone(), start_line: None, edits })
+    }
+
+    /// The edit split into the runs of consecutive blocks editing the same
+    /// file, each with the name of that file
+    ///
+    /// Blocks without a [`file`](EditBlock::file) edit `name`, the name of
+    /// the edit entry, as does an edit without blocks. Only the runs editing
+    /// `name` keep the [`start_line`](Self::start_line) hint.
+    ///
+    /// ```rust
+    /// use emx_txtar::EditRef;
+    ///
+    /// let blocks = "<<<<<<< SEARCH\na\n>>>>>>> DELETE\n@@ file: src/b.rs @@\n<<<<<<< SEARCH\nb\n>>>>>>> DELETE\n";
+    /// let edit = EditRef { command_href: None, start_line: None, edits: EditRef::parse_content(blocks).unwrap() };
+    /// let runs = edit.split_by_file("a.rs");
+    /// assert_eq!(runs.iter().map(|(name, run)| (*name, run.edits.len())).collect::<Vec<_>>(), [("a.rs", 1), ("src/b.rs", 1)]);
+    /// ```
+    pub fn split_by_file<'a>(&'a self, name: &'a str) -> Vec<(&'a str, EditRef)> {
+        if self.edits.is_empty() {
+            return vec![(name, self.clone())];
+        }
+        let mut runs: Vec<(&str, EditRef)> = Vec::new();
+        for edit in &self.edits {
+            let file = edit.file.as_deref().unwrap_or(name);
+            match runs.last_mut() {
+                Some((last, run)) if *last == file => run.edits.push(edit.clone()),
+                _ => runs.push((file, EditRef {
+                    command_href: self.command_href.clone(),
+                    start_line: self.start_line.filter(|_| file == name),
+                    edits: vec![edit.clone()],
+                })),
+            }
+        }
+        runs
+    }
+
+    /// What applying the edits to `content` would change, as the hunks of a
+    /// unified diff
+    ///
+    /// Nothing is modified. The hunks have three lines of context and no
+    /// `---`/`+++` file header, so that the caller can name the file; the
+    /// preview is empty if the edits change nothing. Fails like
+    /// [`apply`](Self::apply).
+    ///
+    /// ```rust
+    /// use emx_txtar::EditRef;
+    ///
+    /// let edit = EditRef::diff("a\nb\nc", "a\nB\nc");
+    /// assert_eq!(edit.preview("a\nb\nc")?, "@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+    /// # Ok::<(), emx_txtar::EditApplyError>(())
+    /// ```
+    pub fn preview(&self, content: &str) -> Result<String, EditApplyError> {
+        let edited = self.apply(content)?;
+        Ok(unified_diff(content, &edited))
+    }
+
     /// Apply all edit blocks to file content.
     ///
     /// This method applies each edit block sequentially to the content.
@@ -517,11 +2072,11 @@ impl EditRef {
     ///     command_href: None,
     ///     start_line: None,
     ///     edits: vec![
-    ///         EditBlock {
-    ///             search: vec!["line 2".to_string()],
-    ///             replacement: vec!["modified line 2".to_string()],
-    ///             operation: EditOperation::Replace,
-    ///         },
+    ///         EditBlock::new(
+    ///             EditOperation::Replace,
+    ///             vec!["line 2".to_string()],
+    ///             vec!["modified line 2".to_string()],
+    ///         ),
     ///     ],
     /// };
     ///
@@ -530,141 +2085,167 @@ impl EditRef {
     /// # Ok::<(), emx_txtar::EditApplyError>(())
     /// ```
     pub fn apply(&self, content: &str) -> Result<String, EditApplyError> {
-        if content.is_empty() && !self.edits.is_empty() {
-            // Only allow edits on empty content if all edits are Insert operations
-            for edit in &self.edits {
-                if edit.operation != EditOperation::Insert {
-                    return Err(EditApplyError::EmptyContent);
-                }
-            }
+        self.apply_counting(content, MatchStrategy::Exact, None)
+    }
+
+    /// Apply the edits like [`apply`](Self::apply), finding SEARCH blocks
+    /// with `strategy`
+    ///
+    /// ```rust
+    /// use emx_txtar::{EditRef, MatchStrategy};
+    ///
+    /// let blocks = "<<<<<<< SEARCH\nif ok {\n    run();\n}\n=======\nif ok {\n    go();\n}\n>>>>>>> REPLACE\n";
+    /// let edit = EditRef { command_href: None, start_line: None, edits: EditRef::parse_content(blocks).unwrap() };
+    /// let content = "\tif ok {\n\t\trun();\n\t}";
+    /// assert!(edit.apply(content).is_err());
+    /// assert_eq!(edit.apply_with(content, MatchStrategy::IgnoreIndentation)?, "if ok {\n    go();\n}");
+    /// # Ok::<(), emx_txtar::EditApplyError>(())
+    /// ```
+    pub fn apply_with(&self, content: &str, strategy: MatchStrategy) -> Result<String, EditApplyError> {
+        self.apply_counting(content, strategy, None)
+    }
+
+    /// Apply the edits like [`apply`](Self::apply), reporting SEARCH blocks
+    /// that match in more than one place as [`Diagnostic::AmbiguousSearch`]
+    /// for the file `name`
+    pub fn apply_reporting(&self, content: &str, name: &str, diagnostics: &dyn Diagnostics) -> Result<String, EditApplyError> {
+        self.apply_with_reporting(content, MatchStrategy::Exact, name, diagnostics)
+    }
+
+    /// Apply the edits like [`apply_with`](Self::apply_with), reporting
+    /// ambiguous SEARCH blocks like [`apply_reporting`](Self::apply_reporting)
+    pub fn apply_with_reporting(
+        &self,
+        content: &str,
+        strategy: MatchStrategy,
+        name: &str,
+        diagnostics: &dyn Diagnostics,
+    ) -> Result<String, EditApplyError> {
+        let mut ambiguous = Vec::new();
+        let edited = self.apply_counting(content, strategy, Some(&mut ambiguous))?;
+        for (edit, matches) in ambiguous {
+            diagnostics.report(Diagnostic::AmbiguousSearch { name: name.to_string(), edit, matches });
+        }
+        Ok(edited)
+    }
+
+    /// Fail unless all edits are Insert operations if `content` is empty
+    fn check_content(&self, content: &str) -> Result<(), EditApplyError> {
+        if content.is_empty() && self.edits.iter().any(|edit| edit.operation != EditOperation::Insert) {
+            return Err(EditApplyError::EmptyContent);
         }
+        Ok(())
+    }
 
-        // Use Cow to avoid unnecessary allocations
+    /// Apply the edits, recording `(edit index, matches)` for SEARCH blocks
+    /// matching more than once if `ambiguous` is given
+    fn apply_counting(
+        &self,
+        content: &str,
+        strategy: MatchStrategy,
+        mut ambiguous: Option<&mut Vec<(usize, usize)>>,
+    ) -> Result<String, EditApplyError> {
+        self.check_content(content)?;
+
+        // Lines borrow from `content`; edits splice this one vector in place
+        // rather than rebuilding it, so large files aren't copied per edit
         let mut lines: Vec<Cow<str>> = content.lines().map(Cow::Borrowed).collect();
 
         // Apply each edit sequentially
         for (edit_index, edit) in self.edits.iter().enumerate() {
-            lines = self.apply_edit_to_lines(lines, edit, edit_index)?;
+            if let Some(ambiguous) = ambiguous.as_deref_mut() {
+                let matches = Self::count_matches(&lines, &edit.search, strategy);
+                if matches > 1 {
+                    ambiguous.push((edit_index, matches));
+                }
+            }
+            self.apply_edit_to_lines(&mut lines, edit, strategy)?;
+            profile_count!(edits_applied, 1);
         }
 
         // Join at the end (only one allocation)
-        Ok(lines.iter().map(|cow| cow.as_ref()).collect::<Vec<&str>>().join("\n"))
+        Ok(lines.join("\n"))
     }
 
-    /// Apply a single edit block to a list of lines
-    fn apply_edit_to_lines<'a>(
+    /// Apply a single edit block to a list of lines, returning the range of
+    /// lines it replaced
+    fn apply_edit_to_lines(
         &self,
-        lines: Vec<Cow<'a, str>>,
+        lines: &mut Vec<Cow<'_, str>>,
         edit: &EditBlock,
-        _edit_index: usize,
-    ) -> Result<Vec<Cow<'a, str>>, EditApplyError> {
+        strategy: MatchStrategy,
+    ) -> Result<Range<usize>, EditApplyError> {
         match edit.operation {
             EditOperation::Replace => {
-                self.replace_lines(lines, &edit.search, &edit.replacement)
+                self.replace_lines(lines, &edit.search, &edit.replacement, strategy)
             }
             EditOperation::Delete => {
-                self.delete_lines(lines, &edit.search)
+                self.delete_lines(lines, &edit.search, strategy)
             }
             EditOperation::Insert => {
-                // Insert at the beginning if content is empty
-                if lines.is_empty() {
-                    Ok(edit.replacement.iter().map(|s| Cow::Owned(s.clone())).collect())
-                } else {
-                    // Insert at the beginning (line 0)
-                    let mut result: Vec<Cow<'a, str>> = edit.replacement.iter()
-                        .map(|s| Cow::Owned(s.clone()))
-                        .collect();
-                    result.extend(lines);
-                    Ok(result)
+                // Insert at the beginning (line 0)
+                lines.splice(0..0, edit.replacement.iter().map(|s| Cow::Owned(s.clone())));
+                Ok(0..0)
+            }
+            EditOperation::InsertAfter | EditOperation::InsertBefore => {
+                let mut at = self.find_search_block(lines, &edit.search, strategy)?;
+                if edit.operation == EditOperation::InsertAfter {
+                    at += edit.search.len();
                 }
+                lines.splice(at..at, edit.replacement.iter().map(|s| Cow::Owned(s.clone())));
+                Ok(at..at)
             }
         }
     }
 
     /// Replace lines matching search pattern with replacement
-    fn replace_lines<'a>(
+    fn replace_lines(
         &self,
-        lines: Vec<Cow<'a, str>>,
+        lines: &mut Vec<Cow<'_, str>>,
         search: &[String],
         replacement: &[String],
-    ) -> Result<Vec<Cow<'a, str>>, EditApplyError> {
-        if search.is_empty() {
-            // Empty search means insert at the beginning
-            let mut result: Vec<Cow<'a, str>> = replacement.iter()
-                .map(|s| Cow::Owned(s.clone()))
-                .collect();
-            result.extend(lines);
-            return Ok(result);
-        }
-
-        let start = self.find_search_block(&lines, search)?;
-
-        let mut result = Vec::with_capacity(lines.len() + replacement.len());
-
-        // Add lines before the match (borrowed, no allocation)
-        result.extend(lines[..start].iter().cloned());
-
-        // Add replacement lines (owned, allocated once)
-        result.extend(replacement.iter().map(|s| Cow::Owned(s.clone())));
-
-        // Add lines after the match (borrowed, no allocation)
-        result.extend(lines[start + search.len()..].iter().cloned());
+        strategy: MatchStrategy,
+    ) -> Result<Range<usize>, EditApplyError> {
+        // Empty search means insert at the beginning
+        let range = if search.is_empty() {
+            0..0
+        } else {
+            let start = self.find_search_block(lines, search, strategy)?;
+            start..start + search.len()
+        };
 
-        Ok(result)
+        // Only the replacement lines are allocated; the rest stay borrowed
+        lines.splice(range.clone(), replacement.iter().map(|s| Cow::Owned(s.clone())));
+        Ok(range)
     }
 
     /// Delete lines matching search pattern
-    fn delete_lines<'a>(
-        &self,
-        lines: Vec<Cow<'a, str>>,
-        search: &[String],
-    ) -> Result<Vec<Cow<'a, str>>, EditApplyError> {
-        let start = self.find_search_block(&lines, search)?;
-
-        let mut result = Vec::with_capacity(lines.len());
-
-        // Add lines before the match
-        result.extend(lines[..start].iter().cloned());
-
-        // Skip the search block
-
-        // Add lines after the match
-        result.extend(lines[start + search.len()..].iter().cloned());
+    fn delete_lines(&self, lines: &mut Vec<Cow<'_, str>>, search: &[String], strategy: MatchStrategy) -> Result<Range<usize>, EditApplyError> {
+        let start = self.find_search_block(lines, search, strategy)?;
+        lines.drain(start..start + search.len());
+        Ok(start..start + search.len())
+    }
 
-        Ok(result)
+    /// Number of places a non-empty search block matches, overlaps included
+    fn count_matches(lines: &[Cow<str>], search: &[String], strategy: MatchStrategy) -> usize {
+        if search.is_empty() {
+            return 0;
+        }
+        strategy.count(lines, search)
     }
 
     /// Find the location of a search block in lines
-    fn find_search_block(&self, lines: &[Cow<str>], search: &[String]) -> Result<usize, EditApplyError> {
+    fn find_search_block(&self, lines: &[Cow<str>], search: &[String], strategy: MatchStrategy) -> Result<usize, EditApplyError> {
         if search.is_empty() {
             return Err(EditApplyError::SearchNotFound {
                 search: "(empty)".to_string(),
             });
         }
 
-        // Try to find exact match
-        for start in 0..=lines.len().saturating_sub(search.len()) {
-            if lines.len() < start + search.len() {
-                break;
-            }
-
-            let mut matches = true;
-            for (i, search_line) in search.iter().enumerate() {
-                if lines[start + i].as_ref() != search_line.as_str() {
-                    matches = false;
-                    break;
-                }
-            }
-
-            if matches {
-                return Ok(start);
-            }
-        }
-
-        // Not found
-        Err(EditApplyError::SearchNotFound {
-            search: search.join("\n"),
-        })
+        strategy.find(lines, search, self.start_line.map(|line| line.saturating_sub(1)))
+            .ok_or_else(|| EditApplyError::SearchNotFound {
+                search: search.join("\n"),
+            })
     }
 }
 
@@ -673,6 +2254,8 @@ struct EditParser {
     edits: Vec<EditBlock>,
     current_search: Option<Vec<String>>,
     current_replace: Option<Vec<String>>,
+    /// Target named by a `@@ file: path @@` line, for the next block
+    current_file: Option<String>,
     state: ParseState,
 }
 
@@ -682,6 +2265,7 @@ impl EditParser {
             edits: Vec::new(),
             current_search: None,
             current_replace: None,
+            current_file: None,
             state: ParseState::Start,
         }
     }
@@ -706,6 +2290,20 @@ impl EditParser {
                 line_number: line_num,
                 line: line.to_string(),
             })
+        } else if let Some(path) = file_header(line) {
+            // A second header before the block has nothing to name
+            if self.current_file.is_some() {
+                return Err(EditParseError::ExpectedSearchStart);
+            }
+            let path = path.trim();
+            if path.is_empty() {
+                return Err(EditParseError::MalformedLine {
+                    line_number: line_num,
+                    line: line.to_string(),
+                });
+            }
+            self.current_file = Some(path.to_string());
+            Ok(())
         } else if !line.is_empty() {
             Err(EditParseError::ExpectedSearchStart)
         } else {
@@ -726,6 +2324,7 @@ impl EditParser {
                 search,
                 replacement: Vec::new(),
                 operation: EditOperation::Delete,
+                file: self.current_file.take(),
             });
 
             self.state = ParseState::Start;
@@ -746,14 +2345,26 @@ impl EditParser {
 
     fn handle_replace(&mut self, line: &str, _line_num: usize) -> Result<(), EditParseError> {
         if line.starts_with(">>>>>>> REPLACE") || line.starts_with(">>>>>>> INSERT") {
-            // Both REPLACE and INSERT markers end the block
+            // Both REPLACE and INSERT markers end the block; INSERT AFTER and
+            // INSERT BEFORE keep the SEARCH lines as an anchor
             let search = self.current_search.take().unwrap_or_default();
             let replacement = self.current_replace.take().unwrap_or_default();
+            let operation = if line.starts_with(">>>>>>> INSERT AFTER") {
+                EditOperation::InsertAfter
+            } else if line.starts_with(">>>>>>> INSERT BEFORE") {
+                EditOperation::InsertBefore
+            } else {
+                EditOperation::Replace // Will be inferred later
+            };
+            if operation != EditOperation::Replace && search.is_empty() {
+                return Err(EditParseError::MissingAnchor);
+            }
 
             self.edits.push(EditBlock {
                 search,
                 replacement,
-                operation: EditOperation::Replace, // Will be inferred later
+                operation,
+                file: self.current_file.take(),
             });
 
             self.state = ParseState::Start;
@@ -773,6 +2384,10 @@ impl EditParser {
         if self.state != ParseState::Start {
             return Err(EditParseError::UnterminatedBlock);
         }
+        // A file header must be followed by the block it names
+        if self.current_file.is_some() {
+            return Err(EditParseError::ExpectedSearchStart);
+        }
 
         // Validate and infer operation types
         for edit in &mut self.edits {
@@ -794,6 +2409,12 @@ impl EditParser {
     }
 }
 
+/// Path of a `@@ file: path @@` line, spaces around `file:` optional
+fn file_header(line: &str) -> Option<&str> {
+    let inner = line.strip_prefix("@@")?.strip_suffix("@@")?;
+    inner.trim_start().strip_prefix("file:")
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum ParseState {
     Start,
@@ -801,6 +2422,214 @@ enum ParseState {
     InReplace,
 }
 
+/// Lines of context around the changes of a [`unified_diff`] hunk
+const DIFF_CONTEXT: usize = 3;
+
+/// Hunks of a unified diff from `original` to `modified`, line by line
+fn unified_diff(original: &str, modified: &str) -> String {
+    let old: Vec<&str> = original.lines().collect();
+    let new: Vec<&str> = modified.lines().collect();
+    let runs = changed_runs(&old, &new);
+    let mut out = String::new();
+
+    let mut rest = &runs[..];
+    while let Some((first, _)) = rest.first() {
+        // Runs closer than twice the context share a hunk
+        let count = 1 + rest.windows(2).take_while(|w| w[1].0.start - w[0].0.end <= 2 * DIFF_CONTEXT).count();
+        let (hunk, next) = rest.split_at(count);
+        rest = next;
+
+        let leading = first.start.min(DIFF_CONTEXT);
+        let (last_old, last_new) = &hunk[count - 1];
+        let trailing = (old.len() - last_old.end).min(DIFF_CONTEXT);
+        let old_lines = first.start - leading..last_old.end + trailing;
+        let new_lines = hunk[0].1.start - leading..last_new.end + trailing;
+        out.push_str(&format!("@@ -{} +{} @@\n", hunk_range(&old_lines), hunk_range(&new_lines)));
+
+        let mut at = old_lines.start;
+        for (removed, added) in hunk {
+            for line in &old[at..removed.start] {
+                out.push_str(&format!(" {}\n", line));
+            }
+            for line in &old[removed.clone()] {
+                out.push_str(&format!("-{}\n", line));
+            }
+            for line in &new[added.clone()] {
+                out.push_str(&format!("+{}\n", line));
+            }
+            at = removed.end;
+        }
+        for line in &old[at..old_lines.end] {
+            out.push_str(&format!(" {}\n", line));
+        }
+    }
+    out
+}
+
+/// `start,count` of a hunk header, with 1-based lines; an empty range
+/// starts at the line before it
+fn hunk_range(lines: &Range<usize>) -> String {
+    match lines.len() {
+        0 => format!("{},0", lines.start),
+        1 => format!("{}", lines.start + 1),
+        n => format!("{},{}", lines.start + 1, n),
+    }
+}
+
+/// Runs of lines that differ between `old` and `new`, as the range each
+/// run takes in either, in order
+fn changed_runs(old: &[&str], new: &[&str]) -> Vec<(Range<usize>, Range<usize>)> {
+    // Only the lines between a common prefix and suffix are compared
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (old_mid, new_mid) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+    let (n, m) = (old_mid.len(), new_mid.len());
+
+    // common[i * (m + 1) + j]: length of the longest common subsequence of
+    // old_mid[i..] and new_mid[j..]
+    let mut common = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            common[i * (m + 1) + j] = if old_mid[i] == new_mid[j] {
+                common[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                common[(i + 1) * (m + 1) + j].max(common[i * (m + 1) + j + 1])
+            };
+        }
+    }
+
+    let mut runs = Vec::new();
+    let mut run: Option<(usize, usize)> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_mid[i] == new_mid[j] {
+            if let Some((from_i, from_j)) = run.take() {
+                runs.push((prefix + from_i..prefix + i, prefix + from_j..prefix + j));
+            }
+            i += 1;
+            j += 1;
+            continue;
+        }
+        run.get_or_insert((i, j));
+        if j == m || (i < n && common[(i + 1) * (m + 1) + j] >= common[i * (m + 1) + j + 1]) {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    if let Some((from_i, from_j)) = run {
+        runs.push((prefix + from_i..prefix + n, prefix + from_j..prefix + m));
+    }
+    runs
+}
+
+/// Block replacing `range` of `lines` with `inserted`, with just enough of
+/// the lines before it to be found there when applied to `lines`
+fn change_block(lines: &[&str], range: Range<usize>, inserted: &[&str]) -> EditBlock {
+    let Range { start, end } = range;
+    let replacement = to_strings(inserted);
+    if start == end && start == 0 {
+        EditBlock { search: Vec::new(), replacement, operation: EditOperation::Insert, file: None }
+    } else if start == end {
+        let from = unique_block_start(lines, start - 1, start);
+        EditBlock { search: to_strings(&lines[from..start]), replacement, operation: EditOperation::InsertAfter, file: None }
+    } else {
+        let from = unique_block_start(lines, start, end);
+        let replacement = [to_strings(&lines[from..start]), replacement].concat();
+        let operation = if replacement.is_empty() { EditOperation::Delete } else { EditOperation::Replace };
+        EditBlock { search: to_strings(&lines[from..end]), replacement, operation, file: None }
+    }
+}
+
+/// Start of the shortest block of `lines` ending at `end` and starting at
+/// or before `start` that matches nowhere else, or failing that the
+/// shortest that an in-order search finds first
+fn unique_block_start(lines: &[&str], start: usize, end: usize) -> usize {
+    let mut first = None;
+    for from in (0..=start).rev() {
+        let search = to_strings(&lines[from..end]);
+        let found = find_line_block(lines, &search, |line, search| *line == search);
+        if found == Some(from) {
+            let unique = find_line_block(&lines[from + 1..], &search, |line, search| *line == search).is_none();
+            if unique {
+                return from;
+            }
+            first.get_or_insert(from);
+        }
+    }
+    first.unwrap_or(0)
+}
+
+fn to_strings(lines: &[&str]) -> Vec<String> {
+    lines.iter().map(|line| line.to_string()).collect()
+}
+
+/// Index of the first run of `lines` matching `search` line by line
+///
+/// Knuth-Morris-Pratt over lines, so each line is compared a bounded number
+/// of times however often the first search line repeats.
+pub(crate) fn find_line_block<T>(lines: &[T], search: &[String], eq: impl Fn(&T, &str) -> bool) -> Option<usize> {
+    if search.is_empty() {
+        return Some(0);
+    }
+
+    // fallback[i]: length of the longest proper prefix of search[..=i] that is also its suffix
+    let mut fallback = vec![0; search.len()];
+    let mut k = 0;
+    for i in 1..search.len() {
+        while k > 0 && search[i] != search[k] {
+            k = fallback[k - 1];
+        }
+        if search[i] == search[k] {
+            k += 1;
+        }
+        fallback[i] = k;
+    }
+
+    let mut matched = 0;
+    for (i, line) in lines.iter().enumerate() {
+        while matched > 0 && !eq(line, &search[matched]) {
+            matched = fallback[matched - 1];
+        }
+        if eq(line, &search[matched]) {
+            matched += 1;
+            if matched == search.len() {
+                return Some(i + 1 - matched);
+            }
+        }
+    }
+    None
+}
+
+/// Index of the run of `lines` matching `search` line by line that starts
+/// nearest line index `near`, the earlier of two as near, or the first
+/// without a hint
+pub(crate) fn find_line_block_near<T>(
+    lines: &[T],
+    search: &[String],
+    eq: impl Fn(&T, &str) -> bool,
+    near: Option<usize>,
+) -> Option<usize> {
+    let Some(near) = near else {
+        return find_line_block(lines, search, eq);
+    };
+    let mut nearest: Option<usize> = None;
+    let mut from = 0;
+    while let Some(start) = find_line_block(&lines[from..], search, &eq).map(|start| from + start) {
+        if nearest.is_none_or(|nearest| start.abs_diff(near) < nearest.abs_diff(near)) {
+            nearest = Some(start);
+        } else if start > near {
+            // Later matches are only further away
+            break;
+        }
+        from = start + 1;
+        if from > lines.len() {
+            break;
+        }
+    }
+    nearest
+}
+
 impl Command {
     /// Parse a command reference from format: [command: cmd](#href)
     /// Returns None if the format doesn't match
@@ -843,8 +2672,246 @@ impl Command {
     }
 }
 
+/// Device names Windows reserves in every directory, with or without an extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Reject names that cannot be written as an entry header
+fn check_name(name: &str) -> Result<(), ArchiveError> {
+    let reason = if name.is_empty() {
+        "empty name"
+    } else if name.contains(['\n', '\r']) {
+        "contains a line break"
+    } else {
+        return Ok(());
+    };
+    Err(ArchiveError::InvalidName { name: name.to_string(), reason })
+}
+
+/// Whether `file` must be the only entry of its name: not a snippet or
+/// edit reference, nor a conditional variant
+pub(crate) fn is_unique(file: &File) -> bool {
+    is_unique_entry(&file.snippet_ref, &file.edit_ref, &file.conditions)
+}
+
+/// [`is_unique`] for an entry known only by its header
+pub(crate) fn is_unique_entry(snippet_ref: &Option<SnippetRef>, edit_ref: &Option<EditRef>, conditions: &[Condition]) -> bool {
+    snippet_ref.is_none() && edit_ref.is_none() && conditions.is_empty()
+}
+
+fn is_windows_forbidden(c: char) -> bool {
+    matches!(c, '<' | '>' | ':' | '"' | '\\' | '|' | '?' | '*') || c.is_ascii_control()
+}
+
+fn is_windows_reserved(component: &str) -> bool {
+    let stem = component.split('.').next().unwrap_or(component).trim_end();
+    WINDOWS_RESERVED_NAMES.iter().any(|reserved| stem.eq_ignore_ascii_case(reserved))
+}
+
+/// Why a path component is not a valid file name on Windows, if it is not
+fn windows_name_problem(component: &str) -> Option<&'static str> {
+    if component.chars().any(is_windows_forbidden) {
+        Some("forbidden character")
+    } else if component != "." && component != ".." && component.ends_with(['.', ' ']) {
+        Some("trailing dot or space")
+    } else if is_windows_reserved(component) {
+        Some("reserved device name")
+    } else {
+        None
+    }
+}
+
+/// Make a path component a valid Windows file name
+fn sanitize_windows_component(component: &str) -> String {
+    let mut name: String = component.chars().map(|c| if is_windows_forbidden(c) { '_' } else { c }).collect();
+    if name != "." && name != ".." {
+        name.truncate(name.trim_end_matches(['.', ' ']).len());
+        if name.is_empty() {
+            name.push('_');
+        }
+    }
+    if is_windows_reserved(&name) {
+        name.insert(name.find('.').unwrap_or(name.len()), '_');
+    }
+    name
+}
+
+/// How extraction treats names that are not valid file names on Windows:
+/// reserved device names (`CON`, `NUL.txt`, `COM1`...), names ending in a dot
+/// or space, and names containing `<>:"\|?*` or control characters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WindowsNamePolicy {
+    /// Write names unchanged (the default except on Windows)
+    #[cfg_attr(not(windows), default)]
+    Allow,
+    /// Fail on such names (the default on Windows)
+    #[cfg_attr(windows, default)]
+    Reject,
+    /// Replace forbidden characters with `_`, drop trailing dots and spaces,
+    /// and append `_` to the stem of reserved names (`CON.txt` becomes
+    /// `CON_.txt`). Distinct names may map to the same path
+    Sanitize,
+}
+
+impl WindowsNamePolicy {
+    /// Parse a policy name: `allow`, `reject` or `sanitize`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "allow" => Some(WindowsNamePolicy::Allow),
+            "reject" => Some(WindowsNamePolicy::Reject),
+            "sanitize" => Some(WindowsNamePolicy::Sanitize),
+            _ => None,
+        }
+    }
+
+    /// Apply the policy to a `/`-separated archive path
+    pub fn apply<'a>(&self, name: &'a str) -> anyhow::Result<Cow<'a, str>> {
+        let problem = name.split('/').find_map(|c| windows_name_problem(c).map(|problem| (c, problem)));
+        match (self, problem) {
+            (WindowsNamePolicy::Allow, _) | (_, None) => Ok(Cow::Borrowed(name)),
+            (WindowsNamePolicy::Reject, Some((component, problem))) => {
+                anyhow::bail!("'{}' is not a valid Windows path: {} in '{}'", name, problem, component)
+            }
+            (WindowsNamePolicy::Sanitize, Some(_)) => {
+                Ok(Cow::Owned(name.split('/').map(sanitize_windows_component).collect::<Vec<_>>().join("/")))
+            }
+        }
+    }
+}
+
+/// Path under `dir` for an archive entry named `name`
+///
+/// Fails on empty names, absolute paths, drive prefixes and `..` components,
+/// so the result always lies below `dir`; `.` components are dropped.
+#[cfg(feature = "std")]
+pub fn extract_path(dir: &Path, name: &str) -> anyhow::Result<PathBuf> {
+    use std::path::Component;
+
+    let mut path = dir.to_path_buf();
+    let mut depth = 0;
+    for component in Path::new(name).components() {
+        match component {
+            Component::Normal(part) => {
+                path.push(part);
+                depth += 1;
+            }
+            Component::CurDir => {}
+            Component::ParentDir => anyhow::bail!("Refusing to extract '{}': path contains '..'", name),
+            Component::RootDir | Component::Prefix(_) => anyhow::bail!("Refusing to extract '{}': path is absolute", name),
+        }
+    }
+    if depth == 0 {
+        anyhow::bail!("Refusing to extract '{}': empty path", name);
+    }
+    Ok(path)
+}
+
+/// Create `dir` and the missing parent directories of `path`, a result of
+/// [`extract_path`] under `dir`
+///
+/// Fails if a directory between `dir` and `path`, or `path` itself, is a
+/// symlink, so that an archive cannot plant a link and then write through
+/// it. The checks see the tree as it is when called; they do not guard
+/// against another process changing it concurrently.
+#[cfg(feature = "std")]
+pub fn prepare_extract_path(dir: &Path, path: &Path) -> anyhow::Result<()> {
+    let relative = path.strip_prefix(dir)
+        .map_err(|_| anyhow::anyhow!("'{}' is not under '{}'", path.display(), dir.display()))?;
+
+    // The target directory itself may be new; only what is below it is checked
+    std::fs::create_dir_all(dir)?;
+    let mut current = dir.to_path_buf();
+    let mut components = relative.components().peekable();
+    while let Some(component) = components.next() {
+        current.push(component);
+        let last = components.peek().is_none();
+        match std::fs::symlink_metadata(&current) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                anyhow::bail!("Refusing to extract through symlink '{}'", current.display());
+            }
+            Ok(metadata) if !last && !metadata.is_dir() => {
+                anyhow::bail!("Cannot extract '{}': '{}' is not a directory", path.display(), current.display());
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if !last {
+                    std::fs::create_dir(&current)?;
+                }
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Apply `edit` to the text of a file on disk, keeping its line breaks
+///
+/// Edits work on lines, so the `\r\n` breaks and the final line break the
+/// result would otherwise lose are put back.
+#[cfg(feature = "std")]
+fn edit_disk_text(edit: &EditRef, content: &str, strategy: MatchStrategy) -> Result<String, EditApplyError> {
+    let mut edited = edit.apply_with(content, strategy)?;
+    if content.ends_with('\n') && !edited.is_empty() {
+        edited.push('\n');
+    }
+    if content.contains("\r\n") {
+        edited = LineEnding::CrLf.apply(&edited).into_owned();
+    }
+    Ok(edited)
+}
+
+/// Options for [`Archive::from_dir_with`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct DirOptions {
+    /// `.gitignore`-style patterns for paths to skip, relative to the root
+    /// and overridden by the rules of ignore files
+    pub ignore: Vec<String>,
+    /// Names of the ignore files read in every directory, by default
+    /// [`IGNORE_FILE_NAME`]; rules apply to the directory and everything
+    /// below it, with deeper files taking precedence
+    pub ignore_files: Vec<String>,
+}
+
+#[cfg(feature = "std")]
+impl Default for DirOptions {
+    fn default() -> Self {
+        Self { ignore: Vec::new(), ignore_files: vec![IGNORE_FILE_NAME.to_string()] }
+    }
+}
+
+/// Options for [`Archive::extract_to_dir`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    /// Also write snippet entries (skipped by default)
+    pub include_snippets: bool,
+    /// Write transcoded text as UTF-8 instead of restoring its original encoding
+    pub utf8: bool,
+    /// Apply recorded permission bits to written files
+    pub preserve_permissions: bool,
+    /// Apply recorded modification times to written files
+    pub preserve_mtimes: bool,
+    /// Handling of names that are not valid on Windows
+    pub windows_names: WindowsNamePolicy,
+}
+
+/// Options for [`Archive::apply_edits_to_dir`]
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApplyOptions {
+    /// Copy each file to the same path with `.bak` appended before changing it
+    pub backup: bool,
+    /// Only check that the edits apply; write nothing
+    pub dry_run: bool,
+    /// How SEARCH blocks are found in the files
+    pub strategy: MatchStrategy,
+}
+
 /// Represents a txtar archive containing multiple files
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Default)]
 pub struct Archive {
     /// Comment lines before the first file
     pub comment: String,
@@ -854,20 +2921,20 @@ pub struct Archive {
     pub files: Vec<File>,
     /// Command index cache for O(1) lookup by href
     /// (Not included in PartialEq/Eq comparisons)
-    command_index: std::collections::HashMap<String, usize>,
+    command_index: alloc::collections::BTreeMap<String, usize>,
+    /// Where each entry was found in the decoded text
+    /// (Not included in PartialEq/Eq comparisons)
+    pub(crate) spans: Vec<crate::position::EntrySpan>,
 }
 
-impl Default for Archive {
-    fn default() -> Self {
-        Self {
-            comment: String::default(),
-            commands: Vec::default(),
-            files: Vec::default(),
-            command_index: std::collections::HashMap::default(),
-        }
+impl PartialEq for Archive {
+    fn eq(&self, other: &Self) -> bool {
+        self.comment == other.comment && self.commands == other.commands && self.files == other.files
     }
 }
 
+impl Eq for Archive {}
+
 /// Error for snippet reference validation
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SnippetRefError {
@@ -892,68 +2959,347 @@ impl Archive {
     }
 
     /// Add a file to the archive
-    /// Returns an error if a normal file (non-snippet, non-edit) with the same name already exists
-    pub fn add_file(&mut self, file: File) -> anyhow::Result<()> {
-        // Check for duplicates only for normal files (not snippet/edit references)
-        if file.snippet_ref.is_none() && file.edit_ref.is_none() {
-            if self.files.iter().any(|f| f.name == file.name && f.snippet_ref.is_none() && f.edit_ref.is_none()) {
-                anyhow::bail!("Duplicate file: {}", file.name);
-            }
+    /// Returns an error if the name is empty or spans lines, or if a normal
+    /// file (non-snippet, non-edit) with the same name already exists
+    pub fn add_file(&mut self, file: File) -> Result<(), ArchiveError> {
+        check_name(&file.name)?;
+        // Check for duplicates only for normal files (not snippet/edit
+        // references or conditional variants)
+        if is_unique(&file) && self.files.iter().any(|f| f.name == file.name && is_unique(f)) {
+            return Err(ArchiveError::DuplicateFile { name: file.name });
         }
         self.files.push(file);
         Ok(())
     }
 
-    /// Add a file from a path
-    pub fn add_file_from_path(&mut self, path: &Path, archive_name: Option<String>) -> anyhow::Result<()> {
-        let data = std::fs::read(path)?;
-
-        // Use provided name or the file's actual name
-        let name = archive_name.unwrap_or_else(|| {
-            path.file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("unknown")
-                .to_string()
-        });
+    /// Remove the normal file (non-snippet, non-edit) `name` and return it
+    ///
+    /// Snippet and edit entries of the same name are kept.
+    pub fn remove_file(&mut self, name: &str) -> Option<File> {
+        let index = self.files.iter().position(|f| f.name == name && f.snippet_ref.is_none() && f.edit_ref.is_none())?;
+        self.remove_span(index);
+        Some(self.files.remove(index))
+    }
 
-        self.add_file(File::new(name, data))?;
+    /// Rename every entry named `old`, the file with its snippet and edit
+    /// entries, to `new`
+    ///
+    /// Fails like [`add_file`](Self::add_file) if `new` is not a valid name
+    /// or a normal file would end up beside another of the same name, and if
+    /// no entry is named `old`.
+    pub fn rename_file(&mut self, old: &str, new: &str) -> Result<(), ArchiveError> {
+        check_name(new)?;
+        if !self.files.iter().any(|f| f.name == old) {
+            return Err(ArchiveError::FileNotFound { name: old.to_string() });
+        }
+        if old == new {
+            return Ok(());
+        }
+        let renamed_unique = self.files.iter().any(|f| f.name == old && is_unique(f));
+        if renamed_unique && self.files.iter().any(|f| f.name == new && is_unique(f)) {
+            return Err(ArchiveError::DuplicateFile { name: new.to_string() });
+        }
+        for file in self.files.iter_mut().filter(|f| f.name == old) {
+            file.name = new.to_string();
+        }
+        // Positions follow the entries
+        for span in self.spans.iter_mut().filter(|span| span.name == old) {
+            span.name = new.to_string();
+        }
         Ok(())
     }
 
-    /// Parse command references from the comment section
-    /// Looks for patterns like [command: cmd](#href) in markdown link format
-    pub fn parse_commands(&mut self) {
-        self.commands.clear();
+    /// Look up a normal file (non-snippet, non-edit) by name
+    pub fn get_file(&self, name: &str) -> Option<&File> {
+        self.files.iter().find(|f| f.name == name && f.snippet_ref.is_none() && f.edit_ref.is_none())
+    }
 
-        // Look for markdown-style links: [command: cmd](#href)
-        // We need to find these in the comment text
-        let text = self.comment.clone();
+    /// Apply edit entries to the files they target, in order, and drop them
+    ///
+    /// Each `[.edit]` entry is applied to the normal file of the same name,
+    /// except for blocks naming another file with a `@@ file: path @@` line
+    /// (see [`EditRef::split_by_file`]). Nothing changes unless every edit
+    /// applies.
+    pub fn apply_edits(&mut self) -> Result<AppliedReport, EditApplyError> {
+        self.apply_edits_reporting(&crate::diagnostics::Ignore)
+    }
 
-        // Simple regex-like search for [command: ...](#...) pattern
-        let mut chars = text.chars().peekable();
-        let mut result = Vec::new();
+    /// Apply edit entries like [`apply_edits`](Self::apply_edits), reporting
+    /// ambiguous SEARCH blocks to `diagnostics`
+    pub fn apply_edits_reporting(&mut self, diagnostics: &dyn Diagnostics) -> Result<AppliedReport, EditApplyError> {
+        self.apply_edits_with(MatchStrategy::Exact, diagnostics)
+    }
 
-        while let Some(c) = chars.next() {
-            if c == '[' {
-                // Try to parse a command reference
-                let mut remaining = String::from("[");
-                while let Some(&next_c) = chars.peek() {
-                    if next_c == '\n' {
-                        break; // Don't span multiple lines
-                    }
-                    // Safety: We just peeked at next_c, so next() should succeed
-                    if let Some(ch) = chars.next() {
-                        remaining.push(ch);
-                    }
-                    if next_c == ')' {
-                        break;
+    /// Apply edit entries like [`apply_edits_reporting`](Self::apply_edits_reporting),
+    /// finding SEARCH blocks with `strategy`
+    pub fn apply_edits_with(&mut self, strategy: MatchStrategy, diagnostics: &dyn Diagnostics) -> Result<AppliedReport, EditApplyError> {
+        trace_span!("apply_edits", edits = self.files.iter().filter(|f| f.edit_ref.is_some()).count());
+        let mut report = AppliedReport::default();
+        // Edited contents by target index, written back once all edits apply
+        let mut edited: Vec<(usize, String)> = Vec::new();
+
+        for file in &self.files {
+            let Some(edit) = &file.edit_ref else { continue };
+            // Index in the entry of the first block of each run
+            let mut first = 0;
+            for (name, run) in edit.split_by_file(&file.name) {
+                let target = self.files.iter()
+                    .position(|f| f.name == name && is_unique(f))
+                    .ok_or_else(|| EditApplyError::TargetNotFound { name: name.to_string() })?;
+                let mut ambiguous = Vec::new();
+                let result = match edited.iter_mut().find(|(index, _)| *index == target) {
+                    Some((_, content)) => run.apply_counting(content, strategy, Some(&mut ambiguous)).map(|text| *content = text),
+                    None => core::str::from_utf8(&self.files[target].data)
+                        .map_err(|_| EditApplyError::InvalidUtf8)
+                        .and_then(|content| run.apply_counting(content, strategy, Some(&mut ambiguous)))
+                        .map(|text| {
+                            edited.push((target, text));
+                            report.files.push(name.to_string());
+                        }),
+                };
+                result.map_err(|error| error.in_file(name))?;
+                for (edit, matches) in ambiguous {
+                    diagnostics.report(Diagnostic::AmbiguousSearch { name: name.to_string(), edit: first + edit, matches });
+                }
+                first += run.edits.len();
+            }
+            trace_event!(file = %file.name, blocks = edit.edits.len(), "applied edit");
+            report.edits += 1;
+            report.blocks += edit.edits.len();
+        }
+
+        for (index, content) in edited {
+            self.files[index].data = content.into_bytes().into();
+        }
+        // Drop the edit entries with their positions, last first so the
+        // earlier entries keep their indices
+        for index in (0..self.files.len()).rev() {
+            if self.files[index].edit_ref.is_some() {
+                self.remove_span(index);
+                self.files.remove(index);
+            }
+        }
+        Ok(report)
+    }
+
+    /// Apply the edit entries to the files they target under `root`, rather
+    /// than to the files of the archive
+    ///
+    /// Each `[.edit]` entry is applied to the file at its name below `root`
+    /// (see [`extract_path`]), or at the name of the `@@ file: path @@` line
+    /// before a block, in order, so several edits of one file apply in turn.
+    /// Files keep their `\r\n` line breaks and final line break.
+    /// Nothing is written unless every edit applies, and targets that are or
+    /// lead through a symlink are an error, as on extraction.
+    ///
+    /// With [`ApplyOptions::dry_run`] the report tells what would change.
+    #[cfg(feature = "std")]
+    pub fn apply_edits_to_dir(&self, root: &Path, options: ApplyOptions) -> anyhow::Result<AppliedReport> {
+        trace_span!("apply_edits_to_dir", dir = %root.display());
+        let mut report = AppliedReport::default();
+        // Edited contents by path, written once all edits apply
+        let mut edited: Vec<(PathBuf, String)> = Vec::new();
+
+        for file in &self.files {
+            let Some(edit) = &file.edit_ref else { continue };
+            for (name, run) in edit.split_by_file(&file.name) {
+                let path = extract_path(root, name)?;
+                let result = match edited.iter_mut().find(|(edited, _)| *edited == path) {
+                    Some((_, content)) => edit_disk_text(&run, content, options.strategy).map(|text| *content = text),
+                    None => {
+                        let data = match std::fs::read(&path) {
+                            Ok(data) => data,
+                            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                                return Err(EditApplyError::TargetNotFound { name: name.to_string() }.into());
+                            }
+                            Err(e) => return Err(e.into()),
+                        };
+                        // The file exists, so this only checks for symlinks
+                        prepare_extract_path(root, &path)?;
+                        String::from_utf8(data)
+                            .map_err(|_| EditApplyError::InvalidUtf8)
+                            .and_then(|content| edit_disk_text(&run, &content, options.strategy))
+                            .map(|text| {
+                                edited.push((path, text));
+                                report.files.push(name.to_string());
+                            })
                     }
+                };
+                result.map_err(|error| error.in_file(name))?;
+            }
+            report.edits += 1;
+            report.blocks += edit.edits.len();
+        }
+
+        if !options.dry_run {
+            for (path, content) in &edited {
+                if options.backup {
+                    let mut backup = path.clone().into_os_string();
+                    backup.push(".bak");
+                    std::fs::copy(path, backup)?;
                 }
+                std::fs::write(path, content)?;
+                trace_event!(path = %path.display(), size = content.len(), "applied edit");
+            }
+        }
+        Ok(report)
+    }
+
+    /// Add a file from a path, named `archive_name` or else by its file name
+    #[cfg(feature = "std")]
+    pub fn add_file_from_path(&mut self, path: &Path, archive_name: Option<String>) -> Result<(), ArchiveError> {
+        let name = match archive_name {
+            Some(name) => name,
+            None => path.file_name()
+                .and_then(|n| n.to_str())
+                .ok_or_else(|| ArchiveError::InvalidName {
+                    name: path.to_string_lossy().into_owned(),
+                    reason: "path has no UTF-8 file name",
+                })?
+                .to_string(),
+        };
+        let data = std::fs::read(path)?;
+        self.add_file(File::new(name, data))
+    }
+
+    /// Build an archive of every file under `dir`, named by relative path
+    ///
+    /// Entries are backed by their paths (see [`File::from_path_lazy`]) and
+    /// are read one at a time when the archive is encoded or extracted.
+    /// Directories are walked in name order so the result is deterministic.
+    /// Paths matched by [`IGNORE_FILE_NAME`] files in the tree are skipped;
+    /// see [`from_dir_with`](Self::from_dir_with) for other rules.
+    #[cfg(feature = "std")]
+    pub fn from_dir(dir: &Path) -> Result<Self, ArchiveError> {
+        Self::from_dir_with(dir, &DirOptions::default())
+    }
 
-                if let Some(cmd) = Command::parse(&remaining) {
-                    result.push(cmd);
+    /// Build an archive like [`from_dir`](Self::from_dir), skipping the
+    /// paths the ignore rules of `options` match
+    ///
+    /// Ignored directories are not walked, so their files cannot be
+    /// re-included.
+    #[cfg(feature = "std")]
+    pub fn from_dir_with(dir: &Path, options: &DirOptions) -> Result<Self, ArchiveError> {
+        use crate::glob::IgnoreRules;
+
+        fn walk(
+            archive: &mut Archive,
+            root: &Path,
+            dir: &Path,
+            options: &DirOptions,
+            scopes: &mut Vec<(PathBuf, IgnoreRules)>,
+        ) -> Result<(), ArchiveError> {
+            let mut rules = if scopes.is_empty() { IgnoreRules::parse(&options.ignore.join("\n")) } else { IgnoreRules::default() };
+            for name in &options.ignore_files {
+                match std::fs::read_to_string(dir.join(name)) {
+                    Ok(content) => rules.extend(&content),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            scopes.push((dir.to_path_buf(), rules));
+
+            let mut entries = std::fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries {
+                let path = entry.path();
+                let is_dir = entry.file_type()?.is_dir();
+                if is_ignored(scopes, &path, is_dir) {
+                    continue;
                 }
+                if is_dir {
+                    walk(archive, root, &path, options, scopes)?;
+                } else {
+                    // Entries found by walking `root` are always below it
+                    let relative = path.strip_prefix(root).unwrap_or(&path);
+                    let name = relative.to_string_lossy().replace('\\', "/");
+                    archive.add_file(File::from_path_lazy(name, path))?;
+                }
+            }
+            scopes.pop();
+            Ok(())
+        }
+
+        /// Rules in deeper directories override those closer to the root
+        fn is_ignored(scopes: &[(PathBuf, IgnoreRules)], path: &Path, is_dir: bool) -> bool {
+            scopes.iter().fold(false, |ignored, (dir, rules)| {
+                let relative = path.strip_prefix(dir).unwrap_or(path).to_string_lossy().replace('\\', "/");
+                rules.matched(&relative, is_dir).unwrap_or(ignored)
+            })
+        }
+
+        let mut archive = Archive::new();
+        walk(&mut archive, dir, dir, options, &mut Vec::new())?;
+        Ok(archive)
+    }
+
+    /// Extract files into a directory, creating parent directories as needed
+    ///
+    /// Entry paths that are absolute, contain `..` or lead through a symlink
+    /// are an error (see [`extract_path`] and [`prepare_extract_path`]).
+    /// Text that was transcoded for storage is written back in its original
+    /// encoding unless [`ExtractOptions::utf8`] is set. Each file is written
+    /// as its [`content`](File::content), final newline included. Edit
+    /// entries are instructions rather than file contents and are never written.
+    ///
+    /// Conditional entries are an error: [`resolve`](Archive::resolve) the
+    /// archive first to pick the variants to write.
+    ///
+    /// Returns the paths written.
+    #[cfg(feature = "std")]
+    pub fn extract_to_dir(&self, dir: &Path, options: &ExtractOptions) -> anyhow::Result<Vec<PathBuf>> {
+        trace_span!("extract", dir = %dir.display(), files = self.files.len());
+        if let Some(file) = self.files.iter().find(|f| !f.conditions.is_empty()) {
+            anyhow::bail!("Unresolved conditional entry: {}", file.archive_name());
+        }
+        let mut written = Vec::new();
+
+        for file in &self.files {
+            if file.edit_ref.is_some() || (file.snippet_ref.is_some() && !options.include_snippets) {
+                continue;
+            }
+
+            let output_path = extract_path(dir, &options.windows_names.apply(&file.name)?)?;
+            prepare_extract_path(dir, &output_path)?;
+
+            if let FileSource::Path(source) = &file.source {
+                // Lazy entries are never transcoded, so copy without loading them
+                std::fs::copy(source, &output_path)?;
+            } else {
+                let data = if options.utf8 { file.utf8_content() } else { file.content() };
+                std::fs::write(&output_path, data)?;
+            }
+            file.metadata.apply(&output_path, options.preserve_permissions, options.preserve_mtimes)?;
+            trace_event!(path = %output_path.display(), size = file.data.len(), "extracted file");
+            written.push(output_path);
+        }
+
+        Ok(written)
+    }
+
+    /// Parse command references from the comment section
+    /// Looks for patterns like [command: cmd](#href) in markdown link format
+    pub fn parse_commands(&mut self) {
+        // Look for markdown-style links: [command: cmd](#href)
+        // The comment is only borrowed here; commands are stored afterwards
+        let text = self.comment.as_str();
+        let mut result = Vec::new();
+        let mut pos = 0;
+
+        while let Some(offset) = text[pos..].find('[') {
+            let start = pos + offset;
+            // A reference ends at the first ')' and doesn't span multiple lines
+            let end = match text[start..].find(['\n', ')']) {
+                Some(i) if text.as_bytes()[start + i] == b')' => start + i + 1,
+                Some(i) => start + i,
+                None => text.len(),
+            };
+
+            if let Some(cmd) = Command::parse(&text[start..end]) {
+                result.push(cmd);
             }
+            pos = end;
         }
 
         self.commands = result;
@@ -986,7 +3332,7 @@ impl Archive {
             if let Some(ref_obj) = &file.snippet_ref {
                 if let Some(href) = &ref_obj.command_href {
                     // Use cached index for O(1) lookup instead of O(n) HashSet
-                    if self.command_index.get(href).is_none() {
+                    if !self.command_index.contains_key(href) {
                         errors.push(SnippetRefError {
                             file: file.name.clone(),
                             missing_command: href.clone(),
@@ -1016,8 +3362,21 @@ mod tests {
 
     #[test]
     fn test_file_needs_binary_encoding_binary() {
-        let file = File::new("image.jpg", &[0xFF, 0xD8, 0xFF, 0xE0]);
+        let file = File::new("image.jpg", [0xFF, 0xD8, 0xFF, 0xE0]);
+        assert!(file.is_binary);
+    }
+
+    #[test]
+    fn test_file_from_static_borrows() {
+        static DATA: &[u8] = b"\xFF\xD8 fixture";
+        let file = File::from_static("image.jpg", DATA);
+        assert!(matches!(file.data, Cow::Borrowed(data) if data.as_ptr() == DATA.as_ptr()));
         assert!(file.is_binary);
+
+        let mut archive = Archive::new();
+        archive.add_file(file).unwrap();
+        let decoded = crate::Decoder::new().decode(&crate::Encoder::new().encode(&archive).unwrap()).unwrap();
+        assert_eq!(&decoded.files[0].data[..], DATA);
     }
 
     #[test]
@@ -1029,6 +3388,52 @@ mod tests {
         assert_eq!(binary_file.archive_name(), "image.jpg[.base64]");
     }
 
+    #[test]
+    fn test_archive_errors() {
+        let mut archive = Archive::new();
+        archive.add_file(File::new("a.txt", "one")).unwrap();
+        assert!(matches!(
+            archive.add_file(File::new("a.txt", "two")),
+            Err(ArchiveError::DuplicateFile { name }) if name == "a.txt"
+        ));
+        assert!(matches!(archive.add_file(File::new("", "x")), Err(ArchiveError::InvalidName { .. })));
+        assert!(matches!(archive.add_file(File::new("a\nb", "x")), Err(ArchiveError::InvalidName { .. })));
+
+        let mut edit = File::new("missing.txt", "");
+        edit.edit_ref = Some(EditRef { command_href: None, start_line: None, edits: Vec::new() });
+        archive.add_file(edit).unwrap();
+        let err = archive.apply_edits().unwrap_err();
+        assert!(matches!(&err, EditApplyError::TargetNotFound { name } if name == "missing.txt"));
+        assert_eq!(err.to_string(), "Edit target not found: missing.txt");
+
+        #[cfg(feature = "std")]
+        {
+            let err = archive.add_file_from_path(Path::new("/nonexistent/file"), None).unwrap_err();
+            assert!(matches!(&err, ArchiveError::Io(io) if io.kind() == std::io::ErrorKind::NotFound));
+            assert!(core::error::Error::source(&err).is_some());
+        }
+    }
+
+    #[test]
+    fn test_escape_name() {
+        let cases = [
+            ("plain.txt", "plain.txt"),
+            ("foo[1].txt", "foo\\[1\\].txt"),
+            ("dir\\file", "dir\\file"),
+            ("a\\[b\\", "a\\\\\\[b\\\\"),
+            ("a--b", "a--b"),
+            ("-- x --", "-\\- x -\\-"),
+            ("--- y -- [z]", "-\\-\\- y -\\- \\[z\\]"),
+        ];
+        for (name, escaped) in cases {
+            assert_eq!(escape_name(name), escaped);
+            assert_eq!(unescape_name(escaped), name);
+            assert!(!has_marker_pattern(&escaped[..find_tag_start(escaped).unwrap_or(escaped.len())]));
+        }
+        assert_eq!(find_tag_start("a\\[b[.base64]"), Some(4));
+        assert_eq!(File::parse_archive_name("a\\[1\\][.base64]"), ("a[1]".to_string(), true));
+    }
+
     #[test]
     fn test_parse_archive_name() {
         assert_eq!(
@@ -1049,7 +3454,10 @@ mod tests {
 with marker pattern"#;
         let file = File::new("test.txt", content);
         assert!(file.is_binary);
-        assert_eq!(file.binary_reason, Some(BinaryReason::ContentConflict));
+        assert_eq!(
+            file.binary_reason,
+            Some(BinaryReason::ContentConflict { line_number: 2, line: "-- some_file.txt --".to_string() })
+        );
     }
 
     #[test]
@@ -1074,14 +3482,14 @@ with empty marker"#;
     fn test_encoding_detection_binary_utf8() {
         let data = b"\xFF\xFE\xFD";
         let detection = File::detect_encoding("test.txt", data, &EncodingConfig::default());
-        assert!(matches!(detection, EncodingDetection::Binary { reason: BinaryReason::InvalidUtf8 }));
+        assert!(matches!(detection, EncodingDetection::Binary { reason: BinaryReason::InvalidUtf8 { .. } }));
     }
 
     #[test]
     fn test_encoding_detection_content_conflict() {
         let data = b"-- file.txt --\ncontent";
         let detection = File::detect_encoding("test.txt", data, &EncodingConfig::default());
-        assert!(matches!(detection, EncodingDetection::Binary { reason: BinaryReason::ContentConflict }));
+        assert!(matches!(detection, EncodingDetection::Binary { reason: BinaryReason::ContentConflict { .. } }));
     }
 
     #[test]
@@ -1090,6 +3498,7 @@ with empty marker"#;
         let config = EncodingConfig {
             check_content_markers: false,
             validate_utf8: true,
+            ..Default::default()
         };
         let detection = File::detect_encoding("test.txt", data, &config);
         // Should not detect content conflict when disabled
@@ -1102,12 +3511,554 @@ with empty marker"#;
         let config = EncodingConfig {
             check_content_markers: true,
             validate_utf8: false,
+            ..Default::default()
         };
         let detection = File::detect_encoding("test.txt", data, &config);
         // Should not detect invalid UTF-8 when disabled
         assert!(matches!(detection, EncodingDetection::Text { .. }));
     }
 
+    #[test]
+    fn test_binary_reason_invalid_utf8_offset() {
+        let file = File::new("data.bin", b"abc\xFFdef".to_vec());
+        assert_eq!(file.binary_reason, Some(BinaryReason::InvalidUtf8 { offset: 3 }));
+        assert_eq!(file.binary_reason.unwrap().to_string(), "invalid UTF-8 at byte offset 3");
+    }
+
+    #[test]
+    fn test_binary_reason_sampled_line_number() {
+        let mut data = "line\n".repeat(100);
+        data.push_str("-- tail.txt --\nend\n");
+        let config = EncodingConfig::builder()
+            .sampling(Some(DetectionSampling { threshold: 100, prefix: 32, suffix: 32 }))
+            .build();
+        let file = File::with_config("big.txt", data, &config);
+        assert_eq!(
+            file.binary_reason,
+            Some(BinaryReason::ContentConflict { line_number: 101, line: "-- tail.txt --".to_string() })
+        );
+    }
+
+    #[test]
+    fn test_heuristics_disabled_by_default() {
+        let data = b"valid\0utf8\0with\0nuls";
+        let detection = File::detect_encoding("blob", data, &EncodingConfig::default());
+        assert!(matches!(detection, EncodingDetection::Text { .. }));
+    }
+
+    #[test]
+    fn test_heuristic_control_chars() {
+        let config = EncodingConfig {
+            max_control_ratio: Some(0.1),
+            ..Default::default()
+        };
+        let detection = File::detect_encoding("blob", b"ab\0\x01cd", &config);
+        assert_eq!(
+            detection,
+            EncodingDetection::Binary { reason: BinaryReason::ControlChars { count: 2, sampled: 6 } }
+        );
+
+        // Tabs and newlines are not control bytes for this purpose
+        let detection = File::detect_encoding("ok.txt", b"a\tb\r\nc", &config);
+        assert!(matches!(detection, EncodingDetection::Text { .. }));
+    }
+
+    #[test]
+    fn test_heuristic_long_line() {
+        let config = EncodingConfig {
+            max_line_length: Some(10),
+            ..Default::default()
+        };
+        let data = "short\nthis line is far too long\n";
+        let detection = File::detect_encoding("min.js", data.as_bytes(), &config);
+        assert_eq!(
+            detection,
+            EncodingDetection::Binary { reason: BinaryReason::LongLine { line: 2, length: 25 } }
+        );
+    }
+
+    #[test]
+    fn test_heuristic_sample_window() {
+        let config = EncodingConfig {
+            max_control_ratio: Some(0.0),
+            heuristic_sample_size: 4,
+            ..Default::default()
+        };
+        // The NUL byte lies outside the sampled window
+        let detection = File::detect_encoding("blob", b"text\0", &config);
+        assert!(matches!(detection, EncodingDetection::Text { .. }));
+    }
+
+    #[test]
+    fn test_override_binary() {
+        let config = EncodingConfig {
+            overrides: vec![("*.png".to_string(), EncodingOverride::Binary)],
+            ..Default::default()
+        };
+        let file = File::with_config("img/logo.png", "looks like text", &config);
+        assert!(file.is_binary);
+        assert_eq!(file.binary_reason, Some(BinaryReason::Override { pattern: "*.png".to_string() }));
+    }
+
+    #[test]
+    fn test_override_text_skips_marker_check() {
+        let config = EncodingConfig {
+            overrides: vec![("docs/**/*.md".to_string(), EncodingOverride::Text)],
+            ..Default::default()
+        };
+        let data = "Example:\n-- file.txt --\ncontent";
+        assert!(!File::with_config("docs/guide/txtar.md", data, &config).is_binary);
+        // Non-matching paths still get the default checks
+        assert!(File::with_config("README.md", data, &config).is_binary);
+        // Invalid UTF-8 cannot be forced into the text section
+        assert!(File::with_config("docs/x.md", vec![0xFF, 0xFE], &config).is_binary);
+    }
+
+    #[test]
+    fn test_latin1_fallback() {
+        // "café" in ISO-8859-1
+        let data = b"caf\xe9 au lait\n".to_vec();
+        assert!(File::new("menu.txt", data.clone()).is_binary);
+
+        let config = EncodingConfig {
+            latin1_fallback: true,
+            ..Default::default()
+        };
+        let file = File::with_config("menu.txt", data.clone(), &config);
+        assert!(!file.is_binary);
+        assert_eq!(file.encoding, TextEncoding::Latin1);
+        assert_eq!(file.data, data);
+        assert_eq!(file.archive_name(), "menu.txt[.enc:latin1]");
+    }
+
+    #[test]
+    fn test_latin1_fallback_rejects_binary() {
+        let config = EncodingConfig {
+            latin1_fallback: true,
+            ..Default::default()
+        };
+        let file = File::with_config("blob", vec![0x00, 0x01, 0x02, 0xFF, 0x90], &config);
+        assert!(file.is_binary);
+        assert_eq!(file.binary_reason, Some(BinaryReason::InvalidUtf8 { offset: 3 }));
+    }
+
+    #[test]
+    fn test_latin1_transcoding() {
+        assert_eq!(TextEncoding::Latin1.decode(b"caf\xe9").as_deref(), Some("café"));
+        assert_eq!(TextEncoding::Latin1.encode("café"), Some(b"caf\xe9".to_vec()));
+        assert_eq!(TextEncoding::Latin1.encode("日本"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn test_cjk_transcoding() {
+        let samples = [
+            (TextEncoding::Gbk, &b"\xc4\xe3\xba\xc3"[..], "你好"),
+            (TextEncoding::ShiftJis, b"\x93\xfa\x96\x7b", "日本"),
+            (TextEncoding::Big5, b"\xa4\xa4\xa4\xe5", "中文"),
+        ];
+        for (encoding, bytes, text) in samples {
+            assert_eq!(encoding.decode(bytes).as_deref(), Some(text));
+            assert_eq!(encoding.encode(text).as_deref(), Some(bytes));
+            assert_eq!(encoding.encode("😀"), None);
+        }
+        // A lead byte without its trail byte is malformed
+        assert_eq!(TextEncoding::Gbk.decode(b"\xc4"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn test_cjk_fallback() {
+        let samples = [
+            (TextEncoding::Gbk, "notes.txt", "这是一个简体中文的测试文件，用于检查编码检测是否正确。\n我们需要足够多的文字。\n"),
+            (TextEncoding::ShiftJis, "readme.txt", "これは日本語のテストファイルです。文字コードの判定を確認します。\n"),
+            (TextEncoding::Big5, "info.txt", "這是一個繁體中文的測試檔案，用於檢查編碼偵測是否正確。\n"),
+        ];
+        for (encoding, name, text) in samples {
+            let data = encoding.encode(text).unwrap();
+            assert!(File::new(name, data.clone()).is_binary);
+
+            let file = File::with_config(name, data.clone(), &EncodingConfig::lenient());
+            assert!(!file.is_binary, "{}", name);
+            assert_eq!(file.encoding, encoding);
+            assert_eq!(&file.utf8_data()[..], text.as_bytes());
+            assert_eq!(file.archive_name(), format!("{}[.enc:{}]", name, encoding.label()));
+        }
+        // Binary data is not mistaken for CJK text
+        let config = EncodingConfig::builder().cjk_fallback(true).build();
+        assert!(File::with_config("blob", vec![0x00, 0x01, 0x02, 0xFF, 0x90], &config).is_binary);
+    }
+
+    #[test]
+    fn test_encoding_config_presets() {
+        let nul_text = b"a\0b";
+        assert!(!File::with_config("f", nul_text.to_vec(), &EncodingConfig::go_compat()).is_binary);
+        assert!(File::with_config("f", nul_text.to_vec(), &EncodingConfig::strict()).is_binary);
+
+        let latin1 = b"caf\xe9".to_vec();
+        assert!(File::with_config("f", latin1.clone(), &EncodingConfig::go_compat()).is_binary);
+        assert!(!File::with_config("f", latin1, &EncodingConfig::lenient()).is_binary);
+    }
+
+    #[test]
+    fn test_encoding_config_builder() {
+        let config = EncodingConfig::builder()
+            .check_content_markers(false)
+            .max_line_length(Some(80))
+            .override_pattern("*.bin", EncodingOverride::Binary)
+            .build();
+        assert!(!config.check_content_markers);
+        assert!(config.validate_utf8);
+        assert_eq!(config.max_line_length, Some(80));
+        assert_eq!(config.overrides.len(), 1);
+
+        let config = EncodingConfig::strict().into_builder().max_control_ratio(None).build();
+        assert_eq!(config.max_control_ratio, None);
+        assert_eq!(config.max_line_length, Some(4096));
+    }
+
+    #[test]
+    fn test_detection_sampling() {
+        let mut data = "line\n".repeat(100);
+        data.push_str("-- hidden.txt --\n");
+        data.push_str(&"line\n".repeat(100));
+
+        // Full scan finds the marker in the middle
+        let detection = File::detect_encoding("big.txt", data.as_bytes(), &EncodingConfig::default());
+        assert!(matches!(detection, EncodingDetection::Binary { reason: BinaryReason::ContentConflict { .. } }));
+
+        // Sampling only looks at the edges
+        let config = EncodingConfig::builder()
+            .sampling(Some(DetectionSampling { threshold: 100, prefix: 64, suffix: 64 }))
+            .build();
+        let detection = File::detect_encoding("big.txt", data.as_bytes(), &config);
+        assert!(matches!(detection, EncodingDetection::Text { .. }));
+
+        // ...but still catches problems inside the windows
+        let mut tail = data.clone().into_bytes();
+        tail.extend_from_slice(b"\xFF\xFE\n");
+        let detection = File::detect_encoding("big.txt", &tail, &config);
+        assert!(matches!(detection, EncodingDetection::Binary { reason: BinaryReason::InvalidUtf8 { .. } }));
+    }
+
+    #[test]
+    fn test_detection_sampling_does_not_split_chars() {
+        // Window edges fall inside multi-byte characters
+        let data = "日本語\n".repeat(50);
+        let config = EncodingConfig::builder()
+            .sampling(Some(DetectionSampling { threshold: 10, prefix: 20, suffix: 20 }))
+            .build();
+        let detection = File::detect_encoding("jp.txt", data.as_bytes(), &config);
+        assert!(matches!(detection, EncodingDetection::Text { .. }));
+    }
+
+    #[test]
+    fn test_detection_sampling_single_line() {
+        // No line break to trim the windows at, so the whole line is checked
+        let mut data = "x".repeat(1000).into_bytes();
+        data.splice(500..500, *b"\xFF\xFE");
+        let config = EncodingConfig::builder()
+            .sampling(Some(DetectionSampling { threshold: 100, prefix: 64, suffix: 64 }))
+            .build();
+        let detection = File::detect_encoding("minified.js", &data, &config);
+        assert!(matches!(detection, EncodingDetection::Binary { reason: BinaryReason::InvalidUtf8 { .. } }));
+    }
+
+    #[test]
+    fn test_detection_hook() {
+        let config = EncodingConfig::builder()
+            .detector(|name, _data| {
+                name.ends_with(".ipynb").then_some(EncodingDetection::Text { encoding: TextEncoding::Utf8 })
+            })
+            .build();
+
+        let notebook = "{\n-- cell.txt --\n}";
+        assert!(!File::with_config("demo.ipynb", notebook, &config).is_binary);
+        // Other files fall through to the built-in rules
+        assert!(File::with_config("demo.json", notebook, &config).is_binary);
+    }
+
+    #[test]
+    fn test_text_encoding_labels() {
+        for enc in [TextEncoding::Utf8, TextEncoding::Gbk, TextEncoding::ShiftJis, TextEncoding::Big5, TextEncoding::Latin1] {
+            assert_eq!(TextEncoding::from_label(enc.label()), Some(enc));
+        }
+        assert_eq!(TextEncoding::from_label("SJIS"), Some(TextEncoding::ShiftJis));
+        assert_eq!(TextEncoding::from_label("koi8-r"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_extract_to_dir_restores_encoding() {
+        let config = EncodingConfig {
+            latin1_fallback: true,
+            ..Default::default()
+        };
+        let mut archive = Archive::new();
+        archive.add_file(File::with_config("legacy.cfg", b"caf\xe9".to_vec(), &config)).unwrap();
+        let mut snippet = File::new("snip.txt", "excerpt");
+        snippet.snippet_ref = SnippetRef::parse("[.snippet:1]").ok();
+        archive.add_file(snippet).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let written = archive.extract_to_dir(dir.path(), &ExtractOptions::default()).unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(std::fs::read(dir.path().join("legacy.cfg")).unwrap(), b"caf\xe9");
+        assert!(!dir.path().join("snip.txt").exists());
+
+        let options = ExtractOptions { utf8: true, include_snippets: true, ..Default::default() };
+        archive.extract_to_dir(dir.path(), &options).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("legacy.cfg")).unwrap(), "café");
+        assert!(dir.path().join("snip.txt").exists());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_extract_round_trips_contents() {
+        let files: [(&str, &[u8]); 5] = [
+            ("lines.txt", b"one\ntwo\n"),
+            ("newline.txt", b"\n"),
+            ("empty.txt", b""),
+            ("blob.bin", b"\xFF\x00\n"),
+            // Only the last section can end without a newline
+            ("z.txt", b"no final newline"),
+        ];
+        let src = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(src.path().join(name), content).unwrap();
+        }
+        let text = crate::Encoder::new().encode(&Archive::from_dir(src.path()).unwrap()).unwrap();
+
+        let decoded = tempfile::tempdir().unwrap();
+        let decoder = crate::Decoder::new();
+        decoder.decode(&text).unwrap().extract_to_dir(decoded.path(), &ExtractOptions::default()).unwrap();
+        let streamed = tempfile::tempdir().unwrap();
+        decoder.extract_reader(text.as_bytes(), streamed.path(), &ExtractOptions::default()).unwrap();
+        for (name, content) in files {
+            assert_eq!(std::fs::read(decoded.path().join(name)).unwrap(), content, "{}", name);
+            assert_eq!(std::fs::read(streamed.path().join(name)).unwrap(), content, "{}", name);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_extract_to_dir_applies_metadata() {
+        let metadata = FileMetadata { mode: Some(0o755), mtime: Some(1_000_000_000) };
+        let mut archive = Archive::new();
+        archive.add_file(File::new("run.sh", "#!/bin/sh").with_metadata(metadata)).unwrap();
+        assert_eq!(archive.files[0].archive_name(), "run.sh[.mode:0755][.mtime:1000000000]");
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = ExtractOptions { preserve_permissions: true, preserve_mtimes: true, ..Default::default() };
+        archive.extract_to_dir(dir.path(), &options).unwrap();
+
+        let written = FileMetadata::from_fs(&std::fs::metadata(dir.path().join("run.sh")).unwrap());
+        assert_eq!(written.mtime, Some(1_000_000_000));
+        #[cfg(unix)]
+        assert_eq!(written.mode, Some(0o755));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_extract_path_rejects_escapes() {
+        let dir = Path::new("out");
+        assert_eq!(extract_path(dir, "./a/b.txt").unwrap(), Path::new("out/a/b.txt"));
+        for name in ["../evil", "a/../../evil", "/etc/passwd", "", "."] {
+            assert!(extract_path(dir, name).is_err(), "{}", name);
+        }
+
+        let mut archive = Archive::new();
+        archive.add_file(File::new("ok.txt", "fine")).unwrap();
+        archive.add_file(File::new("../escape.txt", "bad")).unwrap();
+        let parent = tempfile::tempdir().unwrap();
+        let dest = parent.path().join("dest");
+        assert!(archive.extract_to_dir(&dest, &ExtractOptions::default()).is_err());
+        assert!(!parent.path().join("escape.txt").exists());
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "std"))]
+    fn test_extract_refuses_symlinks() {
+        let outside = tempfile::tempdir().unwrap();
+        let dest = tempfile::tempdir().unwrap();
+        std::os::unix::fs::symlink(outside.path(), dest.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("target"), dest.path().join("file")).unwrap();
+
+        for name in ["link/planted.txt", "file"] {
+            let mut archive = Archive::new();
+            archive.add_file(File::new(name, "payload")).unwrap();
+            let err = archive.extract_to_dir(dest.path(), &ExtractOptions::default()).unwrap_err();
+            assert!(err.to_string().contains("symlink"), "{}: {}", name, err);
+
+            let encoded = crate::Encoder::new().encode(&archive).unwrap();
+            let streamed = crate::Decoder::new().extract_reader(encoded.as_bytes(), dest.path(), &ExtractOptions::default());
+            assert!(streamed.is_err(), "{}", name);
+        }
+        assert_eq!(std::fs::read_dir(outside.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_windows_name_policy() {
+        let policy = WindowsNamePolicy::Sanitize;
+        assert_eq!(policy.apply("src/lib.rs").unwrap(), "src/lib.rs");
+        assert_eq!(policy.apply("docs/a:b?.txt").unwrap(), "docs/a_b_.txt");
+        assert_eq!(policy.apply("con/NUL.tar.gz").unwrap(), "con_/NUL_.tar.gz");
+        assert_eq!(policy.apply("notes. /x.").unwrap(), "notes/x");
+        assert_eq!(policy.apply("../a\\b").unwrap(), "../a_b");
+        assert_eq!(policy.apply("console.log").unwrap(), "console.log");
+
+        let err = WindowsNamePolicy::Reject.apply("dir/aux.c").unwrap_err();
+        assert!(err.to_string().contains("reserved device name in 'aux.c'"));
+        assert!(WindowsNamePolicy::Reject.apply("dir/file.txt").is_ok());
+        assert_eq!(WindowsNamePolicy::Allow.apply("a:b").unwrap(), "a:b");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_extract_to_dir_windows_names() {
+        let mut archive = Archive::new();
+        archive.add_file(File::new("sub/PRN.txt", "p")).unwrap();
+        archive.add_file(File::new("what?", "q")).unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let options = ExtractOptions { windows_names: WindowsNamePolicy::Reject, ..Default::default() };
+        assert!(archive.extract_to_dir(dir.path(), &options).is_err());
+
+        let options = ExtractOptions { windows_names: WindowsNamePolicy::Sanitize, ..Default::default() };
+        let written = archive.extract_to_dir(dir.path(), &options).unwrap();
+        assert_eq!(written, vec![dir.path().join("sub/PRN_.txt"), dir.path().join("what_")]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_from_dir_reads_lazily() {
+        let src = tempfile::tempdir().unwrap();
+        std::fs::create_dir(src.path().join("sub")).unwrap();
+        std::fs::write(src.path().join("b.txt"), "text").unwrap();
+        std::fs::write(src.path().join("sub/a.bin"), b"\xFF\x00").unwrap();
+
+        let archive = Archive::from_dir(src.path()).unwrap();
+        assert_eq!(archive.files.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["b.txt", "sub/a.bin"]);
+        assert!(archive.files.iter().all(|f| f.data.is_empty() && matches!(f.source, FileSource::Path(_))));
+
+        let mut eager = Archive::new();
+        eager.add_file(File::new("b.txt", "text")).unwrap();
+        eager.add_file(File::new("sub/a.bin", b"\xFF\x00".to_vec())).unwrap();
+        let encoded = crate::Encoder::new().encode(&archive).unwrap();
+        assert_eq!(encoded, crate::Encoder::new().encode(&eager).unwrap());
+        let mut written = Vec::new();
+        crate::Encoder::new().encode_to_writer(&archive, &mut written).unwrap();
+        assert_eq!(written, encoded.as_bytes());
+
+        let dest = tempfile::tempdir().unwrap();
+        archive.extract_to_dir(dest.path(), &ExtractOptions::default()).unwrap();
+        assert_eq!(std::fs::read(dest.path().join("sub/a.bin")).unwrap(), b"\xFF\x00");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_from_dir_ignore_rules() {
+        let src = tempfile::tempdir().unwrap();
+        for dir in ["target", "sub/deep"] {
+            std::fs::create_dir_all(src.path().join(dir)).unwrap();
+        }
+        for file in ["a.txt", "a.log", "keep.log", "target/out", "sub/b.tmp", "sub/c.txt", "sub/deep/d.tmp"] {
+            std::fs::write(src.path().join(file), "x").unwrap();
+        }
+        std::fs::write(src.path().join(IGNORE_FILE_NAME), "target/\n*.log\n!keep.log\n").unwrap();
+        std::fs::write(src.path().join("sub").join(IGNORE_FILE_NAME), "*.tmp\n").unwrap();
+        let names = |archive: Archive| archive.files.into_iter().map(|f| f.name).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(Archive::from_dir(src.path()).unwrap()),
+            [IGNORE_FILE_NAME, "a.txt", "keep.log", "sub/.txtarignore", "sub/c.txt"],
+        );
+
+        // Ignore files override the given patterns
+        let options = DirOptions { ignore: vec!["keep.log".into(), "*.txt".into(), ".*".into()], ..Default::default() };
+        assert_eq!(names(Archive::from_dir_with(src.path(), &options).unwrap()), ["keep.log"]);
+
+        let options = DirOptions { ignore: vec!["/sub".into()], ignore_files: Vec::new() };
+        assert_eq!(names(Archive::from_dir_with(src.path(), &options).unwrap()), [IGNORE_FILE_NAME, "a.log", "a.txt", "keep.log", "target/out"]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_file_reader() {
+        use std::io::{Read, Seek, SeekFrom};
+
+        let src = tempfile::tempdir().unwrap();
+        std::fs::write(src.path().join("disk.bin"), b"0123456789").unwrap();
+        let files = [
+            File::from_static("memory.bin", b"0123456789"),
+            File::from_path_lazy("disk.bin", src.path().join("disk.bin")),
+        ];
+        for file in &files {
+            let mut reader = file.reader().unwrap();
+            let mut head = [0u8; 3];
+            reader.read_exact(&mut head).unwrap();
+            assert_eq!(&head, b"012");
+            assert_eq!(reader.seek(SeekFrom::End(-2)).unwrap(), 8);
+            let mut tail = String::new();
+            reader.read_to_string(&mut tail).unwrap();
+            assert_eq!(tail, "89");
+        }
+        assert!(File::from_path_lazy("gone", src.path().join("gone")).reader().is_err());
+    }
+
+    #[test]
+    fn test_get_file_skips_references() {
+        let mut archive = Archive::new();
+        let mut snippet = File::new("a.txt", "excerpt");
+        snippet.snippet_ref = SnippetRef::parse("[.snippet:1]").ok();
+        archive.add_file(snippet).unwrap();
+        archive.add_file(File::new("a.txt", "full")).unwrap();
+
+        assert_eq!(&archive.get_file("a.txt").unwrap().data[..], b"full");
+        assert!(archive.get_file("b.txt").is_none());
+    }
+
+    #[test]
+    fn test_remove_file() {
+        let mut archive = crate::Decoder::new()
+            .decode("-- a.txt --\nA\n-- a.txt[.edit] --\n<<<<<<< SEARCH\nA\n=======\nB\n>>>>>>> REPLACE\n-- b.txt --\nB\n")
+            .unwrap();
+        let removed = archive.remove_file("a.txt").unwrap();
+        assert_eq!(&removed.data[..], b"A");
+        assert!(archive.get_file("a.txt").is_none());
+        // The edit entry stays, now without a target
+        assert_eq!(archive.files.len(), 2);
+        assert!(matches!(archive.apply_edits(), Err(EditApplyError::TargetNotFound { .. })));
+        assert!(archive.remove_file("a.txt").is_none());
+        // Positions now find the edit entry
+        assert_eq!(archive.locate("a.txt", 1).map(|p| p.line), Some(4));
+    }
+
+    #[test]
+    fn test_rename_file() {
+        let input = "-- a.txt --\nA\n-- a.txt[.edit] --\n<<<<<<< SEARCH\nA\n=======\nB\n>>>>>>> REPLACE\n-- b.txt --\nB\n";
+        let mut archive = crate::Decoder::new().decode(input).unwrap();
+        archive.rename_file("a.txt", "c.txt").unwrap();
+        assert!(archive.get_file("a.txt").is_none());
+        assert_eq!(archive.files.iter().filter(|f| f.name == "c.txt").count(), 2);
+        assert_eq!(archive.locate("c.txt", 1).map(|p| p.line), Some(2));
+        archive.apply_edits().unwrap();
+        assert_eq!(&archive.get_file("c.txt").unwrap().data[..], b"B");
+
+        assert!(matches!(archive.rename_file("c.txt", "b.txt"), Err(ArchiveError::DuplicateFile { name }) if name == "b.txt"));
+        assert!(matches!(archive.rename_file("c.txt", "x\ny"), Err(ArchiveError::InvalidName { .. })));
+        assert!(matches!(archive.rename_file("c.txt", ""), Err(ArchiveError::InvalidName { .. })));
+        assert!(matches!(archive.rename_file("gone", "d.txt"), Err(ArchiveError::FileNotFound { .. })));
+        archive.rename_file("c.txt", "c.txt").unwrap();
+
+        // Snippets may share a name with a normal file
+        let mut snippet = File::new("s.txt", "excerpt");
+        snippet.snippet_ref = SnippetRef::parse("[.snippet:1]").ok();
+        archive.add_file(snippet).unwrap();
+        archive.rename_file("s.txt", "b.txt").unwrap();
+        assert_eq!(&archive.get_file("b.txt").unwrap().data[..], b"B");
+    }
+
     // Tests for Command parsing
     #[test]
     fn test_command_parse_simple() {
@@ -1145,6 +4096,17 @@ with empty marker"#;
         assert!(Command::parse(input).is_none());
     }
 
+    #[test]
+    fn test_parse_commands_scans_lines() {
+        let mut archive = Archive::with_comment(
+            "see [command: rg](#a) and [command: fd](#b)\nopen [link\n[command: git diff](#c) [x [command: ls](#d)",
+        );
+        archive.parse_commands();
+        let hrefs: Vec<&str> = archive.commands.iter().map(|c| c.href.as_str()).collect();
+        assert_eq!(hrefs, ["a", "b", "c"]);
+        assert_eq!(archive.get_command("c").unwrap().name, "git diff");
+    }
+
     // Tests for SnippetRef parsing
     #[test]
     fn test_snippet_ref_parse_simple() {
@@ -1197,6 +4159,98 @@ with empty marker"#;
         assert!(matches!(err, SnippetParseError::MissingClosingBracket));
     }
 
+    #[test]
+    fn test_snippet_ref_parse_with_language() {
+        let ref_obj = SnippetRef::parse("[.snippet:42:lang=rust]").unwrap();
+        assert!(ref_obj.command_href.is_none());
+        assert_eq!(ref_obj.line, 42);
+        assert_eq!(ref_obj.language.as_deref(), Some("rust"));
+
+        let ref_obj = SnippetRef::parse("[.#search1:10:lang=python]").unwrap();
+        assert_eq!(ref_obj.command_href.as_deref(), Some("search1"));
+        assert_eq!(ref_obj.line, 10);
+        assert_eq!(ref_obj.language.as_deref(), Some("python"));
+    }
+
+    #[test]
+    fn test_snippet_ref_parse_empty_language() {
+        let err = SnippetRef::parse("[.snippet:42:lang=]").unwrap_err();
+        assert!(matches!(err, SnippetParseError::InvalidFormat));
+    }
+
+    #[test]
+    fn test_snippet_ref_display_round_trip() {
+        for input in ["[.snippet:42]", "[.snippet#search1:10]", "[.snippet#search1:10:lang=rust]"] {
+            let ref_obj = SnippetRef::parse(input).unwrap();
+            assert_eq!(ref_obj.to_string(), input);
+        }
+    }
+
+    #[test]
+    fn test_snippet_to_edit() {
+        let mut snippet = File::new("src/lib.rs", "fn old() {}");
+        snippet.snippet_ref = SnippetRef::parse("[.#search1:12]").ok();
+
+        let edit = snippet.snippet_to_edit("fn new() {}").unwrap();
+        assert_eq!(edit.name, "src/lib.rs");
+        assert!(edit.snippet_ref.is_none());
+        let edit_ref = edit.edit_ref.as_ref().unwrap();
+        assert_eq!(edit_ref.command_href.as_deref(), Some("search1"));
+        assert_eq!(edit_ref.start_line, Some(12));
+        assert_eq!(edit_ref.edits[0].operation, EditOperation::Replace);
+
+        // The rendered content parses back to the same blocks
+        let content = core::str::from_utf8(&edit.data).unwrap();
+        assert_eq!(EditRef::parse_content(content).unwrap(), edit_ref.edits);
+        assert_eq!(edit.archive_name(), "src/lib.rs[.edit#search1:12]");
+    }
+
+    #[test]
+    fn test_snippet_to_edit_keeps_trailing_whitespace() {
+        let mut snippet = File::new("a.md", "line  \n\tindented\t");
+        snippet.snippet_ref = SnippetRef::parse("[.snippet:1]").ok();
+        let edit = snippet.snippet_to_edit("line\n\tindented ").unwrap();
+        let block = &edit.edit_ref.as_ref().unwrap().edits[0];
+        assert_eq!(block.search, ["line  ", "\tindented\t"]);
+        assert_eq!(block.replacement, ["line", "\tindented "]);
+    }
+
+    #[test]
+    fn test_snippet_to_edit_not_a_snippet() {
+        let file = File::new("a.txt", "content");
+        assert!(file.snippet_to_edit("changed").is_none());
+    }
+
+    #[test]
+    fn test_edit_to_snippet() {
+        let mut snippet = File::new("a.txt", "old line");
+        snippet.snippet_ref = SnippetRef::parse("[.snippet:5]").ok();
+        let edit = snippet.snippet_to_edit("new line\nsecond").unwrap();
+
+        let back = edit.edit_to_snippet().unwrap();
+        assert_eq!(&back.data[..], b"new line\nsecond");
+        let ref_obj = back.snippet_ref.as_ref().unwrap();
+        assert_eq!(ref_obj.line, 5);
+        assert!(ref_obj.command_href.is_none());
+        assert_eq!(back.archive_name(), "a.txt[.snippet:5]");
+    }
+
+    #[test]
+    fn test_edit_ref_to_content_delete() {
+        let edit_ref = EditRef {
+            command_href: None,
+            start_line: None,
+            edits: vec![EditBlock {
+                search: vec!["gone".to_string()],
+                replacement: vec![],
+                operation: EditOperation::Delete,
+                file: None,
+            }],
+        };
+        assert_eq!(edit_ref.to_content(), "<<<<<<< SEARCH\ngone\n>>>>>>> DELETE\n");
+        assert_eq!(edit_ref.tag(), "[.edit]");
+    }
+
     // EditRef::apply() tests
     #[test]
     fn test_edit_apply_single_line_replace() {
@@ -1209,6 +4263,7 @@ with empty marker"#;
                     search: vec!["line 2".to_string()],
                     replacement: vec!["modified line 2".to_string()],
                     operation: EditOperation::Replace,
+                    file: None,
                 },
             ],
         };
@@ -1228,6 +4283,7 @@ with empty marker"#;
                     search: vec!["line 2".to_string(), "line 3".to_string()],
                     replacement: vec!["new line 2".to_string(), "new line 3".to_string()],
                     operation: EditOperation::Replace,
+                    file: None,
                 },
             ],
         };
@@ -1247,6 +4303,7 @@ with empty marker"#;
                     search: vec!["line 2".to_string()],
                     replacement: vec![],
                     operation: EditOperation::Delete,
+                    file: None,
                 },
             ],
         };
@@ -1266,6 +4323,7 @@ with empty marker"#;
                     search: vec![],
                     replacement: vec!["inserted line".to_string()],
                     operation: EditOperation::Insert,
+                    file: None,
                 },
             ],
         };
@@ -1285,6 +4343,7 @@ with empty marker"#;
                     search: vec![],
                     replacement: vec!["first line".to_string()],
                     operation: EditOperation::Insert,
+                    file: None,
                 },
             ],
         };
@@ -1304,11 +4363,13 @@ with empty marker"#;
                     search: vec!["line 2".to_string()],
                     replacement: vec!["modified 2".to_string()],
                     operation: EditOperation::Replace,
+                    file: None,
                 },
                 EditBlock {
                     search: vec!["line 3".to_string()],
                     replacement: vec!["modified 3".to_string()],
                     operation: EditOperation::Replace,
+                    file: None,
                 },
             ],
         };
@@ -1317,6 +4378,42 @@ with empty marker"#;
         assert_eq!(result, "line 1\nmodified 2\nmodified 3");
     }
 
+    #[test]
+    fn test_find_line_block() {
+        let find = |lines: &str, search: &str| {
+            let lines: Vec<&str> = lines.split(' ').collect();
+            let search: Vec<String> = search.split(' ').map(String::from).collect();
+            find_line_block(&lines, &search, |line, search| *line == search)
+        };
+        assert_eq!(find("a a a b", "a a b"), Some(1));
+        assert_eq!(find("a b a b a c", "a b a c"), Some(2));
+        assert_eq!(find("x a b", "a b"), Some(1));
+        assert_eq!(find("a a a", "a a b"), None);
+        assert_eq!(find("a", "a a"), None);
+    }
+
+    #[test]
+    fn test_edit_apply_many_edits_large_file() {
+        let content: Vec<String> = (0..20_000).map(|i| format!("line {}", i)).collect();
+        let edits = (0..50).map(|n| {
+            let line = format!("line {}", n * 400);
+            match n % 3 {
+                0 => EditBlock { search: vec![line.clone()], replacement: vec![line, "added".to_string()], operation: EditOperation::Replace, file: None },
+                1 => EditBlock { search: vec![line], replacement: vec![], operation: EditOperation::Delete, file: None },
+                _ => EditBlock { search: vec![], replacement: vec![format!("top {}", n)], operation: EditOperation::Insert, file: None },
+            }
+        }).collect();
+        let edit_ref = EditRef { command_href: None, start_line: None, edits };
+
+        let result = edit_ref.apply(&content.join("\n")).unwrap();
+        let lines: Vec<&str> = result.lines().collect();
+        // 17 lines added by replacements, 17 deleted and 16 inserted
+        assert_eq!(lines.len(), 20_000 + 16);
+        assert_eq!(lines[0], "top 47");
+        assert!(!lines.contains(&"line 400"));
+        assert_eq!(lines.iter().filter(|l| **l == "added").count(), 17);
+    }
+
     #[test]
     fn test_edit_apply_search_not_found() {
         let content = "line 1\nline 2\nline 3";
@@ -1328,6 +4425,7 @@ with empty marker"#;
                     search: vec!["nonexistent".to_string()],
                     replacement: vec!["replacement".to_string()],
                     operation: EditOperation::Replace,
+                    file: None,
                 },
             ],
         };
@@ -1348,6 +4446,7 @@ with empty marker"#;
                     search: vec!["line 1".to_string()],
                     replacement: vec!["replacement".to_string()],
                     operation: EditOperation::Replace,
+                    file: None,
                 },
             ],
         };
@@ -1355,4 +4454,330 @@ with empty marker"#;
         let result = edit_ref.apply(content);
         assert!(matches!(result.unwrap_err(), EditApplyError::EmptyContent));
     }
+
+    #[test]
+    fn test_apply_edits() {
+        let input = "-- a.txt --\nline 1\nline 2\n-- a.txt[.edit] --\n<<<<<<< SEARCH\nline 2\n=======\nline two\n>>>>>>> REPLACE\n";
+        let mut archive = crate::Decoder::new().decode(input).unwrap();
+
+        let report = archive.apply_edits().unwrap();
+        assert_eq!(archive.files.len(), 1);
+        assert_eq!(&archive.files[0].data[..], b"line 1\nline two");
+        assert_eq!(report, AppliedReport { files: vec!["a.txt".into()], edits: 1, blocks: 1 });
+        assert_eq!(archive.apply_edits().unwrap(), AppliedReport::default());
+
+        // Edits of one file apply in turn, each to the result of the last
+        let input = "-- a.txt --\none\ntwo\n-- b.txt --\nB\n\
+            -- a.txt[.edit] --\n<<<<<<< SEARCH\none\n=======\n1\n>>>>>>> REPLACE\n<<<<<<< SEARCH\ntwo\n=======\n2\n>>>>>>> REPLACE\n\
+            -- b.txt[.edit] --\n<<<<<<< SEARCH\nB\n=======\nb\n>>>>>>> REPLACE\n\
+            -- a.txt[.edit] --\n<<<<<<< SEARCH\n1\n2\n=======\n12\n>>>>>>> REPLACE\n";
+        let mut archive = crate::Decoder::new().decode(input).unwrap();
+        let report = archive.apply_edits().unwrap();
+        assert_eq!(report, AppliedReport { files: vec!["a.txt".into(), "b.txt".into()], edits: 3, blocks: 4 });
+        assert_eq!(&archive.get_file("a.txt").unwrap().data[..], b"12");
+        assert_eq!(&archive.get_file("b.txt").unwrap().data[..], b"b");
+        assert_eq!(archive.files.len(), 2);
+        // The files keep their positions, which the edits no longer shadow
+        assert_eq!(archive.locate("b.txt", 1).map(|p| p.line), Some(5));
+
+        // A failing edit leaves the archive as it was
+        let failing = input.replace("SEARCH\nB\n", "SEARCH\nC\n");
+        let mut archive = crate::Decoder::new().with_edit_target_check(false).decode(&failing).unwrap();
+        let before = archive.clone();
+        let err = archive.apply_edits().unwrap_err();
+        assert!(matches!(&err, EditApplyError::InFile { name, error } if name == "b.txt"
+            && matches!(**error, EditApplyError::SearchNotFound { .. })));
+        assert_eq!(archive, before);
+    }
+
+    #[test]
+    fn test_edit_file_headers() {
+        let content = "<<<<<<< SEARCH\nx\n=======\nX\n>>>>>>> REPLACE\n\
+            @@ file: src/b.txt @@\n<<<<<<< SEARCH\nb\n>>>>>>> DELETE\n\
+            @@ file: c.txt @@\n<<<<<<< SEARCH\nc\n=======\nC\n>>>>>>> REPLACE\n\
+            @@ file: c.txt @@\n<<<<<<< SEARCH\nC\n=======\nCC\n>>>>>>> REPLACE\n";
+        let edits = EditRef::parse_content(content).unwrap();
+        let files: Vec<_> = edits.iter().map(|e| e.file.as_deref()).collect();
+        assert_eq!(files, [None, Some("src/b.txt"), Some("c.txt"), Some("c.txt")]);
+        assert_eq!(edits[1], EditBlock::new(EditOperation::Delete, vec!["b".into()], Vec::new()).with_file("src/b.txt"));
+        let edit = EditRef { command_href: None, start_line: Some(1), edits };
+        assert_eq!(edit.to_content(), content);
+        let runs: Vec<_> = edit.split_by_file("a.txt").into_iter().map(|(name, run)| (name, run.edits.len(), run.start_line)).collect();
+        assert_eq!(runs, [("a.txt", 1, Some(1)), ("src/b.txt", 1, None), ("c.txt", 2, None)]);
+        assert_eq!(EditRef::parse_content("@@file:c.txt@@\n<<<<<<< SEARCH\nc\n>>>>>>> DELETE\n").unwrap()[0].file.as_deref(), Some("c.txt"));
+        assert_eq!(EditRef::parse_content("@@ file: c.txt @@\n"), Err(EditParseError::ExpectedSearchStart));
+        assert_eq!(EditRef::parse_content("@@ file: a @@\n@@ file: b @@\n"), Err(EditParseError::ExpectedSearchStart));
+        assert!(matches!(EditRef::parse_content("@@ file:  @@\n"), Err(EditParseError::MalformedLine { line_number: 1, .. })));
+
+        // Each block applies to the file it names
+        let input = alloc::format!("-- x.txt --\nx\n-- src/b.txt --\na\nb\n-- c.txt --\nc\nc\n-- x.txt[.edit] --\n{}", content);
+        let mut archive = crate::Decoder::new().decode(&input).unwrap();
+        #[cfg(feature = "std")]
+        {
+            let collector = crate::diagnostics::Collector::default();
+            archive.clone().apply_edits_reporting(&collector).unwrap();
+            assert!(matches!(
+                collector.take().as_slice(),
+                [Diagnostic::AmbiguousSearch { name, edit: 2, matches: 2 }] if name == "c.txt"
+            ));
+        }
+        let report = archive.apply_edits_reporting(&crate::diagnostics::Ignore).unwrap();
+        assert_eq!(report, AppliedReport { files: vec!["x.txt".into(), "src/b.txt".into(), "c.txt".into()], edits: 1, blocks: 4 });
+        assert_eq!(&archive.get_file("x.txt").unwrap().data[..], b"X");
+        assert_eq!(&archive.get_file("src/b.txt").unwrap().data[..], b"a");
+        assert_eq!(&archive.get_file("c.txt").unwrap().data[..], b"CC\nc");
+
+        // Targets named by headers are checked like entry names
+        let missing = "-- x.txt --\nx\n-- x.txt[.edit] --\n@@ file: gone.txt @@\n<<<<<<< SEARCH\nx\n>>>>>>> DELETE\n";
+        assert!(crate::Decoder::new().decode(missing).unwrap_err().to_string().contains("gone.txt"));
+        let mut archive = crate::Decoder::new().with_edit_target_check(false).decode(missing).unwrap();
+        assert!(matches!(archive.apply_edits(), Err(EditApplyError::TargetNotFound { name }) if name == "gone.txt"));
+        // An entry whose blocks all name other files needs no file of its own
+        let elsewhere = "-- x.txt --\nx\n-- changes[.edit] --\n@@ file: x.txt @@\n<<<<<<< SEARCH\nx\n=======\ny\n>>>>>>> REPLACE\n";
+        let mut archive = crate::Decoder::new().decode(elsewhere).unwrap();
+        assert_eq!(archive.apply_edits().unwrap().files, ["x.txt"]);
+        assert_eq!(&archive.get_file("x.txt").unwrap().data[..], b"y");
+    }
+
+    #[test]
+    fn test_insert_anchors() {
+        let content = "<<<<<<< SEARCH\nfn b() {}\n=======\nfn c() {}\n>>>>>>> INSERT AFTER\n\
+            <<<<<<< SEARCH\nfn b() {}\n=======\nfn a() {}\n>>>>>>> INSERT BEFORE\n";
+        let edits = EditRef::parse_content(content).unwrap();
+        let operations: Vec<_> = edits.iter().map(|e| e.operation.clone()).collect();
+        assert_eq!(operations, [EditOperation::InsertAfter, EditOperation::InsertBefore]);
+        let edit = EditRef { command_href: None, start_line: None, edits };
+        assert_eq!(edit.to_content(), content);
+        assert_eq!(edit.apply("use x;\nfn b() {}\n// end").unwrap(), "use x;\nfn a() {}\nfn b() {}\nfn c() {}\n// end");
+        assert!(matches!(edit.apply("fn z() {}"), Err(EditApplyError::SearchNotFound { .. })));
+        assert!(matches!(edit.apply(""), Err(EditApplyError::EmptyContent)));
+
+        // Plain INSERT still prepends
+        let top = EditRef::parse_content("<<<<<<< SEARCH\n=======\n// top\n>>>>>>> INSERT\n").unwrap();
+        assert_eq!(top[0].operation, EditOperation::Insert);
+        assert_eq!(
+            EditRef::parse_content("<<<<<<< SEARCH\n=======\nx\n>>>>>>> INSERT AFTER\n"),
+            Err(EditParseError::MissingAnchor)
+        );
+    }
+
+    #[test]
+    fn test_edit_diff() {
+        let block = |search: &[&str], replacement: &[&str], operation| EditBlock {
+            search: search.iter().map(|s| s.to_string()).collect(),
+            replacement: replacement.iter().map(|s| s.to_string()).collect(),
+            operation,
+            file: None,
+        };
+        assert!(EditRef::diff("a\nb", "a\nb\n").edits.is_empty());
+
+        let edit = EditRef::diff("a\nb\nc\nd", "top\na\nB\nc");
+        assert_eq!(edit.edits, [
+            block(&[], &["top"], EditOperation::Insert),
+            block(&["b"], &["B"], EditOperation::Replace),
+            block(&["d"], &[], EditOperation::Delete),
+        ]);
+        assert_eq!(edit.command_href, None);
+
+        // Repeated lines take the preceding lines as context
+        let original = "fn a() {\n}\nfn b() {\n}";
+        let edit = EditRef::diff(original, "fn a() {\n}\nfn b() {\n    run();\n}");
+        assert_eq!(edit.edits, [block(&["fn b() {"], &["    run();"], EditOperation::InsertAfter)]);
+        let edit = EditRef::diff(original, "fn a() {\n}\nfn b() {");
+        assert_eq!(edit.edits, [block(&["fn b() {", "}"], &["fn b() {"], EditOperation::Replace)]);
+        assert_eq!(edit.apply(original).unwrap(), "fn a() {\n}\nfn b() {");
+
+        assert_eq!(EditRef::diff("", "x\ny").apply("").unwrap(), "x\ny");
+        assert_eq!(EditRef::diff("x\ny", "").apply("x\ny").unwrap(), "");
+    }
+
+    #[test]
+    fn test_edit_preview() {
+        let original: Vec<String> = (1..=20).map(|n| n.to_string()).collect();
+        let original = original.join("\n");
+        let modified = original.replacen("\n2\n", "\ntwo\n", 1).replace("\n7\n", "\n").replace("19", "19\n19.5");
+        let edit = EditRef::diff(&original, &modified);
+        assert_eq!(
+            edit.preview(&original).unwrap(),
+            "@@ -1,10 +1,9 @@\n 1\n-2\n+two\n 3\n 4\n 5\n 6\n-7\n 8\n 9\n 10\n\
+             @@ -17,4 +16,5 @@\n 17\n 18\n 19\n+19.5\n 20\n",
+        );
+        // The content is left alone
+        assert_eq!(edit.apply(&original).unwrap(), modified);
+
+        assert_eq!(EditRef::diff("a", "top\na").preview("a").unwrap(), "@@ -1 +1,2 @@\n+top\n a\n");
+        assert_eq!(EditRef::diff("", "x\ny").preview("").unwrap(), "@@ -0,0 +1,2 @@\n+x\n+y\n");
+        assert_eq!(EditRef::diff("x", "").preview("x").unwrap(), "@@ -1 +0,0 @@\n-x\n");
+        assert_eq!(EditRef::diff("x", "x").preview("x").unwrap(), "");
+        let blocks = "<<<<<<< SEARCH\nmissing\n>>>>>>> DELETE\n";
+        let edit = EditRef { command_href: None, start_line: None, edits: EditRef::parse_content(blocks).unwrap() };
+        assert!(matches!(edit.preview("x"), Err(EditApplyError::SearchNotFound { .. })));
+    }
+
+    #[test]
+    fn test_edit_invert() {
+        let blocks = "<<<<<<< SEARCH\nb\n=======\nB\nB2\n>>>>>>> REPLACE\n\
+            <<<<<<< SEARCH\n=======\ntop\n>>>>>>> REPLACE\n\
+            <<<<<<< SEARCH\nd\n>>>>>>> DELETE\n\
+            <<<<<<< SEARCH\nc\n=======\nafter c\n>>>>>>> INSERT AFTER\n\
+            <<<<<<< SEARCH\na\n=======\nbefore a\n>>>>>>> INSERT BEFORE\n";
+        let edit = EditRef { command_href: Some("#fix".to_string()), start_line: Some(3), edits: EditRef::parse_content(blocks).unwrap() };
+        let original = "a\nb\nc\nd\ne";
+        let edited = edit.apply(original).unwrap();
+        assert_eq!(edited, "top\nbefore a\na\nB\nB2\nc\nafter c\ne");
+        let inverse = edit.invert(original).unwrap();
+        assert_eq!(inverse.edits.len(), 5);
+        assert_eq!(inverse.edits[0].operation, EditOperation::Delete);
+        assert_eq!(inverse.edits[2].operation, EditOperation::InsertAfter);
+        assert_eq!(inverse.edits[2].replacement, ["d"]);
+        assert_eq!(inverse.command_href.as_deref(), Some("#fix"));
+        assert_eq!(inverse.start_line, None);
+        assert_eq!(inverse.apply(&edited).unwrap(), original);
+
+        // Repeated lines take context, fuzzy matches restore the exact lines
+        let edit = EditRef::diff("x\n}\ny\n}", "x\n}\ny");
+        assert_eq!(edit.invert("x\n}\ny\n}").unwrap().apply("x\n}\ny").unwrap(), "x\n}\ny\n}");
+        let blocks = "<<<<<<< SEARCH\nif ok {\n=======\nif done {\n>>>>>>> REPLACE\n";
+        let edit = EditRef { command_href: None, start_line: None, edits: EditRef::parse_content(blocks).unwrap() };
+        let inverse = edit.invert_with("\tif ok {", MatchStrategy::IgnoreIndentation).unwrap();
+        assert_eq!(inverse.apply("if done {").unwrap(), "\tif ok {");
+
+        // Deleting everything inverts to insertions, no-ops vanish
+        let edit = EditRef::diff("x\ny", "");
+        assert_eq!(edit.invert("x\ny").unwrap().apply("").unwrap(), "x\ny");
+        let blocks = "<<<<<<< SEARCH\nx\n=======\nx\n>>>>>>> REPLACE\n";
+        let edit = EditRef { command_href: None, start_line: None, edits: EditRef::parse_content(blocks).unwrap() };
+        assert_eq!(edit.invert("x").unwrap().edits.len(), 1);
+        assert!(matches!(edit.invert("y"), Err(EditApplyError::SearchNotFound { .. })));
+        assert!(matches!(edit.invert(""), Err(EditApplyError::EmptyContent)));
+    }
+
+    #[test]
+    fn test_apply_with_match_strategy() {
+        let edit = |blocks: &str| EditRef { command_href: None, start_line: None, edits: EditRef::parse_content(blocks).unwrap() };
+        let content = "fn main() {\n\tlet x = 1;  \n\tprintln!(\"{}\", x);\n}";
+        let indented = edit("<<<<<<< SEARCH\n    let x = 1;\n=======\n    let x = 2;\n>>>>>>> REPLACE\n");
+        let trailing = edit("<<<<<<< SEARCH\n\tlet x = 1;\n=======\n\tlet x = 2;\n>>>>>>> REPLACE\n");
+
+        assert!(matches!(trailing.apply(content), Err(EditApplyError::SearchNotFound { .. })));
+        let edited = trailing.apply_with(content, MatchStrategy::TrimWhitespace).unwrap();
+        assert_eq!(edited, "fn main() {\n\tlet x = 2;\n\tprintln!(\"{}\", x);\n}");
+        assert!(indented.apply_with(content, MatchStrategy::TrimWhitespace).is_err());
+        // Replacement lines are inserted as written
+        let edited = indented.apply_with(content, MatchStrategy::IgnoreIndentation).unwrap();
+        assert_eq!(edited, "fn main() {\n    let x = 2;\n\tprintln!(\"{}\", x);\n}");
+
+        // Fuzzy matching takes the most similar lines, if similar enough
+        let typo = edit("<<<<<<< SEARCH\nlet x = 1;\nprintln!(\"{}\",x);\n=======\nprint(1);\n>>>>>>> REPLACE\n");
+        assert!(typo.apply_with(content, MatchStrategy::IgnoreIndentation).is_err());
+        let edited = typo.apply_with(content, MatchStrategy::Fuzzy { threshold: 0.9 }).unwrap();
+        assert_eq!(edited, "fn main() {\nprint(1);\n}");
+        assert!(typo.apply_with(content, MatchStrategy::Fuzzy { threshold: 1.0 }).is_err());
+        let other = edit("<<<<<<< SEARCH\nsomething else\n>>>>>>> DELETE\n");
+        assert!(other.apply_with(content, MatchStrategy::Fuzzy { threshold: 0.8 }).is_err());
+        assert_eq!(edit_distance(&['k', 'i', 't', 't', 'e', 'n'], &['s', 'i', 't', 't', 'i', 'n', 'g']), 3);
+        assert_eq!(edit_distance(&[], &['a']), 1);
+
+        // Ambiguity is judged with the same strategy
+        #[cfg(feature = "std")]
+        {
+            let collector = crate::diagnostics::Collector::default();
+            let repeated = "a\n  a\nb";
+            let one = edit("<<<<<<< SEARCH\na\n=======\nc\n>>>>>>> REPLACE\n");
+            assert_eq!(one.apply_with_reporting(repeated, MatchStrategy::Exact, "f", &collector).unwrap(), "c\n  a\nb");
+            assert!(collector.take().is_empty());
+            one.apply_with_reporting(repeated, MatchStrategy::IgnoreIndentation, "f", &collector).unwrap();
+            assert_eq!(collector.take().len(), 1);
+        }
+
+        let input = "-- a.rs --\n  x\n-- a.rs[.edit] --\n<<<<<<< SEARCH\nx\n=======\ny\n>>>>>>> REPLACE\n";
+        let mut archive = crate::Decoder::new().decode(input).unwrap();
+        assert!(archive.clone().apply_edits().is_err());
+        archive.apply_edits_with(MatchStrategy::IgnoreIndentation, &crate::diagnostics::Ignore).unwrap();
+        assert_eq!(&archive.get_file("a.rs").unwrap().data[..], b"y");
+    }
+
+    #[test]
+    fn test_apply_near_start_line() {
+        let blocks = "<<<<<<< SEARCH\nreturn;\n=======\nreturn 0;\n>>>>>>> REPLACE\n";
+        let edit = |start_line| EditRef { command_href: None, start_line, edits: EditRef::parse_content(blocks).unwrap() };
+        let content = "a\nreturn;\nb\nreturn;\nc\nreturn;";
+        assert_eq!(edit(None).apply(content).unwrap(), "a\nreturn 0;\nb\nreturn;\nc\nreturn;");
+        assert_eq!(edit(Some(4)).apply(content).unwrap(), "a\nreturn;\nb\nreturn 0;\nc\nreturn;");
+        assert_eq!(edit(Some(5)).apply(content).unwrap(), "a\nreturn;\nb\nreturn 0;\nc\nreturn;");
+        assert_eq!(edit(Some(100)).apply(content).unwrap(), "a\nreturn;\nb\nreturn;\nc\nreturn 0;");
+        // Still reported as ambiguous
+        #[cfg(feature = "std")]
+        {
+            let collector = crate::diagnostics::Collector::default();
+            edit(Some(4)).apply_reporting(content, "f", &collector).unwrap();
+            assert_eq!(collector.take().len(), 1);
+        }
+
+        // Fuzzy matching prefers similar lines near the start line to
+        // closer matches elsewhere
+        let mut content: Vec<String> = (0..100).map(|i| format!("line {}", i)).collect();
+        content[5] = "value = compute(1);".into();
+        content[80] = "value = compute(2) ;".into();
+        let content = content.join("\n");
+        let blocks = "<<<<<<< SEARCH\nvalue = compute(1) ;\n=======\nvalue = 0;\n>>>>>>> REPLACE\n";
+        let fuzzy = MatchStrategy::Fuzzy { threshold: 0.9 };
+        let edit = |start_line| EditRef { command_href: None, start_line, edits: EditRef::parse_content(blocks).unwrap() };
+        assert_eq!(edit(None).apply_with(&content, fuzzy).unwrap().lines().nth(5), Some("value = 0;"));
+        assert_eq!(edit(Some(78)).apply_with(&content, fuzzy).unwrap().lines().nth(80), Some("value = 0;"));
+        // Outside the window, the best match in the file
+        assert_eq!(edit(Some(40)).apply_with(&content, fuzzy).unwrap().lines().nth(5), Some("value = 0;"));
+
+        let lines = ["x", "y", "x", "y", "x"];
+        let search = ["x".to_string()];
+        let find = |near| find_line_block_near(&lines, &search, |line, search| *line == search, near);
+        assert_eq!(find(None), Some(0));
+        assert_eq!(find(Some(1)), Some(0));
+        assert_eq!(find(Some(3)), Some(2));
+        assert_eq!(find(Some(9)), Some(4));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_apply_edits_to_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("src/a.txt"), "one\ntwo\n").unwrap();
+        std::fs::write(dir.path().join("b.txt"), "B\r\nC\r\n").unwrap();
+        let input = "-- src/a.txt[.edit] --\n<<<<<<< SEARCH\none\n=======\n1\n>>>>>>> REPLACE\n\
+            -- b.txt[.edit] --\n<<<<<<< SEARCH\nB\n=======\nb\n>>>>>>> REPLACE\n\
+            -- src/a.txt[.edit] --\n<<<<<<< SEARCH\ntwo\n=======\n2\n>>>>>>> REPLACE\n";
+        let archive = crate::Decoder::new().with_edit_target_check(false).decode(input).unwrap();
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+
+        let options = ApplyOptions { dry_run: true, ..Default::default() };
+        let report = archive.apply_edits_to_dir(dir.path(), options).unwrap();
+        assert_eq!(report, AppliedReport { files: vec!["src/a.txt".into(), "b.txt".into()], edits: 3, blocks: 3 });
+        assert_eq!(read("src/a.txt"), "one\ntwo\n");
+
+        let options = ApplyOptions { backup: true, ..Default::default() };
+        assert_eq!(archive.apply_edits_to_dir(dir.path(), options).unwrap(), report);
+        assert_eq!(read("src/a.txt"), "1\n2\n");
+        assert_eq!(read("b.txt"), "b\r\nC\r\n");
+        assert_eq!(read("src/a.txt.bak"), "one\ntwo\n");
+        assert_eq!(read("b.txt.bak"), "B\r\nC\r\n");
+        // An edit that does not apply leaves every file as it was
+        std::fs::copy(dir.path().join("src/a.txt.bak"), dir.path().join("src/a.txt")).unwrap();
+        let err = archive.apply_edits_to_dir(dir.path(), ApplyOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("b.txt"), "{}", err);
+        assert_eq!(read("src/a.txt"), "one\ntwo\n");
+
+        // Blocks naming another file apply to it
+        let routed = crate::Decoder::new().with_edit_target_check(false)
+            .decode("-- b.txt[.edit] --\n@@ file: src/a.txt @@\n<<<<<<< SEARCH\ntwo\n>>>>>>> DELETE\n").unwrap();
+        let report = routed.apply_edits_to_dir(dir.path(), ApplyOptions::default()).unwrap();
+        assert_eq!(report.files, ["src/a.txt"]);
+        assert_eq!(read("src/a.txt"), "one\n");
+        assert_eq!(read("b.txt"), "b\r\nC\r\n");
+
+        let missing = crate::Decoder::new().with_edit_target_check(false)
+            .decode("-- gone.txt[.edit] --\n<<<<<<< SEARCH\nx\n=======\ny\n>>>>>>> REPLACE\n").unwrap();
+        let err = missing.apply_edits_to_dir(dir.path(), ApplyOptions::default()).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(EditApplyError::TargetNotFound { name }) if name == "gone.txt"));
+    }
 }
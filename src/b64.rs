@@ -0,0 +1,196 @@
+//! Base64 for binary sections
+//!
+//! Backed by the `base64` crate. With the `simd` feature, x86_64 CPUs that
+//! support SSSE3 encode and decode 12-byte blocks with vector instructions;
+//! output and errors are identical to the scalar path.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use base64::alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD};
+use base64::engine::DecodePaddingMode;
+use base64::{DecodeError, Engine};
+
+/// Standard alphabet, accepting missing padding and non-zero trailing bits
+const LENIENT: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+/// Length of the padded base64 encoding of `len` bytes
+pub(crate) fn encoded_len(len: usize) -> usize {
+    len.div_ceil(3) * 4
+}
+
+/// Append `data` to `out` as padded standard base64
+pub(crate) fn encode_into(data: &[u8], out: &mut String) {
+    out.reserve(encoded_len(data.len()));
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if std::is_x86_feature_detected!("ssse3") {
+        // Safety: SSSE3 support was just checked
+        return unsafe { ssse3::encode_into(data, out) };
+    }
+    STANDARD.encode_string(data, out);
+}
+
+/// Decode standard base64, appending the bytes to `out`
+pub(crate) fn decode_vec(input: &[u8], out: &mut Vec<u8>) -> Result<(), DecodeError> {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    if std::is_x86_feature_detected!("ssse3") {
+        let start = out.len();
+        // Safety: SSSE3 support was just checked
+        if unsafe { ssse3::decode_vec(input, out) }.is_ok() {
+            return Ok(());
+        }
+        // Let the scalar decoder report the error with its usual offsets
+        out.truncate(start);
+    }
+    STANDARD.decode_vec(input, out)
+}
+
+/// Decode like [`decode_vec`], also accepting input without padding or with
+/// non-zero trailing bits; returns whether that leniency was needed
+pub(crate) fn decode_vec_lenient(input: &[u8], out: &mut Vec<u8>) -> Result<bool, DecodeError> {
+    let start = out.len();
+    if decode_vec(input, out).is_ok() {
+        return Ok(false);
+    }
+    out.truncate(start);
+    LENIENT.decode_vec(input, out).map(|()| true)
+}
+
+/// Rewrite URL-safe characters to the standard alphabet
+pub(crate) fn normalize_alphabet(c: char) -> char {
+    match c {
+        '-' => '+',
+        '_' => '/',
+        c => c,
+    }
+}
+
+/// SSSE3 codec after Muła and Lemire, "Faster Base64 Encoding and Decoding
+/// Using AVX2 Instructions"
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod ssse3 {
+    use super::*;
+    use core::arch::x86_64::*;
+
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn encode_into(data: &[u8], out: &mut String) {
+        // Only ASCII from the base64 alphabet is pushed, so `out` stays UTF-8
+        let bytes = out.as_mut_vec();
+        let mut i = 0;
+        // Each load reads 16 bytes and uses the first 12
+        while i + 16 <= data.len() {
+            let input = _mm_loadu_si128(data.as_ptr().add(i).cast());
+            let mut block = [0u8; 16];
+            _mm_storeu_si128(block.as_mut_ptr().cast(), translate(reshuffle(input)));
+            bytes.extend_from_slice(&block);
+            i += 12;
+        }
+        // `i` is a multiple of 3, so the tail encodes independently
+        STANDARD.encode_string(&data[i..], out);
+    }
+
+    /// Spread 12 bytes into 16 six-bit indices
+    #[target_feature(enable = "ssse3")]
+    unsafe fn reshuffle(input: __m128i) -> __m128i {
+        let input = _mm_shuffle_epi8(input, _mm_set_epi8(10, 11, 9, 10, 7, 8, 6, 7, 4, 5, 3, 4, 1, 2, 0, 1));
+        let t0 = _mm_and_si128(input, _mm_set1_epi32(0x0fc0fc00));
+        let t1 = _mm_mulhi_epu16(t0, _mm_set1_epi32(0x04000040));
+        let t2 = _mm_and_si128(input, _mm_set1_epi32(0x003f03f0));
+        let t3 = _mm_mullo_epi16(t2, _mm_set1_epi32(0x01000010));
+        _mm_or_si128(t1, t3)
+    }
+
+    /// Map six-bit indices to the base64 alphabet
+    #[target_feature(enable = "ssse3")]
+    unsafe fn translate(indices: __m128i) -> __m128i {
+        let offsets = _mm_setr_epi8(65, 71, -4, -4, -4, -4, -4, -4, -4, -4, -4, -4, -19, -16, 0, 0);
+        // 0 for A-Z, 1 for a-z, 2..=11 for digits, 12 for '+' and 13 for '/'
+        let ranges = _mm_subs_epu8(indices, _mm_set1_epi8(51));
+        let ranges = _mm_sub_epi8(ranges, _mm_cmpgt_epi8(indices, _mm_set1_epi8(25)));
+        _mm_add_epi8(_mm_shuffle_epi8(offsets, ranges), indices)
+    }
+
+    /// Decode 16-character blocks, leaving the final quantum (which may be
+    /// padded) to the scalar decoder. Any invalid character is an error.
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn decode_vec(input: &[u8], out: &mut Vec<u8>) -> Result<(), DecodeError> {
+        out.reserve(input.len() / 4 * 3);
+        let lut_lo = _mm_setr_epi8(
+            0x15, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x13, 0x1a, 0x1b, 0x1b, 0x1b, 0x1a,
+        );
+        let lut_hi = _mm_setr_epi8(
+            0x10, 0x10, 0x01, 0x02, 0x04, 0x08, 0x04, 0x08, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x10,
+        );
+        let lut_roll = _mm_setr_epi8(0, 16, 19, 4, -65, -65, -71, -71, 0, 0, 0, 0, 0, 0, 0, 0);
+        let mask_2f = _mm_set1_epi8(0x2f);
+
+        let mut i = 0;
+        while input.len() - i > 16 {
+            let chars = _mm_loadu_si128(input.as_ptr().add(i).cast());
+            let hi_nibbles = _mm_and_si128(_mm_srli_epi32(chars, 4), mask_2f);
+            let lo_nibbles = _mm_and_si128(chars, mask_2f);
+            let lo = _mm_shuffle_epi8(lut_lo, lo_nibbles);
+            let hi = _mm_shuffle_epi8(lut_hi, hi_nibbles);
+            let invalid = _mm_cmpeq_epi8(_mm_and_si128(lo, hi), _mm_setzero_si128());
+            if _mm_movemask_epi8(invalid) != 0xffff {
+                return Err(DecodeError::InvalidByte(i, 0));
+            }
+
+            let eq_2f = _mm_cmpeq_epi8(chars, mask_2f);
+            let roll = _mm_shuffle_epi8(lut_roll, _mm_add_epi8(eq_2f, hi_nibbles));
+            let values = _mm_add_epi8(chars, roll);
+            let merged = _mm_maddubs_epi16(values, _mm_set1_epi32(0x01400140));
+            let packed = _mm_madd_epi16(merged, _mm_set1_epi32(0x00011000));
+            let packed = _mm_shuffle_epi8(packed, _mm_setr_epi8(2, 1, 0, 6, 5, 4, 10, 9, 8, 14, 13, 12, -1, -1, -1, -1));
+
+            let mut block = [0u8; 16];
+            _mm_storeu_si128(block.as_mut_ptr().cast(), packed);
+            out.extend_from_slice(&block[..12]);
+            i += 16;
+        }
+        STANDARD.decode_vec(&input[i..], out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_matches_scalar() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        for len in (0..64).chain([999, 1000]) {
+            let mut encoded = String::from("prefix");
+            encode_into(&data[..len], &mut encoded);
+            let encoded = &encoded["prefix".len()..];
+            assert_eq!(encoded.len(), encoded_len(len));
+            assert_eq!(encoded, STANDARD.encode(&data[..len]), "length {}", len);
+
+            let mut decoded = vec![0xAA];
+            decode_vec(encoded.as_bytes(), &mut decoded).unwrap();
+            assert_eq!(&decoded[1..], &data[..len]);
+        }
+
+        let mut bad = STANDARD.encode(&data[..100]).into_bytes();
+        bad[20] = b'!';
+        let mut out = Vec::new();
+        assert_eq!(decode_vec(&bad, &mut out), STANDARD.decode_vec(&bad, &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_decode_lenient() {
+        for (input, lenient) in [("SGVsbG8=", false), ("SGVsbG8", true), ("SGVsbG9=", true)] {
+            let mut out = vec![b'>'];
+            assert_eq!(decode_vec_lenient(input.as_bytes(), &mut out), Ok(lenient));
+            assert_eq!(out, b">Hello", "{}", input);
+        }
+        assert!(decode_vec(b"SGVsbG8", &mut Vec::new()).is_err());
+        assert!(decode_vec_lenient(b"SGVsb!8=", &mut Vec::new()).is_err());
+    }
+}
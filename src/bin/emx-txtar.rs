@@ -3,11 +3,37 @@
 //! Create and extract txtar archives (similar to tar command).
 
 use anyhow::{Result, Context};
-use clap::{Parser, Subcommand};
-use emx_txtar::{Archive, File, Encoder, Decoder};
+use clap::{Parser, Subcommand, ValueEnum};
+use emx_txtar::archive::{extract_path, prepare_extract_path};
+use emx_txtar::checksum::crc32;
+use emx_txtar::compress::Compression;
+use emx_txtar::diagnostics::{Collector, Diagnostic};
+use emx_txtar::{glob, parallel, version, Archive, Conditions, File, FileMetadata, Encoder, Decoder, WindowsNamePolicy};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+#[cfg(feature = "walkdir")]
+use std::collections::HashMap;
 use std::fs;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Set by `--quiet`: suppress status messages and warnings
+static QUIET: AtomicBool = AtomicBool::new(false);
+/// Set by `--strict`: treat archive warnings as validation failures
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Print a status message unless `--quiet` was given
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !QUIET.load(Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
 
 #[derive(Parser, Debug)]
 #[command(name = "emx-txtar")]
@@ -17,6 +43,50 @@ use std::path::{Path, PathBuf};
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Silence status messages and warnings
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Fail on warnings (unresolved snippet references) and on headers that
+    /// do not round-trip (unknown tags, unescaped marker patterns)
+    #[arg(long, global = true)]
+    strict: bool,
+}
+
+/// Failure classes, mapped to the process exit code
+///
+/// 0 is success; errors without a class exit with 1 (usage) unless they
+/// stem from an I/O error (4).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Failure {
+    Usage = 1,
+    Parse = 2,
+    Validation = 3,
+    Io = 4,
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Failure::Usage => "usage error",
+            Failure::Parse => "failed to parse archive",
+            Failure::Validation => "validation failed",
+            Failure::Io => "I/O error",
+        })
+    }
+}
+
+impl Failure {
+    fn of(err: &anyhow::Error) -> Self {
+        if let Some(failure) = err.downcast_ref::<Failure>() {
+            *failure
+        } else if err.chain().any(|cause| cause.is::<io::Error>()) {
+            Failure::Io
+        } else {
+            Failure::Usage
+        }
+    }
 }
 
 #[derive(Subcommand, Debug)]
@@ -24,13 +94,49 @@ enum Commands {
     /// Create a txtar archive from files/directories
     Create {
         /// Files and directories to archive
-        #[arg(required = true)]
+        #[arg(required_unless_present = "files_from")]
         inputs: Vec<PathBuf>,
 
+        /// Read paths to archive from a file ("-" for stdin), one per line
+        #[arg(short = 'T', long, value_name = "FILE")]
+        files_from: Option<PathBuf>,
+
+        /// Paths in --files-from are NUL-separated
+        #[arg(short = '0', long, requires = "files_from")]
+        null: bool,
+
         /// Output archive file (default: stdout)
         #[arg(short = 'o', long)]
         output: Option<PathBuf>,
 
+        /// Compress the archive (gzip or zstd)
+        #[arg(long, value_name = "FORMAT", value_parser = parse_compression)]
+        compress: Option<Compression>,
+
+        /// Only archive paths matching this glob (repeatable)
+        #[arg(long = "include", value_name = "GLOB")]
+        include: Vec<String>,
+
+        /// Skip paths matching this glob (repeatable)
+        #[arg(long = "exclude", value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Honor .gitignore/.ignore files (and skip .git) in directories
+        #[arg(long)]
+        gitignore: bool,
+
+        /// Skip version control directories and files
+        #[arg(long)]
+        exclude_vcs: bool,
+
+        /// Record file permissions and modification times
+        #[arg(long)]
+        metadata: bool,
+
+        /// Threads used to read and encode files (default: number of CPUs)
+        #[arg(short = 'j', long, value_name = "N")]
+        jobs: Option<usize>,
+
         /// Verbose output
         #[arg(short, long)]
         verbose: bool,
@@ -51,6 +157,160 @@ enum Commands {
         #[arg(long)]
         include_snippets: bool,
 
+        /// Only extract paths matching this glob (repeatable)
+        #[arg(long = "include", value_name = "GLOB")]
+        include: Vec<String>,
+
+        /// Skip paths matching this glob (repeatable)
+        #[arg(long = "exclude", value_name = "GLOB")]
+        exclude: Vec<String>,
+
+        /// Write transcoded text as UTF-8 instead of its original encoding
+        #[arg(long)]
+        utf8: bool,
+
+        /// Write file contents to stdout instead of the directory
+        #[arg(short = 'O', long)]
+        to_stdout: bool,
+
+        /// Strip N leading components from archive paths
+        #[arg(long, value_name = "N", default_value_t = 0)]
+        strip_components: usize,
+
+        /// Rewrite archive paths with a sed-style expression (repeatable),
+        /// e.g. 's#^old/#new/#'
+        #[arg(long = "transform", value_name = "EXPR")]
+        transforms: Vec<String>,
+
+        /// Never replace existing files
+        #[arg(long, conflicts_with_all = ["overwrite", "keep_newer"])]
+        no_clobber: bool,
+
+        /// Replace existing files (the default)
+        #[arg(long, conflicts_with = "keep_newer")]
+        overwrite: bool,
+
+        /// Keep existing files that are newer than the archive
+        #[arg(long)]
+        keep_newer: bool,
+
+        /// Print what would be written without touching the filesystem
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Names invalid on Windows: allow, reject or sanitize
+        /// (default: reject on Windows, allow elsewhere)
+        #[arg(long, value_name = "POLICY", value_parser = parse_windows_names)]
+        windows_names: Option<WindowsNamePolicy>,
+
+        /// Apply recorded file permissions
+        #[arg(short = 'p', long)]
+        preserve_permissions: bool,
+
+        /// Don't restore recorded modification times
+        #[arg(short = 'm', long)]
+        touch: bool,
+
+        /// Verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Write a single file from a txtar archive to stdout
+    Cat {
+        /// Archive file ("-" for stdin)
+        archive: PathBuf,
+
+        /// Name of the file in the archive
+        name: String,
+
+        /// Write transcoded text as UTF-8 instead of its original encoding
+        #[arg(long)]
+        utf8: bool,
+    },
+
+    /// Add files/directories to an existing archive, replacing same-named files
+    Add {
+        /// Archive file to modify
+        archive: PathBuf,
+
+        /// Files and directories to add
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Update an archive from files/directories, rewriting only changed entries
+    #[command(name = "u")]
+    Update {
+        /// Archive file to update
+        archive: PathBuf,
+
+        /// Files and directories to re-read
+        #[arg(required = true)]
+        inputs: Vec<PathBuf>,
+
+        /// Verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Remove files from an existing archive
+    Rm {
+        /// Archive file to modify
+        archive: PathBuf,
+
+        /// Names of the files to remove
+        #[arg(required = true)]
+        names: Vec<String>,
+
+        /// Verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Open a file from an archive in $VISUAL/$EDITOR and store it back on
+    /// save, or without a name apply the archive's edit entries to its files
+    Edit {
+        /// Archive file to modify
+        archive: PathBuf,
+
+        /// Name of the file in the archive
+        name: Option<String>,
+
+        /// Print the changes the edit entries would make as a unified diff
+        /// instead of applying them
+        #[arg(long, conflicts_with = "name")]
+        dry_run: bool,
+    },
+
+    /// Convert between txtar, tar and zip archives (format from the file extension)
+    Convert {
+        /// Input archive (.txtar, .tar or .zip)
+        input: PathBuf,
+
+        /// Output archive (.txtar, .tar or .zip)
+        output: PathBuf,
+
+        /// Verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// Upgrade archives written by older versions of this tool to the
+    /// current format version, in place
+    Migrate {
+        /// Archive files to upgrade
+        #[arg(required = true)]
+        archives: Vec<PathBuf>,
+
+        /// Only report archives needing an upgrade, failing if any do
+        #[arg(long)]
+        check: bool,
+
         /// Verbose output
         #[arg(short, long)]
         verbose: bool,
@@ -63,36 +323,356 @@ enum Commands {
         #[arg(short = 'i', long)]
         input: Option<PathBuf>,
 
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+
+        /// Show files as a tree grouped by directory, with size rollups
+        #[arg(long, conflicts_with = "format")]
+        tree: bool,
+
+        /// Terminate names with NUL instead of newline (for `xargs -0`)
+        #[arg(short = '0', long, conflicts_with_all = ["format", "tree", "verbose"])]
+        print0: bool,
+
         /// Verbose output
         #[arg(short, long)]
         verbose: bool,
     },
 }
 
-fn main() -> Result<()> {
-    let cli = Cli::parse();
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            let _ = err.print();
+            // --help and --version are not failures
+            return if err.use_stderr() { ExitCode::from(Failure::Usage as u8) } else { ExitCode::SUCCESS };
+        }
+    };
+    QUIET.store(cli.quiet, Ordering::Relaxed);
+    STRICT.store(cli.strict, Ordering::Relaxed);
+
+    match run(cli.command) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {:#}", err);
+            ExitCode::from(Failure::of(&err) as u8)
+        }
+    }
+}
+
+fn run(command: Commands) -> Result<()> {
+    match command {
+        Commands::Create {
+            inputs, files_from, null, output, compress, include, exclude, gitignore, exclude_vcs, metadata, jobs, verbose,
+        } => {
+            let filter = PathFilter { include, exclude };
+            let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+            let walk = WalkOptions { gitignore, exclude_vcs, record_metadata: metadata, jobs };
+            let listed = match files_from {
+                Some(list) => read_file_list(&list, null)?,
+                None => Vec::new(),
+            };
+            create_archive(inputs, listed, output, compress, &filter, &walk, verbose)?;
+        }
+        Commands::Extract {
+            input, directory, include_snippets, include, exclude, utf8, to_stdout,
+            strip_components, transforms, no_clobber, overwrite: _, keep_newer, dry_run,
+            windows_names, preserve_permissions, touch, verbose,
+        } => {
+            let overwrite = if no_clobber {
+                OverwritePolicy::Never
+            } else if keep_newer {
+                OverwritePolicy::KeepNewer(archive_mtime(input.as_deref())?)
+            } else {
+                OverwritePolicy::Always
+            };
+            let settings = ExtractSettings {
+                include_snippets,
+                filter: PathFilter { include, exclude },
+                rewrite: PathRewrite {
+                    strip_components,
+                    transforms: transforms.iter().map(|t| Transform::parse(t)).collect::<Result<_>>()?,
+                },
+                utf8,
+                to_stdout,
+                overwrite,
+                dry_run,
+                windows_names: windows_names.unwrap_or_default(),
+                preserve_permissions,
+                preserve_mtimes: !touch,
+            };
+            extract_archive(input, directory, &settings, verbose)?;
+        }
+        Commands::Cat { archive, name, utf8 } => {
+            cat_file(archive, &name, utf8)?;
+        }
+        Commands::Add { archive, inputs, verbose } => {
+            add_to_archive(&archive, &inputs, verbose)?;
+        }
+        Commands::Update { archive, inputs, verbose } => {
+            update_archive(&archive, &inputs, verbose)?;
+        }
+        Commands::Rm { archive, names, verbose } => {
+            remove_from_archive(&archive, &names, verbose)?;
+        }
+        Commands::Edit { archive, name: Some(name), .. } => {
+            edit_file(&archive, &name)?;
+        }
+        Commands::Edit { archive, name: None, dry_run } => {
+            apply_edit_entries(&archive, dry_run)?;
+        }
+        Commands::Convert { input, output, verbose } => {
+            convert_archive(&input, &output, verbose)?;
+        }
+        Commands::Migrate { archives, check, verbose } => {
+            migrate_archives(&archives, check, verbose)?;
+        }
+        Commands::List { input, format, tree, print0, verbose } => {
+            list_archive(input, format, tree, print0, verbose)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Output format for listing commands
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable text
+    Text,
+    /// A JSON array of records
+    Json,
+}
+
+/// Include/exclude glob filters on archive paths
+struct PathFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+impl PathFilter {
+    fn allows(&self, name: &str) -> bool {
+        glob::is_selected(&self.include, &self.exclude, name)
+    }
+}
+
+/// Options for reading input files and directories
+#[derive(Default)]
+struct WalkOptions {
+    gitignore: bool,
+    exclude_vcs: bool,
+    /// Record permissions and modification times
+    record_metadata: bool,
+    /// Threads reading and detecting files (0 or 1 reads on this thread)
+    jobs: usize,
+}
+
+/// Version control metadata skipped by `--exclude-vcs`
+const VCS_NAMES: &[&str] = &[
+    ".git", ".gitignore", ".gitattributes", ".gitmodules",
+    ".hg", ".hgignore", ".hgtags", ".svn", ".bzr", ".bzrignore", "CVS", ".cvsignore",
+];
+
+fn create_archive(
+    inputs: Vec<PathBuf>,
+    listed: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    compress: Option<Compression>,
+    filter: &PathFilter,
+    walk: &WalkOptions,
+    verbose: bool,
+) -> Result<()> {
+    let mut archive = Archive::new();
+    let mut files = collect_inputs(&inputs, walk, false)?;
+    files.extend(collect_listed(&listed, walk)?);
+
+    for file in files {
+        if !filter.allows(&file.name) {
+            continue;
+        }
+        if verbose {
+            status!("Added: {} ({} bytes)", file.name, file.data.len());
+        }
+        archive.add_file(file)?;
+    }
+
+    let encoder = Encoder::new().with_jobs(walk.jobs);
+    let txtar_content = encoder.encode(&archive)?;
+
+    let bytes = match compress {
+        Some(compression) => compression.compress(txtar_content.as_bytes())?,
+        None => txtar_content.into_bytes(),
+    };
 
-    match cli.command {
-        Commands::Create { inputs, output, verbose } => {
-            create_archive(inputs, output, verbose)?;
+    if let Some(mut output_path) = output {
+        if let Some(compression) = compress {
+            if Compression::from_path(&output_path) != Some(compression) {
+                let mut name = output_path.clone().into_os_string();
+                name.push(".");
+                name.push(compression.extension());
+                output_path = PathBuf::from(name);
+            }
         }
-        Commands::Extract { input, directory, include_snippets, verbose } => {
-            extract_archive(input, directory, include_snippets, verbose)?;
+        fs::write(&output_path, bytes)
+            .with_context(|| format!("Failed to write: {}", output_path.display()))?;
+
+        if verbose {
+            status!("Created: {} ({} files)", output_path.display(), archive.files.len());
+        }
+    } else {
+        io::stdout().write_all(&bytes)?;
+    }
+
+    Ok(())
+}
+
+fn add_to_archive(path: &Path, inputs: &[PathBuf], verbose: bool) -> Result<()> {
+    let mut archive = read_archive(Some(path))?;
+
+    for file in collect_inputs(inputs, &WalkOptions::default(), verbose)? {
+        let existing = archive.files.iter_mut()
+            .find(|f| f.name == file.name && f.snippet_ref.is_none() && f.edit_ref.is_none());
+        match existing {
+            Some(existing) => *existing = file,
+            None => archive.add_file(file)?,
         }
-        Commands::List { input, verbose } => {
-            list_archive(input, verbose)?;
+    }
+
+    write_archive_atomic(path, &archive)?;
+
+    if verbose {
+        status!("Updated: {} ({} files)", path.display(), archive.files.len());
+    }
+
+    Ok(())
+}
+
+fn update_archive(path: &Path, inputs: &[PathBuf], verbose: bool) -> Result<()> {
+    let mut archive = read_archive(Some(path))?;
+    let (mut updated, mut added, mut unchanged) = (0, 0, 0);
+
+    for file in collect_inputs(inputs, &WalkOptions::default(), false)? {
+        let existing = archive.files.iter_mut()
+            .find(|f| f.name == file.name && f.snippet_ref.is_none() && f.edit_ref.is_none());
+        match existing {
+            Some(existing) if encode_entry(existing)? == encode_entry(&file)? => {
+                unchanged += 1;
+            }
+            Some(existing) => {
+                if verbose {
+                    status!("Updated: {}", file.name);
+                }
+                *existing = file;
+                updated += 1;
+            }
+            None => {
+                if verbose {
+                    status!("Added: {}", file.name);
+                }
+                archive.add_file(file)?;
+                added += 1;
+            }
         }
     }
 
+    if updated + added > 0 {
+        write_archive_atomic(path, &archive)?;
+    }
+
+    status!("{} updated, {} added, {} unchanged", updated, added, unchanged);
+
     Ok(())
 }
 
-fn create_archive(inputs: Vec<PathBuf>, output: Option<PathBuf>, verbose: bool) -> Result<()> {
+/// Encode a single entry, for comparing files as they would be archived
+fn encode_entry(file: &File) -> Result<String> {
     let mut archive = Archive::new();
+    archive.add_file(file.clone())?;
+    Encoder::new().encode(&archive)
+}
+
+fn remove_from_archive(path: &Path, names: &[String], verbose: bool) -> Result<()> {
+    let mut archive = read_archive(Some(path))?;
+
+    for name in names {
+        let before = archive.files.len();
+        archive.files.retain(|f| &f.name != name);
+        if archive.files.len() == before {
+            anyhow::bail!("File not found in archive: {}", name);
+        }
+
+        if verbose {
+            status!("Removed: {}", name);
+        }
+    }
 
-    for input in &inputs {
+    write_archive_atomic(path, &archive)?;
+
+    Ok(())
+}
+
+/// Read a `--files-from` list, from stdin if the path is "-"
+fn read_file_list(list: &Path, null: bool) -> Result<Vec<PathBuf>> {
+    let content = if list.as_os_str() == "-" {
+        let mut buffer = Vec::new();
+        io::stdin().read_to_end(&mut buffer)?;
+        buffer
+    } else {
+        fs::read(list).with_context(|| format!("Failed to read: {}", list.display()))?
+    };
+
+    let separator = if null { b'\0' } else { b'\n' };
+    let paths = content
+        .split(|&b| b == separator)
+        .map(|entry| String::from_utf8_lossy(entry))
+        .map(|entry| if null { entry } else { Cow::Owned(entry.trim_end_matches('\r').to_string()) })
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| PathBuf::from(entry.as_ref()))
+        .collect();
+    Ok(paths)
+}
+
+/// Read listed paths into archive entries, keeping each file's path as its name
+fn collect_listed(paths: &[PathBuf], walk: &WalkOptions) -> Result<Vec<File>> {
+    let mut files = Vec::new();
+
+    let read = parallel::map(paths, walk.jobs, |_, path| -> Result<Vec<File>> {
+        if path.is_dir() {
+            return collect_inputs(std::slice::from_ref(path), walk, false);
+        }
+        let content = fs::read(path)
+            .with_context(|| format!("Failed to read file: {}", path.display()))?;
+        let name = path.to_string_lossy().replace('\\', "/");
+        let name = name.trim_start_matches("./");
+        Ok(vec![input_file(name, content, path, walk)?])
+    });
+    for listed in read {
+        files.extend(listed?);
+    }
+
+    Ok(files)
+}
+
+/// Build an archive entry for an input file, recording metadata if requested
+fn input_file(name: &str, content: Vec<u8>, path: &Path, walk: &WalkOptions) -> Result<File> {
+    let file = File::from_disk(name, content);
+    if !walk.record_metadata {
+        return Ok(file);
+    }
+    let metadata = fs::metadata(path)
+        .with_context(|| format!("Failed to read metadata: {}", path.display()))?;
+    Ok(file.with_metadata(FileMetadata::from_fs(&metadata)))
+}
+
+/// Read files and directories into archive entries
+fn collect_inputs(inputs: &[PathBuf], walk: &WalkOptions, verbose: bool) -> Result<Vec<File>> {
+    let mut files = Vec::new();
+
+    for input in inputs {
         if input.is_dir() {
-            add_directory(&mut archive, input, verbose)?;
+            add_directory(&mut files, input, walk, verbose)?;
         } else {
             let content = fs::read(input)
                 .with_context(|| format!("Failed to read file: {}", input.display()))?;
@@ -102,54 +682,47 @@ fn create_archive(inputs: Vec<PathBuf>, output: Option<PathBuf>, verbose: bool)
                 .to_string_lossy()
                 .to_string();
 
-            archive.add_file(File::new(&name, content.clone()))?;
-
             if verbose {
-                println!("Added: {} ({} bytes)", name, content.len());
+                status!("Added: {} ({} bytes)", name, content.len());
             }
-        }
-    }
-
-    let encoder = Encoder::new();
-    let txtar_content = encoder.encode(&archive)?;
 
-    if let Some(output_path) = output {
-        fs::write(&output_path, txtar_content)
-            .with_context(|| format!("Failed to write: {}", output_path.display()))?;
-
-        if verbose {
-            println!("Created: {} ({} files)", output_path.display(), archive.files.len());
+            files.push(input_file(&name, content, input, walk)?);
         }
-    } else {
-        print!("{}", txtar_content);
     }
 
-    Ok(())
+    Ok(files)
 }
 
-fn add_directory(archive: &mut Archive, dir: &Path, verbose: bool) -> Result<()> {
+fn add_directory(files: &mut Vec<File>, dir: &Path, walk: &WalkOptions, verbose: bool) -> Result<()> {
     #[cfg(feature = "walkdir")]
     {
+        let mut ignore_cache = HashMap::new();
         let entries = walkdir::WalkDir::new(dir)
             .into_iter()
+            .filter_entry(|e| !is_skipped(dir, e, walk, &mut ignore_cache))
             .filter_map(|e| e.ok())
             .filter(|e| e.file_type().is_file())
             .collect::<Vec<_>>();
 
-        for entry in entries {
+        // Reading and binary detection run in parallel; entries keep walk order
+        let read = parallel::map(&entries, walk.jobs, |_, entry| -> Result<File> {
             let path = entry.path();
-            let content = fs::read(&path)
+            let content = fs::read(path)
                 .with_context(|| format!("Failed to read: {}", path.display()))?;
 
             let relative_path = path.strip_prefix(dir)
                 .map_err(|_| anyhow::anyhow!("Failed to get relative path"))?;
 
             let name = relative_path.to_string_lossy().replace('\\', "/");
-            archive.add_file(File::new(&name, content.clone()))?;
+            input_file(&name, content, path, walk)
+        });
 
+        for file in read {
+            let file = file?;
             if verbose {
-                println!("Added: {} ({} bytes)", name, content.len());
+                status!("Added: {} ({} bytes)", file.name, file.data.len());
             }
+            files.push(file);
         }
     }
 
@@ -161,68 +734,522 @@ fn add_directory(archive: &mut Archive, dir: &Path, verbose: bool) -> Result<()>
     Ok(())
 }
 
+/// Check whether a directory entry is excluded by the walk options
+#[cfg(feature = "walkdir")]
+fn is_skipped(
+    root: &Path,
+    entry: &walkdir::DirEntry,
+    walk: &WalkOptions,
+    ignore_cache: &mut HashMap<PathBuf, glob::IgnoreRules>,
+) -> bool {
+    if entry.depth() == 0 {
+        return false;
+    }
+
+    let name = entry.file_name().to_string_lossy();
+    let is_dir = entry.file_type().is_dir();
+    if walk.exclude_vcs && VCS_NAMES.contains(&name.as_ref()) {
+        return true;
+    }
+    if !walk.gitignore {
+        return false;
+    }
+    if is_dir && name == ".git" {
+        return true;
+    }
+
+    // Rules in deeper directories override those closer to the root
+    let mut ignored = false;
+    let mut ancestors: Vec<&Path> = entry.path().ancestors().skip(1)
+        .take_while(|p| p.starts_with(root))
+        .collect();
+    ancestors.reverse();
+    for ancestor in ancestors {
+        let rules = ignore_cache.entry(ancestor.to_path_buf()).or_insert_with(|| {
+            let mut rules = glob::IgnoreRules::default();
+            for ignore_file in [".gitignore", ".ignore"] {
+                if let Ok(content) = fs::read_to_string(ancestor.join(ignore_file)) {
+                    rules.extend(&content);
+                }
+            }
+            rules
+        });
+
+        let Ok(relative) = entry.path().strip_prefix(ancestor) else { continue };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        if let Some(matched) = rules.matched(&relative, is_dir) {
+            ignored = matched;
+        }
+    }
+
+    ignored
+}
+
+/// Options for the extract command
+struct ExtractSettings {
+    include_snippets: bool,
+    filter: PathFilter,
+    rewrite: PathRewrite,
+    utf8: bool,
+    to_stdout: bool,
+    overwrite: OverwritePolicy,
+    dry_run: bool,
+    windows_names: WindowsNamePolicy,
+    preserve_permissions: bool,
+    preserve_mtimes: bool,
+}
+
+/// What to do when an extracted file already exists
+enum OverwritePolicy {
+    /// Replace the existing file
+    Always,
+    /// Keep the existing file
+    Never,
+    /// Keep the existing file if it was modified after this time
+    KeepNewer(SystemTime),
+}
+
+impl OverwritePolicy {
+    /// Check whether the file at `path` may be written
+    fn allows(&self, path: &Path) -> Result<bool> {
+        let Ok(metadata) = fs::metadata(path) else {
+            return Ok(true);
+        };
+        Ok(match self {
+            OverwritePolicy::Always => true,
+            OverwritePolicy::Never => false,
+            OverwritePolicy::KeepNewer(archive_time) => metadata.modified()? <= *archive_time,
+        })
+    }
+}
+
+/// Modification time of the archive, or now when it is read from stdin
+fn archive_mtime(input: Option<&Path>) -> Result<SystemTime> {
+    match input {
+        Some(path) => fs::metadata(path)
+            .and_then(|m| m.modified())
+            .with_context(|| format!("Failed to read modification time: {}", path.display())),
+        None => Ok(SystemTime::now()),
+    }
+}
+
+/// Rewriting of archive paths before extraction
+struct PathRewrite {
+    strip_components: usize,
+    transforms: Vec<Transform>,
+}
+
+impl PathRewrite {
+    /// Rewrite a path, or return `None` if nothing is left of it
+    fn apply(&self, name: &str) -> Option<String> {
+        let components: Vec<&str> = name.split('/').collect();
+        if components.len() <= self.strip_components {
+            return None;
+        }
+
+        let mut name = components[self.strip_components..].join("/");
+        for transform in &self.transforms {
+            name = transform.apply(&name);
+        }
+        let name = name.trim_start_matches('/');
+        (!name.is_empty()).then(|| name.to_string())
+    }
+}
+
+/// A sed-style `s/pattern/replacement/flags` path substitution
+///
+/// The pattern is literal text, optionally anchored with `^` and `$`;
+/// the `g` flag replaces every occurrence.
+struct Transform {
+    pattern: String,
+    replacement: String,
+    anchor_start: bool,
+    anchor_end: bool,
+    global: bool,
+}
+
+impl Transform {
+    fn parse(expr: &str) -> Result<Self> {
+        let invalid = || anyhow::anyhow!("Invalid transform expression: {}", expr);
+
+        let rest = expr.strip_prefix('s').ok_or_else(invalid)?;
+        let delimiter = rest.chars().next().ok_or_else(invalid)?;
+        let parts: Vec<&str> = rest[delimiter.len_utf8()..].split(delimiter).collect();
+        let [pattern, replacement, flags] = parts[..] else {
+            return Err(invalid());
+        };
+        if flags.chars().any(|c| c != 'g') {
+            return Err(invalid());
+        }
+
+        let (anchor_start, pattern) = match pattern.strip_prefix('^') {
+            Some(p) => (true, p),
+            None => (false, pattern),
+        };
+        let (anchor_end, pattern) = match pattern.strip_suffix('$') {
+            Some(p) => (true, p),
+            None => (false, pattern),
+        };
+
+        Ok(Self {
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            anchor_start,
+            anchor_end,
+            global: flags.contains('g'),
+        })
+    }
+
+    fn apply(&self, name: &str) -> String {
+        match (self.anchor_start, self.anchor_end) {
+            (true, true) if name == self.pattern => self.replacement.clone(),
+            (true, false) if name.starts_with(&self.pattern) => {
+                format!("{}{}", self.replacement, &name[self.pattern.len()..])
+            }
+            (false, true) if name.ends_with(&self.pattern) => {
+                format!("{}{}", &name[..name.len() - self.pattern.len()], self.replacement)
+            }
+            (false, false) if !self.pattern.is_empty() => {
+                if self.global {
+                    name.replace(&self.pattern, &self.replacement)
+                } else {
+                    name.replacen(&self.pattern, &self.replacement, 1)
+                }
+            }
+            _ => name.to_string(),
+        }
+    }
+}
+
 fn extract_archive(
     input: Option<PathBuf>,
     directory: PathBuf,
-    include_snippets: bool,
+    settings: &ExtractSettings,
     verbose: bool,
 ) -> Result<()> {
-    let txtar_content = if let Some(input_path) = input {
-        fs::read_to_string(&input_path)
-            .with_context(|| format!("Failed to read: {}", input_path.display()))?
-    } else {
-        let mut buffer = String::new();
-        io::stdin().read_to_string(&mut buffer)?;
-        buffer
-    };
-
-    let decoder = Decoder::new();
-    let archive = decoder.decode(&txtar_content)?;
+    let mut archive = read_archive(input.as_deref())?;
+    // Conditional variants are chosen for the running platform
+    archive.resolve(&Conditions::host())?;
 
+    // Keep stdout clean for file contents
+    let verbose = verbose && !settings.to_stdout;
     if verbose {
-        println!("Files: {}", archive.files.len());
+        status!("Files: {}", archive.files.len());
     }
 
+    let mut stdout = io::stdout().lock();
+
     for file in &archive.files {
-        if file.snippet_ref.is_some() && !include_snippets {
+        if !settings.filter.allows(&file.name) {
+            continue;
+        }
+
+        if file.snippet_ref.is_some() && !settings.include_snippets {
             if verbose {
-                println!("Skipped snippet: {}", file.name);
+                status!("Skipped snippet: {}", file.name);
+            }
+            continue;
+        }
+
+        let Some(name) = settings.rewrite.apply(&file.name) else {
+            continue;
+        };
+
+        let data = if settings.utf8 { file.utf8_content() } else { file.content() };
+        if settings.to_stdout {
+            if settings.dry_run {
+                println!("Would write: {} ({} bytes)", name, data.len());
+            } else {
+                stdout.write_all(&data)?;
             }
             continue;
         }
 
-        let output_path = directory.join(&file.name);
+        let name = settings.windows_names.apply(&name)?;
+        let output_path = extract_path(&directory, &name)?;
 
-        if let Some(parent) = output_path.parent() {
-            fs::create_dir_all(parent)?;
+        if !settings.overwrite.allows(&output_path)? {
+            if verbose || settings.dry_run {
+                status!("Skipped existing: {}", name);
+            }
+            continue;
         }
 
-        fs::write(&output_path, &file.data)?;
+        if settings.dry_run {
+            println!("Would extract: {} ({} bytes)", name, data.len());
+            continue;
+        }
+
+        prepare_extract_path(&directory, &output_path)?;
+        fs::write(&output_path, data)?;
+        file.metadata.apply(&output_path, settings.preserve_permissions, settings.preserve_mtimes)?;
 
         if verbose {
-            println!("Extracted: {}", file.name);
+            status!("Extracted: {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+fn cat_file(archive: PathBuf, name: &str, utf8: bool) -> Result<()> {
+    let input = if archive.as_os_str() == "-" { None } else { Some(archive.as_path()) };
+    let archive = read_archive(input)?;
+
+    let file = archive.get_file(name)
+        .ok_or_else(|| anyhow::anyhow!("File not found in archive: {}", name))?;
+    let data = if utf8 { file.utf8_content() } else { file.content() };
+
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(&data)?;
+    stdout.flush()?;
+
+    Ok(())
+}
+
+fn edit_file(path: &Path, name: &str) -> Result<()> {
+    let mut archive = read_archive(Some(path))?;
+    let index = archive.files.iter()
+        .position(|f| f.name == name && f.snippet_ref.is_none() && f.edit_ref.is_none())
+        .ok_or_else(|| anyhow::anyhow!("File not found in archive: {}", name))?;
+
+    // Keep the base name so editors can pick up the file type
+    let base_name = name.rsplit('/').next().unwrap_or(name);
+    let temp_path = std::env::temp_dir().join(format!("emx-txtar-{}-{}", std::process::id(), base_name));
+    fs::write(&temp_path, &archive.files[index].data)
+        .with_context(|| format!("Failed to write: {}", temp_path.display()))?;
+
+    let edited = run_editor(&temp_path).and_then(|()| {
+        fs::read(&temp_path).with_context(|| format!("Failed to read: {}", temp_path.display()))
+    });
+    let _ = fs::remove_file(&temp_path);
+    let edited = edited?;
+
+    // Re-run detection on the new content
+    let edited = File::new(name, edited);
+    if encode_entry(&edited)? == encode_entry(&archive.files[index])? {
+        status!("No changes: {}", name);
+        return Ok(());
+    }
+
+    archive.files[index] = edited;
+    write_archive_atomic(path, &archive)?;
+    status!("Updated: {}", name);
+
+    Ok(())
+}
+
+/// Apply the `[.edit]` entries of an archive to the files they target in it,
+/// or print what they would change
+fn apply_edit_entries(path: &Path, dry_run: bool) -> Result<()> {
+    let mut archive = read_archive(Some(path))?;
+
+    if dry_run {
+        // Contents as edited so far, so later edits of a file preview on
+        // top of earlier ones
+        let mut edited: BTreeMap<&str, String> = BTreeMap::new();
+        let mut stdout = io::stdout().lock();
+        for file in &archive.files {
+            let Some(edit) = &file.edit_ref else { continue };
+            for (name, run) in edit.split_by_file(&file.name) {
+                let content = match edited.remove(name) {
+                    Some(content) => content,
+                    None => {
+                        let target = archive.get_file(name)
+                            .ok_or_else(|| anyhow::anyhow!("Edit target not found in archive: {}", name))?;
+                        String::from_utf8(target.data.to_vec())
+                            .with_context(|| format!("Edit target is not UTF-8 text: {}", name))?
+                    }
+                };
+                let preview = run.preview(&content)
+                    .with_context(|| format!("Failed to apply edit to {}", name))
+                    .context(Failure::Validation)?;
+                if !preview.is_empty() {
+                    write!(stdout, "--- a/{}\n+++ b/{}\n{}", name, name, preview)?;
+                }
+                edited.insert(name, run.apply(&content)?);
+            }
         }
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    let report = archive.apply_edits().context(Failure::Validation)?;
+    if report.edits == 0 {
+        status!("No edits: {}", path.display());
+        return Ok(());
+    }
+    write_archive_atomic(path, &archive)?;
+    for name in &report.files {
+        status!("Edited: {}", name);
     }
 
     Ok(())
 }
 
-fn list_archive(input: Option<PathBuf>, verbose: bool) -> Result<()> {
-    let txtar_content = if let Some(input_path) = input {
-        fs::read_to_string(&input_path)?
+/// Open a file in the user's editor and wait for it to exit
+fn run_editor(path: &Path) -> Result<()> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().ok_or_else(|| anyhow::anyhow!("Empty editor command"))?;
+
+    let status = std::process::Command::new(program)
+        .args(parts)
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to run editor: {}", editor))?;
+    if !status.success() {
+        anyhow::bail!("Editor exited with {}; archive left unchanged", status);
+    }
+
+    Ok(())
+}
+
+/// Archive formats understood by `convert`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveFormat {
+    Txtar,
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    fn from_path(path: &Path) -> Result<Self> {
+        let ext = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+        match ext.as_deref() {
+            Some("tar") => Ok(ArchiveFormat::Tar),
+            Some("zip") => Ok(ArchiveFormat::Zip),
+            Some("txtar") | Some("txt") => Ok(ArchiveFormat::Txtar),
+            _ => anyhow::bail!("Cannot tell archive format from extension: {}", path.display()),
+        }
+    }
+}
+
+fn convert_archive(input: &Path, output: &Path, verbose: bool) -> Result<()> {
+    let format = ArchiveFormat::from_path(input)?;
+    let archive = if format == ArchiveFormat::Txtar {
+        read_archive(Some(input))?
     } else {
-        let mut buffer = String::new();
-        io::stdin().read_to_string(&mut buffer)?;
-        buffer
+        let reader = io::BufReader::new(
+            fs::File::open(input).with_context(|| format!("Failed to read: {}", input.display()))?,
+        );
+        read_foreign(format, reader)?
     };
 
-    let decoder = Decoder::new();
-    let archive = decoder.decode(&txtar_content)?;
+    let format = ArchiveFormat::from_path(output)?;
+    if format == ArchiveFormat::Txtar {
+        let txtar_content = Encoder::new().encode(&archive)?;
+        fs::write(output, txtar_content)
+            .with_context(|| format!("Failed to write: {}", output.display()))?;
+    } else {
+        let writer = io::BufWriter::new(
+            fs::File::create(output).with_context(|| format!("Failed to write: {}", output.display()))?,
+        );
+        write_foreign(format, &archive, writer)?;
+    }
+
+    if verbose {
+        status!("Converted: {} -> {} ({} files)", input.display(), output.display(), archive.files.len());
+    }
+
+    Ok(())
+}
+
+fn migrate_archives(paths: &[PathBuf], check: bool, verbose: bool) -> Result<()> {
+    let mut outdated = Vec::new();
+    for path in paths {
+        let text = read_archive_text(Some(path))?;
+        let migration = version::migrate(&text)
+            .with_context(|| format!("Failed to migrate: {}", path.display()))
+            .context(Failure::Parse)?;
+        if migration.text == text {
+            if verbose {
+                status!("Up to date: {}", path.display());
+            }
+            continue;
+        }
+        if check {
+            status!("Needs migration from v{}: {}", migration.from, path.display());
+            outdated.push(path.display().to_string());
+            continue;
+        }
+        write_text_atomic(path, migration.text)?;
+        if verbose {
+            status!("Migrated: {} (v{} -> v{})", path.display(), migration.from, version::FORMAT_VERSION);
+        }
+    }
+    if !outdated.is_empty() {
+        return Err(anyhow::anyhow!("{} archive(s) need migration", outdated.len()).context(Failure::Validation));
+    }
+    Ok(())
+}
+
+/// Read a tar or zip archive
+fn read_foreign<R: Read>(format: ArchiveFormat, reader: R) -> Result<Archive> {
+    match format {
+        #[cfg(feature = "tar")]
+        ArchiveFormat::Tar => emx_txtar::interop::tar::read(reader).context(Failure::Parse),
+        #[cfg(feature = "zip")]
+        ArchiveFormat::Zip => emx_txtar::interop::zip::read(reader).context(Failure::Parse),
+        _ => {
+            let _ = reader;
+            anyhow::bail!("Support for {:?} archives is not enabled in this build", format)
+        }
+    }
+}
+
+/// Write a tar or zip archive
+fn write_foreign<W: Write>(format: ArchiveFormat, archive: &Archive, writer: W) -> Result<()> {
+    match format {
+        #[cfg(feature = "tar")]
+        ArchiveFormat::Tar => emx_txtar::interop::tar::write(archive, writer),
+        #[cfg(feature = "zip")]
+        ArchiveFormat::Zip => emx_txtar::interop::zip::write(archive, writer),
+        _ => {
+            let _ = (archive, writer);
+            anyhow::bail!("Support for {:?} archives is not enabled in this build", format)
+        }
+    }
+}
+
+fn list_archive(input: Option<PathBuf>, format: OutputFormat, tree: bool, print0: bool, verbose: bool) -> Result<()> {
+    let archive = read_archive(input.as_deref())?;
+
+    if tree {
+        let mut root = TreeNode::default();
+        for file in &archive.files {
+            root.insert(&file.name, file.data.len());
+        }
+        println!(". ({})", root.summary());
+        root.render("");
+        return Ok(());
+    }
+
+    if format == OutputFormat::Json {
+        let records: Vec<String> = archive.files.iter().map(json_record).collect();
+        println!("[{}]", records.join(",\n "));
+        return Ok(());
+    }
+
+    if print0 {
+        let mut stdout = io::stdout().lock();
+        for file in &archive.files {
+            stdout.write_all(file.name.as_bytes())?;
+            stdout.write_all(b"\0")?;
+        }
+        stdout.flush()?;
+        return Ok(());
+    }
 
     for file in &archive.files {
         if verbose {
             let enc = if file.is_binary { "binary" } else { "text" };
-            println!("{}  {}  {}", file.name, enc, file.data.len());
+            match file.mime() {
+                Some(mime) => println!("{}  {}  {}  {}", file.name, enc, file.data.len(), mime),
+                None => println!("{}  {}  {}", file.name, enc, file.data.len()),
+            }
         } else {
             println!("{}", file.name);
         }
@@ -230,3 +1257,190 @@ fn list_archive(input: Option<PathBuf>, verbose: bool) -> Result<()> {
 
     Ok(())
 }
+
+/// A directory in the `--tree` listing
+#[derive(Default)]
+struct TreeNode {
+    dirs: BTreeMap<String, TreeNode>,
+    files: Vec<(String, usize)>,
+    /// Total bytes of all files below this directory
+    size: usize,
+    /// Number of files below this directory
+    count: usize,
+}
+
+impl TreeNode {
+    fn insert(&mut self, path: &str, size: usize) {
+        self.size += size;
+        self.count += 1;
+        match path.split_once('/') {
+            Some((dir, rest)) => self.dirs.entry(dir.to_string()).or_default().insert(rest, size),
+            None => self.files.push((path.to_string(), size)),
+        }
+    }
+
+    fn summary(&self) -> String {
+        let noun = if self.count == 1 { "file" } else { "files" };
+        format!("{} {}, {}", self.count, noun, byte_count(self.size))
+    }
+
+    fn render(&self, indent: &str) {
+        let total = self.dirs.len() + self.files.len();
+        let mut index = 0;
+        let mut branch = || {
+            index += 1;
+            if index == total { ("└── ", "    ") } else { ("├── ", "│   ") }
+        };
+
+        for (name, dir) in &self.dirs {
+            let (branch, child_indent) = branch();
+            println!("{}{}{}/ ({})", indent, branch, name, dir.summary());
+            dir.render(&format!("{}{}", indent, child_indent));
+        }
+        for (name, size) in &self.files {
+            let (branch, _) = branch();
+            println!("{}{}{} ({})", indent, branch, name, byte_count(*size));
+        }
+    }
+}
+
+/// `size` with a singular or plural unit, e.g. `1 byte` or `12 bytes`
+fn byte_count(size: usize) -> String {
+    format!("{} {}", size, if size == 1 { "byte" } else { "bytes" })
+}
+
+/// Classify an entry as text, binary, snippet or edit
+fn entry_kind(file: &File) -> &'static str {
+    if file.edit_ref.is_some() {
+        "edit"
+    } else if file.snippet_ref.is_some() {
+        "snippet"
+    } else if file.is_binary {
+        "binary"
+    } else {
+        "text"
+    }
+}
+
+/// Render an entry as a JSON object with its name, size, kind, header tags and checksum
+fn json_record(file: &File) -> String {
+    let header = file.archive_name();
+    let tags: Vec<String> = header[file.name.len()..]
+        .split_inclusive(']')
+        .map(json_string)
+        .collect();
+    format!(
+        "{{\"name\":{},\"size\":{},\"kind\":\"{}\",\"tags\":[{}],\"crc32\":\"{:08x}\"}}",
+        json_string(&file.name),
+        file.data.len(),
+        entry_kind(file),
+        tags.join(","),
+        crc32(&file.data),
+    )
+}
+
+/// Quote and escape a string as a JSON string literal
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Parse a `--compress` format name
+fn parse_windows_names(name: &str) -> Result<WindowsNamePolicy, String> {
+    WindowsNamePolicy::from_name(name).ok_or_else(|| format!("unknown policy '{}' (expected allow, reject or sanitize)", name))
+}
+
+fn parse_compression(name: &str) -> Result<Compression, String> {
+    Compression::from_name(name).ok_or_else(|| format!("unknown compression format '{}' (expected gzip or zstd)", name))
+}
+
+/// Read and decode an archive from a file, or from stdin if no path is given.
+/// gzip/zstd input is decompressed transparently, detected by magic bytes or extension.
+fn read_archive(input: Option<&Path>) -> Result<Archive> {
+    let txtar_content = read_archive_text(input)?;
+
+    // The decoder reports unescaped marker patterns in names and unresolved
+    // snippets, or rejects the former under --strict
+    let diagnostics = Arc::new(Collector::default());
+    let decoder = Decoder::new()
+        .with_strict(STRICT.load(Ordering::Relaxed))
+        .with_diagnostics(diagnostics.clone());
+    let archive = decoder.decode(&txtar_content).context(Failure::Parse)?;
+    check_archive(diagnostics.take())?;
+    Ok(archive)
+}
+
+/// Read the text of an archive, decompressed as for `read_archive`
+fn read_archive_text(input: Option<&Path>) -> Result<String> {
+    let mut bytes = if let Some(input_path) = input {
+        fs::read(input_path)
+            .with_context(|| format!("Failed to read: {}", input_path.display()))?
+    } else {
+        let mut buffer = Vec::new();
+        io::stdin().read_to_end(&mut buffer)?;
+        buffer
+    };
+
+    let compression = Compression::detect(&bytes).or_else(|| input.and_then(Compression::from_path));
+    if let Some(compression) = compression {
+        bytes = compression.decompress(&bytes).context(Failure::Parse)?;
+    }
+    String::from_utf8(bytes)
+        .context("Archive is not valid UTF-8")
+        .context(Failure::Parse)
+}
+
+/// Report archive warnings, failing on them under `--strict`
+fn check_archive(diagnostics: Vec<Diagnostic>) -> Result<()> {
+    let warnings: Vec<String> = diagnostics.iter().map(ToString::to_string).collect();
+    if STRICT.load(Ordering::Relaxed) && !warnings.is_empty() {
+        return Err(anyhow::anyhow!(warnings.join("; ")).context(Failure::Validation));
+    }
+    if !QUIET.load(Ordering::Relaxed) {
+        for warning in &warnings {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+    Ok(())
+}
+
+/// Encode an archive and replace the file at `path` via a temporary sibling
+fn write_archive_atomic(path: &Path, archive: &Archive) -> Result<()> {
+    let encoder = Encoder::new();
+    write_text_atomic(path, encoder.encode(archive)?)
+}
+
+/// Replace the file at `path` with archive text via a temporary sibling,
+/// compressed as its extension asks
+fn write_text_atomic(path: &Path, txtar_content: String) -> Result<()> {
+    let mut tmp_name = path.file_name()
+        .ok_or_else(|| anyhow::anyhow!("Invalid archive path: {}", path.display()))?
+        .to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let bytes = match Compression::from_path(path) {
+        Some(compression) => compression.compress(txtar_content.as_bytes())?,
+        None => txtar_content.into_bytes(),
+    };
+
+    fs::write(&tmp_path, bytes)
+        .with_context(|| format!("Failed to write: {}", tmp_path.display()))?;
+    fs::rename(&tmp_path, path)
+        .with_context(|| format!("Failed to replace: {}", path.display()))?;
+
+    Ok(())
+}
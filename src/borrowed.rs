@@ -0,0 +1,199 @@
+//! Archives borrowing their contents from the decoded text
+//!
+//! [`Decoder::parse_borrowed`](crate::Decoder::parse_borrowed) decodes an
+//! archive without copying the contents of plain text entries: the
+//! [`FileRef`]s of the resulting [`ArchiveRef`] point into the input string,
+//! so inspecting a large archive costs little more memory than its text.
+//! Entries whose contents differ from their section of the text (base64,
+//! marker escapes, patches, other encodings, CRLF line endings, edits,
+//! custom tags and includes) are decoded into owned copies as usual.
+//!
+//! ```
+//! use emx_txtar::Decoder;
+//!
+//! let input = "Fixtures\n-- a.txt --\nhello\n-- b.bin[.base64] --\nAAE=\n";
+//! let archive = Decoder::new().parse_borrowed(input).unwrap();
+//! let a = archive.get_file("a.txt").unwrap();
+//! assert_eq!(a.borrowed(), Some("hello"));
+//! assert!(core::ptr::eq(a.data().as_ptr(), input[21..].as_ptr()));
+//! assert_eq!(archive.get_file("b.bin").unwrap().data(), [0, 1]);
+//! assert_eq!(archive.into_archive(), Decoder::new().decode(input).unwrap());
+//! ```
+
+use crate::archive::{Archive, EditRef, File, FileMetadata, SnippetRef, TextEncoding};
+use alloc::vec::Vec;
+
+/// Archive whose plain text contents are slices of the text it was decoded
+/// from
+#[derive(Debug, Clone)]
+pub struct ArchiveRef<'a> {
+    /// The archive, with empty contents for the borrowed entries
+    archive: Archive,
+    /// Borrowed contents, by file index
+    contents: Vec<Option<&'a str>>,
+}
+
+impl<'a> ArchiveRef<'a> {
+    pub(crate) fn new(archive: Archive, contents: Vec<Option<&'a str>>) -> Self {
+        Self { archive, contents }
+    }
+
+    /// Text before the first file
+    pub fn comment(&self) -> &str {
+        &self.archive.comment
+    }
+
+    /// Number of files
+    pub fn len(&self) -> usize {
+        self.archive.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.archive.files.is_empty()
+    }
+
+    /// The files, in order
+    pub fn files(&self) -> impl ExactSizeIterator<Item = FileRef<'_, 'a>> + '_ {
+        self.archive.files.iter().zip(&self.contents).map(|(file, &content)| FileRef { file, content })
+    }
+
+    /// Look up a normal file (non-snippet, non-edit) by name
+    pub fn get_file(&self, name: &str) -> Option<FileRef<'_, 'a>> {
+        self.files().find(|f| f.name() == name && f.snippet_ref().is_none() && f.edit_ref().is_none())
+    }
+
+    /// Copy the borrowed contents into an owned [`Archive`]
+    pub fn into_archive(self) -> Archive {
+        let mut archive = self.archive;
+        for (file, content) in archive.files.iter_mut().zip(self.contents) {
+            if let Some(content) = content {
+                file.data = content.as_bytes().to_vec().into();
+            }
+        }
+        archive
+    }
+}
+
+/// File of an [`ArchiveRef`]
+#[derive(Debug, Clone, Copy)]
+pub struct FileRef<'r, 'a> {
+    file: &'r File,
+    content: Option<&'a str>,
+}
+
+impl<'r, 'a: 'r> FileRef<'r, 'a> {
+    /// Name of the file (may include subdirectories)
+    pub fn name(&self) -> &'r str {
+        &self.file.name
+    }
+
+    /// Contents of the file
+    pub fn data(&self) -> &'r [u8] {
+        self.content.map_or(self.file.data.as_ref(), str::as_bytes)
+    }
+
+    /// Contents as text, if they are UTF-8
+    pub fn text(&self) -> Option<&'r str> {
+        self.content.or_else(|| core::str::from_utf8(self.data()).ok())
+    }
+
+    /// The contents as a slice of the input, `None` if they were decoded
+    /// into a copy
+    pub fn borrowed(&self) -> Option<&'a str> {
+        self.content
+    }
+
+    pub fn is_binary(&self) -> bool {
+        self.file.is_binary
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.file.encoding
+    }
+
+    pub fn metadata(&self) -> &'r FileMetadata {
+        &self.file.metadata
+    }
+
+    pub fn snippet_ref(&self) -> Option<&'r SnippetRef> {
+        self.file.snippet_ref.as_ref()
+    }
+
+    pub fn edit_ref(&self) -> Option<&'r EditRef> {
+        self.file.edit_ref.as_ref()
+    }
+
+    /// Owned copy of the file
+    pub fn to_file(&self) -> File {
+        let mut file = self.file.clone();
+        if let Some(content) = self.content {
+            file.data = content.as_bytes().to_vec().into();
+        }
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Decoder;
+
+    fn within(outer: &str, inner: &[u8]) -> bool {
+        let range = outer.as_bytes().as_ptr_range();
+        range.contains(&inner.as_ptr()) || (inner.is_empty() && inner.as_ptr() == range.end)
+    }
+
+    #[test]
+    fn test_parse_borrowed_matches_decode() {
+        let inputs = [
+            "",
+            "Only a comment\n",
+            "# Fixtures\n\n-- a.txt --\none\ntwo\n-- empty --\n-- last --\nno newline",
+            "-- crlf.txt --\r\nA\r\nB\r\n-- lf.txt --\nC\n",
+            "-- cr.txt --\nA\r\r\nB\r",
+            "-- doc.md[.esc:1] --\n\\-- x --\n-- bin[.base64] --\nAAE=\n-- l1[.enc:latin1] --\ncafé\n",
+            "---\ntxtar-ext: v2\n---\n-- a.txt --\nA\n-- a.txt[.edit] --\n<<<<<<< SEARCH\nA\n=======\nB\n>>>>>>> REPLACE\n",
+        ];
+        for input in inputs {
+            let decoded = Decoder::new().decode(input).unwrap();
+            let borrowed = Decoder::new().parse_borrowed(input).unwrap();
+            assert_eq!(borrowed.len(), decoded.files.len(), "{:?}", input);
+            for (file, expected) in borrowed.files().zip(&decoded.files) {
+                assert_eq!(file.data(), expected.data.as_ref(), "{:?} in {:?}", file.name(), input);
+                assert_eq!(file.to_file(), *expected);
+            }
+            assert_eq!(borrowed.into_archive(), decoded);
+        }
+    }
+
+    #[test]
+    fn test_parse_borrowed_borrows_plain_text() {
+        let input = "-- a.txt --\none\ntwo\n-- crlf --\r\nA\r\nB\r\n-- bin[.base64] --\nAAE=\n-- doc.md[.esc:1] --\n\\-- x --\n";
+        let archive = Decoder::new().parse_borrowed(input).unwrap();
+        let a = archive.get_file("a.txt").unwrap();
+        assert_eq!(a.borrowed(), Some("one\ntwo"));
+        assert!(within(input, a.data()));
+        assert_eq!(a.text(), Some("one\ntwo"));
+        for name in ["crlf", "bin", "doc.md"] {
+            let file = archive.get_file(name).unwrap();
+            assert_eq!(file.borrowed(), None, "{}", name);
+            assert!(!within(input, file.data()), "{}", name);
+        }
+        assert_eq!(archive.get_file("crlf").unwrap().text(), Some("A\nB"));
+        assert_eq!(archive.get_file("missing").map(|f| f.name()), None);
+
+        // Positions are still recorded
+        let archive = archive.into_archive();
+        assert_eq!(archive.file_at(3).map(|(f, line)| (f.name.as_str(), line)), Some(("a.txt", 2)));
+    }
+
+    #[test]
+    fn test_parse_borrowed_errors() {
+        assert!(Decoder::new().parse_borrowed("-- a --\n-- a --\n").is_err());
+        assert!(Decoder::new().parse_borrowed("---\ntxtar-ext: v9\n---\n-- a --\n").is_err());
+        assert!(Decoder::new().parse_borrowed("-- b[.base64] --\n!!\n").is_err());
+        // Borrowed contents do not count towards the memory limit
+        let input = alloc::format!("-- a --\n{}\n", "x".repeat(1000));
+        assert!(Decoder::new().with_memory_limit(100).decode(&input).is_err());
+        assert!(Decoder::new().with_memory_limit(100).parse_borrowed(&input).is_ok());
+    }
+}
@@ -0,0 +1,189 @@
+//! Title and description read from the comment as markdown
+//!
+//! The title is the text of the first heading in the comment (`# ...` to
+//! `###### ...`). The description is the first paragraph after it, or the
+//! first paragraph of the comment if it has no heading, with its lines
+//! joined by spaces. Setting either rewrites just that part of the comment,
+//! so the change is encoded with the rest of it. A frontmatter block at the
+//! start of the comment is skipped.
+
+use crate::archive::Archive;
+use crate::frontmatter::Frontmatter;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+impl Archive {
+    /// Text of the first markdown heading in the comment
+    ///
+    /// ```
+    /// use emx_txtar::Archive;
+    ///
+    /// let archive = Archive::with_comment("# Parser fixtures\n\nInputs that\nmust round-trip.\n");
+    /// assert_eq!(archive.title(), Some("Parser fixtures"));
+    /// assert_eq!(archive.description().as_deref(), Some("Inputs that must round-trip."));
+    /// ```
+    pub fn title(&self) -> Option<&str> {
+        let (range, level) = Outline::of(&self.comment).title?;
+        Some(heading_text(&self.comment[range], level))
+    }
+
+    /// First paragraph of the comment after the title, lines joined by spaces
+    pub fn description(&self) -> Option<String> {
+        let range = Outline::of(&self.comment).description?;
+        Some(self.comment[range].lines().map(str::trim).collect::<Vec<_>>().join(" "))
+    }
+
+    /// Replace the text of the title heading, adding a `# ` heading at the
+    /// top of the comment if it has none
+    pub fn set_title(&mut self, title: &str) {
+        let outline = Outline::of(&self.comment);
+        let body = body_start(&self.comment);
+        match outline.title {
+            Some((range, level)) => self.comment.replace_range(range, &format!("{} {}", "#".repeat(level), title)),
+            None if self.comment[body..].trim().is_empty() => self.comment.replace_range(body.., &format!("# {}", title)),
+            None => self.comment.insert_str(body, &format!("# {}\n\n", title)),
+        }
+        self.parse_commands();
+    }
+
+    /// Replace the description paragraph, adding one after the title if the
+    /// comment has none
+    pub fn set_description(&mut self, description: &str) {
+        let outline = Outline::of(&self.comment);
+        match (outline.description, outline.title) {
+            (Some(range), _) => self.comment.replace_range(range, description),
+            (None, Some((title, _))) => {
+                let rest = if title.end < self.comment.len() { "\n" } else { "" };
+                self.comment.insert_str(title.end, &format!("\n\n{}{}", description, rest));
+            }
+            (None, None) => {
+                let body = body_start(&self.comment);
+                self.comment.replace_range(body.., description);
+            }
+        }
+        self.parse_commands();
+    }
+
+    /// Set the title, as [`set_title`](Self::set_title) does
+    pub fn with_title(mut self, title: &str) -> Self {
+        self.set_title(title);
+        self
+    }
+
+    /// Set the description, as [`set_description`](Self::set_description) does
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.set_description(description);
+        self
+    }
+}
+
+/// Byte ranges of the title line and description paragraph in a comment,
+/// without their final line breaks
+#[derive(Default)]
+struct Outline {
+    /// Title line and heading level
+    title: Option<(Range<usize>, usize)>,
+    description: Option<Range<usize>>,
+}
+
+impl Outline {
+    fn of(comment: &str) -> Self {
+        let mut lines = Vec::new();
+        let mut start = body_start(comment);
+        for line in comment[start..].split_inclusive('\n') {
+            let text = line.trim_end_matches(['\n', '\r']);
+            lines.push((start..start + text.len(), text));
+            start += line.len();
+        }
+
+        let mut outline = Outline::default();
+        let title_index = lines.iter().position(|(_, text)| heading_level(text).is_some());
+        if let Some(i) = title_index {
+            let (range, text) = &lines[i];
+            outline.title = Some((range.clone(), heading_level(text).unwrap_or(1)));
+        }
+
+        let mut paragraph: Option<Range<usize>> = None;
+        for (range, text) in lines.iter().skip(title_index.map_or(0, |i| i + 1)) {
+            if heading_level(text).is_some() {
+                break;
+            }
+            if text.trim().is_empty() {
+                if paragraph.is_some() {
+                    break;
+                }
+                continue;
+            }
+            paragraph = Some(paragraph.map_or(range.clone(), |p| p.start..range.end));
+        }
+        outline.description = paragraph;
+        outline
+    }
+}
+
+/// Offset of the comment after any frontmatter
+fn body_start(comment: &str) -> usize {
+    comment.len() - Frontmatter::split(comment).1.len()
+}
+
+/// Level of an ATX heading line, 1 for `#` to 6 for `######`
+fn heading_level(line: &str) -> Option<usize> {
+    let level = line.bytes().take_while(|&b| b == b'#').count();
+    let rest = &line[level..];
+    ((1..=6).contains(&level) && (rest.is_empty() || rest.starts_with([' ', '\t']))).then_some(level)
+}
+
+/// Text of a heading line without its markers or a closing `#` sequence
+fn heading_text(line: &str, level: usize) -> &str {
+    let text = line[level..].trim();
+    let unclosed = text.trim_end_matches('#');
+    if unclosed.is_empty() || unclosed.ends_with([' ', '\t']) { unclosed.trim_end() } else { text }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, Encoder};
+
+    #[test]
+    fn test_title_and_description() {
+        let archive = Archive::with_comment("Intro line\n\n## Fixtures ##\n\nFirst\nparagraph.\n\nSecond.\n");
+        assert_eq!(archive.title(), Some("Fixtures"));
+        assert_eq!(archive.description().as_deref(), Some("First paragraph."));
+
+        let archive = Archive::with_comment("No heading here,\njust text.\n\nMore.");
+        assert_eq!(archive.title(), None);
+        assert_eq!(archive.description().as_deref(), Some("No heading here, just text."));
+
+        let archive = Archive::with_comment("# C# notes\n## Next\ntext");
+        assert_eq!(archive.title(), Some("C# notes"));
+        assert_eq!(archive.description(), None);
+        assert_eq!(Archive::with_comment("#hashtag").title(), None);
+        assert_eq!(Archive::new().description(), None);
+    }
+
+    #[test]
+    fn test_set_title_and_description() {
+        let input = "## Old\n\nOld text\nspanning lines.\n\nSee [command: rg](#search1)\n-- a.txt --\nA\n";
+        let mut archive = Decoder::new().decode(input).unwrap();
+        archive.set_title("New");
+        archive.set_description("New text.");
+        assert_eq!(archive.title(), Some("New"));
+        assert_eq!(archive.description().as_deref(), Some("New text."));
+        let expected = "## New\n\nNew text.\n\nSee [command: rg](#search1)\n-- a.txt --\nA\n";
+        assert_eq!(Encoder::new().encode(&archive).unwrap(), expected);
+        assert!(archive.get_command("search1").is_some());
+
+        let archive = Archive::new().with_description("Text.").with_title("Title");
+        assert_eq!(archive.comment, "# Title\n\nText.");
+
+        let archive = Archive::with_comment("# Title\n## Section").with_description("Text.");
+        assert_eq!(archive.comment, "# Title\n\nText.\n\n## Section");
+        assert_eq!(archive.description().as_deref(), Some("Text."));
+
+        let archive = Archive::with_comment("---\nowner: qa\n---\n").with_title("Title");
+        assert_eq!(archive.comment, "---\nowner: qa\n---\n# Title");
+    }
+}
@@ -0,0 +1,197 @@
+//! Compression of whole archive files
+//!
+//! gzip, and the raw DEFLATE streams of zip entries, go through [`flate2`];
+//! Zstandard goes through [`zstd`]. Multi-member gzip streams are
+//! decompressed in full.
+
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Zstandard level used when compressing, the library's default
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression format of an archive file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// gzip (`.gz`)
+    Gzip,
+    /// Zstandard (`.zst`)
+    Zstd,
+}
+
+impl Compression {
+    /// Parse a format name such as `gzip` or `zstd`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gzip" | "gz" => Some(Compression::Gzip),
+            "zstd" | "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// File extension for the format, without the dot
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Guess the format from a path's extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "zst" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Detect the format from a stream's magic bytes
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if data.starts_with(&ZSTD_MAGIC) {
+            Some(Compression::Zstd)
+        } else {
+            None
+        }
+    }
+
+    /// Compress data in this format
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => Ok(gzip(data)),
+            Compression::Zstd => zstd::encode_all(data, ZSTD_LEVEL).context("zstd compression failed"),
+        }
+    }
+
+    /// Decompress data in this format
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        self.decompress_limited(data, usize::MAX)
+    }
+
+    /// Decompress data in this format, stopping with an error as soon as the
+    /// output exceeds `limit` bytes
+    pub fn decompress_limited(&self, data: &[u8], limit: usize) -> Result<Vec<u8>> {
+        match self {
+            Compression::Gzip => read_limited(flate2::read::MultiGzDecoder::new(data), limit, "gzip"),
+            Compression::Zstd => read_limited(zstd::Decoder::new(data)?, limit, "zstd"),
+        }
+    }
+}
+
+/// Compress data as a single-member gzip stream
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data).expect("writing to a Vec cannot fail");
+    encoder.finish().expect("writing to a Vec cannot fail")
+}
+
+/// Decompress a raw DEFLATE stream, as stored in zip entries, that should
+/// inflate to `size` bytes
+pub fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let out = read_limited(flate2::read::DeflateDecoder::new(data), size, "deflate")?;
+    if out.len() != size {
+        bail!("Deflate stream inflates to {} bytes, expected {}", out.len(), size);
+    }
+    Ok(out)
+}
+
+/// Decompress a gzip stream, concatenating all of its members
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    Compression::Gzip.decompress(data)
+}
+
+/// Read the output of a decoder for `format`, failing once it exceeds `limit` bytes
+fn read_limited<R: Read>(reader: R, limit: usize, format: &str) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    reader.take((limit as u64).saturating_add(1))
+        .read_to_end(&mut out)
+        .with_context(|| format!("Invalid {} stream", format))?;
+    if out.len() > limit {
+        bail!("Decompressed {} data exceeds {} bytes", format, limit);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let text = "-- a.txt --\nhello hello hello\n".repeat(200);
+        for data in [&b""[..], b"x", text.as_bytes(), &(0..=255u8).cycle().take(70_000).collect::<Vec<_>>()] {
+            let compressed = gzip(data);
+            assert_eq!(Compression::detect(&compressed), Some(Compression::Gzip));
+            assert_eq!(gunzip(&compressed).unwrap(), data);
+        }
+        assert!(gzip(text.as_bytes()).len() < text.len() / 10);
+    }
+
+    #[test]
+    fn test_gunzip_dynamic_blocks() {
+        // Produced by Python's gzip module, which uses dynamic Huffman blocks
+        let compressed = base64::engine::general_purpose::STANDARD
+            .decode("H4sIAAAAAAACA4XRSwqDQBBF0bmrqA2U2H9dTgglNoiCtMHlK2T0AuGN75ldVZnrakPfriaq3Vo3k0H2WdpiT7raeZi8jvdSP9bpVzvQhWgP2kXCA3DvCI/IR8IT8JAIz8CjJ7wgnwgfgadM+AQ8B3YJpxZ69Wfr3683KT+gDzQCAAA=")
+            .unwrap();
+        let expected: String = (0..12)
+            .map(|i| format!("-- file{}.txt --\nline {} of the fixture archive\n", i, i * 7))
+            .collect();
+        assert_eq!(gunzip(&compressed).unwrap(), expected.as_bytes());
+    }
+
+    #[test]
+    fn test_gunzip_rejects_corruption() {
+        let mut compressed = gzip(b"some archive text");
+        let crc_pos = compressed.len() - 8;
+        compressed[crc_pos] ^= 0xff;
+        assert_eq!(gunzip(&compressed).unwrap_err().to_string(), "Invalid gzip stream");
+    }
+
+    #[test]
+    fn test_inflate_checks_size() {
+        let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"hello hello hello").unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(inflate(&compressed, 17).unwrap(), b"hello hello hello");
+        assert!(inflate(&compressed, 5).unwrap_err().to_string().contains("exceeds 5 bytes"));
+        assert!(inflate(&compressed, 20).is_err());
+    }
+
+    #[test]
+    fn test_decompress_limited() {
+        let zeros = vec![0u8; 1 << 20];
+        for format in [Compression::Gzip, Compression::Zstd] {
+            let compressed = format.compress(&zeros).unwrap();
+            assert!(compressed.len() < 4096);
+            assert_eq!(format.decompress_limited(&compressed, 1 << 20).unwrap(), zeros);
+            let err = format.decompress_limited(&compressed, 1000).unwrap_err();
+            assert!(err.to_string().contains("exceeds 1000 bytes"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_gunzip_multiple_members() {
+        let mut compressed = gzip(b"first\n");
+        compressed.extend_from_slice(&gzip(b"second\n"));
+        assert_eq!(gunzip(&compressed).unwrap(), b"first\nsecond\n");
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        let text = "-- a.txt --\nhello hello hello\n".repeat(200);
+        for data in [&b""[..], b"x", text.as_bytes()] {
+            let compressed = Compression::Zstd.compress(data).unwrap();
+            assert_eq!(Compression::detect(&compressed), Some(Compression::Zstd));
+            assert_eq!(Compression::Zstd.decompress(&compressed).unwrap(), data);
+        }
+        assert!(Compression::Zstd.compress(text.as_bytes()).unwrap().len() < text.len() / 10);
+        assert!(Compression::Zstd.decompress(&[0x28, 0xb5, 0x2f, 0xfd, 0, 0]).is_err());
+    }
+}
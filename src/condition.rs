@@ -0,0 +1,187 @@
+//! Conditional entries
+//!
+//! An `[.if:key=value]` tag limits an entry to targets where `key` has
+//! `value`, e.g. `[.if:os=windows]` or `[.if:feature=serde]`. An entry may
+//! carry several conditions, all of which must hold. Entries with
+//! conditions are exempt from the duplicate name check, so each variant of
+//! a file can have its own entry; [`Archive::resolve`] keeps the entries
+//! whose conditions hold and drops the rest.
+//!
+//! ```
+//! use emx_txtar::{Conditions, Decoder};
+//!
+//! let input = "-- run.sh[.if:os=linux] --\n./build\n-- run.bat[.if:os=windows] --\nbuild.exe\n";
+//! let mut archive = Decoder::new().decode(input).unwrap();
+//! archive.resolve(&Conditions::new().with("os", "windows")).unwrap();
+//! assert_eq!(archive.files.len(), 1);
+//! assert_eq!(archive.files[0].name, "run.bat");
+//! ```
+
+use crate::archive::{Archive, ArchiveError};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Prefix of a condition tag, `[.if:key=value]`
+pub const CONDITION_TAG_PREFIX: &str = "[.if:";
+
+/// A requirement that a key has a value, tagged `[.if:key=value]`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Condition {
+    pub key: String,
+    pub value: String,
+}
+
+impl Condition {
+    pub fn new(key: impl Into<String>, value: impl Into<String>) -> Self {
+        Self { key: key.into(), value: value.into() }
+    }
+
+    /// Parse a `[.if:key=value]` tag; `None` if it is not a condition tag
+    /// or the key or value is empty
+    pub fn parse(tag: &str) -> Option<Self> {
+        let inner = tag.strip_prefix(CONDITION_TAG_PREFIX)?.strip_suffix(']')?;
+        let (key, value) = inner.split_once('=')?;
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            return None;
+        }
+        Some(Self::new(key, value))
+    }
+}
+
+impl core::fmt::Display for Condition {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}{}={}]", CONDITION_TAG_PREFIX, self.key, self.value)
+    }
+}
+
+/// The facts conditions are evaluated against
+///
+/// A key may have several values (e.g. enabled features); a condition holds
+/// if its key has its value. Keys that are not set hold no value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Conditions {
+    facts: Vec<(String, String)>,
+}
+
+impl Conditions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a value for `key`
+    pub fn with(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.facts.push((key.into(), value.into()));
+        self
+    }
+
+    /// The running platform: `os`, `family` and `arch` as named by
+    /// [`std::env::consts`]
+    #[cfg(feature = "std")]
+    pub fn host() -> Self {
+        use std::env::consts;
+        Self::new().with("os", consts::OS).with("family", consts::FAMILY).with("arch", consts::ARCH)
+    }
+
+    /// Whether `condition` holds
+    pub fn holds(&self, condition: &Condition) -> bool {
+        self.facts.iter().any(|(key, value)| *key == condition.key && *value == condition.value)
+    }
+}
+
+impl Archive {
+    /// Keep the entries whose conditions all hold, with their conditions
+    /// removed, and drop the others
+    ///
+    /// Fails without changing the archive if two kept normal files share a
+    /// name.
+    pub fn resolve(&mut self, conditions: &Conditions) -> Result<(), ArchiveError> {
+        let mut resolved = Archive::new();
+        for file in &self.files {
+            if file.conditions.iter().all(|c| conditions.holds(c)) {
+                let mut file = file.clone();
+                file.conditions.clear();
+                resolved.add_file(file)?;
+            }
+        }
+        self.files = resolved.files;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, Encoder, File};
+    use alloc::vec;
+
+    #[test]
+    fn test_condition_parse() {
+        assert_eq!(Condition::parse("[.if:os=windows]"), Some(Condition::new("os", "windows")));
+        assert_eq!(Condition::parse("[.if:feature=a=b]"), Some(Condition::new("feature", "a=b")));
+        assert_eq!(Condition::parse("[.if:os]"), None);
+        assert_eq!(Condition::parse("[.if:=x]"), None);
+        assert_eq!(Condition::new("os", "linux").to_string(), "[.if:os=linux]");
+
+        let err = Decoder::new().decode("-- a[.if:os] --\n").unwrap_err();
+        assert!(err.to_string().contains("Invalid condition tag '[.if:os]'"), "{}", err);
+    }
+
+    #[test]
+    fn test_resolve_conditional_entries() {
+        let input = "-- config[.if:os=linux] --\nunix\n-- config[.if:os=windows] --\nwin\n\
+                     -- extra.rs[.if:os=linux][.if:feature=extra] --\nfn extra() {}\n-- main.rs --\nfn main() {}\n";
+        let archive = Decoder::new().decode(input).unwrap();
+        assert_eq!(archive.files.len(), 4);
+        assert_eq!(archive.files[2].archive_name(), "extra.rs[.if:os=linux][.if:feature=extra]");
+        assert_eq!(Encoder::new().encode(&archive).unwrap(), input);
+
+        let mut linux = archive.clone();
+        linux.resolve(&Conditions::new().with("os", "linux").with("feature", "extra")).unwrap();
+        let names: Vec<_> = linux.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["config", "extra.rs", "main.rs"]);
+        assert_eq!(linux.files[0].data.as_ref(), b"unix");
+        assert!(linux.files.iter().all(|f| f.conditions.is_empty()));
+
+        let mut windows = archive.clone();
+        windows.resolve(&Conditions::new().with("os", "windows")).unwrap();
+        assert_eq!(windows.files.len(), 2);
+        assert_eq!(windows.get_file("config").unwrap().data.as_ref(), b"win");
+
+        let mut none = archive;
+        none.resolve(&Conditions::new()).unwrap();
+        assert_eq!(none.files.len(), 1);
+    }
+
+    #[test]
+    fn test_resolve_rejects_duplicates() {
+        let mut archive = Archive::new();
+        let mut conditional = File::new("a.txt", "variant");
+        conditional.conditions = vec![Condition::new("os", "linux")];
+        archive.add_file(conditional).unwrap();
+        archive.add_file(File::new("a.txt", "default")).unwrap();
+
+        let before = archive.clone();
+        let err = archive.resolve(&Conditions::new().with("os", "linux")).unwrap_err();
+        assert!(matches!(err, ArchiveError::DuplicateFile { name } if name == "a.txt"));
+        assert_eq!(archive, before);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_extract_requires_resolved_entries() {
+        let input = "-- a.txt[.if:os=linux] --\nL\n-- a.txt[.if:os=windows] --\nW\n";
+        let dir = tempfile::tempdir().unwrap();
+        let options = crate::ExtractOptions::default();
+        let mut archive = Decoder::new().decode(input).unwrap();
+        let err = archive.extract_to_dir(dir.path(), &options).unwrap_err();
+        assert_eq!(err.to_string(), "Unresolved conditional entry: a.txt[.if:os=linux]");
+        let err = Decoder::new().extract_reader(input.as_bytes(), dir.path(), &options).unwrap_err();
+        assert_eq!(err.to_string(), "Unresolved conditional entry: a.txt[.if:os=linux]");
+        assert!(!dir.path().join("a.txt").exists());
+
+        archive.resolve(&Conditions::new().with("os", "windows")).unwrap();
+        archive.extract_to_dir(dir.path(), &options).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "W\n");
+    }
+}
@@ -0,0 +1,162 @@
+//! Encrypted file entries
+//!
+//! A `[.crypt:xchacha20poly1305]` entry stores its contents encrypted with a
+//! 32-byte key shared by whoever needs to read them (see [`CRYPT_SCHEME`]
+//! for the format). Such entries decode and re-encode without the key;
+//! [`Archive::decrypt_with`] replaces them with their plaintext.
+//!
+//! ```
+//! use emx_txtar::crypt::Identity;
+//! use emx_txtar::{Archive, Decoder, Encoder, File};
+//!
+//! let identity = Identity::from_bytes([7; 32]);
+//! let mut archive = Archive::new();
+//! archive.add_file(File::new("token.txt", "s3cret").encrypt(&identity).unwrap()).unwrap();
+//!
+//! let encoded = Encoder::new().encode(&archive).unwrap();
+//! assert!(!encoded.contains("s3cret"));
+//!
+//! let mut decoded = Decoder::new().decode(&encoded).unwrap();
+//! decoded.decrypt_with(&identity).unwrap();
+//! assert_eq!(decoded.files[0].data.as_ref(), b"s3cret");
+//! ```
+
+use crate::archive::{Archive, File, CRYPT_SCHEME};
+use anyhow::{anyhow, bail, Result};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+const NONCE_LEN: usize = 24;
+
+/// A key that encrypts and decrypts file entries
+#[derive(Clone)]
+pub struct Identity(XChaCha20Poly1305);
+
+impl Identity {
+    /// Use a 32-byte key
+    pub fn from_bytes(key: [u8; 32]) -> Self {
+        Self(XChaCha20Poly1305::new(&key.into()))
+    }
+
+    /// A random key, returned with its bytes so it can be stored
+    pub fn generate() -> (Self, [u8; 32]) {
+        let key: [u8; 32] = XChaCha20Poly1305::generate_key(&mut OsRng).into();
+        (Self::from_bytes(key), key)
+    }
+}
+
+impl core::fmt::Debug for Identity {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str("Identity(..)")
+    }
+}
+
+impl File {
+    /// An encrypted copy of the file, stored as `[.base64][.crypt:...]`
+    ///
+    /// The name, metadata and nested archive flag stay readable; the file
+    /// name is authenticated, so the entry cannot be renamed unnoticed.
+    pub fn encrypt(&self, identity: &Identity) -> Result<File> {
+        if self.is_encrypted {
+            bail!("File '{}' is already encrypted", self.name);
+        }
+        if self.snippet_ref.is_some() || self.edit_ref.is_some() {
+            bail!("Snippet and edit entry '{}' cannot be encrypted", self.name);
+        }
+        let file = self.load()?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = identity.0.encrypt(&nonce, Payload { msg: &file.data, aad: file.name.as_bytes() })
+            .map_err(|_| anyhow!("Failed to encrypt '{}'", file.name))?;
+
+        let mut data = nonce.to_vec();
+        data.extend_from_slice(&ciphertext);
+        let mut encrypted = File::with_encoding(file.name.clone(), data, true).with_metadata(file.metadata);
+        encrypted.is_archive = file.is_archive;
+        encrypted.conditions = file.conditions.clone();
+        encrypted.custom_tags = file.custom_tags.clone();
+        encrypted.trailing_newline = file.trailing_newline;
+        encrypted.line_ending = file.line_ending;
+        encrypted.is_encrypted = true;
+        Ok(encrypted)
+    }
+
+    /// The plaintext of an encrypted file, classified as text or binary
+    /// like a new file
+    pub fn decrypt(&self, identity: &Identity) -> Result<File> {
+        if !self.is_encrypted {
+            bail!("File '{}' is not encrypted", self.name);
+        }
+        if self.data.len() < NONCE_LEN {
+            bail!("Encrypted file '{}' is too short", self.name);
+        }
+        let (nonce, ciphertext) = self.data.split_at(NONCE_LEN);
+        let plaintext = identity.0.decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: self.name.as_bytes() })
+            .map_err(|_| anyhow!("Failed to decrypt '{}' as {}: wrong key or corrupted data", self.name, CRYPT_SCHEME))?;
+
+        let mut file = File::new(self.name.clone(), plaintext).with_metadata(self.metadata);
+        file.is_archive = self.is_archive;
+        file.conditions = self.conditions.clone();
+        file.custom_tags = self.custom_tags.clone();
+        file.trailing_newline = self.trailing_newline;
+        file.line_ending = self.line_ending;
+        Ok(file)
+    }
+}
+
+impl Archive {
+    /// Replace every encrypted entry with its plaintext
+    ///
+    /// Fails without changing the archive if any entry does not decrypt.
+    pub fn decrypt_with(&mut self, identity: &Identity) -> Result<()> {
+        let decrypted = self.files.iter()
+            .map(|f| if f.is_encrypted { f.decrypt(identity).map(Some) } else { Ok(None) })
+            .collect::<Result<Vec<_>>>()?;
+        for (file, plain) in self.files.iter_mut().zip(decrypted) {
+            if let Some(plain) = plain {
+                *file = plain;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, Encoder, FileMetadata};
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let identity = Identity::from_bytes([7; 32]);
+        let secret = File::new("creds/token.txt", "hunter2\n")
+            .with_metadata(FileMetadata { mode: Some(0o600), mtime: None });
+        let mut archive = Archive::new();
+        archive.add_file(File::new("README", "public")).unwrap();
+        archive.add_file(secret.encrypt(&identity).unwrap()).unwrap();
+
+        let encoded = Encoder::new().encode(&archive).unwrap();
+        assert!(encoded.contains("-- creds/token.txt[.base64][.crypt:xchacha20poly1305][.mode:0600] --\n"));
+        assert!(!encoded.contains("hunter2"));
+
+        // Entries survive decoding and re-encoding without the key
+        let decoded = Decoder::new().decode(&encoded).unwrap();
+        assert!(decoded.files[1].is_encrypted);
+        assert_eq!(Encoder::new().encode(&decoded).unwrap(), encoded);
+
+        let mut wrong = decoded.clone();
+        assert!(wrong.decrypt_with(&Identity::generate().0).is_err());
+        assert_eq!(wrong, decoded);
+
+        let mut plain = decoded;
+        plain.decrypt_with(&identity).unwrap();
+        assert_eq!(plain.files[1], secret);
+    }
+
+    #[test]
+    fn test_encrypted_name_is_authenticated() {
+        let identity = Identity::from_bytes([7; 32]);
+        let mut file = File::new("a.txt", "x").encrypt(&identity).unwrap();
+        file.name = "b.txt".into();
+        assert!(file.decrypt(&identity).is_err());
+    }
+}
@@ -1,162 +1,675 @@
 //! Txtar archive decoder
 
-use crate::archive::{Archive, File, SnippetRef, EditRef};
-use anyhow::{anyhow, Result};
+use crate::condition::{Condition, CONDITION_TAG_PREFIX};
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use crate::include::{IncludeResolver, INCLUDE_PREFIX};
+use crate::position::EntrySpan;
+use crate::borrowed::ArchiveRef;
+use crate::frontmatter::Frontmatter;
+use crate::version;
+use crate::tags::{CustomTag, TagRegistry};
+use crate::archive::{find_tag_start, has_marker_pattern, is_unique, parse_stream_delimiter, unescape_name, Archive, BinaryReason, File, FileMetadata, LineEnding, SnippetRef, EditRef, TextEncoding};
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{anyhow, bail, Result};
 use base64::Engine;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use crate::archive::{extract_path, prepare_extract_path, ExtractOptions};
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
 
 // Re-export constants from archive module
-use crate::archive::{MARKER_PREFIX, MARKER_SUFFIX, MARKER_PREFIX_LEN, MARKER_SUFFIX_LEN, BASE64_SUFFIX};
+use crate::archive::{MARKER_PREFIX, MARKER_SUFFIX, ARCHIVE_TAG, BASE64_SUFFIX, CRYPT_SCHEME, CRYPT_TAG_PREFIX, ENCODING_TAG_PREFIX, ESCAPE_TAG_PREFIX, MARKER_ESCAPE, PATCH_TAG_PREFIX, MODE_TAG_PREFIX, MTIME_TAG_PREFIX};
 
 // Binary data constants
+#[cfg(feature = "std")]
 const BINARY_NEWLINE: u8 = b'\n';
-const BINARY_CARRIAGE_RETURN: u8 = b'\r';
+
+/// Default limit on how deeply `[.txtar]` entries may nest
+const DEFAULT_MAX_DEPTH: usize = 8;
+
+/// Header information parsed from a file marker line
+struct FileHeader {
+    name: String,
+    is_binary: bool,
+    snippet_ref: Option<SnippetRef>,
+    edit_ref: Option<EditRef>,
+    encoding: TextEncoding,
+    /// Whether the contents are a nested archive
+    is_archive: bool,
+    /// Whether the base64 contents are encrypted
+    is_encrypted: bool,
+    /// `[.if:...]` conditions limiting the entry
+    conditions: Vec<Condition>,
+    /// Tags parsed by registered handlers
+    custom_tags: Vec<CustomTag>,
+    /// 1-based numbers of lines carrying a marker escape
+    escaped_lines: Vec<usize>,
+    /// Placeholder offsets in the text and the original bytes they stand for
+    patches: Vec<(usize, Vec<u8>)>,
+    /// Recorded permissions and modification time
+    metadata: FileMetadata,
+    /// Unrecognized tags, ignored unless strict
+    unknown_tags: Vec<String>,
+    /// Whether non-standard base64 was already reported for this entry
+    base64_reported: bool,
+    /// Line break shared by the marker and content lines so far, `Preserve`
+    /// once they differ
+    line_ending: Option<LineEnding>,
+    /// 1-based line number of the marker, 0 if not known
+    line: usize,
+}
+
+impl FileHeader {
+    /// Fold the line break of a line of the section, the marker included,
+    /// into the line ending shared by them
+    fn record_line_ending(&mut self, crlf: bool) {
+        let ending = if crlf { LineEnding::CrLf } else { LineEnding::Lf };
+        self.line_ending = match self.line_ending {
+            Some(seen) if seen != ending => Some(LineEnding::Preserve),
+            _ => Some(ending),
+        };
+    }
+
+    /// Whether the contents are the text of the section as is, so they can
+    /// be borrowed from the input
+    fn is_plain_text(&self, decoder: &Decoder) -> bool {
+        !self.is_binary
+            && self.escaped_lines.is_empty()
+            && self.patches.is_empty()
+            && self.encoding == TextEncoding::Utf8
+            && self.custom_tags.is_empty()
+            && self.edit_ref.is_none()
+            && !(decoder.includes.is_some() && self.name.starts_with(INCLUDE_PREFIX))
+    }
+}
+
+/// What the decoder does with an entry named like an earlier normal file
+/// (one that is not a snippet, edit or conditional variant)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    /// Fail decoding, the default
+    #[default]
+    Error,
+    /// Keep the earlier file and drop the entry
+    KeepFirst,
+    /// Drop the earlier file and keep the entry, at the end
+    KeepLast,
+    /// Keep both, giving the entry the first free name of the form
+    /// `name-2.ext`, `name-3.ext`...
+    Rename,
+}
 
 /// Decodes a txtar archive
+#[derive(Clone)]
 pub struct Decoder {
-    /// Verbosity level for conflict detection warnings
-    verbose: u8,
+    /// Receiver of warnings about the input
+    diagnostics: Option<Arc<dyn Diagnostics>>,
+    /// Maximum bytes of comment and file content held while decoding
+    memory_limit: Option<usize>,
+    /// Reject headers with unknown tags or unescaped marker patterns
+    strict: bool,
+    /// Nesting level of the archive being decoded, 0 at top level
+    depth: usize,
+    /// Deepest nesting level [`decode_nested`](Self::decode_nested) accepts
+    max_depth: usize,
+    /// Source of archives named by `@include` entries
+    includes: Option<Arc<dyn IncludeResolver>>,
+    /// Paths of the included archives being decoded, outermost first
+    include_stack: Vec<String>,
+    /// Require the target of every edit entry to exist
+    check_edit_targets: bool,
+    /// Handlers for application-defined tags
+    tags: TagRegistry,
+    /// Read archives declaring an unsupported format version anyway
+    lenient_version: bool,
+    /// Handling of repeated file names
+    duplicates: DuplicatePolicy,
+    /// Keep the line breaks ending the comment
+    fidelity: bool,
+    /// Line breaks of the decoded text
+    line_ending: LineEnding,
 }
 
 impl Decoder {
     /// Create a new decoder
     pub fn new() -> Self {
-        Self { verbose: 0 }
+        Self {
+            diagnostics: None,
+            memory_limit: None,
+            strict: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            includes: None,
+            include_stack: Vec::new(),
+            check_edit_targets: true,
+            tags: TagRegistry::new(),
+            lenient_version: false,
+            duplicates: DuplicatePolicy::Error,
+            fidelity: false,
+            line_ending: LineEnding::Lf,
+        }
+    }
+
+    /// Report warnings (marker patterns in names, ignored tags, non-standard
+    /// base64, unresolved snippets) to `diagnostics`
+    pub fn with_diagnostics(mut self, diagnostics: Arc<dyn Diagnostics>) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
     }
 
-    /// Set verbosity level (0-3)
+    /// Print warnings to stderr when `level` is above 0 (with the `std`
+    /// feature), or stop reporting them when it is 0
+    #[deprecated(note = "use `with_diagnostics(Arc::new(diagnostics::Stderr))` instead")]
     pub fn with_verbose(mut self, level: u8) -> Self {
-        self.verbose = level;
+        #[cfg(feature = "std")]
+        if level > 0 {
+            return self.with_diagnostics(Arc::new(crate::diagnostics::Stderr));
+        }
+        let _ = level;
+        self.diagnostics = None;
         self
     }
 
-    /// Create a File from accumulated data, handling binary decoding
-    fn create_file_from_data(&self, name: String, is_binary: bool, data: Vec<u8>) -> Result<File> {
-        if is_binary {
-            // Decode base64 data
-            let base64_str = Self::filter_base64_data(&data);
-            let decoded = base64::engine::general_purpose::STANDARD
-                .decode(&base64_str)
-                .map_err(|e| anyhow!("Failed to decode base64 for file '{}': {}", name, e))?;
-            Ok(File::with_encoding(name, decoded, true))
-        } else {
-            // Remove trailing newline if present
-            let mut data = data;
-            if data.ends_with(b"\n") {
-                data.pop();
+    fn report(&self, diagnostic: Diagnostic) {
+        if let Some(diagnostics) = &self.diagnostics {
+            diagnostics.report(diagnostic);
+        }
+    }
+
+    /// Reject entries that would not round-trip: unknown tags, text after
+    /// the tags, names with an unescaped marker pattern, and base64 sections
+    /// with blank lines, whitespace, missing padding or URL-safe characters
+    ///
+    /// Otherwise unknown tags are ignored, marker patterns only reported as
+    /// diagnostics, blank lines and whitespace in base64
+    /// sections skipped, and base64 in the URL-safe alphabet, without
+    /// padding or with non-zero trailing bits accepted, as produced by other
+    /// tools or by hand.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Fail decoding once the comment and file contents held in memory
+    /// exceed `bytes`
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Replace `@include <path>` entries with the files of the archive
+    /// `resolver` returns for `<path>` (see [`include`](crate::include))
+    pub fn with_includes(mut self, resolver: Arc<dyn IncludeResolver>) -> Self {
+        self.includes = Some(resolver);
+        self
+    }
+
+    /// Whether an edit entry whose target is neither in the archive nor in
+    /// the current directory is an error, the default
+    ///
+    /// Turn this off for edits meant for another directory, such as the
+    /// patches of a [`PatchSeries`](crate::series::PatchSeries).
+    pub fn with_edit_target_check(mut self, check: bool) -> Self {
+        self.check_edit_targets = check;
+        self
+    }
+
+    /// Keep the line breaks that end the comment, so that encoding the
+    /// archive gives back the text it was decoded from
+    ///
+    /// Files record their final newline in [`File::trailing_newline`] either
+    /// way: [`File::data`] leaves it out and [`File::content`], which is
+    /// what extraction writes, puts it back. Archives in the form the
+    /// default encoder writes (tag spelling, base64 line length) then
+    /// round-trip byte for byte. Only a final comment or marker line without
+    /// a line break gains one.
+    pub fn with_fidelity(mut self, fidelity: bool) -> Self {
+        self.fidelity = fidelity;
+        self
+    }
+
+    /// Line breaks of decoded text contents and comment: `Lf` (the default)
+    /// turns `\r\n` into `\n`, `CrLf` turns every line break into `\r\n`,
+    /// and `Preserve` keeps each as it is in the archive
+    ///
+    /// Whatever the setting, text whose section lines, marker included, all
+    /// end with `\r\n` in the archive is decoded with [`File::line_ending`] set to `CrLf`, so an
+    /// encoder writes it back with them.
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// Line break to store for a line ending with `\r\n` if `crlf`
+    fn line_break(&self, crlf: bool) -> &'static str {
+        match self.line_ending {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Preserve if crlf => "\r\n",
+            LineEnding::Preserve => "\n",
+        }
+    }
+
+    /// Recover from repeated file names, as in hand-written or generated
+    /// archives, instead of failing; resolved duplicates are reported as
+    /// [`Diagnostic::DuplicateFile`]
+    pub fn with_duplicate_policy(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Parse and validate the custom tags `tags` has handlers for, keeping
+    /// them in [`File::custom_tags`]
+    pub fn with_tags(mut self, tags: TagRegistry) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Decode archives declaring a newer or invalid format version (see
+    /// [`version`](crate::version)) as far as this crate understands them,
+    /// reporting [`Diagnostic::UnsupportedVersion`] instead of failing
+    pub fn with_lenient_version(mut self, lenient: bool) -> Self {
+        self.lenient_version = lenient;
+        self
+    }
+
+    /// Check the format version declared in the frontmatter of `comment`
+    fn check_version(&self, comment: &str) -> Result<()> {
+        let error = match version::declared_version(comment) {
+            Ok(Some(declared)) if declared > version::FORMAT_VERSION => version::unsupported(declared),
+            Ok(_) => return Ok(()),
+            Err(error) => error,
+        };
+        if !self.lenient_version {
+            return Err(error);
+        }
+        let declared = Frontmatter::split(comment).0.and_then(|f| f.get(version::VERSION_KEY).map(String::from));
+        self.report(Diagnostic::UnsupportedVersion { declared: declared.unwrap_or_default() });
+        Ok(())
+    }
+
+    /// Decode the archive an `@include` entry names, failing on cycles
+    fn decode_include(&self, resolver: &dyn IncludeResolver, path: &str) -> Result<Archive> {
+        if self.include_stack.iter().any(|p| p == path) {
+            let mut cycle = self.include_stack.clone();
+            cycle.push(path.to_string());
+            bail!("Include cycle: {}", cycle.join(" -> "));
+        }
+        let text = resolver.resolve(path)
+            .map_err(|e| anyhow!("Failed to include '{}': {}", path, e))?;
+        let mut decoder = self.clone();
+        decoder.include_stack.push(path.to_string());
+        decoder.decode(&text).map_err(|e| anyhow!("In included archive '{}': {}", path, e))
+    }
+
+    /// Limit how many levels of `[.txtar]` entries may be decoded, 8 by default
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Decode the contents of a `[.txtar]` entry as an archive
+    ///
+    /// The entry is one level below the archives this decoder decodes.
+    /// Decode archives nested inside the result with [`nested`](Self::nested)
+    /// so that their levels count toward the depth limit.
+    pub fn decode_nested(&self, file: &File) -> Result<Archive> {
+        if !file.is_archive {
+            bail!("File '{}' is not a nested archive", file.name);
+        }
+        let decoder = self.nested()
+            .ok_or_else(|| anyhow!("Nested archive '{}' exceeds the depth limit of {}", file.name, self.max_depth))?;
+        trace_span!("decode_nested", file = %file.name, depth = decoder.depth);
+        #[cfg(feature = "std")]
+        let file = file.load()?;
+        let data = file.utf8_data();
+        let text = core::str::from_utf8(&data)
+            .map_err(|_| anyhow!("Nested archive '{}' is not valid UTF-8", file.name))?;
+        decoder.decode(text).map_err(|e| anyhow!("In nested archive '{}': {}", file.name, e))
+    }
+
+    /// Decoder for the archives nested one level below those this one
+    /// decodes, or `None` beyond the depth limit
+    pub fn nested(&self) -> Option<Decoder> {
+        let depth = self.depth + 1;
+        (depth <= self.max_depth).then(|| Decoder { depth, ..self.clone() })
+    }
+
+    /// Create a File from a parsed header and accumulated data, already
+    /// decoded for base64 entries
+    fn create_file(&self, header: FileHeader, mut data: Vec<u8>) -> Result<File> {
+        profile_count!(files_decoded, 1);
+        if !header.is_binary && self.line_ending != LineEnding::Lf && data.ends_with(b"\r\n") {
+            // The line break ending the section is not part of the contents
+            data.remove(data.len() - 2);
+        }
+        let mut file = Self::create_file_from_data(header.name, header.is_binary, data);
+        let escaped = !file.is_binary && !header.escaped_lines.is_empty();
+        if escaped {
+            Self::unescape_marker_lines(&mut file, &header.escaped_lines)?;
+        }
+        if !file.is_binary && !header.patches.is_empty() {
+            Self::apply_patches(&mut file, &header.patches)?;
+        } else if file.is_binary {
+            // Binary entries may still record their source text encoding
+            file.encoding = header.encoding;
+        } else if header.encoding != TextEncoding::Utf8 {
+            // Restore the original bytes from the UTF-8 text section
+            let text = core::str::from_utf8(&file.data)
+                .map_err(|_| anyhow!("File '{}' is not valid UTF-8", file.name))?;
+            file.data = header.encoding.encode(text).ok_or_else(|| {
+                anyhow!("Cannot encode file '{}' as {}", file.name, header.encoding.label())
+            })?.into();
+            file.encoding = header.encoding;
+        }
+        if escaped {
+            // Keep the file flagged as conflicting so that re-encoding
+            // does not emit the markers unescaped
+            file.is_binary = true;
+        }
+        if !header.is_binary && header.line_ending == Some(LineEnding::CrLf) {
+            file.line_ending = LineEnding::CrLf;
+        }
+        file.snippet_ref = header.snippet_ref;
+        file.edit_ref = header.edit_ref;
+        file.metadata = header.metadata;
+        file.is_archive = header.is_archive;
+        file.is_encrypted = header.is_encrypted;
+        file.conditions = header.conditions;
+        file.custom_tags = header.custom_tags;
+        self.tags.validate(&file)?;
+        trace_event!(file = %file.name, size = file.data.len(), binary = file.is_binary, "decoded file");
+        Ok(file)
+    }
+
+    /// Restore escaped marker lines and record the conflict as the binary reason
+    fn unescape_marker_lines(file: &mut File, escaped_lines: &[usize]) -> Result<()> {
+        let text = core::str::from_utf8(&file.data)
+            .map_err(|_| anyhow!("File '{}' is not valid UTF-8", file.name))?;
+        let mut lines: Vec<&str> = text.split('\n').collect();
+
+        for &n in escaped_lines {
+            let line = lines.get_mut(n.wrapping_sub(1))
+                .ok_or_else(|| anyhow!("Escaped line {} out of range in file '{}'", n, file.name))?;
+            *line = line.strip_prefix(MARKER_ESCAPE)
+                .ok_or_else(|| anyhow!("Line {} of file '{}' is not escaped", n, file.name))?;
+        }
+
+        let first = escaped_lines[0];
+        let restored = lines.join("\n");
+        file.binary_reason = Some(BinaryReason::ContentConflict {
+            line_number: first,
+            line: restored.split('\n').nth(first - 1).unwrap_or_default().trim().to_string(),
+        });
+        file.data = restored.into_bytes().into();
+        Ok(())
+    }
+
+    /// Put the original bytes back in place of the U+FFFD placeholders
+    fn apply_patches(file: &mut File, patches: &[(usize, Vec<u8>)]) -> Result<()> {
+        const PLACEHOLDER: &[u8] = "\u{FFFD}".as_bytes();
+
+        let mut data = Vec::with_capacity(file.data.len());
+        let mut pos = 0;
+        for (offset, bytes) in patches {
+            let end = offset + PLACEHOLDER.len();
+            if *offset < pos || file.data.get(*offset..end) != Some(PLACEHOLDER) {
+                bail!("No placeholder at offset {} in file '{}'", offset, file.name);
             }
-            Ok(File::with_encoding(name, data, false))
+            data.extend_from_slice(&file.data[pos..*offset]);
+            data.extend_from_slice(bytes);
+            pos = end;
         }
+        data.extend_from_slice(&file.data[pos..]);
+
+        // The restored data is not UTF-8, so the file is binary as on detection
+        let offset = core::str::from_utf8(&data).err().map(|e| e.valid_up_to()).unwrap_or(0);
+        file.data = data.into();
+        file.is_binary = true;
+        file.binary_reason = Some(BinaryReason::InvalidUtf8 { offset });
+        Ok(())
     }
 
-    /// Filter base64 data by removing newlines and carriage returns
-    fn filter_base64_data(data: &[u8]) -> String {
-        data.iter()
-            .filter(|&&c| c != BINARY_NEWLINE && c != BINARY_CARRIAGE_RETURN)
-            .map(|&c| c as char)
-            .collect()
+    /// Create a File from accumulated data
+    fn create_file_from_data(name: String, is_binary: bool, mut data: Vec<u8>) -> File {
+        // Remove the trailing newline of text
+        if !is_binary && data.ends_with(b"\n") {
+            data.pop();
+        }
+        File::with_encoding(name, data, is_binary)
+    }
+
+    /// Check line `number` of the base64 section of `name`, returning the
+    /// characters to decode (empty for a skipped blank line) in the
+    /// standard alphabet
+    fn base64_line<'a>(&self, line: &'a str, number: usize, header: &mut FileHeader) -> Result<Cow<'a, str>> {
+        fn standard(c: char) -> bool {
+            c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '=')
+        }
+
+        let start = if self.strict { 0 } else { line.len() - line.trim_start().len() };
+        let data = if self.strict { line } else { line.trim() };
+        let name = &header.name;
+        if data.is_empty() && self.strict {
+            bail!("Blank line {} in base64 section of '{}'", number, name);
+        }
+        let invalid = data.char_indices().find(|&(_, c)| {
+            !(standard(c) || (!self.strict && (matches!(c, '-' | '_') || c.is_ascii_whitespace())))
+        });
+        if let Some((offset, c)) = invalid {
+            let column = line[..start + offset].chars().count() + 1;
+            bail!("Invalid base64 character {:?} in '{}' at line {}, column {}", c, name, number, column);
+        }
+        if data.chars().all(standard) {
+            return Ok(Cow::Borrowed(data));
+        }
+        if !core::mem::replace(&mut header.base64_reported, true) {
+            self.report(Diagnostic::NonCanonicalBase64 { name: name.clone(), line: number });
+        }
+        Ok(data.chars()
+            .filter(|c| !c.is_ascii_whitespace())
+            .map(crate::b64::normalize_alphabet)
+            .collect())
+    }
+
+    /// Decode base64 checked by [`base64_line`](Self::base64_line),
+    /// tolerating missing padding unless strict
+    fn decode_base64(
+        &self,
+        input: &[u8],
+        out: &mut Vec<u8>,
+        name: &str,
+        line: usize,
+    ) -> core::result::Result<(), base64::DecodeError> {
+        if self.strict {
+            return crate::b64::decode_vec(input, out);
+        }
+        if crate::b64::decode_vec_lenient(input, out)? {
+            self.report(Diagnostic::NonCanonicalBase64 { name: name.to_string(), line });
+        }
+        Ok(())
     }
 
     /// Decode a txtar archive from a string
     pub fn decode(&self, input: &str) -> Result<Archive> {
-        let mut archive = Archive::new();
-        let mut current_file: Option<(String, bool, Option<SnippetRef>, Option<EditRef>, Vec<u8>)> = None;
-
-        for (_line_num, line) in input.lines().enumerate() {
-            // Check for file marker
-            if let Some((name, is_binary, snippet_ref, edit_ref)) = self.parse_file_marker(line) {
-                // Save previous file using helper method
-                if let Some((name, is_binary, snippet_ref, edit_ref, data)) = current_file.take() {
-                    let mut file = self.create_file_from_data(name, is_binary, data)?;
-                    file.snippet_ref = snippet_ref;
-                    file.edit_ref = edit_ref;
-                    archive.add_file(file)?;
-                }
+        profile_count!(bytes_decoded, input.len());
+        trace_span!("decode", bytes = input.len());
+        let mut state = DecodeState::default();
+        // A carriage return ending the input ends its last line, as it does
+        // when reading line by line
+        let cr_end = input.ends_with('\r');
+        for line in input.strip_suffix('\r').unwrap_or(input).split_inclusive('\n') {
+            state.push_raw_line(self, line, cr_end)?;
+        }
+        state.finish(self)
+    }
 
-                // Start new file
-                current_file = Some((name, is_binary, snippet_ref, edit_ref, Vec::new()));
-                continue;
+    /// Decode a txtar archive, borrowing the contents of plain text entries
+    /// from `input` instead of copying them
+    ///
+    /// UTF-8 text entries without marker escapes, patches or CRLF line
+    /// endings refer to their section of `input`; other entries are decoded
+    /// into owned copies as by [`decode`](Self::decode), which this
+    /// otherwise matches. Borrowed contents do not count towards the memory
+    /// limit. See [`borrowed`](crate::borrowed).
+    pub fn parse_borrowed<'a>(&self, input: &'a str) -> Result<ArchiveRef<'a>> {
+        profile_count!(bytes_decoded, input.len());
+        trace_span!("parse_borrowed", bytes = input.len());
+        let cr_end = input.ends_with('\r');
+        let input = input.strip_suffix('\r').unwrap_or(input);
+        let mut state = DecodeState { borrowed: Some(Vec::new()), ..Default::default() };
+        let mut offset = 0;
+        for line in input.split_inclusive('\n') {
+            state.line_range = offset..offset + line.len();
+            offset += line.len();
+            state.push_raw_line(self, line, cr_end)?;
+        }
+        // End the last entry here, so that its range is kept
+        state.finish_entry(self, state.line, state.line_range.end)?;
+        let borrowed = state.borrowed.take().unwrap_or_default();
+        let mut archive = state.finish(self)?;
+
+        let mut contents = vec![None; archive.files.len()];
+        for (index, range) in borrowed {
+            // The section without the line break that ends the file
+            let text = strip_line_ending(&input[range]);
+            match self.line_ending.apply(text) {
+                Cow::Borrowed(text) => contents[index] = Some(text),
+                // Line breaks were converted, so the contents are not the text
+                Cow::Owned(text) => archive.files[index].data = text.into_bytes().into(),
             }
+        }
+        Ok(ArchiveRef::new(archive, contents))
+    }
 
-            // Add content to current file
-            if let Some((_, is_binary, _, _, ref mut data)) = current_file {
-                if is_binary {
-                    // Accumulate base64 lines
-                    if !line.trim().is_empty() {
-                        data.extend_from_slice(line.as_bytes());
-                        data.push(BINARY_NEWLINE);
-                    }
-                } else {
-                    // Text content
-                    data.extend_from_slice(line.as_bytes());
-                    data.push(BINARY_NEWLINE);
+    /// Decode a stream of archives separated by `=== archive: name ===` lines
+    ///
+    /// Input without any delimiter is a single archive with an empty name.
+    /// Only blank lines may precede the first delimiter, and archive names
+    /// must be unique.
+    pub fn decode_stream(&self, input: &str) -> Result<Vec<(String, Archive)>> {
+        let mut sections: Vec<(String, String)> = Vec::new();
+        let mut preamble = String::new();
+
+        for line in input.split_inclusive('\n') {
+            if let Some(name) = parse_stream_delimiter(line) {
+                if sections.iter().any(|(existing, _)| existing == name) {
+                    bail!("Duplicate archive name in stream: {}", name);
                 }
+                sections.push((name.to_string(), String::new()));
+            } else if let Some((_, body)) = sections.last_mut() {
+                body.push_str(line);
             } else {
-                // Before first file - this is comment
-                // Preserve empty lines for heredoc support in test scripts
-                if !archive.comment.is_empty() {
-                    archive.comment.push('\n');
-                }
-                archive.comment.push_str(line);
+                preamble.push_str(line);
             }
         }
 
-        // Save last file using helper method
-        if let Some((name, is_binary, snippet_ref, edit_ref, data)) = current_file.take() {
-            let mut file = self.create_file_from_data(name, is_binary, data)?;
-            file.snippet_ref = snippet_ref;
-            file.edit_ref = edit_ref;
-            archive.add_file(file)?;
+        if sections.is_empty() {
+            return Ok(vec![(String::new(), self.decode(input)?)]);
+        }
+        if !preamble.trim().is_empty() {
+            bail!("Content before the first archive delimiter");
         }
 
-        // Parse commands from comment section
-        archive.parse_commands();
-
-        // Parse edit blocks and validate file existence
-        self.parse_and_validate_edits(&mut archive)?;
-
-        Ok(archive)
+        sections.into_iter()
+            .map(|(name, body)| {
+                let archive = self.decode(&body).map_err(|e| anyhow!("In archive '{}': {}", name, e))?;
+                Ok((name, archive))
+            })
+            .collect()
     }
 
     /// Parse a file marker line like "-- filename --" or "-- filename[.base64] --"
     /// Also handles snippet references like "-- filename[.snippet:N] --" or "-- filename[.#href:line] --"
     /// And edit references like "-- filename[.edit] --" or "-- filename[.edit#href:line] --"
-    fn parse_file_marker(&self, line: &str) -> Option<(String, bool, Option<SnippetRef>, Option<EditRef>)> {
+    fn parse_file_marker(&self, line: &str) -> Result<Option<FileHeader>> {
         let trimmed = line.trim();
 
-        // Must start with "-- " and end with " --"
-        if !trimmed.starts_with(MARKER_PREFIX) || !trimmed.ends_with(MARKER_SUFFIX) {
-            return None;
-        }
-
-        // Extract the name between the markers
-        let name_part = &trimmed[MARKER_PREFIX_LEN..trimmed.len() - MARKER_SUFFIX_LEN];
+        // Must start with "-- " and end with " --" around a non-blank name,
+        // as in `File::is_marker_line`; the markers overlap in `-- --`
+        let name_part = match trimmed.strip_prefix(MARKER_PREFIX).and_then(|rest| rest.strip_suffix(MARKER_SUFFIX)) {
+            Some(name_part) if !name_part.trim().is_empty() => name_part,
+            _ => return Ok(None),
+        };
 
         // Parse filename with all bracket-enclosed tags
-        let (filename, is_binary, snippet_ref, edit_ref) = Self::parse_name_and_tags(name_part);
-
-        // Check for filename conflicts (only if not already marked as binary)
-        if !is_binary && self.check_filename_conflict(&filename) {
-            if self.verbose > 0 {
-                eprintln!("Warning: Filename '{}' contains txtar marker pattern, but is not marked as binary", filename);
-            }
+        let header = Self::parse_name_and_tags(name_part, self.strict, &self.tags)?;
+
+        // Check for filename conflicts in the name as written (only if not
+        // already marked as binary); escaped names never conflict
+        let raw_name = &name_part[..find_tag_start(name_part).unwrap_or(name_part.len())];
+        let conflict = !header.is_binary && has_marker_pattern(raw_name);
+        if conflict && self.strict {
+            bail!("Filename '{}' contains an unescaped txtar marker pattern", header.name);
         }
+        if conflict {
+            self.report(Diagnostic::MarkerPatternInName { name: header.name.clone() });
+        }
+        for tag in &header.unknown_tags {
+            self.report(Diagnostic::UnknownTag { name: header.name.clone(), tag: tag.clone() });
+        }
+
+        Ok(Some(header))
+    }
 
-        Some((filename, is_binary, snippet_ref, edit_ref))
+    /// Name of the entry a marker line starts and whether it is a normal file
+    /// (neither snippet nor edit, and unconditional), or `None` if the line
+    /// is not a marker
+    pub(crate) fn entry_marker(line: &str) -> Result<Option<(String, bool)>> {
+        Ok(Self::new().parse_file_marker(line)?
+            .map(|header| {
+                let normal = header.snippet_ref.is_none() && header.edit_ref.is_none() && header.conditions.is_empty();
+                (header.name, normal)
+            }))
     }
 
     /// Parse filename with optional bracket-enclosed tags
     /// Handles formats like: filename, filename[.base64], filename[.snippet:N],
-    /// filename[.base64][.snippet:N], filename[.#href:line], filename[.edit], etc.
-    fn parse_name_and_tags(name_part: &str) -> (String, bool, Option<SnippetRef>, Option<EditRef>) {
+    /// filename[.base64][.snippet:N], filename[.#href:line], filename[.edit],
+    /// filename[.enc:latin1], etc. The filename is unescaped, and in strict
+    /// mode unrecognized tags are an error.
+    fn parse_name_and_tags(name_part: &str, strict: bool, tags: &TagRegistry) -> Result<FileHeader> {
         let mut is_binary = false;
         let mut snippet_ref = None;
         let mut edit_ref = None;
-
-        // Find the base filename (before first bracket)
-        let base_name = if let Some(bracket_start) = name_part.find('[') {
+        let mut encoding = TextEncoding::Utf8;
+        let mut is_archive = false;
+        let mut is_encrypted = false;
+        let mut conditions = Vec::new();
+        let mut custom_tags = Vec::new();
+        let mut escaped_lines = Vec::new();
+        let mut patches = Vec::new();
+        let mut metadata = FileMetadata::default();
+        let mut unknown_tags = Vec::new();
+
+        // Find the base filename (before first unescaped bracket)
+        let base_name = if let Some(bracket_start) = find_tag_start(name_part) {
             &name_part[..bracket_start]
         } else {
-            return (name_part.trim().to_string(), false, None, None);
+            return Ok(FileHeader {
+                name: unescape_name(name_part.trim()).into_owned(),
+                is_binary: false,
+                snippet_ref: None,
+                edit_ref: None,
+                encoding,
+                is_archive,
+                is_encrypted,
+                conditions,
+                custom_tags,
+                escaped_lines,
+                patches,
+                metadata,
+                unknown_tags,
+                base64_reported: false,
+                line_ending: None,
+                line: 0,
+            });
         };
 
         // Process each bracket-enclosed tag
@@ -168,6 +681,55 @@ impl Decoder {
             if tag == BASE64_SUFFIX {
                 is_binary = true;
             }
+            // Check for nested archive tag
+            else if tag == ARCHIVE_TAG {
+                is_archive = true;
+            }
+            // Check for encryption tag
+            else if let Some(scheme) = tag.strip_prefix(CRYPT_TAG_PREFIX).and_then(|t| t.strip_suffix(']')) {
+                if scheme != CRYPT_SCHEME {
+                    bail!("Unknown encryption scheme '{}' for file '{}'", scheme, base_name.trim());
+                }
+                is_encrypted = true;
+            }
+            // Check for condition tag
+            else if tag.starts_with(CONDITION_TAG_PREFIX) {
+                conditions.push(Condition::parse(tag)
+                    .ok_or_else(|| anyhow!("Invalid condition tag '{}' for file '{}'", tag, base_name.trim()))?);
+            }
+            // Check for text encoding tag
+            else if let Some(label) = tag.strip_prefix(ENCODING_TAG_PREFIX).and_then(|t| t.strip_suffix(']')) {
+                encoding = TextEncoding::from_label(label)
+                    .ok_or_else(|| anyhow!("Unknown text encoding '{}' for file '{}'", label, base_name.trim()))?;
+            }
+            // Check for escaped marker lines tag
+            else if let Some(list) = tag.strip_prefix(ESCAPE_TAG_PREFIX).and_then(|t| t.strip_suffix(']')) {
+                escaped_lines = list.split(',')
+                    .map(|n| n.trim().parse::<usize>())
+                    .collect::<core::result::Result<_, _>>()
+                    .map_err(|_| anyhow!("Invalid escape tag '{}' for file '{}'", tag, base_name.trim()))?;
+            }
+            // Check for invalid UTF-8 patch tag
+            else if let Some(list) = tag.strip_prefix(PATCH_TAG_PREFIX).and_then(|t| t.strip_suffix(']')) {
+                patches = list.split(',')
+                    .map(|entry| {
+                        let (offset, bytes) = entry.split_once('=')?;
+                        let offset = offset.trim().parse::<usize>().ok()?;
+                        let bytes = base64::engine::general_purpose::STANDARD.decode(bytes.trim()).ok()?;
+                        Some((offset, bytes))
+                    })
+                    .collect::<Option<_>>()
+                    .ok_or_else(|| anyhow!("Invalid patch tag '{}' for file '{}'", tag, base_name.trim()))?;
+            }
+            // Check for metadata tags
+            else if let Some(mode) = tag.strip_prefix(MODE_TAG_PREFIX).and_then(|t| t.strip_suffix(']')) {
+                metadata.mode = Some(u32::from_str_radix(mode, 8)
+                    .map_err(|_| anyhow!("Invalid mode tag '{}' for file '{}'", tag, base_name.trim()))?);
+            }
+            else if let Some(mtime) = tag.strip_prefix(MTIME_TAG_PREFIX).and_then(|t| t.strip_suffix(']')) {
+                metadata.mtime = Some(mtime.parse()
+                    .map_err(|_| anyhow!("Invalid mtime tag '{}' for file '{}'", tag, base_name.trim()))?);
+            }
             // Check for snippet reference tags
             else if let Ok(ref_obj) = SnippetRef::parse(tag) {
                 snippet_ref = Some(ref_obj);
@@ -180,16 +742,57 @@ impl Decoder {
                     edits: Vec::new(), // Will be parsed later from file content
                 });
             }
+            // Check for tags with a registered handler
+            else if let Some(custom) = tags.parse(tag) {
+                custom_tags.push(custom.map_err(|e| {
+                    anyhow!("Invalid tag '{}' for file '{}': {}", tag, unescape_name(base_name.trim()), e)
+                })?);
+            }
+            else if strict {
+                bail!("Unrecognized tag '{}' in header of '{}'", tag, base_name.trim());
+            }
+            else {
+                unknown_tags.push(tag.to_string());
+            }
 
             // Move to next tag
             rest = &rest[bracket_end + 1..];
         }
+        if strict && !rest.trim().is_empty() {
+            bail!("Unexpected '{}' after the tags of '{}'", rest, base_name.trim());
+        }
+        if is_encrypted && !is_binary {
+            bail!("Encrypted file '{}' is not base64 encoded", base_name.trim());
+        }
 
-        (base_name.trim().to_string(), is_binary, snippet_ref, edit_ref)
+        Ok(FileHeader {
+            name: unescape_name(base_name.trim()).into_owned(),
+            is_binary,
+            snippet_ref,
+            edit_ref,
+            encoding,
+            is_archive,
+            is_encrypted,
+            conditions,
+            custom_tags,
+            escaped_lines,
+            patches,
+            metadata,
+            unknown_tags,
+            base64_reported: false,
+            line_ending: None,
+            line: 0,
+        })
     }
 
-    /// Parse an edit tag like [.edit] or [.edit#href:line]
+    /// Parse an edit tag like [.edit], [.edit:line] or [.edit#href:line]
     fn parse_edit_tag(tag: &str) -> Option<(Option<String>, Option<usize>)> {
+        // Try [.edit:line] format
+        if let Some(content) = tag.strip_prefix("[.edit:") {
+            let line = content.strip_suffix(']')?.parse::<usize>().ok()?;
+            return Some((None, Some(line)));
+        }
+
         // Try [.edit#href:line] format
         if tag.starts_with("[.edit#") {
             let content = tag.strip_prefix("[.edit#")?;
@@ -211,11 +814,6 @@ impl Decoder {
         None
     }
 
-    /// Check if a filename conflicts with txtar marker pattern
-    fn check_filename_conflict(&self, name: &str) -> bool {
-        name.contains("-- ") && name.contains(" --")
-    }
-
     /// Parse edit blocks from files and validate file existence
     fn parse_and_validate_edits(&self, archive: &mut Archive) -> Result<()> {
         // First, collect files that need validation
@@ -225,26 +823,31 @@ impl Decoder {
             .filter_map(|(i, f)| f.edit_ref.as_ref().map(|_er| (i, f.name.clone())))
             .collect();
 
-        // Validate file existence first (before any modifications)
-        for (_, filename) in &files_to_process {
-            self.validate_file_exists_for_edit(archive, filename)?;
+        // Parse edit blocks, which may name other targets than the entry
+        let mut parsed = Vec::with_capacity(files_to_process.len());
+        for (idx, filename) in files_to_process {
+            let content = core::str::from_utf8(&archive.files[idx].data)
+                .map_err(|_| anyhow!("File '{}' is not valid UTF-8", filename))?;
+            let edits = EditRef::parse_content(content)
+                .map_err(|e| anyhow!("Failed to parse edit blocks in '{}': {}", filename, e))?;
+            parsed.push((idx, filename, edits));
         }
 
-        // Then parse edit blocks
-        for (idx, _) in files_to_process {
-            let file = &mut archive.files[idx];
-            // Safety: We filtered files to only include those with edit_ref
-            let _edit_ref = file.edit_ref.as_ref()
-                .expect("edit_ref should be Some (filtered by filter_map)");
-
-            // Parse edit blocks from file content
-            let content = std::str::from_utf8(&file.data)
-                .map_err(|_| anyhow!("File '{}' is not valid UTF-8", file.name))?;
-            let edits = EditRef::parse_content(content)
-                .map_err(|e| anyhow!("Failed to parse edit blocks in '{}': {}", file.name, e))?;
+        // Validate file existence (before any modifications)
+        if self.check_edit_targets {
+            for (_, filename, edits) in &parsed {
+                if edits.is_empty() {
+                    self.validate_file_exists_for_edit(archive, filename)?;
+                }
+                for target in edits.iter().map(|edit| edit.file.as_ref().unwrap_or(filename)) {
+                    self.validate_file_exists_for_edit(archive, target)?;
+                }
+            }
+        }
 
-            // Update file with parsed edits
-            if let Some(er) = &mut file.edit_ref {
+        // Update files with parsed edits
+        for (idx, _, edits) in parsed {
+            if let Some(er) = &mut archive.files[idx].edit_ref {
                 er.edits = edits;
             }
         }
@@ -259,7 +862,10 @@ impl Decoder {
             .any(|f| f.name == filename && f.edit_ref.is_none());
 
         // Check if file exists in filesystem
+        #[cfg(feature = "std")]
         let exists_on_fs = std::path::Path::new(filename).exists();
+        #[cfg(not(feature = "std"))]
+        let exists_on_fs = false;
 
         if !exists_in_txtar && !exists_on_fs {
             Err(anyhow!(
@@ -272,12 +878,587 @@ impl Decoder {
     }
 }
 
+/// Archive being assembled a line at a time
+#[derive(Default)]
+struct DecodeState {
+    archive: Archive,
+    /// Entry being read, with its text or decoded base64 content so far and,
+    /// if its contents are borrowed, the offset they start at
+    current: Option<(FileHeader, Vec<u8>, Base64Lines, Option<usize>)>,
+    /// Bytes of content in `archive.files`
+    stored: usize,
+    /// Number of lines read
+    line: usize,
+    /// Whether the comment has ended and its format version been checked
+    version_checked: bool,
+    /// Whether the last line read had no line break
+    unterminated: bool,
+    /// Whether the last line read ended with `\r\n`
+    crlf: bool,
+    /// Line break to put before the next line of the comment
+    comment_break: &'static str,
+    /// Byte range of the line being read in the input, kept when borrowing
+    line_range: Range<usize>,
+    /// Index and byte range in the input of each entry whose contents were
+    /// left empty to be borrowed, if borrowing plain text entries
+    borrowed: Option<Vec<(usize, Range<usize>)>>,
+}
+
+impl DecodeState {
+    /// Feed a line as `split_inclusive('\n')` yields it from input whose
+    /// final `\r`, if `cr_end`, was removed as ending the last line
+    fn push_raw_line(&mut self, decoder: &Decoder, line: &str, cr_end: bool) -> Result<bool> {
+        self.crlf = line.ends_with("\r\n");
+        self.unterminated = !line.ends_with('\n') && !cr_end;
+        self.push_line(decoder, strip_line_ending(line))
+    }
+
+    /// Feed one line without its terminator; returns true if it completed an entry
+    fn push_line(&mut self, decoder: &Decoder, line: &str) -> Result<bool> {
+        self.line += 1;
+
+        // Check for file marker; the first one ends the comment, whose
+        // format version decides how the headers are read
+        let marker = decoder.parse_file_marker(line);
+        if !self.version_checked && !matches!(marker, Ok(None)) {
+            self.version_checked = true;
+            decoder.check_version(&self.archive.comment)?;
+        }
+        if let Some(mut header) = marker? {
+            header.line = self.line;
+            if !self.unterminated {
+                header.record_line_ending(self.crlf);
+            }
+            let completed = self.finish_entry(decoder, self.line - 1, self.line_range.start)?;
+            let start = (self.borrowed.is_some() && header.is_plain_text(decoder)).then_some(self.line_range.end);
+            self.current = Some((header, Vec::new(), Base64Lines::default(), start));
+            return Ok(completed);
+        }
+
+        // Add content to current file
+        if let Some((ref mut header, ref mut data, ref mut base64, start)) = self.current {
+            if header.is_binary {
+                // Decode base64 as it arrives, so the text is never held whole
+                let line = decoder.base64_line(line, self.line, header)?;
+                base64.push(decoder, &header.name, &line, header.line, data)
+                    .map_err(|e| section_error(header, e))?;
+            } else {
+                if !self.unterminated {
+                    header.record_line_ending(self.crlf);
+                }
+                if start.is_none() {
+                    // Text content
+                    data.extend_from_slice(line.as_bytes());
+                    data.extend_from_slice(decoder.line_break(self.crlf).as_bytes());
+                }
+            }
+        } else {
+            // Before first file - this is comment
+            // Preserve empty lines for heredoc support in test scripts
+            let comment = &mut self.archive.comment;
+            let line_break = decoder.line_break(self.crlf);
+            if decoder.fidelity {
+                comment.push_str(line);
+                comment.push_str(line_break);
+            } else {
+                if !comment.is_empty() {
+                    comment.push_str(self.comment_break);
+                }
+                comment.push_str(line);
+                self.comment_break = line_break;
+            }
+        }
+        self.check_limit(decoder)?;
+        Ok(false)
+    }
+
+    /// Add the entry being read, if any, to the archive; `end` is its last
+    /// line and `end_offset` the offset its contents end at
+    fn finish_entry(&mut self, decoder: &Decoder, end: usize, end_offset: usize) -> Result<bool> {
+        let Some((header, mut data, base64, start)) = self.current.take() else {
+            return Ok(false);
+        };
+        base64.finish(decoder, &header.name, header.line, &mut data).map_err(|e| section_error(&header, e))?;
+        let span = (header.line > 0).then(|| EntrySpan {
+            name: header.name.clone(),
+            header: header.line,
+            lines: end - header.line,
+            base64: header.is_binary,
+            escaped_lines: header.escaped_lines.clone(),
+        });
+        if let (Some(resolver), Some(path)) = (&decoder.includes, header.name.strip_prefix(INCLUDE_PREFIX)) {
+            self.archive.spans.extend(span);
+            for file in decoder.decode_include(resolver.as_ref(), path.trim())?.files {
+                self.add_file(decoder, file)?;
+            }
+            self.check_limit(decoder)?;
+            return Ok(true);
+        }
+        // Text sections record whether they ended with the newline that
+        // `create_file` drops; base64 and patched data are kept whole
+        let trailing_newline = (!header.is_binary && header.patches.is_empty())
+            .then_some(end > header.line && !(self.unterminated && end == self.line));
+        let mut file = decoder.create_file(header, data)?;
+        file.trailing_newline = trailing_newline;
+        if self.add_file(decoder, file)? {
+            let file = self.archive.files.last().expect("file was just added");
+            if let Some(mut span) = span {
+                span.name.clone_from(&file.name);
+                self.archive.spans.push(span);
+            }
+            if let (Some(borrowed), Some(start)) = (&mut self.borrowed, start) {
+                borrowed.push((self.archive.files.len() - 1, start..end_offset));
+            }
+        }
+        self.check_limit(decoder)?;
+        Ok(true)
+    }
+
+    /// Add `file` last, resolving a repeated name by the decoder's
+    /// [`DuplicatePolicy`]; returns false if the file was dropped
+    fn add_file(&mut self, decoder: &Decoder, mut file: File) -> Result<bool> {
+        let files = &self.archive.files;
+        let earlier = files.iter().position(|f| f.name == file.name && is_unique(f));
+        if let Some(index) = earlier.filter(|_| is_unique(&file) && decoder.duplicates != DuplicatePolicy::Error) {
+            let name = file.name.clone();
+            let mut renamed = None;
+            match decoder.duplicates {
+                DuplicatePolicy::KeepFirst => {
+                    decoder.report(Diagnostic::DuplicateFile { name, policy: decoder.duplicates, renamed });
+                    return Ok(false);
+                }
+                DuplicatePolicy::Rename => {
+                    file.name = unique_name(&name, |candidate| files.iter().any(|f| f.name == candidate));
+                    renamed = Some(file.name.clone());
+                }
+                _ => {
+                    self.archive.remove_span(index);
+                    self.stored -= self.archive.files.remove(index).data.len();
+                    if let Some(borrowed) = &mut self.borrowed {
+                        borrowed.retain(|&(i, _)| i != index);
+                        for (i, _) in borrowed.iter_mut().filter(|(i, _)| *i > index) {
+                            *i -= 1;
+                        }
+                    }
+                }
+            }
+            decoder.report(Diagnostic::DuplicateFile { name, policy: decoder.duplicates, renamed });
+        }
+        self.stored += file.data.len();
+        self.archive.add_file(file)?;
+        Ok(true)
+    }
+
+    fn check_limit(&self, decoder: &Decoder) -> Result<()> {
+        let Some(limit) = decoder.memory_limit else {
+            return Ok(());
+        };
+        let buffered = self.current.as_ref().map_or(0, |(_, data, base64, _)| data.len() + base64.carry.len());
+        if self.stored + buffered + self.archive.comment.len() > limit {
+            bail!("Archive exceeds the memory limit of {} bytes", limit);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self, decoder: &Decoder) -> Result<Archive> {
+        if decoder.fidelity && !self.version_checked && self.unterminated {
+            // The comment is the whole archive and has no final line break
+            let len = self.archive.comment.len() - decoder.line_break(false).len();
+            self.archive.comment.truncate(len);
+        }
+        if !self.version_checked {
+            decoder.check_version(&self.archive.comment)?;
+        }
+        self.finish_entry(decoder, self.line, self.line_range.end)?;
+
+        // Parse commands from comment section
+        self.archive.parse_commands();
+        if decoder.diagnostics.is_some() {
+            for error in self.archive.validate_snippet_refs().err().unwrap_or_default() {
+                decoder.report(Diagnostic::UnresolvedSnippet { name: error.file, command: error.missing_command });
+            }
+        }
+
+        // Parse edit blocks and validate file existence
+        decoder.parse_and_validate_edits(&mut self.archive)?;
+
+        Ok(self.archive)
+    }
+}
+
+/// Base64 characters buffered before decoding them in one go
+const BASE64_CHUNK: usize = 64 * 1024;
+
+/// Base64 section decoded as it is read, a chunk at a time
+#[derive(Default)]
+struct Base64Lines {
+    /// Characters not decoded yet
+    carry: Vec<u8>,
+    /// Whether the characters so far end with a padded quantum
+    padded: bool,
+}
+
+impl Base64Lines {
+    /// Add the characters of a line checked by [`Decoder::base64_line`],
+    /// decoding whole quanta into `out` once a chunk is buffered; lenient
+    /// decoding is reported at line `line`
+    fn push(&mut self, decoder: &Decoder, name: &str, chars: &str, line: usize, out: &mut Vec<u8>) -> Result<()> {
+        if chars.is_empty() {
+            return Ok(());
+        }
+        if self.padded {
+            bail!("data after padding");
+        }
+        profile_count!(base64_decoded, chars.len());
+        self.carry.extend_from_slice(chars.as_bytes());
+        self.padded = self.carry.len().is_multiple_of(4) && self.carry.ends_with(b"=");
+        if self.carry.len() >= BASE64_CHUNK {
+            let whole = self.carry.len() / 4 * 4;
+            decoder.decode_base64(&self.carry[..whole], out, name, line).map_err(|e| anyhow!("{}", e))?;
+            self.carry.drain(..whole);
+        }
+        Ok(())
+    }
+
+    /// Decode the characters left into `out`
+    fn finish(self, decoder: &Decoder, name: &str, line: usize, out: &mut Vec<u8>) -> Result<()> {
+        if !self.carry.is_empty() {
+            decoder.decode_base64(&self.carry, out, name, line).map_err(|e| anyhow!("{}", e))?;
+        }
+        Ok(())
+    }
+}
+
+/// `name` with `-2`, `-3`... inserted before its extension, the first such
+/// name not `taken`
+fn unique_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    let base = name.rfind('/').map_or(0, |i| i + 1);
+    let split = name[base..].rfind('.').filter(|&i| i > 0).map_or(name.len(), |i| base + i);
+    let mut n = 2;
+    loop {
+        let candidate = format!("{}-{}{}", &name[..split], n, &name[split..]);
+        if !taken(&candidate) {
+            return candidate;
+        }
+        n += 1;
+    }
+}
+
+/// `line` without a trailing `\n` or `\r\n`, as [`str::lines`] yields it
+fn strip_line_ending(line: &str) -> &str {
+    line.strip_suffix('\n').map_or(line, |line| line.strip_suffix('\r').unwrap_or(line))
+}
+
+/// Error decoding the base64 section of the entry `header` starts
+fn section_error(header: &FileHeader, error: anyhow::Error) -> anyhow::Error {
+    match header.line {
+        0 => anyhow!("Failed to decode base64 for file '{}': {}", header.name, error),
+        line => anyhow!("Failed to decode base64 for file '{}': {} (section starting at line {})", header.name, error, line),
+    }
+}
+
+impl core::str::FromStr for Archive {
+    type Err = anyhow::Error;
+
+    /// Decode an archive with the default decoder
+    fn from_str(s: &str) -> Result<Self> {
+        Decoder::new().decode(s)
+    }
+}
+
 impl Default for Decoder {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// Destination of an entry's content during streaming extraction
+#[cfg(feature = "std")]
+enum Sink {
+    /// Entry that is not written (edits, and snippets unless requested)
+    Skip,
+    /// Entry needing its whole content before it can be restored
+    Buffer(Vec<u8>),
+    /// Plain text, written line by line, with the line break of the last
+    /// line written before the next or when the entry ends
+    Text { out: std::io::BufWriter<std::fs::File>, pending: Option<&'static str>, last_break: Option<&'static str> },
+    /// Base64, decoded a line at a time
+    Base64 { out: std::io::BufWriter<std::fs::File>, lines: Base64Lines, decoded: Vec<u8> },
+}
+
+/// Entry being extracted by [`Decoder::extract_reader`]
+#[cfg(feature = "std")]
+struct StreamEntry {
+    header: FileHeader,
+    path: PathBuf,
+    sink: Sink,
+}
+
+#[cfg(feature = "std")]
+impl StreamEntry {
+    /// Open the destination of the entry `header` starts, unless `skip`
+    fn start(header: FileHeader, dir: &Path, options: &ExtractOptions, skip: bool) -> Result<Self> {
+        let path = extract_path(dir, &options.windows_names.apply(&header.name)?)?;
+        let skip = skip || header.edit_ref.is_some() || (header.snippet_ref.is_some() && !options.include_snippets);
+        let whole = !header.is_binary
+            && (!header.escaped_lines.is_empty() || !header.patches.is_empty() || header.encoding != TextEncoding::Utf8);
+
+        if !skip {
+            prepare_extract_path(dir, &path)?;
+        }
+        let sink = if skip {
+            Sink::Skip
+        } else if whole {
+            Sink::Buffer(Vec::new())
+        } else {
+            let out = std::io::BufWriter::new(std::fs::File::create(&path)?);
+            if header.is_binary {
+                Sink::Base64 { out, lines: Base64Lines::default(), decoded: Vec::new() }
+            } else {
+                Sink::Text { out, pending: None, last_break: None }
+            }
+        };
+        Ok(Self { header, path, sink })
+    }
+
+    /// Feed line `number` of the archive, which ended with `\r\n` if `crlf`
+    /// and with no line break at all unless `terminated`
+    fn push_line(&mut self, decoder: &Decoder, line: &str, number: usize, crlf: bool, terminated: bool) -> Result<()> {
+        use std::io::Write;
+
+        if terminated && !self.header.is_binary {
+            self.header.record_line_ending(crlf);
+        }
+        match &mut self.sink {
+            Sink::Skip => {}
+            Sink::Buffer(data) => {
+                data.extend_from_slice(line.as_bytes());
+                if terminated {
+                    data.extend_from_slice(decoder.line_break(crlf).as_bytes());
+                }
+            }
+            Sink::Text { out, pending, last_break } => {
+                if let Some(line_break) = pending.take() {
+                    out.write_all(line_break.as_bytes())?;
+                    *last_break = Some(line_break);
+                }
+                out.write_all(line.as_bytes())?;
+                *pending = terminated.then(|| decoder.line_break(crlf));
+            }
+            Sink::Base64 { out, lines, decoded } => {
+                let line = decoder.base64_line(line, number, &mut self.header)?;
+                decoded.clear();
+                lines.push(decoder, &self.header.name, &line, number, decoded)
+                    .map_err(|e| anyhow!("Failed to decode base64 for file '{}' at line {}: {}", self.header.name, number, e))?;
+                out.write_all(decoded)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush the entry, returning its path if it was written
+    fn finish(self, decoder: &Decoder, options: &ExtractOptions) -> Result<Option<PathBuf>> {
+        use std::io::Write;
+
+        let Self { header, path, sink } = self;
+        let metadata = header.metadata;
+        match sink {
+            Sink::Skip => return Ok(None),
+            Sink::Buffer(data) => {
+                let trailing_newline = header.patches.is_empty().then_some(data.ends_with(b"\n"));
+                let mut file = decoder.create_file(header, data)?;
+                file.trailing_newline = trailing_newline;
+                let data = if options.utf8 { file.utf8_content() } else { file.content() };
+                std::fs::write(&path, data)?;
+            }
+            Sink::Text { mut out, pending, last_break } => {
+                if pending.is_some() {
+                    // The final newline follows the contents, as `File::content` writes it
+                    let crlf = header.line_ending == Some(LineEnding::CrLf);
+                    let line_break = last_break.unwrap_or(if crlf { "\r\n" } else { "\n" });
+                    out.write_all(line_break.as_bytes())?;
+                }
+                out.flush()?;
+            }
+            Sink::Base64 { mut out, lines, mut decoded } => {
+                decoded.clear();
+                lines.finish(decoder, &header.name, header.line, &mut decoded).map_err(|e| section_error(&header, e))?;
+                out.write_all(&decoded)?;
+                out.flush()?;
+            }
+        }
+        metadata.apply(&path, options.preserve_permissions, options.preserve_mtimes)?;
+        trace_event!(path = %path.display(), "extracted file");
+        Ok(Some(path))
+    }
+}
+
+/// Read a line into `buf`, returning the bytes consumed and the line as
+/// `str::lines` yields it, or `None` at the end of input
+#[cfg(feature = "std")]
+fn read_line<'a>(reader: &mut impl std::io::BufRead, buf: &'a mut Vec<u8>) -> Result<Option<(usize, &'a str)>> {
+    buf.clear();
+    let read = reader.read_until(BINARY_NEWLINE, buf)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if buf.ends_with(b"\n") {
+        buf.pop();
+    }
+    if buf.ends_with(b"\r") {
+        buf.pop();
+    }
+    let line = core::str::from_utf8(buf).map_err(|_| anyhow!("Archive is not valid UTF-8"))?;
+    Ok(Some((read, line)))
+}
+
+/// Bytes read between progress reports within an entry
+#[cfg(feature = "std")]
+const PROGRESS_INTERVAL: u64 = 64 * 1024;
+
+#[cfg(feature = "std")]
+impl Decoder {
+    /// Decode an archive read from `reader` without loading its text whole
+    ///
+    /// The archive is parsed a line at a time and base64 sections are
+    /// decoded as they are read, so only the decoded contents are held. See
+    /// [`decode_with_progress`](Self::decode_with_progress) to follow
+    /// progress on large inputs.
+    pub fn decode_from_reader<R: std::io::Read>(&self, reader: R) -> Result<Archive> {
+        self.decode_with_progress(std::io::BufReader::new(reader), |_, _| {})
+    }
+
+    /// Decode an archive read from `reader`, reporting progress as
+    /// `progress(bytes_read, files_done)`
+    ///
+    /// The archive is read a line at a time rather than loaded whole.
+    /// `progress` is called after each entry, after any line that takes the
+    /// bytes read 64 KiB past the last report, and once at the end. Combine with
+    /// [`with_memory_limit`](Self::with_memory_limit) to stop decoding
+    /// archives too large to hold.
+    pub fn decode_with_progress(
+        &self,
+        mut reader: impl std::io::BufRead,
+        mut progress: impl FnMut(u64, usize),
+    ) -> Result<Archive> {
+        trace_span!("decode_reader");
+        let mut state = DecodeState::default();
+        let mut line = Vec::new();
+        let mut bytes_read = 0u64;
+        let mut files_done = 0;
+        let mut reported = 0u64;
+
+        while let Some((read, line)) = read_line(&mut reader, &mut line)? {
+            bytes_read += read as u64;
+            state.unterminated = read == line.len();
+            state.crlf = read == line.len() + 2;
+            if state.push_line(self, line)? {
+                files_done += 1;
+                progress(bytes_read, files_done);
+                reported = bytes_read;
+            } else if bytes_read - reported >= PROGRESS_INTERVAL {
+                progress(bytes_read, files_done);
+                reported = bytes_read;
+            }
+        }
+        profile_count!(bytes_decoded, bytes_read);
+
+        let files_before = state.archive.files.len();
+        let archive = state.finish(self)?;
+        files_done += archive.files.len() - files_before;
+        progress(bytes_read, files_done);
+        Ok(archive)
+    }
+
+    /// Extract an archive read from `reader` into a directory, streaming each
+    /// file to disk instead of decoding the whole archive in memory
+    ///
+    /// Text and base64 entries are written as they are read, base64 being
+    /// decoded a line at a time, so memory use does not grow with file size.
+    /// Entries with escaped markers, patches or a non-UTF-8 encoding are
+    /// buffered one at a time. Otherwise writes the same files as
+    /// [`Archive::extract_to_dir`], except that edit entries are neither
+    /// parsed nor validated.
+    ///
+    /// Returns the paths written.
+    pub fn extract_reader(
+        &self,
+        mut reader: impl std::io::BufRead,
+        dir: &Path,
+        options: &ExtractOptions,
+    ) -> Result<Vec<PathBuf>> {
+        trace_span!("extract_reader", dir = %dir.display());
+        let mut written = Vec::new();
+        let mut names = std::collections::HashSet::new();
+        let mut current: Option<StreamEntry> = None;
+        let mut line = Vec::new();
+
+        let mut comment = String::new();
+        let mut version_checked = false;
+        let mut number = 0;
+        while let Some((read, line)) = read_line(&mut reader, &mut line)? {
+            number += 1;
+            let crlf = read == line.len() + 2;
+            let terminated = read > line.len();
+            let marker = self.parse_file_marker(line);
+            if !version_checked && !matches!(marker, Ok(None)) {
+                version_checked = true;
+                self.check_version(&comment)?;
+            }
+            if let Some(mut header) = marker? {
+                header.line = number;
+                if terminated {
+                    header.record_line_ending(crlf);
+                }
+                if let Some(entry) = current.take() {
+                    written.extend(entry.finish(self, options)?);
+                }
+                if !header.conditions.is_empty() {
+                    let conditions: String = header.conditions.iter().map(|c| c.to_string()).collect();
+                    bail!("Unresolved conditional entry: {}{}", header.name, conditions);
+                }
+                let mut skip = false;
+                let unique = crate::archive::is_unique_entry(&header.snippet_ref, &header.edit_ref, &header.conditions);
+                if unique && !names.insert(header.name.clone()) {
+                    let name = header.name.clone();
+                    let mut renamed = None;
+                    match self.duplicates {
+                        DuplicatePolicy::Error => bail!("Duplicate file: {}", header.name),
+                        DuplicatePolicy::KeepFirst => skip = true,
+                        // The file is written again
+                        DuplicatePolicy::KeepLast => {}
+                        DuplicatePolicy::Rename => {
+                            header.name = unique_name(&name, |candidate| names.contains(candidate));
+                            names.insert(header.name.clone());
+                            renamed = Some(header.name.clone());
+                        }
+                    }
+                    self.report(Diagnostic::DuplicateFile { name, policy: self.duplicates, renamed });
+                }
+                current = Some(StreamEntry::start(header, dir, options, skip)?);
+            } else if let Some(entry) = &mut current {
+                entry.push_line(self, line, number, crlf, terminated)?;
+            } else {
+                comment.push_str(line);
+                comment.push('\n');
+            }
+        }
+        if !version_checked {
+            self.check_version(&comment)?;
+        }
+
+        if let Some(entry) = current.take() {
+            written.extend(entry.finish(self, options)?);
+        }
+        if self.duplicates == DuplicatePolicy::KeepLast {
+            // Files written again are listed once
+            let mut seen = std::collections::HashSet::new();
+            written.retain(|path| seen.insert(path.clone()));
+        }
+        Ok(written)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -293,7 +1474,7 @@ Hello, world!"#;
 
         assert_eq!(archive.files.len(), 1);
         assert_eq!(archive.files[0].name, "file1.txt");
-        assert_eq!(archive.files[0].data, b"Hello, world!");
+        assert_eq!(&archive.files[0].data[..], b"Hello, world!");
         assert!(!archive.files[0].is_binary);
     }
 
@@ -311,6 +1492,54 @@ Hello, world!"#;
         assert!(archive.files[0].is_binary);
     }
 
+    #[test]
+    fn test_decode_base64_errors_and_blank_lines() {
+        let input = "note\n-- a.bin[.base64] --\n/9j/\n\n  AAAA \n";
+        let archive = Decoder::new().decode(input).unwrap();
+        assert_eq!(archive.files[0].data, vec![0xFF, 0xD8, 0xFF, 0, 0, 0]);
+
+        let err = Decoder::new().with_strict(true).decode(input).unwrap_err();
+        assert_eq!(err.to_string(), "Blank line 4 in base64 section of 'a.bin'");
+
+        let err = Decoder::new().decode("-- a.bin[.base64] --\nAAAA\n  AA!A\n").unwrap_err();
+        assert_eq!(err.to_string(), "Invalid base64 character '!' in 'a.bin' at line 3, column 5");
+
+        let err = Decoder::new().decode("x\n-- a.bin[.base64] --\nAA=A\n").unwrap_err();
+        assert!(err.to_string().ends_with("(section starting at line 2)"), "{}", err);
+        let err = Decoder::new().decode("-- a.bin[.base64] --\nAA\n==\nAAAA\n").unwrap_err();
+        assert_eq!(err.to_string(), "Failed to decode base64 for file 'a.bin': data after padding (section starting at line 1)");
+
+        #[cfg(feature = "std")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let options = ExtractOptions::default();
+            let err = Decoder::new().extract_reader("-- a.bin[.base64] --\nAAAA\nA#AA\n".as_bytes(), dir.path(), &options);
+            assert!(err.unwrap_err().to_string().contains("at line 3, column 2"));
+        }
+    }
+
+    #[test]
+    fn test_decode_base64_variants() {
+        // 0xFB 0xFF 0xBF is "+/+/" in the standard alphabet and "-_-_" URL-safe
+        let expected = [0xFB, 0xFF, 0xBF, 0x48, 0x69];
+        #[cfg(feature = "std")]
+        let dir = tempfile::tempdir().unwrap();
+        for body in ["+/+/SGk=", "-_-_SGk=", "+/+/SGk", "-_-_\nSGk", "+/ +/\tSG k=", "+/+/SGl="] {
+            let input = format!("-- a.bin[.base64] --\n{}\n", body);
+            let archive = Decoder::new().decode(&input).unwrap();
+            assert_eq!(archive.files[0].data.as_ref(), expected, "{:?}", body);
+
+            #[cfg(feature = "std")]
+            {
+                let written = Decoder::new().extract_reader(input.as_bytes(), dir.path(), &ExtractOptions::default()).unwrap();
+                assert_eq!(std::fs::read(&written[0]).unwrap(), expected, "{:?}", body);
+            }
+            if body != "+/+/SGk=" {
+                assert!(Decoder::new().with_strict(true).decode(&input).is_err(), "{:?}", body);
+            }
+        }
+    }
+
     #[test]
     fn test_decode_multiple_files() {
         let input = r#"-- file1.txt --
@@ -399,7 +1628,7 @@ Content of file"#;
         let archive = decoder.decode(input).unwrap();
 
         assert_eq!(archive.files[0].name, "file.txt");
-        assert_eq!(archive.files[0].data, b"Content of file");
+        assert_eq!(&archive.files[0].data[..], b"Content of file");
         assert!(archive.files[0].snippet_ref.is_some());
         let ref_obj = archive.files[0].snippet_ref.as_ref().unwrap();
         assert!(ref_obj.command_href.is_none());
@@ -415,7 +1644,7 @@ Content of file"#;
         let archive = decoder.decode(input).unwrap();
 
         assert_eq!(archive.files[0].name, "file.txt");
-        assert_eq!(archive.files[0].data, b"Content of file");
+        assert_eq!(&archive.files[0].data[..], b"Content of file");
         assert!(archive.files[0].snippet_ref.is_some());
         let ref_obj = archive.files[0].snippet_ref.as_ref().unwrap();
         assert_eq!(ref_obj.command_href.as_deref(), Some("search1"));
@@ -431,13 +1660,153 @@ Content of file"#;
         let archive = decoder.decode(input).unwrap();
 
         assert_eq!(archive.files[0].name, "file.txt");
-        assert_eq!(archive.files[0].data, b"Content of file");
+        assert_eq!(&archive.files[0].data[..], b"Content of file");
         assert!(archive.files[0].snippet_ref.is_some());
         let ref_obj = archive.files[0].snippet_ref.as_ref().unwrap();
         assert_eq!(ref_obj.command_href.as_deref(), Some("search1"));
         assert_eq!(ref_obj.line, 10);
     }
 
+    #[test]
+    fn test_decode_snippet_with_language() {
+        let input = r#"-- main.rs[.#search1:10:lang=rust] --
+fn main() {}"#;
+
+        let decoder = Decoder::new();
+        let archive = decoder.decode(input).unwrap();
+
+        assert_eq!(archive.files[0].name, "main.rs");
+        let ref_obj = archive.files[0].snippet_ref.as_ref().unwrap();
+        assert_eq!(ref_obj.command_href.as_deref(), Some("search1"));
+        assert_eq!(ref_obj.line, 10);
+        assert_eq!(ref_obj.language.as_deref(), Some("rust"));
+    }
+
+    #[test]
+    fn test_decode_encoding_tags() {
+        let input = "-- a.txt[.enc:utf-8] --\nplain\n-- b.txt[.enc:latin1] --\ncafé\n-- c.txt[.base64][.enc:gbk] --\nxOO6ww==\n";
+
+        let decoder = Decoder::new();
+        let archive = decoder.decode(input).unwrap();
+
+        assert_eq!(archive.files[0].encoding, TextEncoding::Utf8);
+        assert_eq!(&archive.files[0].data[..], b"plain");
+        assert_eq!(archive.files[1].encoding, TextEncoding::Latin1);
+        assert_eq!(&archive.files[1].data[..], b"caf\xe9");
+        assert!(archive.files[2].is_binary);
+        assert_eq!(archive.files[2].encoding, TextEncoding::Gbk);
+        assert_eq!(archive.files[2].archive_name(), "c.txt[.base64][.enc:gbk]");
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    #[allow(deprecated)]
+    fn test_with_verbose_forwards_to_diagnostics() {
+        assert!(Decoder::new().with_verbose(0).diagnostics.is_none());
+        assert!(Decoder::new().with_verbose(2).diagnostics.is_some());
+        let collector = Arc::new(crate::diagnostics::Collector::default());
+        assert!(Decoder::new().with_diagnostics(collector).with_verbose(0).diagnostics.is_none());
+    }
+
+    #[test]
+    fn test_decode_escape_tag_mismatch() {
+        let input = "-- doc.md[.esc:1] --\nnot escaped\n";
+
+        let decoder = Decoder::new();
+        let err = decoder.decode(input).unwrap_err();
+        assert!(err.to_string().contains("is not escaped"));
+    }
+
+    #[test]
+    fn test_decode_patch_tag() {
+        let input = "-- app.log[.patch:4=/w==] --\nbad \u{FFFD} byte\n";
+
+        let decoder = Decoder::new();
+        let archive = decoder.decode(input).unwrap();
+        assert_eq!(&archive.files[0].data[..], b"bad \xFF byte");
+        assert_eq!(archive.files[0].binary_reason, Some(BinaryReason::InvalidUtf8 { offset: 4 }));
+
+        let err = decoder.decode("-- app.log[.patch:0=/w==] --\nbad\n").unwrap_err();
+        assert!(err.to_string().contains("No placeholder at offset 0"));
+    }
+
+    #[test]
+    fn test_decode_metadata_tags() {
+        let input = "-- run.sh[.mode:0755][.mtime:1700000000] --\n#!/bin/sh\n";
+
+        let decoder = Decoder::new();
+        let archive = decoder.decode(input).unwrap();
+        assert_eq!(archive.files[0].name, "run.sh");
+        assert_eq!(archive.files[0].metadata, FileMetadata { mode: Some(0o755), mtime: Some(1_700_000_000) });
+
+        let err = decoder.decode("-- run.sh[.mode:rwx] --\n").unwrap_err();
+        assert!(err.to_string().contains("Invalid mode tag"));
+    }
+
+    #[test]
+    fn test_decode_nested_archive() {
+        let mut inner = Archive::with_comment("inner suite");
+        inner.add_file(File::new("input.txt", "hello\n")).unwrap();
+        let mut outer = Archive::new();
+        outer.add_file(File::from_archive("suite.txtar", &inner).unwrap()).unwrap();
+        outer.add_file(File::new("plain.txt", "-- not nested --\n")).unwrap();
+
+        let encoded = crate::Encoder::new().encode(&outer).unwrap();
+        assert!(encoded.contains("-- suite.txtar[.base64][.txtar] --"), "{}", encoded);
+        let decoded = Decoder::new().decode(&encoded).unwrap();
+        assert!(decoded.files[0].is_archive);
+        let nested = decoded.files[0].as_archive().unwrap();
+        assert_eq!(nested.comment, "inner suite");
+        assert_eq!(nested.files[0].content().as_ref(), b"hello\n");
+        assert!(decoded.files[1].as_archive().is_err());
+
+        // Each level wraps the previous one; decoding stops at the limit
+        let mut file = File::from_archive("level0", &inner).unwrap();
+        for level in 1..=3 {
+            let mut archive = Archive::new();
+            archive.add_file(file).unwrap();
+            file = File::from_archive(format!("level{}", level), &archive).unwrap();
+        }
+        let decoder = Decoder::new().with_max_depth(2);
+        let level2 = decoder.decode_nested(&file).unwrap();
+        let decoder = decoder.nested().unwrap();
+        let level1 = decoder.decode_nested(&level2.files[0]).unwrap();
+        let decoder = decoder.nested().unwrap();
+        let err = decoder.decode_nested(&level1.files[0]).unwrap_err();
+        assert!(err.to_string().contains("exceeds the depth limit of 2"), "{}", err);
+        assert!(decoder.nested().is_none());
+        assert!(Decoder::new().decode_nested(&level1.files[0]).is_ok());
+    }
+
+    #[test]
+    fn test_decode_crypt_tag() {
+        let decoded = Decoder::new().decode("-- a[.base64][.crypt:xchacha20poly1305] --\nAAAA\n").unwrap();
+        assert!(decoded.files[0].is_encrypted);
+        assert_eq!(decoded.files[0].archive_name(), "a[.base64][.crypt:xchacha20poly1305]");
+
+        let err = Decoder::new().decode("-- a[.base64][.crypt:rot13] --\nAAAA\n").unwrap_err();
+        assert!(err.to_string().contains("Unknown encryption scheme 'rot13'"), "{}", err);
+        let err = Decoder::new().decode("-- a[.crypt:xchacha20poly1305] --\ntext\n").unwrap_err();
+        assert!(err.to_string().contains("is not base64 encoded"), "{}", err);
+    }
+
+    #[test]
+    fn test_decode_unknown_encoding_tag() {
+        let input = "-- a.txt[.enc:klingon] --\ntext\n";
+
+        let decoder = Decoder::new();
+        let err = decoder.decode(input).unwrap_err();
+        assert!(err.to_string().contains("Unknown text encoding 'klingon'"));
+    }
+
+    #[test]
+    fn test_decode_unencodable_text() {
+        let input = "-- a.txt[.enc:latin1] --\n日本\n";
+
+        let decoder = Decoder::new();
+        assert!(decoder.decode(input).is_err());
+    }
+
     #[test]
     fn test_decode_with_commands_in_comment() {
         let input = r#"This is a commit block with command references:
@@ -514,16 +1883,149 @@ Content 2"#;
     }
 
     #[test]
+    fn test_decode_fidelity_round_trip() {
+        use crate::Encoder;
+
+        let decoder = Decoder::new().with_fidelity(true);
+        let inputs = [
+            "",
+            "Notes\n\n",
+            "# Fixtures\n\n\n-- a.txt --\nA\n-- empty --\n-- blank --\n\n-- two --\n\n\n-- last --\nno newline",
+            "-- a --\nA\n\n-- bin[.base64] --\nAAE=\n",
+            "-- only --\n",
+        ];
+        for input in inputs {
+            let archive = decoder.decode(input).unwrap();
+            assert_eq!(Encoder::new().encode(&archive).unwrap(), input);
+            #[cfg(feature = "std")]
+            assert_eq!(decoder.decode_from_reader(input.as_bytes()).unwrap(), archive);
+            // The files are those decoded without fidelity
+            assert_eq!(archive.files, Decoder::new().decode(input).unwrap().files);
+        }
+
+        // Files record their own final newline, which content puts back
+        let archive = decoder.decode("-- empty --\n-- blank --\n\n-- two --\nA\n\n-- last --\ntext").unwrap();
+        let flags: Vec<_> = archive.files.iter().map(|f| f.trailing_newline).collect();
+        assert_eq!(flags, [Some(false), Some(true), Some(true), Some(false)]);
+        let contents: Vec<_> = archive.files.iter().map(|f| f.content().into_owned()).collect();
+        assert_eq!(contents, [&b""[..], b"\n", b"A\n\n", b"text"]);
+
+        // Files added after a section without a line break start on a new line
+        let mut archive = archive;
+        archive.add_file(File::new("next", "N")).unwrap();
+        let text = Encoder::new().encode(&archive).unwrap();
+        assert!(text.ends_with("-- last --\ntext\n-- next --\nN\n"));
+        let stream = Encoder::new().encode_stream(&[("x".into(), decoder.decode("-- a --\nA").unwrap()), ("y".into(), Archive::new())]).unwrap();
+        assert_eq!(stream, "=== archive: x ===\n-- a --\nA\n=== archive: y ===\n");
+
+        // Without fidelity the comment loses its closing blank lines
+        assert_eq!(Decoder::new().decode("Notes\n\n").unwrap().comment, "Notes\n");
+        // A final comment line without a line break gains one
+        assert_eq!(Encoder::new().encode(&decoder.decode("Notes").unwrap()).unwrap(), "Notes\n");
+    }
+
+    #[test]
+    fn test_decode_line_endings() {
+        use crate::Encoder;
+
+        let input = "Notes\r\nmore\r\n-- crlf.txt --\r\nA\r\nB\r\n-- lf.txt --\nC\nD\n-- mixed --\nE\r\nF\n";
+        let decode = |line_ending| {
+            let decoder = Decoder::new().with_line_ending(line_ending);
+            let archive = decoder.decode(input).unwrap();
+            #[cfg(feature = "std")]
+            assert_eq!(decoder.decode_from_reader(input.as_bytes()).unwrap(), archive);
+            assert_eq!(decoder.parse_borrowed(input).unwrap().into_archive(), archive);
+            let data: Vec<_> = archive.files.iter().map(|f| String::from_utf8(f.data.to_vec()).unwrap()).collect();
+            (archive, data)
+        };
+
+        let (archive, data) = decode(LineEnding::Lf);
+        assert_eq!(archive.comment, "Notes\nmore");
+        assert_eq!(data, ["A\nB", "C\nD", "E\nF"]);
+        let endings: Vec<_> = archive.files.iter().map(|f| f.line_ending).collect();
+        assert_eq!(endings, [LineEnding::CrLf, LineEnding::Preserve, LineEnding::Preserve]);
+        // The encoder writes the CRLF file back as it was
+        let encoded = Encoder::new().encode(&archive).unwrap();
+        assert_eq!(encoded, "Notes\nmore\n-- crlf.txt --\r\nA\r\nB\r\n-- lf.txt --\nC\nD\n-- mixed --\nE\nF\n");
+
+        let (archive, data) = decode(LineEnding::Preserve);
+        assert_eq!(archive.comment, "Notes\r\nmore");
+        assert_eq!(data, ["A\r\nB", "C\nD", "E\r\nF"]);
+        assert_eq!(archive.files[0].line_ending, LineEnding::CrLf);
+
+        let (archive, data) = decode(LineEnding::CrLf);
+        assert_eq!(archive.comment, "Notes\r\nmore");
+        assert_eq!(data, ["A\r\nB", "C\r\nD", "E\r\nF"]);
+
+        // Plain text with CRLF line breaks is still borrowed when kept
+        let borrowed = Decoder::new().with_line_ending(LineEnding::Preserve).parse_borrowed(input).unwrap();
+        assert_eq!(borrowed.get_file("crlf.txt").unwrap().borrowed(), Some("A\r\nB"));
+        assert_eq!(Decoder::new().parse_borrowed(input).unwrap().get_file("crlf.txt").unwrap().borrowed(), None);
+
+        // Kept line breaks and fidelity reproduce the archive exactly
+        let decoder = Decoder::new().with_line_ending(LineEnding::Preserve).with_fidelity(true);
+        for input in [input, "Notes\r\n", "-- a --\r\nA\r\n-- b --\r\nB"] {
+            assert_eq!(Encoder::new().encode(&decoder.decode(input).unwrap()).unwrap(), input);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_decode_duplicate_policy() {
+        use crate::diagnostics::Collector;
+
+        let input = "-- a.txt --\n1\n-- dir/a.txt --\nx\n-- a.txt --\n2\n-- a.txt --\n3\n";
+        let decode = |policy| {
+            let collector = Arc::new(Collector::default());
+            let archive = Decoder::new().with_duplicate_policy(policy).with_diagnostics(collector.clone()).decode(input);
+            let files = archive.map(|a| a.files.iter().map(|f| (f.name.clone(), f.text().unwrap().to_string())).collect::<Vec<_>>());
+            (files, collector.take())
+        };
+        let pairs = |items: &[(&str, &str)]| items.iter().map(|&(n, t)| (n.to_string(), t.to_string())).collect::<Vec<_>>();
+
+        assert!(decode(DuplicatePolicy::Error).0.is_err());
+        let (files, diagnostics) = decode(DuplicatePolicy::KeepFirst);
+        assert_eq!(files.unwrap(), pairs(&[("a.txt", "1"), ("dir/a.txt", "x")]));
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].to_string(), "Dropped duplicate file 'a.txt', keeping the first");
+        assert_eq!(decode(DuplicatePolicy::KeepLast).0.unwrap(), pairs(&[("dir/a.txt", "x"), ("a.txt", "3")]));
+        let (files, diagnostics) = decode(DuplicatePolicy::Rename);
+        assert_eq!(files.unwrap(), pairs(&[("a.txt", "1"), ("dir/a.txt", "x"), ("a-2.txt", "2"), ("a-3.txt", "3")]));
+        assert_eq!(diagnostics[1], Diagnostic::DuplicateFile {
+            name: "a.txt".into(),
+            policy: DuplicatePolicy::Rename,
+            renamed: Some("a-3.txt".into()),
+        });
+        assert_eq!(unique_name("dir.d/Makefile", |_| false), "dir.d/Makefile-2");
+        assert_eq!(unique_name(".env", |_| false), ".env-2");
+
+        // Positions and borrowed contents follow the kept entries
+        let decoder = Decoder::new().with_duplicate_policy(DuplicatePolicy::KeepLast);
+        let archive = decoder.decode(input).unwrap();
+        assert_eq!(archive.locate("a.txt", 1).map(|p| p.line), Some(8));
+        let borrowed = decoder.parse_borrowed(input).unwrap();
+        assert_eq!(borrowed.files().map(|f| f.borrowed().unwrap()).collect::<Vec<_>>(), ["x", "3"]);
+        let archive = Decoder::new().with_duplicate_policy(DuplicatePolicy::Rename).decode(input).unwrap();
+        assert_eq!(archive.locate("a-2.txt", 1).map(|p| p.line), Some(6));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
     fn test_decode_invalid_command_reference_warning() {
         let input = r#"-- file.txt[.#nonexistent:10] --
 Content"#;
 
-        let decoder = Decoder::new().with_verbose(1);
+        let collector = Arc::new(crate::diagnostics::Collector::default());
+        let decoder = Decoder::new().with_diagnostics(collector.clone());
         let archive = decoder.decode(input).unwrap();
 
         // Should succeed but the invalid reference is noted
         assert_eq!(archive.files.len(), 1);
         assert!(archive.files[0].snippet_ref.is_some());
+        assert_eq!(collector.take(), vec![Diagnostic::UnresolvedSnippet {
+            name: "file.txt".to_string(),
+            command: "nonexistent".to_string(),
+        }]);
     }
 
     #[test]
@@ -615,6 +2117,27 @@ new line
         assert_eq!(edit_ref.edits.len(), 1);
     }
 
+    #[test]
+    fn test_decode_edit_file_with_line_only() {
+        let input = r#"-- target.txt --
+original
+
+-- target.txt[.edit:7] --
+<<<<<<< SEARCH
+original
+=======
+changed
+>>>>>>> REPLACE"#;
+
+        let decoder = Decoder::new();
+        let archive = decoder.decode(input).unwrap();
+
+        let edit_ref = archive.files[1].edit_ref.as_ref().unwrap();
+        assert!(edit_ref.command_href.is_none());
+        assert_eq!(edit_ref.start_line, Some(7));
+        assert_eq!(edit_ref.edits.len(), 1);
+    }
+
     #[test]
     fn test_decode_edit_delete_operation() {
         let input = r#"-- target.txt --
@@ -755,6 +2278,10 @@ new
         // Should fail - target file doesn't exist in txtar or filesystem
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("not found in archive or filesystem"));
+
+        // Unless the edit is meant for another directory
+        let archive = decoder.with_edit_target_check(false).decode(input).unwrap();
+        assert_eq!(archive.files[0].edit_ref.as_ref().unwrap().edits.len(), 1);
     }
 
     #[test]
@@ -782,6 +2309,153 @@ new
         assert!(archive.files[1].edit_ref.is_some());
     }
 
+    #[test]
+    fn test_decode_stream() {
+        let input = "\n=== archive: first ===\nsetup\n-- a.txt --\nA\n=== archive: second ===\n-- b.txt --\nB\n";
+
+        let archives = Decoder::new().decode_stream(input).unwrap();
+        assert_eq!(archives.len(), 2);
+        assert_eq!(archives[0].0, "first");
+        assert_eq!(archives[0].1.comment, "setup");
+        assert_eq!(&archives[0].1.files[0].data[..], b"A");
+        assert_eq!(archives[1].0, "second");
+        assert_eq!(archives[1].1.files[0].name, "b.txt");
+
+        let single = Decoder::new().decode_stream("-- a.txt --\nA\n").unwrap();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].0, "");
+
+        let duplicate = "=== archive: x ===\n=== archive: x ===\n";
+        assert!(Decoder::new().decode_stream(duplicate).is_err());
+        assert!(Decoder::new().decode_stream("stray\n=== archive: x ===\n").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_extract_reader_duplicate_policy() {
+        let input = "-- a.txt --\n1\n-- a.txt --\n2\n";
+        let options = ExtractOptions::default();
+        for (policy, expected) in [
+            (DuplicatePolicy::KeepFirst, &[("a.txt", "1\n")][..]),
+            (DuplicatePolicy::KeepLast, &[("a.txt", "2\n")][..]),
+            (DuplicatePolicy::Rename, &[("a.txt", "1\n"), ("a-2.txt", "2\n")][..]),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let decoder = Decoder::new().with_duplicate_policy(policy);
+            let written = decoder.extract_reader(input.as_bytes(), dir.path(), &options).unwrap();
+            assert_eq!(written.len(), expected.len(), "{:?}", policy);
+            for (name, text) in expected {
+                assert_eq!(std::fs::read_to_string(dir.path().join(name)).unwrap(), *text, "{:?}", policy);
+            }
+        }
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Decoder::new().extract_reader(input.as_bytes(), dir.path(), &options).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_extract_reader_matches_extract_to_dir() {
+        use crate::archive::{EncodingConfig, ExtractOptions, FileMetadata};
+
+        let binary: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 256) as u8).collect();
+        let mut archive = Archive::new();
+        archive.add_file(File::new("dir/a.txt", "line 1\nline 2\n")).unwrap();
+        archive.add_file(File::new("blob.bin", binary.clone())
+            .with_metadata(FileMetadata { mode: Some(0o600), mtime: Some(1_000_000_000) })).unwrap();
+        let latin1 = EncodingConfig { latin1_fallback: true, ..Default::default() };
+        archive.add_file(File::with_config("legacy.cfg", b"caf\xe9".to_vec(), &latin1)).unwrap();
+        archive.add_file(File::new("doc.md", "-- a.txt --\nexample")).unwrap();
+        archive.add_file(File::new("empty.txt", "")).unwrap();
+        let text = crate::Encoder::new().encode(&archive).unwrap().replace('\n', "\r\n");
+
+        let options = ExtractOptions { preserve_mtimes: true, ..Default::default() };
+        let streamed = tempfile::tempdir().unwrap();
+        let written = Decoder::new().extract_reader(text.as_bytes(), streamed.path(), &options).unwrap();
+        let expected = tempfile::tempdir().unwrap();
+        Decoder::new().decode(&text).unwrap().extract_to_dir(expected.path(), &options).unwrap();
+
+        assert_eq!(written.len(), 5);
+        for file in &archive.files {
+            let data = std::fs::read(streamed.path().join(&file.name)).unwrap();
+            assert_eq!(data, std::fs::read(expected.path().join(&file.name)).unwrap(), "{}", file.name);
+        }
+        assert_eq!(std::fs::read(streamed.path().join("blob.bin")).unwrap(), binary);
+        let metadata = FileMetadata::from_fs(&std::fs::metadata(streamed.path().join("blob.bin")).unwrap());
+        assert_eq!(metadata.mtime, Some(1_000_000_000));
+
+        let duplicate = "-- a.txt --\nA\n-- a.txt --\nB\n";
+        assert!(Decoder::new().extract_reader(duplicate.as_bytes(), streamed.path(), &options).is_err());
+        let bad = "-- a.bin[.base64] --\nAAAA=\nAAAA\n";
+        assert!(Decoder::new().extract_reader(bad.as_bytes(), streamed.path(), &options).is_err());
+    }
+
+    #[test]
+    fn test_decode_escaped_names() {
+        let names = ["foo[1].txt", "-- x --", "a\\[b].bin", "dir\\file"];
+        let mut archive = Archive::new();
+        for name in names {
+            archive.add_file(File::new(name, "content")).unwrap();
+        }
+        let encoded = crate::Encoder::new().encode(&archive).unwrap();
+        assert!(encoded.contains("-- foo\\[1\\].txt --\n"));
+
+        let strict = Decoder::new().with_strict(true);
+        let decoded = strict.decode(&encoded).unwrap();
+        let decoded_names: Vec<&str> = decoded.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(decoded_names, names);
+
+        // Unescaped, the bracket reads as an unknown tag and `.txt` is lost
+        let raw = "-- foo[1].txt --\ncontent\n";
+        assert_eq!(Decoder::new().decode(raw).unwrap().files[0].name, "foo");
+        assert!(strict.decode(raw).is_err());
+        assert!(strict.decode("-- a.txt[.bogus] --\n").is_err());
+        assert!(strict.decode("-- -- x -- --\n").is_err());
+        assert_eq!(Decoder::new().decode("-- -- x -- --\n").unwrap().files[0].name, "-- x --");
+    }
+
+    #[test]
+    fn test_decode_degenerate_markers() {
+        let archive = Decoder::new().decode("-- a --\n-- --\n--  --\n-- b --\n").unwrap();
+        assert_eq!(archive.files.len(), 2);
+        assert_eq!(archive.files[0].data.as_ref(), b"-- --\n--  --");
+
+        // A trailing carriage return ends the last line
+        let archive = Decoder::new().decode("comment\r").unwrap();
+        assert_eq!(archive.comment, "comment");
+        #[cfg(feature = "std")]
+        assert_eq!(Decoder::new().decode_with_progress(&b"comment\r"[..], |_, _| {}).unwrap(), archive);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_decode_with_progress() {
+        let mut archive = Archive::with_comment("comment");
+        archive.add_file(File::new("a.txt", "A\r\nB")).unwrap();
+        let blob: Vec<u8> = (0..100_000u32).map(|i| (i.wrapping_mul(2654435761) >> 13) as u8).collect();
+        archive.add_file(File::new("blob.bin", blob)).unwrap();
+        archive.add_file(File::new("c.txt", "C")).unwrap();
+        let encoded = crate::Encoder::new().encode(&archive).unwrap();
+
+        let mut reports = Vec::new();
+        let decoded = Decoder::new()
+            .decode_with_progress(encoded.as_bytes(), |bytes, files| reports.push((bytes, files)))
+            .unwrap();
+        assert_eq!(decoded, Decoder::new().decode(&encoded).unwrap());
+        assert_eq!(reports.last(), Some(&(encoded.len() as u64, 3)));
+        // The blob is reported before the marker after it completes the entry
+        assert!(reports.iter().any(|&(bytes, files)| files == 1 && bytes > reports[0].0), "{:?}", reports);
+        assert!(reports.windows(2).all(|w| w[0] <= w[1]));
+
+        assert_eq!(Decoder::new().decode_from_reader(encoded.as_bytes()).unwrap(), decoded);
+        // Base64 is decoded as it is read, so the limit need not cover its text
+        assert!(Decoder::new().with_memory_limit(110_000).decode_from_reader(encoded.as_bytes()).is_ok());
+
+        let limited = Decoder::new().with_memory_limit(50_000);
+        assert!(limited.decode_with_progress(encoded.as_bytes(), |_, _| {}).is_err());
+        assert!(limited.decode(&encoded).is_err());
+        assert!(Decoder::new().with_memory_limit(200_000).decode(&encoded).is_ok());
+    }
+
     #[test]
     fn test_decode_edit_empty_search_with_replacement() {
         let input = r#"-- empty.txt --
@@ -0,0 +1,197 @@
+//! Warnings reported while decoding, encoding and applying edits
+//!
+//! Problems that do not stop an operation are passed to a [`Diagnostics`]
+//! sink instead of being printed, so host applications decide where they
+//! go. Nothing is reported unless a sink is set, e.g. with
+//! [`Decoder::with_diagnostics`](crate::Decoder::with_diagnostics):
+//!
+//! ```
+//! use emx_txtar::diagnostics::{Collector, Diagnostic};
+//! use emx_txtar::Decoder;
+//! use std::sync::Arc;
+//!
+//! let collector = Arc::new(Collector::default());
+//! let decoder = Decoder::new().with_diagnostics(collector.clone());
+//! decoder.decode("-- a.txt[.color:red] --\nhello\n").unwrap();
+//! assert!(matches!(&collector.take()[..], [Diagnostic::UnknownTag { .. }]));
+//! ```
+
+use crate::archive::BinaryReason;
+use crate::decoder::DuplicatePolicy;
+use alloc::string::String;
+#[cfg(feature = "std")]
+use alloc::vec::Vec;
+
+/// A warning about an archive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// A header name contains an unescaped `-- ` ... ` --` marker pattern
+    MarkerPatternInName { name: String },
+    /// An unrecognized header tag was ignored
+    UnknownTag { name: String, tag: String },
+    /// A base64 section used the URL-safe alphabet or inner whitespace
+    /// (reported once, at the first such line) or lacked padding (reported
+    /// at the entry's marker line)
+    NonCanonicalBase64 { name: String, line: usize },
+    /// A snippet references a command missing from the comment
+    UnresolvedSnippet { name: String, command: String },
+    /// A text file was stored as base64 because it contains a marker line
+    StoredAsBase64 { name: String, reason: BinaryReason },
+    /// A SEARCH block matched in several places; the first one, or the one
+    /// nearest the edit's start line, was edited
+    AmbiguousSearch { name: String, edit: usize, matches: usize },
+    /// An archive declaring a newer or invalid format version was decoded
+    /// as the current version
+    UnsupportedVersion { declared: String },
+    /// A normal file name appeared again and the entry was kept, dropped or
+    /// renamed (to `renamed`) as `policy` says
+    DuplicateFile { name: String, policy: DuplicatePolicy, renamed: Option<String> },
+}
+
+impl core::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Diagnostic::MarkerPatternInName { name } => {
+                write!(f, "Filename '{}' contains txtar marker pattern, but is not marked as binary", name)
+            }
+            Diagnostic::UnknownTag { name, tag } => write!(f, "Ignored unknown tag '{}' in header of '{}'", tag, name),
+            Diagnostic::NonCanonicalBase64 { name, line } => {
+                write!(f, "Non-standard base64 in '{}' from line {}", name, line)
+            }
+            Diagnostic::UnresolvedSnippet { name, command } => {
+                write!(f, "Snippet '{}' references unknown command '#{}'", name, command)
+            }
+            Diagnostic::StoredAsBase64 { name, reason } => write!(f, "Stored '{}' as base64: {}", name, reason),
+            Diagnostic::AmbiguousSearch { name, edit, matches } => write!(
+                f,
+                "SEARCH block {} for '{}' matches {} times; only one match was edited",
+                edit + 1, name, matches
+            ),
+            Diagnostic::UnsupportedVersion { declared } => write!(
+                f,
+                "Read archive declaring format version '{}' as v{}",
+                declared, crate::version::FORMAT_VERSION
+            ),
+            Diagnostic::DuplicateFile { name, policy, renamed } => match (policy, renamed) {
+                (_, Some(renamed)) => write!(f, "Renamed duplicate file '{}' to '{}'", name, renamed),
+                (DuplicatePolicy::KeepFirst, None) => write!(f, "Dropped duplicate file '{}', keeping the first", name),
+                (DuplicatePolicy::KeepLast, None) => write!(f, "Replaced duplicate file '{}' with the last", name),
+                _ => write!(f, "Duplicate file: {}", name),
+            },
+        }
+    }
+}
+
+/// Receiver of [`Diagnostic`]s
+pub trait Diagnostics: Send + Sync {
+    /// Handle one diagnostic
+    fn report(&self, diagnostic: Diagnostic);
+}
+
+/// Drops every diagnostic
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Ignore;
+
+impl Diagnostics for Ignore {
+    fn report(&self, _diagnostic: Diagnostic) {}
+}
+
+/// Keeps diagnostics in memory for inspection
+#[cfg(feature = "std")]
+#[derive(Debug, Default)]
+pub struct Collector(std::sync::Mutex<Vec<Diagnostic>>);
+
+#[cfg(feature = "std")]
+impl Collector {
+    /// Remove and return the diagnostics collected so far
+    pub fn take(&self) -> Vec<Diagnostic> {
+        core::mem::take(&mut *self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Diagnostics for Collector {
+    fn report(&self, diagnostic: Diagnostic) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(diagnostic);
+    }
+}
+
+/// Prints each diagnostic to stderr as `Warning: ...`
+#[cfg(feature = "std")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Stderr;
+
+#[cfg(feature = "std")]
+impl Diagnostics for Stderr {
+    fn report(&self, diagnostic: Diagnostic) {
+        eprintln!("Warning: {}", diagnostic);
+    }
+}
+
+/// Emits each diagnostic as a `tracing` warning event
+#[cfg(feature = "tracing")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tracing;
+
+#[cfg(feature = "tracing")]
+impl Diagnostics for Tracing {
+    fn report(&self, diagnostic: Diagnostic) {
+        tracing::warn!(target: "emx_txtar", "{}", diagnostic);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{Archive, Decoder, EditBlock, EditOperation, EditRef, Encoder, File};
+    use alloc::sync::Arc;
+    use alloc::vec;
+
+    #[test]
+    fn test_decoder_diagnostics() {
+        let collector = Arc::new(Collector::default());
+        let decoder = Decoder::new().with_diagnostics(collector.clone());
+        let input = "-- -- a -- --\ntext\n-- b.bin[.base64][.color:red] --\n-_-_\nSGk=\n-- c.bin[.base64] --\nSGk\n";
+        decoder.decode(input).unwrap();
+        assert_eq!(collector.take(), vec![
+            Diagnostic::MarkerPatternInName { name: "-- a --".into() },
+            Diagnostic::UnknownTag { name: "b.bin".into(), tag: "[.color:red]".into() },
+            Diagnostic::NonCanonicalBase64 { name: "b.bin".into(), line: 4 },
+            Diagnostic::NonCanonicalBase64 { name: "c.bin".into(), line: 6 },
+        ]);
+
+        assert!(Decoder::new().with_strict(true).with_diagnostics(collector.clone()).decode(input).is_err());
+        assert!(collector.take().is_empty());
+    }
+
+    #[test]
+    fn test_encoder_diagnostics() {
+        let collector = Arc::new(Collector::default());
+        let mut archive = Archive::new();
+        archive.add_file(File::new("doc.md", "-- a.txt --\n")).unwrap();
+        archive.add_file(File::new("blob", vec![0xFF])).unwrap();
+
+        Encoder::new().with_diagnostics(collector.clone()).encode(&archive).unwrap();
+        let diagnostics = collector.take();
+        assert!(matches!(&diagnostics[..], [Diagnostic::StoredAsBase64 { name, .. }] if name == "doc.md"));
+        assert_eq!(diagnostics[0].to_string(), "Stored 'doc.md' as base64: line 1 looks like a file marker: '-- a.txt --'");
+
+        Encoder::new().with_diagnostics(collector.clone()).with_marker_escaping(true).encode(&archive).unwrap();
+        assert!(collector.take().is_empty());
+    }
+
+    #[test]
+    fn test_apply_diagnostics() {
+        let mut archive = Archive::new();
+        archive.add_file(File::new("a.txt", "x\ny\nx\nx")).unwrap();
+        let mut edit = File::new("a.txt", "");
+        let block = EditBlock { search: vec!["x".into()], replacement: vec!["z".into()], operation: EditOperation::Replace, file: None };
+        edit.edit_ref = Some(EditRef { command_href: None, start_line: None, edits: vec![block] });
+        archive.add_file(edit).unwrap();
+
+        let collector = Collector::default();
+        archive.apply_edits_reporting(&collector).unwrap();
+        assert_eq!(archive.files[0].data.as_ref(), b"z\ny\nx\nx");
+        assert_eq!(collector.take(), vec![Diagnostic::AmbiguousSearch { name: "a.txt".into(), edit: 0, matches: 3 }]);
+    }
+}
@@ -0,0 +1,54 @@
+//! Embedding txtar archives in binaries
+//!
+//! [`include_txtar!`](crate::include_txtar) embeds an archive file with
+//! `include_str!`, so no file I/O happens at runtime. The text is decoded
+//! once, on first use, and shared as a `&'static Archive`.
+//!
+//! ```ignore
+//! let fixtures: &'static emx_txtar::Archive = emx_txtar::include_txtar!("fixtures/demo.txtar");
+//! ```
+
+/// Embed a txtar file and decode it lazily into a `&'static Archive`
+///
+/// The path is resolved relative to the invoking source file, as with
+/// `include_str!`.
+///
+/// # Panics
+///
+/// The archive is only decoded at run time, so a malformed file still
+/// compiles; the first evaluation of the macro then panics with the file's
+/// path and the decode error. Check embedded fixtures in a test that calls
+/// the macro.
+#[macro_export]
+macro_rules! include_txtar {
+    ($path:expr) => {{
+        static ARCHIVE: ::std::sync::OnceLock<$crate::Archive> = ::std::sync::OnceLock::new();
+        ARCHIVE.get_or_init(|| {
+            <$crate::Archive as ::std::str::FromStr>::from_str(include_str!($path))
+                .unwrap_or_else(|err| panic!("invalid embedded txtar {}: {:#}", $path, err))
+        })
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Archive;
+
+    fn greeting() -> &'static Archive {
+        include_txtar!("../examples/fixtures/greeting.txtar")
+    }
+
+    #[test]
+    fn test_include_txtar() {
+        let archive = greeting();
+        assert_eq!(archive.comment, "Greeting fixture");
+        assert_eq!(&archive.get_file("hello.txt").unwrap().data[..], b"Hello, world!");
+        assert!(std::ptr::eq(archive, greeting()));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid embedded txtar ../examples/fixtures/malformed.txtar")]
+    fn test_include_txtar_malformed() {
+        include_txtar!("../examples/fixtures/malformed.txtar");
+    }
+}
@@ -1,79 +1,437 @@
 //! Txtar archive encoder
 
-use crate::archive::{Archive, File};
-use anyhow::Result;
+use crate::archive::{
+    parse_stream_delimiter, Archive, BinaryReason, File, LineEnding, TextEncoding, ESCAPE_TAG_PREFIX, MARKER_ESCAPE, PATCH_TAG_PREFIX,
+    STREAM_DELIMITER_PREFIX, STREAM_DELIMITER_SUFFIX,
+};
+use crate::decoder::Decoder;
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use crate::frontmatter::Provenance;
+use crate::tags::TagRegistry;
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use anyhow::{bail, Result};
 use base64::Engine;
 
 /// Encodes an archive into txtar format
 pub struct Encoder {
-    // Currently stateless, but reserved for future options
+    /// Receiver of warnings about the output
+    diagnostics: Option<Arc<dyn Diagnostics>>,
+    /// Escape marker lines in text instead of base64-encoding the file
+    escape_markers: bool,
+    /// Maximum fraction of invalid UTF-8 bytes stored as patches on otherwise readable text
+    mixed_content: Option<f32>,
+    /// Handlers for application-defined tags
+    tags: TagRegistry,
+    /// Provenance stamped into the comment frontmatter
+    provenance: Option<Provenance>,
+    /// Line breaks of the output
+    line_ending: LineEnding,
+    /// Number of threads encoding entries
+    #[cfg(feature = "std")]
+    jobs: usize,
 }
 
 impl Encoder {
     /// Create a new encoder
     pub fn new() -> Self {
-        Self {}
+        Self {
+            diagnostics: None,
+            escape_markers: false,
+            mixed_content: None,
+            tags: TagRegistry::new(),
+            provenance: None,
+            line_ending: LineEnding::Preserve,
+            #[cfg(feature = "std")]
+            jobs: 1,
+        }
+    }
+
+    /// Keep files with conflicting marker lines readable
+    ///
+    /// Instead of base64-encoding files whose only problem is a line like
+    /// `-- name --`, each such line is prefixed with `\` and its line number
+    /// is recorded in an `[.esc:N,...]` tag so the decoder can restore it.
+    pub fn with_marker_escaping(mut self, enabled: bool) -> Self {
+        self.escape_markers = enabled;
+        self
+    }
+
+    /// Report text files stored as base64 because of marker lines to `diagnostics`
+    pub fn with_diagnostics(mut self, diagnostics: Arc<dyn Diagnostics>) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    /// Validate and serialize the custom tags `tags` has handlers for
+    ///
+    /// Custom tags without a handler are written as they are.
+    pub fn with_tags(mut self, tags: TagRegistry) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Record `provenance` in the comment frontmatter of each archive
+    ///
+    /// Other frontmatter fields are kept; see [`crate::frontmatter`].
+    pub fn with_provenance(mut self, provenance: Provenance) -> Self {
+        self.provenance = Some(provenance);
+        self
+    }
+
+    /// Line breaks of the output: `Lf` or `CrLf` converts every line break
+    /// of the archive text, contents included, while `Preserve` (the
+    /// default) writes the contents as they are, with `\r\n` for files
+    /// whose [`File::line_ending`] is `CrLf`
+    ///
+    /// Applies to [`encode`](Self::encode), `encode_to_writer` and
+    /// [`encode_stream`](Self::encode_stream).
+    pub fn with_line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// `text` with its line breaks converted to the configured ending
+    fn convert_line_breaks(&self, text: String) -> String {
+        match self.line_ending.apply(&text) {
+            Cow::Borrowed(_) => text,
+            Cow::Owned(converted) => converted,
+        }
     }
 
     /// Encode an archive to a string
     pub fn encode(&self, archive: &Archive) -> Result<String> {
-        let mut output = String::new();
+        trace_span!("encode", files = archive.files.len());
+        let mut output = String::with_capacity(self.estimate_len(archive));
+        self.encode_comment(&mut output, archive);
 
-        // Write comment if present
-        if !archive.comment.is_empty() {
-            output.push_str(&archive.comment);
-            if !archive.comment.ends_with('\n') {
-                output.push('\n');
+        #[cfg(feature = "std")]
+        if self.jobs > 1 && archive.files.len() > 1 {
+            for part in self.encode_parallel(&archive.files)? {
+                output.push_str(&part);
             }
+            return Ok(self.convert_line_breaks(output));
         }
 
         // Write each file
-        for file in &archive.files {
-            self.encode_file(&mut output, file)?;
+        for (index, file) in archive.files.iter().enumerate() {
+            self.encode_file(&mut output, file, index + 1 == archive.files.len())?;
+        }
+
+        Ok(self.convert_line_breaks(output))
+    }
+
+    /// Encode entries on up to `jobs` threads
+    ///
+    /// The output is identical to encoding on one thread.
+    #[cfg(feature = "std")]
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Encode each file separately on worker threads, returning them in order
+    #[cfg(feature = "std")]
+    fn encode_parallel(&self, files: &[File]) -> Result<Vec<String>> {
+        crate::parallel::map(files, self.jobs, |index, file| {
+            let mut part = String::new();
+            self.encode_file(&mut part, file, index + 1 == files.len()).map(|()| part)
+        }).into_iter().collect()
+    }
+
+    /// Keep mostly-text files with a few invalid UTF-8 bytes readable
+    ///
+    /// Files that are binary only because of invalid UTF-8, where the invalid
+    /// bytes make up at most `max_invalid_ratio` of the data, are stored as
+    /// text with each invalid run replaced by U+FFFD. The original bytes are
+    /// recorded as `offset=base64` pairs in a `[.patch:...]` tag, where the
+    /// offset is the byte position of the placeholder in the stored text.
+    pub fn with_mixed_content(mut self, max_invalid_ratio: Option<f32>) -> Self {
+        self.mixed_content = max_invalid_ratio;
+        self
+    }
+
+    /// Estimated length of the encoding of `archive`, for sizing buffers
+    ///
+    /// Exact for plain UTF-8 text and base64 entries, apart from header tags;
+    /// transcoded, escaped and path-backed entries are estimated generously.
+    pub fn estimate_len(&self, archive: &Archive) -> usize {
+        let comment = archive.comment.len() + 1;
+        comment + archive.files.iter().map(Self::estimate_file_len).sum::<usize>()
+    }
+
+    fn estimate_file_len(file: &File) -> usize {
+        // Markers, newlines and common tags
+        const HEADER: usize = 32;
+
+        #[cfg(feature = "std")]
+        if let crate::archive::FileSource::Path(path) = &file.source {
+            let len = std::fs::metadata(path).map_or(0, |m| m.len() as usize);
+            return HEADER + file.name.len() + crate::b64::encoded_len(len);
+        }
+
+        let content = if file.is_binary {
+            crate::b64::encoded_len(file.data.len())
+        } else if file.encoding == TextEncoding::Utf8 {
+            file.data.len()
+        } else {
+            // Each legacy byte becomes at most two UTF-8 bytes
+            file.data.len() * 2
+        };
+        HEADER + file.name.len() + content
+    }
+
+    /// The comment as written, with any provenance stamped in
+    pub(crate) fn comment<'a>(&self, archive: &'a Archive) -> Cow<'a, str> {
+        match &self.provenance {
+            Some(provenance) => Cow::Owned(provenance.stamp(&archive.comment)),
+            None => Cow::Borrowed(&archive.comment),
         }
+    }
 
-        Ok(output)
+    /// Write the comment, if present
+    fn encode_comment(&self, output: &mut String, archive: &Archive) {
+        let comment = self.comment(archive);
+        if !comment.is_empty() {
+            output.push_str(&comment);
+            if !comment.ends_with('\n') {
+                output.push('\n');
+            }
+        }
     }
 
-    /// Encode a single file
-    fn encode_file(&self, output: &mut String, file: &File) -> Result<()> {
+    /// Encode a single file, `last` in the archive
+    fn encode_file(&self, output: &mut String, file: &File, last: bool) -> Result<()> {
+        // Path-backed entries are read here, one at a time
+        #[cfg(feature = "std")]
+        let loaded = file.load()?;
+        #[cfg(feature = "std")]
+        let file: &File = &loaded;
+        let serialized = self.tags.serialize(file)?;
+        let file: &File = &serialized;
+
+        profile_count!(files_encoded, 1);
+        trace_event!(file = %file.name, size = file.data.len(), binary = file.is_binary, "encoding file");
+        // Patched text already ends with the newline the decoder strips
+        let readable = match self.escaped_text(file) {
+            Some(escaped) => Some((escaped, false)),
+            None => self.patched_text(file).map(|patched| (patched, true)),
+        };
+        if let Some(((content, tag), terminated)) = readable {
+            output.push_str("-- ");
+            output.push_str(&file.header_name(false, &[tag]));
+            output.push_str(" --\n");
+            output.push_str(&content);
+            if !terminated && Self::ends_section(file, &content, last) {
+                output.push('\n');
+            }
+            return Ok(());
+        }
+
+        // Files keep their own line breaks unless the whole output is converted
+        let ending = match self.line_ending {
+            LineEnding::Preserve if !file.is_binary => file.line_ending,
+            _ => LineEnding::Preserve,
+        };
+        let line_break = if ending == LineEnding::CrLf { "\r\n" } else { "\n" };
+
         // Write file header
         output.push_str("-- ");
         output.push_str(&file.archive_name());
-        output.push_str(" --\n");
+        output.push_str(" --");
+        output.push_str(line_break);
+        let start = output.len();
 
-        // Write file content
-        let content = if file.is_binary {
+        if let (Some(diagnostics), Some(reason @ BinaryReason::ContentConflict { .. })) = (&self.diagnostics, &file.binary_reason) {
+            diagnostics.report(Diagnostic::StoredAsBase64 { name: file.name.clone(), reason: reason.clone() });
+        }
+
+        // Write file content straight into the output
+        if file.is_binary {
             // Encode binary data as base64
-            base64::engine::general_purpose::STANDARD.encode(&file.data)
+            profile_count!(base64_encoded, file.data.len());
+            crate::b64::encode_into(&file.data, output);
+        } else if let (TextEncoding::Utf8, Ok(text)) = (file.encoding, core::str::from_utf8(&file.data)) {
+            output.push_str(text);
         } else {
-            // Use UTF-8 validation (should already be validated)
-            std::str::from_utf8(&file.data)
-                .map_err(|_| anyhow::anyhow!("File {} is not valid UTF-8 but not marked as binary", file.name))?
-                .to_string()
-        };
-
-        output.push_str(&content);
+            // Transcode to UTF-8 (should already be validated)
+            let text = file.encoding.decode(&file.data).ok_or_else(|| {
+                anyhow::anyhow!(
+                    "File {} is not valid {} but not marked as binary",
+                    file.name,
+                    file.encoding.label()
+                )
+            })?;
+            output.push_str(&text);
+        }
+        if let Cow::Owned(converted) = ending.apply(&output[start..]) {
+            output.truncate(start);
+            output.push_str(&converted);
+        }
 
         // Ensure trailing newline
-        if !content.ends_with('\n') {
-            output.push('\n');
+        if Self::ends_section(file, &output[start..], last) {
+            output.push_str(line_break);
         }
 
         Ok(())
     }
 
+    /// Whether to write a line break after the contents `content` of `file`:
+    /// the one a decoded file recorded, otherwise one ending non-empty
+    /// contents, as Go's txtar does, unless a last file recorded none
+    fn ends_section(file: &File, content: &str, last: bool) -> bool {
+        match file.trailing_newline {
+            Some(true) => true,
+            recorded => !(content.is_empty() || content.ends_with('\n') || recorded == Some(false) && last),
+        }
+    }
+
+    /// Escape the marker lines of a file that is binary only because of them
+    ///
+    /// Returns the escaped text and the `[.esc:...]` tag listing the escaped
+    /// lines, or `None` if escaping is disabled or does not apply to this file.
+    fn escaped_text(&self, file: &File) -> Option<(String, String)> {
+        if !self.escape_markers
+            || !file.is_binary
+            || !matches!(file.binary_reason, Some(BinaryReason::ContentConflict { .. }))
+        {
+            return None;
+        }
+        let text = file.encoding.decode(&file.data)?;
+
+        let mut escaped_lines = Vec::new();
+        let lines: Vec<String> = text
+            .split('\n')
+            .enumerate()
+            .map(|(i, line)| {
+                if File::is_marker_line(line) {
+                    escaped_lines.push(i + 1);
+                    format!("{}{}", MARKER_ESCAPE, line)
+                } else {
+                    line.to_string()
+                }
+            })
+            .collect();
+
+        let numbers: Vec<String> = escaped_lines.iter().map(|n| n.to_string()).collect();
+        let tag = format!("{}{}]", ESCAPE_TAG_PREFIX, numbers.join(","));
+        Some((lines.join("\n"), tag))
+    }
+
+    /// Replace the invalid UTF-8 runs of a mostly-text file with placeholders
+    ///
+    /// Returns the text and its `[.patch:...]` tag, or `None` if mixed content
+    /// is disabled, the file has too many invalid bytes, or the text would
+    /// contain marker lines.
+    fn patched_text(&self, file: &File) -> Option<(String, String)> {
+        let max_ratio = self.mixed_content?;
+        if !file.is_binary || !matches!(file.binary_reason, Some(BinaryReason::InvalidUtf8 { .. })) {
+            return None;
+        }
+
+        let mut text = String::with_capacity(file.data.len());
+        let mut patches: Vec<(usize, Vec<u8>)> = Vec::new();
+        let mut rest = &file.data[..];
+        loop {
+            match core::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    break;
+                }
+                Err(e) => {
+                    let valid = e.valid_up_to();
+                    let bad_len = e.error_len().unwrap_or(rest.len() - valid);
+                    text.push_str(core::str::from_utf8(&rest[..valid]).ok()?);
+                    let bad = &rest[valid..valid + bad_len];
+                    match patches.last_mut() {
+                        // Merge with a directly preceding invalid run
+                        Some((offset, bytes)) if *offset + '\u{FFFD}'.len_utf8() == text.len() => {
+                            bytes.extend_from_slice(bad);
+                        }
+                        _ => {
+                            patches.push((text.len(), bad.to_vec()));
+                            text.push('\u{FFFD}');
+                        }
+                    }
+                    rest = &rest[valid + bad_len..];
+                }
+            }
+        }
+
+        let invalid: usize = patches.iter().map(|(_, bytes)| bytes.len()).sum();
+        if invalid as f32 / file.data.len() as f32 > max_ratio || text.lines().any(File::is_marker_line) {
+            return None;
+        }
+
+        let entries: Vec<String> = patches
+            .iter()
+            .map(|(offset, bytes)| format!("{}={}", offset, base64::engine::general_purpose::STANDARD.encode(bytes)))
+            .collect();
+        // Patched data is restored byte for byte, so always terminate the text
+        // with the newline the decoder strips
+        text.push('\n');
+
+        let tag = format!("{}{}]", PATCH_TAG_PREFIX, entries.join(","));
+        Some((text, tag))
+    }
+
+    /// Encode named archives as one stream, each preceded by an `=== archive: name ===` line
+    ///
+    /// Fails if a name is empty or spans lines, or if an encoded archive
+    /// contains a line that would be read back as a delimiter.
+    pub fn encode_stream(&self, archives: &[(String, Archive)]) -> Result<String> {
+        let mut output = String::new();
+
+        for (index, (name, archive)) in archives.iter().enumerate() {
+            let delimiter = format!("{}{}{}", STREAM_DELIMITER_PREFIX, name, STREAM_DELIMITER_SUFFIX);
+            if name.contains('\n') || parse_stream_delimiter(&delimiter) != Some(name.as_str()) {
+                bail!("Invalid archive name for stream: {:?}", name);
+            }
+
+            let encoded = self.encode(archive)?;
+            if encoded.lines().any(|line| parse_stream_delimiter(line).is_some()) {
+                bail!("Archive '{}' contains an archive delimiter line", name);
+            }
+
+            output.push_str(&delimiter);
+            output.push('\n');
+            output.push_str(&encoded);
+            if !encoded.is_empty() && !encoded.ends_with('\n') && index + 1 < archives.len() {
+                output.push('\n');
+            }
+        }
+
+        Ok(self.convert_line_breaks(output))
+    }
+
     /// Encode an archive directly to a writer
+    #[cfg(feature = "std")]
     pub fn encode_to_writer<W: std::io::Write>(&self, archive: &Archive, mut writer: W) -> Result<()> {
-        let encoded = self.encode(archive)?;
-        writer.write_all(encoded.as_bytes())?;
+        trace_span!("encode_to_writer", files = archive.files.len());
+        // Write entry by entry so the whole archive is never held in memory
+        let mut output = String::new();
+        self.encode_comment(&mut output, archive);
+        writer.write_all(self.line_ending.apply(&output).as_bytes())?;
+        for (index, file) in archive.files.iter().enumerate() {
+            output.clear();
+            self.encode_file(&mut output, file, index + 1 == archive.files.len())?;
+            writer.write_all(self.line_ending.apply(&output).as_bytes())?;
+        }
         Ok(())
     }
 
     /// Encode an archive to a file
+    #[cfg(feature = "std")]
     pub fn encode_to_file(&self, archive: &Archive, path: &std::path::Path) -> Result<()> {
-        let encoded = self.encode(archive)?;
-        std::fs::write(path, encoded)?;
+        use std::io::Write;
+
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        self.encode_to_writer(archive, &mut writer)?;
+        writer.flush()?;
         Ok(())
     }
 }
@@ -84,6 +442,149 @@ impl Default for Encoder {
     }
 }
 
+/// Section of an archive being rewritten by [`ArchiveWriter`]
+enum Section<'a> {
+    /// Bytes of the previous encoding, reused as they are
+    Original(&'a str),
+    /// A replaced or added entry
+    Encoded(String),
+}
+
+/// Entry of the previous encoding
+struct Entry<'a> {
+    name: String,
+    /// Neither snippet nor edit, so it can be replaced by name
+    normal: bool,
+    /// `None` once removed
+    section: Option<Section<'a>>,
+}
+
+/// Rewrites entries of an encoded archive without re-encoding the rest
+///
+/// The previous encoding is split into entries at its marker lines; only
+/// updated or added entries are encoded, and every other byte is copied
+/// from the previous text unchanged.
+///
+/// ```rust
+/// use emx_txtar::{ArchiveWriter, File};
+///
+/// let previous = "-- a.txt --\nA\n-- b.txt --\nB\n";
+/// let mut writer = ArchiveWriter::new(previous)?;
+/// writer.update(&File::new("b.txt", "changed"))?;
+/// assert_eq!(writer.finish(), "-- a.txt --\nA\n-- b.txt --\nchanged\n");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+pub struct ArchiveWriter<'a> {
+    comment: &'a str,
+    entries: Vec<Entry<'a>>,
+    encoder: Encoder,
+}
+
+impl<'a> ArchiveWriter<'a> {
+    /// Index the entries of a previously encoded archive
+    pub fn new(previous: &'a str) -> Result<Self> {
+        let mut starts = Vec::new();
+        let mut offset = 0;
+        for line in previous.split_inclusive('\n') {
+            let text = line.strip_suffix('\n').unwrap_or(line);
+            let text = text.strip_suffix('\r').unwrap_or(text);
+            if let Some((name, normal)) = Decoder::entry_marker(text)? {
+                starts.push((offset, name, normal));
+            }
+            offset += line.len();
+        }
+
+        let comment_end = starts.first().map_or(previous.len(), |(start, _, _)| *start);
+        let mut entries: Vec<Entry<'a>> = Vec::with_capacity(starts.len());
+        for (i, (start, name, normal)) in starts.iter().enumerate() {
+            let end = starts.get(i + 1).map_or(previous.len(), |(next, _, _)| *next);
+            if *normal && entries.iter().any(|e| e.normal && e.name == *name) {
+                bail!("Duplicate file: {}", name);
+            }
+            entries.push(Entry {
+                name: name.clone(),
+                normal: *normal,
+                section: Some(Section::Original(&previous[*start..end])),
+            });
+        }
+
+        Ok(Self { comment: &previous[..comment_end], entries, encoder: Encoder::new() })
+    }
+
+    /// Use `encoder` for updated entries
+    pub fn with_encoder(mut self, encoder: Encoder) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    /// Replace the normal entry with the file's name, or append the file
+    /// if there is none
+    pub fn update(&mut self, file: &File) -> Result<()> {
+        let mut encoded = String::new();
+        self.encoder.encode_file(&mut encoded, file, false)?;
+        let normal = file.snippet_ref.is_none() && file.edit_ref.is_none();
+
+        let existing = self.entries.iter_mut()
+            .find(|e| normal && e.normal && e.name == file.name && e.section.is_some());
+        match existing {
+            Some(entry) => entry.section = Some(Section::Encoded(encoded)),
+            None => self.entries.push(Entry {
+                name: file.name.clone(),
+                normal,
+                section: Some(Section::Encoded(encoded)),
+            }),
+        }
+        Ok(())
+    }
+
+    /// Remove every entry named `name`, returning whether there was one
+    pub fn remove(&mut self, name: &str) -> bool {
+        let mut removed = false;
+        for entry in self.entries.iter_mut().filter(|e| e.name == name) {
+            removed |= entry.section.take().is_some();
+        }
+        removed
+    }
+
+    /// Sections of the new encoding in order, each ending with a newline
+    /// when another follows
+    fn for_each_section(&self, mut emit: impl FnMut(&str) -> Result<()>) -> Result<()> {
+        let sections = core::iter::once(self.comment)
+            .chain(self.entries.iter().filter_map(|e| match e.section.as_ref()? {
+                Section::Original(text) => Some(*text),
+                Section::Encoded(text) => Some(text.as_str()),
+            }))
+            .filter(|text| !text.is_empty());
+
+        let mut needs_newline = false;
+        for text in sections {
+            if needs_newline {
+                emit("\n")?;
+            }
+            emit(text)?;
+            needs_newline = !text.ends_with('\n');
+        }
+        Ok(())
+    }
+
+    /// The rewritten archive
+    pub fn finish(&self) -> String {
+        let mut output = String::new();
+        let _ = self.for_each_section(|text| {
+            output.push_str(text);
+            Ok(())
+        });
+        output
+    }
+
+    /// Write the rewritten archive, copying untouched entries straight from
+    /// the previous encoding
+    #[cfg(feature = "std")]
+    pub fn write_to<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
+        self.for_each_section(|text| Ok(writer.write_all(text.as_bytes())?))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -130,6 +631,181 @@ mod tests {
         assert!(result.contains("Content 2"));
     }
 
+    #[test]
+    fn test_encode_snippet_with_language() {
+        let mut archive = Archive::new();
+        let mut file = File::new("src/main.rs", "fn main() {}");
+        file.snippet_ref = Some(crate::archive::SnippetRef {
+            command_href: None,
+            line: 3,
+            language: Some("rust".to_string()),
+        });
+        archive.add_file(file).unwrap();
+
+        let encoder = Encoder::new();
+        let result = encoder.encode(&archive).unwrap();
+        assert!(result.contains("-- src/main.rs[.snippet:3:lang=rust] --"));
+
+        let decoded = crate::decoder::Decoder::new().decode(&result).unwrap();
+        assert_eq!(decoded.files[0].snippet_ref, archive.files[0].snippet_ref);
+    }
+
+    #[test]
+    fn test_encode_latin1_round_trip() {
+        let config = crate::archive::EncodingConfig {
+            latin1_fallback: true,
+            ..Default::default()
+        };
+        let mut archive = Archive::new();
+        archive.add_file(File::with_config("legacy.cfg", b"name=Jos\xe9".to_vec(), &config)).unwrap();
+
+        let encoder = Encoder::new();
+        let result = encoder.encode(&archive).unwrap();
+        assert!(result.contains("-- legacy.cfg[.enc:latin1] --\nname=José\n"));
+
+        let decoded = crate::decoder::Decoder::new().decode(&result).unwrap();
+        assert_eq!(&decoded.files[0].data[..], b"name=Jos\xe9");
+        assert_eq!(decoded.files[0].encoding, crate::archive::TextEncoding::Latin1);
+    }
+
+    #[test]
+    #[cfg(feature = "encoding")]
+    fn test_encode_cjk_round_trip() {
+        let data = crate::archive::TextEncoding::ShiftJis.encode("名前=山田太郎\n住所=東京都千代田区\n").unwrap();
+        let mut archive = Archive::new();
+        archive
+            .add_file(File::with_config("legacy.cfg", data.clone(), &crate::archive::EncodingConfig::lenient()))
+            .unwrap();
+
+        let encoder = Encoder::new();
+        let result = encoder.encode(&archive).unwrap();
+        assert!(result.contains("-- legacy.cfg[.enc:shift_jis] --\n名前=山田太郎\n住所=東京都千代田区\n"));
+
+        let decoded = crate::decoder::Decoder::new().decode(&result).unwrap();
+        assert_eq!(decoded.files[0].content(), data);
+        assert_eq!(decoded.files[0].encoding, crate::archive::TextEncoding::ShiftJis);
+    }
+
+    #[test]
+    fn test_encode_marker_escaping() {
+        let content = "Example archive:\n-- a.txt --\nhello\n  -- b.txt --";
+        let mut archive = Archive::new();
+        archive.add_file(File::new("doc.md", content)).unwrap();
+        assert!(archive.files[0].is_binary);
+
+        let encoder = Encoder::new().with_marker_escaping(true);
+        let result = encoder.encode(&archive).unwrap();
+        assert!(result.contains("-- doc.md[.esc:2,4] --\nExample archive:\n\\-- a.txt --\nhello\n\\  -- b.txt --\n"));
+
+        let decoded = crate::decoder::Decoder::new().decode(&result).unwrap();
+        assert_eq!(decoded.files.len(), 1);
+        assert_eq!(decoded.files[0].data, content.as_bytes());
+        // Still flagged so a plain re-encode stays safe
+        assert!(decoded.files[0].is_binary);
+        assert!(Encoder::new().encode(&decoded).unwrap().contains("-- doc.md[.base64] --"));
+    }
+
+    #[test]
+    fn test_encode_marker_escaping_skips_real_binary() {
+        let mut archive = Archive::new();
+        archive.add_file(File::new("blob", vec![0xFF, 0x00])).unwrap();
+
+        let encoder = Encoder::new().with_marker_escaping(true);
+        let result = encoder.encode(&archive).unwrap();
+        assert!(result.contains("-- blob[.base64] --"));
+    }
+
+    #[test]
+    fn test_encode_mixed_content() {
+        let mut data = b"INFO start\nWARN bad byte: ".to_vec();
+        data.extend_from_slice(&[0xFF, 0xFE]);
+        data.extend_from_slice(b"\nINFO done \xC3\n");
+        let mut archive = Archive::new();
+        archive.add_file(File::new("app.log", data.clone())).unwrap();
+
+        let encoder = Encoder::new().with_mixed_content(Some(0.1));
+        let result = encoder.encode(&archive).unwrap();
+        assert!(result.contains("-- app.log[.patch:26=//4=,40=ww==] --\nINFO start\nWARN bad byte: \u{FFFD}\nINFO done \u{FFFD}\n"));
+
+        let decoded = crate::decoder::Decoder::new().decode(&result).unwrap();
+        assert_eq!(decoded.files[0].data, data);
+        assert!(decoded.files[0].is_binary);
+    }
+
+    #[test]
+    fn test_encode_mixed_content_ratio_exceeded() {
+        let mut archive = Archive::new();
+        archive.add_file(File::new("blob", vec![b'a', 0xFF, 0xFE, 0xFD])).unwrap();
+
+        let encoder = Encoder::new().with_mixed_content(Some(0.1));
+        let result = encoder.encode(&archive).unwrap();
+        assert!(result.contains("-- blob[.base64] --"));
+    }
+
+    #[test]
+    fn test_round_trip_trailing_newlines() {
+        let contents: [&[u8]; 6] = [b"", b"\n", b"\n\n", b"A", b"A\n", b"A\n\n"];
+        let mut archive = Archive::new();
+        for (i, content) in contents.iter().enumerate() {
+            archive.add_file(File::new(format!("{}.txt", i), *content)).unwrap();
+        }
+
+        // Written as Go's txtar writes them
+        let encoded = Encoder::new().encode(&archive).unwrap();
+        assert!(encoded.starts_with("-- 0.txt --\n-- 1.txt --\n\n-- 2.txt --\n\n\n-- 3.txt --\nA\n-- 4.txt --\nA\n"));
+
+        // Decoded files record the newline their data leaves out
+        let decoded = crate::decoder::Decoder::new().decode(&encoded).unwrap();
+        let flags: Vec<_> = decoded.files.iter().map(|f| f.trailing_newline).collect();
+        assert_eq!(flags, [Some(false), Some(true), Some(true), Some(true), Some(true), Some(true)]);
+        assert_eq!(&decoded.files[0].data[..], b"");
+        assert_eq!(&decoded.files[1].data[..], b"");
+        assert_eq!(&decoded.files[1].content()[..], b"\n");
+        assert_eq!(&decoded.files[5].content()[..], b"A\n\n");
+
+        // and keep it through another round trip
+        let reencoded = Encoder::new().encode(&decoded).unwrap();
+        assert_eq!(reencoded, encoded);
+        assert_eq!(crate::decoder::Decoder::new().decode(&reencoded).unwrap(), decoded);
+    }
+
+    #[test]
+    fn test_encode_line_endings() {
+        assert_eq!(LineEnding::CrLf.apply("A\nB\r\nC"), "A\r\nB\r\nC");
+        assert_eq!(LineEnding::Lf.apply("A\nB\r\nC\r"), "A\nB\nC\r");
+        assert!(matches!(LineEnding::CrLf.apply("A\r\nB"), Cow::Borrowed(_)));
+
+        let mut archive = Archive::with_comment("Notes");
+        archive.add_file(File::new("a", "A\nB")).unwrap();
+        let mut file = File::new("b", "C\nD");
+        file.line_ending = LineEnding::CrLf;
+        archive.add_file(file).unwrap();
+        archive.add_file(File::new("c.bin", [0xffu8, 0xfe])).unwrap();
+
+        let encoded = Encoder::new().encode(&archive).unwrap();
+        assert_eq!(encoded, "Notes\n-- a --\nA\nB\n-- b --\r\nC\r\nD\r\n-- c.bin[.base64] --\n//4=\n");
+        let lf = Encoder::new().with_line_ending(LineEnding::Lf).encode(&archive).unwrap();
+        assert_eq!(lf, "Notes\n-- a --\nA\nB\n-- b --\nC\nD\n-- c.bin[.base64] --\n//4=\n");
+        let encoder = Encoder::new().with_line_ending(LineEnding::CrLf);
+        let crlf = encoder.encode(&archive).unwrap();
+        assert_eq!(crlf, "Notes\r\n-- a --\r\nA\r\nB\r\n-- b --\r\nC\r\nD\r\n-- c.bin[.base64] --\r\n//4=\r\n");
+        #[cfg(feature = "std")]
+        {
+            let mut written = Vec::new();
+            encoder.encode_to_writer(&archive, &mut written).unwrap();
+            assert_eq!(written, crlf.as_bytes());
+            let parallel = Encoder::new().with_line_ending(LineEnding::CrLf).with_jobs(2);
+            assert_eq!(parallel.encode(&archive).unwrap(), crlf);
+        }
+        let stream = encoder.encode_stream(&[("x".into(), archive.clone())]).unwrap();
+        assert_eq!(stream, format!("=== archive: x ===\r\n{}", crlf));
+
+        // Decoding normalizes the contents and records the files as CRLF
+        let decoded = crate::decoder::Decoder::new().decode(&crlf).unwrap();
+        assert_eq!(decoded.files[0].data.as_ref(), b"A\nB");
+        assert_eq!(decoded.files[0].line_ending, LineEnding::CrLf);
+    }
+
     #[test]
     fn test_encode_with_subdirectories() {
         let mut archive = Archive::new();
@@ -141,4 +817,78 @@ mod tests {
         assert!(result.contains("-- dir/subdir/file.txt --"));
         assert!(result.contains("Content"));
     }
+
+    #[test]
+    fn test_encode_stream() {
+        let mut first = Archive::with_comment("setup\n");
+        first.add_file(File::new("a.txt", "A\n")).unwrap();
+        let mut second = Archive::new();
+        second.add_file(File::new("b.txt", "B\n")).unwrap();
+        let archives = vec![("first".to_string(), first), ("second".to_string(), second)];
+
+        let encoded = Encoder::new().encode_stream(&archives).unwrap();
+        assert_eq!(encoded, "=== archive: first ===\nsetup\n-- a.txt --\nA\n=== archive: second ===\n-- b.txt --\nB\n");
+
+        let decoded = crate::Decoder::new().decode_stream(&encoded).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].1.files[0].name, "b.txt");
+
+        let mut nested = Archive::new();
+        nested.add_file(File::new("notes.txt", "=== archive: inner ===\n")).unwrap();
+        let err = Encoder::new().encode_stream(&[("outer".to_string(), nested)]).unwrap_err();
+        assert!(err.to_string().contains("delimiter"));
+        assert!(Encoder::new().encode_stream(&[(" ".to_string(), Archive::new())]).is_err());
+    }
+
+    #[test]
+    fn test_estimate_len_covers_encoding() {
+        let mut archive = Archive::with_comment("comment");
+        archive.add_file(File::new("a.txt", "x".repeat(1000))).unwrap();
+        archive.add_file(File::new("b.bin", vec![0xFFu8; 3000])).unwrap();
+        archive.add_file(File::new("empty.txt", "")).unwrap();
+
+        let encoder = Encoder::new();
+        let estimate = encoder.estimate_len(&archive);
+        let actual = encoder.encode(&archive).unwrap().len();
+        assert!(estimate >= actual && estimate < actual + 128, "{} vs {}", estimate, actual);
+    }
+
+    #[test]
+    fn test_archive_writer_rewrites_changed_entries() {
+        let mut archive = Archive::with_comment("comment");
+        archive.add_file(File::new("a.txt", "A")).unwrap();
+        archive.add_file(File::new("b.bin", vec![0xFF, 0x00])).unwrap();
+        archive.add_file(File::new("c.txt", "C")).unwrap();
+        let previous = Encoder::new().encode(&archive).unwrap();
+
+        let mut writer = ArchiveWriter::new(&previous).unwrap();
+        writer.update(&File::new("b.bin", "now text")).unwrap();
+        assert!(writer.remove("c.txt"));
+        assert!(!writer.remove("missing.txt"));
+        writer.update(&File::new("d.txt", "D")).unwrap();
+
+        archive.files[1] = File::new("b.bin", "now text");
+        archive.files.remove(2);
+        archive.add_file(File::new("d.txt", "D")).unwrap();
+        assert_eq!(writer.finish(), Encoder::new().encode(&archive).unwrap());
+
+        // An unterminated last entry still gets a line break before additions
+        let mut writer = ArchiveWriter::new("-- a.txt --\nA").unwrap();
+        writer.update(&File::new("b.txt", "B")).unwrap();
+        assert_eq!(writer.finish(), "-- a.txt --\nA\n-- b.txt --\nB\n");
+        assert!(ArchiveWriter::new("-- a --\n-- a --\n").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_encode_with_jobs_matches_serial() {
+        let mut archive = Archive::with_comment("comment");
+        for i in 0..40u8 {
+            let data = if i % 3 == 0 { vec![i, 0xFF, 0x00] } else { format!("file {}\n", i).into_bytes() };
+            archive.add_file(File::new(format!("f{}.txt", i), data)).unwrap();
+        }
+
+        let serial = Encoder::new().encode(&archive).unwrap();
+        assert_eq!(Encoder::new().with_jobs(4).encode(&archive).unwrap(), serial);
+    }
 }
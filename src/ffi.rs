@@ -0,0 +1,269 @@
+//! C ABI
+//!
+//! Functions declared in `include/emx_txtar.h`, for build tooling written
+//! in C or using Python's ctypes. Build a shared library with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`.
+//! The header is generated by `cbindgen`; regenerate it after changing
+//! this module with `cbindgen --config cbindgen.toml --output include/emx_txtar.h src/ffi.rs`.
+//!
+//! Archives are opaque handles. Functions report failure with `NULL` or
+//! `-1` and leave a message for [`emx_txtar_last_error`].
+
+use crate::archive::{Archive, File};
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use anyhow::{anyhow, Result};
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::ptr;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Opaque archive handle
+pub struct EmxTxtarArchive {
+    archive: Archive,
+    names: Vec<CString>,
+}
+
+impl EmxTxtarArchive {
+    fn new(archive: Archive) -> Self {
+        let mut handle = Self { archive, names: Vec::new() };
+        handle.refresh_names();
+        handle
+    }
+
+    fn refresh_names(&mut self) {
+        self.names = self.archive.files.iter()
+            .map(|f| CString::new(f.name.replace('\0', "")).unwrap_or_default())
+            .collect();
+    }
+}
+
+/// Record an error for `emx_txtar_last_error`
+fn set_error(err: anyhow::Error) {
+    let message = CString::new(format!("{:#}", err).replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    if data.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data, len)
+    }
+}
+
+unsafe fn c_str<'a>(text: *const c_char) -> Result<&'a str> {
+    if text.is_null() {
+        return Err(anyhow!("Null string argument"));
+    }
+    Ok(CStr::from_ptr(text).to_str()?)
+}
+
+/// Create an empty archive; free it with `emx_txtar_archive_free`
+#[no_mangle]
+pub extern "C" fn emx_txtar_archive_new() -> *mut EmxTxtarArchive {
+    Box::into_raw(Box::new(EmxTxtarArchive::new(Archive::new())))
+}
+
+/// Decode txtar text, returning null on error (see `emx_txtar_last_error`)
+///
+/// # Safety
+/// `text` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn emx_txtar_decode(text: *const c_char, len: usize) -> *mut EmxTxtarArchive {
+    let result = std::str::from_utf8(bytes(text.cast(), len))
+        .map_err(anyhow::Error::from)
+        .and_then(|text| Decoder::new().decode(text));
+    match result {
+        Ok(archive) => Box::into_raw(Box::new(EmxTxtarArchive::new(archive))),
+        Err(err) => {
+            set_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Encode an archive as NUL-terminated text, returning null on error
+///
+/// The length is stored in `out_len` if it is not null. Free the text with
+/// `emx_txtar_string_free`.
+///
+/// # Safety
+/// `archive` must be a live handle; `out_len` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn emx_txtar_encode(archive: *const EmxTxtarArchive, out_len: *mut usize) -> *mut c_char {
+    let Some(handle) = archive.as_ref() else {
+        set_error(anyhow!("Null archive"));
+        return ptr::null_mut();
+    };
+    let result = Encoder::new().encode(&handle.archive)
+        .and_then(|text| CString::new(text).map_err(|_| anyhow!("Encoded archive contains a NUL byte")));
+    match result {
+        Ok(text) => {
+            if !out_len.is_null() {
+                *out_len = text.as_bytes().len();
+            }
+            text.into_raw()
+        }
+        Err(err) => {
+            set_error(err);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Add a file, detecting binary content; returns 0 on success and -1 on error
+///
+/// # Safety
+/// `archive` must be a live handle, `name` a NUL-terminated string and
+/// `data` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn emx_txtar_add_file(
+    archive: *mut EmxTxtarArchive,
+    name: *const c_char,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let result = (|| {
+        let handle = archive.as_mut().ok_or_else(|| anyhow!("Null archive"))?;
+        let name = c_str(name)?;
+        handle.archive.add_file(File::new(name, bytes(data, len).to_vec()))?;
+        handle.refresh_names();
+        Ok(())
+    })();
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            set_error(err);
+            -1
+        }
+    }
+}
+
+/// Number of entries, including snippet and edit entries
+///
+/// # Safety
+/// `archive` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn emx_txtar_file_count(archive: *const EmxTxtarArchive) -> usize {
+    archive.as_ref().map_or(0, |handle| handle.archive.files.len())
+}
+
+/// Name of the entry at `index` as NUL-terminated UTF-8, or null if out of range
+///
+/// The name stays valid until the archive is modified or freed.
+///
+/// # Safety
+/// `archive` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn emx_txtar_file_name(archive: *const EmxTxtarArchive, index: usize) -> *const c_char {
+    archive.as_ref()
+        .and_then(|handle| handle.names.get(index))
+        .map_or(ptr::null(), |name| name.as_ptr())
+}
+
+/// Contents of the named file, or null if there is none
+///
+/// Snippet and edit entries are excluded. The length is stored in `out_len`,
+/// and the contents stay valid until the archive is modified or freed.
+///
+/// # Safety
+/// `archive` must be null or a live handle, `name` a NUL-terminated string
+/// and `out_len` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn emx_txtar_get_file(
+    archive: *const EmxTxtarArchive,
+    name: *const c_char,
+    out_len: *mut usize,
+) -> *const u8 {
+    let Some(handle) = archive.as_ref() else {
+        return ptr::null();
+    };
+    let Ok(name) = c_str(name) else {
+        return ptr::null();
+    };
+    match handle.archive.get_file(name) {
+        Some(file) => {
+            if !out_len.is_null() {
+                *out_len = file.data.len();
+            }
+            file.data.as_ptr()
+        }
+        None => ptr::null(),
+    }
+}
+
+/// Message of the last error on this thread, or null; valid until the next call
+#[no_mangle]
+pub extern "C" fn emx_txtar_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
+
+/// Free an archive handle; null is ignored
+///
+/// # Safety
+/// `archive` must be null or a handle not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn emx_txtar_archive_free(archive: *mut EmxTxtarArchive) {
+    if !archive.is_null() {
+        drop(Box::from_raw(archive));
+    }
+}
+
+/// Free a string returned by `emx_txtar_encode`; null is ignored
+///
+/// # Safety
+/// `text` must be null or a string from `emx_txtar_encode` not yet freed.
+#[no_mangle]
+pub unsafe extern "C" fn emx_txtar_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ffi_round_trip() {
+        unsafe {
+            let archive = emx_txtar_archive_new();
+            assert_eq!(emx_txtar_add_file(archive, c"a.txt".as_ptr(), b"hello\n".as_ptr(), 6), 0);
+            assert_eq!(emx_txtar_add_file(archive, c"a.txt".as_ptr(), ptr::null(), 0), -1);
+            let error = CStr::from_ptr(emx_txtar_last_error()).to_str().unwrap();
+            assert!(error.contains("Duplicate file"));
+
+            let mut len = 0;
+            let text = emx_txtar_encode(archive, &mut len);
+            assert_eq!(CStr::from_ptr(text).to_str().unwrap(), "-- a.txt --\nhello\n");
+            emx_txtar_archive_free(archive);
+
+            let decoded = emx_txtar_decode(text, len);
+            emx_txtar_string_free(text);
+            assert_eq!(emx_txtar_file_count(decoded), 1);
+            assert_eq!(CStr::from_ptr(emx_txtar_file_name(decoded, 0)).to_str().unwrap(), "a.txt");
+            assert!(emx_txtar_file_name(decoded, 1).is_null());
+
+            let data = emx_txtar_get_file(decoded, c"a.txt".as_ptr(), &mut len);
+            assert_eq!(std::slice::from_raw_parts(data, len), b"hello");
+            assert!(emx_txtar_get_file(decoded, c"missing".as_ptr(), &mut len).is_null());
+            emx_txtar_archive_free(decoded);
+        }
+    }
+
+    #[test]
+    fn test_header_declares_exports() {
+        let header = include_str!("../include/emx_txtar.h");
+        for name in [
+            "emx_txtar_archive_new", "emx_txtar_decode", "emx_txtar_encode", "emx_txtar_add_file",
+            "emx_txtar_file_count", "emx_txtar_file_name", "emx_txtar_get_file", "emx_txtar_last_error",
+            "emx_txtar_archive_free", "emx_txtar_string_free",
+        ] {
+            assert!(header.contains(&format!("{}(", name)), "{} missing from header", name);
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! Frontmatter and provenance
+//!
+//! A comment may start with a frontmatter block of `key: value` lines
+//! between `---` lines:
+//!
+//! ```text
+//! ---
+//! tool: emx-txtar
+//! tool-version: 0.1.0
+//! created: 1760000000
+//! ---
+//! Fixtures for the parser
+//! ```
+//!
+//! [`Archive::frontmatter`] reads it and [`Archive::set_frontmatter`]
+//! rewrites it, leaving the rest of the comment alone. A [`Provenance`]
+//! given to [`Encoder::with_provenance`](crate::Encoder::with_provenance)
+//! is stamped into the frontmatter of every archive that encoder writes,
+//! and [`Archive::provenance`] reads it back, so archives can be traced to
+//! the tool and source they came from.
+//!
+//! ```
+//! use emx_txtar::frontmatter::Provenance;
+//! use emx_txtar::{Archive, Decoder, Encoder};
+//!
+//! let provenance = Provenance::new("fixture-gen", "1.4.0").with_commit("3f2a9c1");
+//! let text = Encoder::new().with_provenance(provenance).encode(&Archive::with_comment("Fixtures")).unwrap();
+//! let archive = Decoder::new().decode(&text).unwrap();
+//! let provenance = archive.provenance().unwrap();
+//! assert_eq!(provenance.tool, "fixture-gen");
+//! assert_eq!(provenance.commit.as_deref(), Some("3f2a9c1"));
+//! ```
+
+use crate::archive::Archive;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Line opening and closing a frontmatter block
+pub const FRONTMATTER_DELIMITER: &str = "---";
+
+/// `key: value` fields at the start of a comment, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Frontmatter {
+    fields: Vec<(String, String)>,
+}
+
+impl Frontmatter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Split a comment into its frontmatter, if it starts with a complete,
+    /// well-formed block, and the rest of the comment
+    pub fn split(comment: &str) -> (Option<Frontmatter>, &str) {
+        let mut lines = comment.split_inclusive('\n');
+        let mut end = match lines.next() {
+            Some(line) if line.trim_end() == FRONTMATTER_DELIMITER => line.len(),
+            _ => return (None, comment),
+        };
+        let mut frontmatter = Frontmatter::new();
+        for line in lines {
+            end += line.len();
+            let text = line.trim_end();
+            if text == FRONTMATTER_DELIMITER {
+                return (Some(frontmatter), &comment[end..]);
+            }
+            match text.split_once(':') {
+                Some((key, value)) if is_key(key) => frontmatter.fields.push((key.into(), value.trim().into())),
+                _ => return (None, comment),
+            }
+        }
+        (None, comment)
+    }
+
+    /// Value of `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Set `key` to `value`, in place if it is present and last otherwise;
+    /// line breaks in the value become spaces
+    ///
+    /// Panics if `key` is empty or holds whitespace or `:`.
+    pub fn set(&mut self, key: &str, value: &str) {
+        assert!(is_key(key), "invalid frontmatter key {:?}", key);
+        let value = value.trim().replace(['\r', '\n'], " ");
+        match self.fields.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.fields.push((key.into(), value)),
+        }
+    }
+
+    /// Remove `key`, returning its value
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.fields.iter().position(|(k, _)| k == key)?;
+        Some(self.fields.remove(index).1)
+    }
+
+    /// The fields, in order
+    pub fn fields(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+impl core::fmt::Display for Frontmatter {
+    /// The block with its delimiters, ending in a line break
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "{}", FRONTMATTER_DELIMITER)?;
+        for (key, value) in &self.fields {
+            writeln!(f, "{}: {}", key, value)?;
+        }
+        writeln!(f, "{}", FRONTMATTER_DELIMITER)
+    }
+}
+
+fn is_key(key: &str) -> bool {
+    !key.is_empty() && !key.contains(|c: char| c.is_whitespace() || c == ':')
+}
+
+/// Where an archive came from, kept in the frontmatter as `tool`,
+/// `tool-version`, `created` (Unix seconds), `source` and `commit`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// Name of the program that wrote the archive
+    pub tool: String,
+    pub tool_version: Option<String>,
+    /// Creation time in seconds since the Unix epoch
+    pub created: Option<u64>,
+    /// Directory or other source the files were taken from
+    pub source: Option<String>,
+    /// Revision of the source, such as a git commit id
+    pub commit: Option<String>,
+}
+
+impl Provenance {
+    pub fn new(tool: impl Into<String>, version: impl Into<String>) -> Self {
+        Self { tool: tool.into(), tool_version: Some(version.into()), ..Default::default() }
+    }
+
+    /// Provenance created at the current time
+    #[cfg(feature = "std")]
+    pub fn now(tool: impl Into<String>, version: impl Into<String>) -> Self {
+        let created = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).ok().map(|d| d.as_secs());
+        Self { created, ..Self::new(tool, version) }
+    }
+
+    pub fn with_created(mut self, unix_seconds: u64) -> Self {
+        self.created = Some(unix_seconds);
+        self
+    }
+
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    pub fn with_commit(mut self, commit: impl Into<String>) -> Self {
+        self.commit = Some(commit.into());
+        self
+    }
+
+    /// Read the provenance fields; `None` without a `tool` field
+    ///
+    /// A `created` value that is not a number is left out.
+    pub fn from_frontmatter(frontmatter: &Frontmatter) -> Option<Self> {
+        let field = |key| frontmatter.get(key).map(String::from);
+        Some(Self {
+            tool: field("tool")?,
+            tool_version: field("tool-version"),
+            created: frontmatter.get("created").and_then(|c| c.parse().ok()),
+            source: field("source"),
+            commit: field("commit"),
+        })
+    }
+
+    /// Set the provenance fields, removing those that are not known
+    pub fn apply(&self, frontmatter: &mut Frontmatter) {
+        let created = self.created.map(|c| c.to_string());
+        let fields = [
+            ("tool", Some(&self.tool)),
+            ("tool-version", self.tool_version.as_ref()),
+            ("created", created.as_ref()),
+            ("source", self.source.as_ref()),
+            ("commit", self.commit.as_ref()),
+        ];
+        for (key, value) in fields {
+            match value {
+                Some(value) => frontmatter.set(key, value),
+                None => drop(frontmatter.remove(key)),
+            }
+        }
+    }
+
+    /// `comment` with this provenance in its frontmatter
+    pub(crate) fn stamp(&self, comment: &str) -> String {
+        let (frontmatter, body) = Frontmatter::split(comment);
+        let mut frontmatter = frontmatter.unwrap_or_default();
+        self.apply(&mut frontmatter);
+        frontmatter.to_string() + body
+    }
+}
+
+impl Archive {
+    /// The frontmatter block at the start of the comment
+    pub fn frontmatter(&self) -> Option<Frontmatter> {
+        Frontmatter::split(&self.comment).0
+    }
+
+    /// Replace the frontmatter block, or remove it if `frontmatter` is empty
+    pub fn set_frontmatter(&mut self, frontmatter: &Frontmatter) {
+        let body = Frontmatter::split(&self.comment).1;
+        self.comment = if frontmatter.is_empty() { body.to_string() } else { frontmatter.to_string() + body };
+        self.parse_commands();
+    }
+
+    /// Provenance recorded in the frontmatter
+    pub fn provenance(&self) -> Option<Provenance> {
+        Provenance::from_frontmatter(&self.frontmatter()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, Encoder, File};
+
+    #[test]
+    fn test_frontmatter_split() {
+        let (frontmatter, body) = Frontmatter::split("---\ntool: x\nurl: http://a:b\n---\nBody\n");
+        let frontmatter = frontmatter.unwrap();
+        assert_eq!(frontmatter.get("tool"), Some("x"));
+        assert_eq!(frontmatter.get("url"), Some("http://a:b"));
+        assert_eq!(body, "Body\n");
+        assert_eq!(frontmatter.to_string(), "---\ntool: x\nurl: http://a:b\n---\n");
+
+        // Closing delimiter without a line break, as decoded
+        assert_eq!(Frontmatter::split("---\n---").1, "");
+        // A rule followed by prose is not frontmatter
+        assert_eq!(Frontmatter::split("---\nnot a field\n---\n"), (None, "---\nnot a field\n---\n"));
+        assert_eq!(Frontmatter::split("---\ntool: x\n").0, None);
+        assert_eq!(Frontmatter::split("Intro\n---\n").0, None);
+    }
+
+    #[test]
+    fn test_set_frontmatter() {
+        let mut archive = Archive::with_comment("# Title\n\nText.");
+        let mut frontmatter = Frontmatter::new();
+        frontmatter.set("owner", "qa\nteam");
+        archive.set_frontmatter(&frontmatter);
+        assert_eq!(archive.comment, "---\nowner: qa team\n---\n# Title\n\nText.");
+        assert_eq!(archive.title(), Some("Title"));
+        assert_eq!(archive.description().as_deref(), Some("Text."));
+
+        frontmatter.remove("owner");
+        archive.set_frontmatter(&frontmatter);
+        assert_eq!(archive.comment, "# Title\n\nText.");
+    }
+
+    #[test]
+    fn test_provenance_round_trip() {
+        let mut archive = Archive::with_comment("---\nowner: qa\ncommit: old\n---\nFixtures");
+        archive.add_file(File::new("a.txt", "A")).unwrap();
+        let provenance = Provenance::new("gen", "1.0").with_created(1_700_000_000).with_source("tests/data");
+        let encoder = Encoder::new().with_provenance(provenance.clone());
+        let text = encoder.encode(&archive).unwrap();
+        assert_eq!(
+            text,
+            "---\nowner: qa\ntool: gen\ntool-version: 1.0\ncreated: 1700000000\nsource: tests/data\n---\nFixtures\n-- a.txt --\nA\n",
+        );
+        let decoded = Decoder::new().decode(&text).unwrap();
+        assert_eq!(decoded.provenance(), Some(provenance));
+        assert_eq!(decoded.frontmatter().unwrap().get("owner"), Some("qa"));
+
+        assert_eq!(Archive::with_comment("Fixtures").provenance(), None);
+        let mut frontmatter = Frontmatter::new();
+        frontmatter.set("tool", "gen");
+        frontmatter.set("created", "yesterday");
+        assert_eq!(Provenance::from_frontmatter(&frontmatter).unwrap().created, None);
+    }
+
+    #[cfg(feature = "signing")]
+    #[test]
+    fn test_provenance_is_signed() {
+        let key = ed25519_dalek::SigningKey::from_bytes(&[3; 32]);
+        let encoder = Encoder::new().with_provenance(Provenance::new("gen", "1.0"));
+        let text = encoder.encode_signed(&Archive::with_comment("Fixtures"), &key).unwrap();
+        let archive = Decoder::new().decode(&text).unwrap();
+        archive.verify_signature(&key.verifying_key()).unwrap();
+    }
+}
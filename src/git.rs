@@ -0,0 +1,209 @@
+//! Archives of git trees
+//!
+//! [`Archive::from_git_tree`] reads the files of any revision straight from
+//! the object database, so no checkout is needed. Contents are what a
+//! checkout would write as far as `.gitattributes` in the tree decides it:
+//! files with `eol=crlf` get CRLF line endings, while everything else keeps
+//! the bytes stored in the repository (local `core.autocrlf` settings are
+//! ignored so snapshots do not depend on the machine).
+
+use crate::archive::{Archive, File, FileMetadata};
+use crate::glob;
+use anyhow::{anyhow, Result};
+use git2::{ObjectType, Pathspec, PathspecFlags, Repository, TreeWalkMode, TreeWalkResult};
+use std::path::Path;
+
+impl Archive {
+    /// Build an archive of the files of revision `rev` (a commit, tag or
+    /// tree, as `git rev-parse` accepts) in the repository at `repo`
+    ///
+    /// Only paths matched by `pathspec` are included, or all if it is
+    /// empty. Executable files are recorded with mode `0755`; symlinks and
+    /// submodules are skipped.
+    pub fn from_git_tree(repo: &Path, rev: &str, pathspec: &[&str]) -> Result<Archive> {
+        let repository = Repository::open(repo)
+            .map_err(|e| anyhow!("Failed to open repository {}: {}", repo.display(), e.message()))?;
+        let tree = repository.revparse_single(rev)
+            .and_then(|object| object.peel_to_tree())
+            .map_err(|e| anyhow!("Failed to resolve '{}': {}", rev, e.message()))?;
+        let pathspec = Pathspec::new(pathspec.iter())?;
+
+        let mut blobs = Vec::new();
+        tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                if let Some(name) = entry.name() {
+                    blobs.push((format!("{}{}", dir, name), entry.id(), entry.filemode()));
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+
+        let mut attributes = Attributes::default();
+        for (name, id, _) in &blobs {
+            if name == ".gitattributes" || name.ends_with("/.gitattributes") {
+                let blob = repository.find_blob(*id)?;
+                let dir = &name[..name.len() - ".gitattributes".len()];
+                attributes.extend(dir, &String::from_utf8_lossy(blob.content()));
+            }
+        }
+
+        let mut archive = Archive::new();
+        for (name, id, mode) in blobs {
+            const EXECUTABLE: i32 = 0o100755;
+            const REGULAR: i32 = 0o100644;
+            if (mode != REGULAR && mode != EXECUTABLE) || !pathspec.matches_path(Path::new(&name), PathspecFlags::DEFAULT) {
+                continue;
+            }
+            let blob = repository.find_blob(id)?;
+            let data = if attributes.crlf(&name, blob.content()) { to_crlf(blob.content()) } else { blob.content().to_vec() };
+            let mut file = File::new(name, data);
+            if mode == EXECUTABLE {
+                file = file.with_metadata(FileMetadata { mode: Some(0o755), mtime: None });
+            }
+            archive.add_file(file)?;
+        }
+        Ok(archive)
+    }
+}
+
+/// The `text` and `eol` attributes of `.gitattributes` files
+#[derive(Default)]
+struct Attributes {
+    /// Directory of the file, pattern and settings, shallower files first
+    rules: Vec<(String, String, Setting)>,
+}
+
+#[derive(Default, Clone, Copy)]
+struct Setting {
+    /// `text` set (`Some(Some(true))`), unset with `-text` or `binary`
+    /// (`Some(Some(false))`) or `text=auto` (`Some(None)`)
+    text: Option<Option<bool>>,
+    /// `eol=crlf` (`Some(true)`) or `eol=lf` (`Some(false)`)
+    crlf: Option<bool>,
+}
+
+impl Attributes {
+    fn extend(&mut self, dir: &str, content: &str) {
+        let mut rules = Vec::new();
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next().filter(|p| !p.starts_with('#')) else { continue };
+            let mut setting = Setting::default();
+            for attribute in fields {
+                match attribute {
+                    "text" => setting.text = Some(Some(true)),
+                    "-text" | "binary" => setting.text = Some(Some(false)),
+                    "text=auto" => setting.text = Some(None),
+                    "eol=crlf" => setting.crlf = Some(true),
+                    "eol=lf" => setting.crlf = Some(false),
+                    _ => {}
+                }
+            }
+            rules.push((dir.to_string(), pattern.to_string(), setting));
+        }
+        self.rules.extend(rules);
+        self.rules.sort_by_key(|(dir, _, _)| dir.matches('/').count());
+    }
+
+    /// Whether checking out `path` converts its line endings to CRLF
+    fn crlf(&self, path: &str, content: &[u8]) -> bool {
+        let mut text = None;
+        let mut crlf = false;
+        for (dir, pattern, setting) in &self.rules {
+            let Some(relative) = path.strip_prefix(dir.as_str()) else { continue };
+            let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+            if glob::matches(pattern, relative) {
+                text = setting.text.or(text);
+                crlf = setting.crlf.unwrap_or(crlf);
+            }
+        }
+        match text {
+            // As in git, `eol` alone marks the file as text
+            None => crlf,
+            Some(None) => crlf && !content.contains(&0),
+            Some(Some(text)) => crlf && text,
+        }
+    }
+}
+
+/// Replace the bare `\n` line endings of `data` with `\r\n`
+fn to_crlf(data: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(data.len() + data.len() / 16);
+    for (i, &byte) in data.iter().enumerate() {
+        if byte == b'\n' && (i == 0 || data[i - 1] != b'\r') {
+            output.push(b'\r');
+        }
+        output.push(byte);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{IndexAddOption, Signature};
+
+    fn repository() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(dir.path()).unwrap();
+        let files: &[(&str, &[u8])] = &[
+            (".gitattributes", b"*.bat eol=crlf\n*.bin binary\n* text=auto\n"),
+            ("fixtures/run.bat", b"echo one\necho two\n"),
+            ("fixtures/nul.bat", b"a\x00\nb"),
+            ("fixtures/data.bin", b"\x00\n\x01"),
+            ("fixtures/nested/.gitattributes", b"*.txt eol=crlf\n"),
+            ("fixtures/nested/notes.txt", b"a\nb\n"),
+            ("fixtures/plain.txt", b"a\nb\n"),
+            ("tool.sh", b"#!/bin/sh\n"),
+        ];
+        for (name, content) in files {
+            let path = dir.path().join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let mut index = repo.index().unwrap();
+        index.add_all(["*"], IndexAddOption::DEFAULT, None).unwrap();
+        let mut entry = index.get_path(Path::new("tool.sh"), 0).unwrap();
+        entry.mode = 0o100755;
+        index.add(&entry).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("Test", "test@example.com").unwrap();
+        let commit = repo.commit(Some("HEAD"), &signature, &signature, "fixtures", &tree, &[]).unwrap();
+        repo.tag_lightweight("v1.2", &repo.find_object(commit, None).unwrap(), false).unwrap();
+
+        // Later changes must not show up in the tagged snapshot
+        std::fs::write(dir.path().join("fixtures/plain.txt"), "changed\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_from_git_tree() {
+        let dir = repository();
+        let archive = Archive::from_git_tree(dir.path(), "v1.2", &["fixtures"]).unwrap();
+        let names: Vec<_> = archive.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, [
+            "fixtures/data.bin", "fixtures/nested/.gitattributes", "fixtures/nested/notes.txt",
+            "fixtures/nul.bat", "fixtures/plain.txt", "fixtures/run.bat",
+        ]);
+        assert_eq!(archive.get_file("fixtures/run.bat").unwrap().data.as_ref(), b"echo one\r\necho two\r\n");
+        assert_eq!(archive.get_file("fixtures/nested/notes.txt").unwrap().data.as_ref(), b"a\r\nb\r\n");
+        assert_eq!(archive.get_file("fixtures/plain.txt").unwrap().data.as_ref(), b"a\nb\n");
+        assert_eq!(archive.get_file("fixtures/data.bin").unwrap().data.as_ref(), b"\x00\n\x01");
+        // `text=auto` leaves content that looks binary alone
+        assert_eq!(archive.get_file("fixtures/nul.bat").unwrap().data.as_ref(), b"a\x00\nb");
+
+        let all = Archive::from_git_tree(dir.path(), "HEAD", &[]).unwrap();
+        assert_eq!(all.files.len(), 8);
+        assert_eq!(all.get_file("tool.sh").unwrap().metadata.mode, Some(0o755));
+        assert_eq!(all.get_file("fixtures/plain.txt").unwrap().metadata.mode, None);
+
+        let err = Archive::from_git_tree(dir.path(), "v9", &[]).unwrap_err();
+        assert!(err.to_string().contains("Failed to resolve 'v9'"), "{}", err);
+    }
+
+    #[test]
+    fn test_to_crlf() {
+        assert_eq!(to_crlf(b"a\nb\r\n\n"), b"a\r\nb\r\n\r\n");
+    }
+}
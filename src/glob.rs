@@ -0,0 +1,193 @@
+//! Minimal glob matching for archive paths
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// Match an archive path against a glob pattern
+///
+/// Supported syntax:
+/// - `*` matches any sequence of characters except `/`
+/// - `**` matches any sequence of characters including `/`
+///   (`**/` may also match zero directories)
+/// - `?` matches a single character except `/`
+///
+/// Patterns without a `/` are matched against the final path component,
+/// so `*.png` matches `images/logo.png`.
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let target = if pattern.contains('/') {
+        path
+    } else {
+        path.rsplit('/').next().unwrap_or(path)
+    };
+    matches_path(pattern, target)
+}
+
+/// Match a glob pattern against the whole path
+fn matches_path(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let target: Vec<char> = path.chars().collect();
+    match_from(&pattern, &target)
+}
+
+/// Check whether any of the patterns matches the path
+pub fn matches_any<S: AsRef<str>>(patterns: &[S], path: &str) -> bool {
+    patterns.iter().any(|p| matches(p.as_ref(), path))
+}
+
+/// Apply include/exclude filters to a path
+///
+/// A path is selected when it matches one of the `include` patterns (or
+/// `include` is empty) and none of the `exclude` patterns.
+pub fn is_selected<S: AsRef<str>>(include: &[S], exclude: &[S], path: &str) -> bool {
+    (include.is_empty() || matches_any(include, path)) && !matches_any(exclude, path)
+}
+
+/// Rules parsed from a `.gitignore`-style file
+///
+/// Supports comments, `!` negation, trailing `/` for directory-only rules
+/// and anchoring: patterns containing a `/` match the path relative to the
+/// ignore file's directory, all others match the final component at any
+/// depth. Ignoring a directory does not cover its contents here, so callers
+/// walking a tree should prune ignored directories.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<IgnoreRule>,
+}
+
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+impl IgnoreRules {
+    /// Parse the contents of an ignore file
+    pub fn parse(content: &str) -> Self {
+        let mut rules = Self::default();
+        rules.extend(content);
+        rules
+    }
+
+    /// Append the rules of another ignore file, which take precedence
+    pub fn extend(&mut self, content: &str) {
+        for line in content.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            let anchored = line.contains('/');
+            let pattern = line.strip_prefix('/').unwrap_or(line).to_string();
+            if pattern.is_empty() {
+                continue;
+            }
+
+            self.rules.push(IgnoreRule { pattern, negated, dir_only, anchored });
+        }
+    }
+
+    /// Check a `/`-separated path relative to the ignore file's directory
+    ///
+    /// Returns `Some(true)` if the last matching rule ignores the path,
+    /// `Some(false)` if it re-includes it, and `None` if no rule matches.
+    pub fn matched(&self, path: &str, is_dir: bool) -> Option<bool> {
+        self.rules.iter().rev()
+            .find(|rule| {
+                let hit = if rule.anchored { matches_path(&rule.pattern, path) } else { matches(&rule.pattern, path) };
+                (is_dir || !rule.dir_only) && hit
+            })
+            .map(|rule| !rule.negated)
+    }
+}
+
+fn match_from(p: &[char], s: &[char]) -> bool {
+    match p.first() {
+        None => s.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') => {
+            let rest = &p[2..];
+            // `**/` may match zero directories
+            if rest.first() == Some(&'/') && match_from(&rest[1..], s) {
+                return true;
+            }
+            (0..=s.len()).any(|i| match_from(rest, &s[i..]))
+        }
+        Some('*') => {
+            let rest = &p[1..];
+            for i in 0..=s.len() {
+                if match_from(rest, &s[i..]) {
+                    return true;
+                }
+                if i < s.len() && s[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => !s.is_empty() && s[0] != '/' && match_from(&p[1..], &s[1..]),
+        Some(c) => s.first() == Some(c) && match_from(&p[1..], &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_star_matches_basename() {
+        assert!(matches("*.png", "logo.png"));
+        assert!(matches("*.png", "images/logo.png"));
+        assert!(!matches("*.png", "logo.png.txt"));
+    }
+
+    #[test]
+    fn test_star_does_not_cross_directories() {
+        assert!(matches("src/*.rs", "src/lib.rs"));
+        assert!(!matches("src/*.rs", "src/bin/main.rs"));
+    }
+
+    #[test]
+    fn test_double_star() {
+        assert!(matches("src/**/*.rs", "src/lib.rs"));
+        assert!(matches("src/**/*.rs", "src/bin/main.rs"));
+        assert!(matches("testdata/**", "testdata/a/b.txt"));
+        assert!(!matches("testdata/**", "other/a.txt"));
+    }
+
+    #[test]
+    fn test_is_selected() {
+        let none: [&str; 0] = [];
+        assert!(is_selected(&none, &none, "a.txt"));
+        assert!(is_selected(&["src/**/*.rs"], &none, "src/lib.rs"));
+        assert!(!is_selected(&["src/**/*.rs"], &none, "README.md"));
+        assert!(!is_selected(&["src/**"], &["*.bak"], "src/lib.rs.bak"));
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let rules = IgnoreRules::parse("# build output\ntarget/\n*.log\n!keep.log\n/root.txt\ndocs/*.tmp\n");
+        assert_eq!(rules.matched("target", true), Some(true));
+        assert_eq!(rules.matched("target", false), None);
+        assert_eq!(rules.matched("sub/debug.log", false), Some(true));
+        assert_eq!(rules.matched("keep.log", false), Some(false));
+        assert_eq!(rules.matched("root.txt", false), Some(true));
+        assert_eq!(rules.matched("sub/root.txt", false), None);
+        assert_eq!(rules.matched("docs/a.tmp", false), Some(true));
+        assert_eq!(rules.matched("README.md", false), None);
+    }
+
+    #[test]
+    fn test_question_mark() {
+        assert!(matches("file?.txt", "file1.txt"));
+        assert!(!matches("file?.txt", "file10.txt"));
+    }
+}
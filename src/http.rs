@@ -0,0 +1,249 @@
+//! Fetching archives over HTTP and HTTPS
+//!
+//! Archives are downloaded with [`reqwest`], blocking or async, following
+//! redirects, for pulling fixture archives from artifact stores and gists
+//! (and uploading them with [`put`]). gzip content encoding is undone while
+//! the body is read, and gzip or zstd compressed archive files are
+//! decompressed as well. [`HttpOptions::max_size`] bounds the body as read
+//! and each decompressed form of it; reading stops once it is exceeded.
+//!
+//! The blocking functions must not be called from within an async runtime;
+//! use [`Decoder::decode_url_async`] and [`fetch_async`] there.
+
+use crate::archive::Archive;
+use crate::compress::Compression;
+use crate::decoder::Decoder;
+use anyhow::{bail, Context, Result};
+use reqwest::redirect::Policy;
+use std::io::Read;
+use std::time::Duration;
+
+const USER_AGENT: &str = concat!("emx-txtar/", env!("CARGO_PKG_VERSION"));
+
+/// Options for [`Decoder::decode_url_with`]
+#[derive(Debug, Clone)]
+pub struct HttpOptions {
+    /// Maximum size of the response body, before and after decompression
+    pub max_size: usize,
+    /// Timeout for a whole request, from connecting to reading the body
+    pub timeout: Option<Duration>,
+    /// Number of redirects to follow
+    pub max_redirects: usize,
+}
+
+impl Default for HttpOptions {
+    fn default() -> Self {
+        Self {
+            max_size: 64 * 1024 * 1024,
+            timeout: Some(Duration::from_secs(30)),
+            max_redirects: 5,
+        }
+    }
+}
+
+impl Decoder {
+    /// Download and decode an archive with the default [`HttpOptions`]
+    pub fn decode_url(&self, url: &str) -> Result<Archive> {
+        self.decode_url_with(url, &HttpOptions::default())
+    }
+
+    /// Download and decode an archive
+    pub fn decode_url_with(&self, url: &str, options: &HttpOptions) -> Result<Archive> {
+        self.decode_body(url, fetch(url, options)?)
+    }
+
+    /// Download and decode an archive with the default [`HttpOptions`],
+    /// without blocking
+    pub async fn decode_url_async(&self, url: &str) -> Result<Archive> {
+        self.decode_url_with_async(url, &HttpOptions::default()).await
+    }
+
+    /// Download and decode an archive without blocking
+    pub async fn decode_url_with_async(&self, url: &str, options: &HttpOptions) -> Result<Archive> {
+        self.decode_body(url, fetch_async(url, options).await?)
+    }
+
+    fn decode_body(&self, url: &str, body: Vec<u8>) -> Result<Archive> {
+        let text = String::from_utf8(body).with_context(|| format!("Archive at {} is not valid UTF-8", url))?;
+        self.decode(&text)
+    }
+}
+
+/// Download a URL, following redirects and undoing content and archive compression
+pub fn fetch(url: &str, options: &HttpOptions) -> Result<Vec<u8>> {
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(USER_AGENT)
+        .redirect(Policy::limited(options.max_redirects))
+        .timeout(options.timeout)
+        .build()?;
+    let response = client.get(url).send().with_context(|| format!("Failed to fetch {}", url))?;
+    check_response(url, response.status(), response.content_length(), options)?;
+
+    let mut body = Vec::new();
+    response.take(options.max_size as u64 + 1)
+        .read_to_end(&mut body)
+        .with_context(|| format!("Failed to read {}", url))?;
+    finish_body(url, body, options)
+}
+
+/// Download a URL as [`fetch`] does, without blocking
+pub async fn fetch_async(url: &str, options: &HttpOptions) -> Result<Vec<u8>> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(USER_AGENT)
+        .redirect(Policy::limited(options.max_redirects));
+    if let Some(timeout) = options.timeout {
+        builder = builder.timeout(timeout);
+    }
+    let mut response = builder.build()?.get(url).send().await.with_context(|| format!("Failed to fetch {}", url))?;
+    check_response(url, response.status(), response.content_length(), options)?;
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.with_context(|| format!("Failed to read {}", url))? {
+        body.extend_from_slice(&chunk);
+        if body.len() > options.max_size {
+            break;
+        }
+    }
+    finish_body(url, body, options)
+}
+
+/// Upload data to a URL with a PUT request
+pub fn put(url: &str, data: &[u8], options: &HttpOptions) -> Result<()> {
+    let client = reqwest::blocking::Client::builder()
+        .user_agent(USER_AGENT)
+        .timeout(options.timeout)
+        .build()?;
+    let response = client.put(url).body(data.to_vec()).send().with_context(|| format!("Failed to upload {}", url))?;
+    if !response.status().is_success() {
+        bail!("HTTP {} uploading {}", response.status().as_u16(), url);
+    }
+    Ok(())
+}
+
+/// Fail on an unsuccessful status or a declared length over the limit
+fn check_response(url: &str, status: reqwest::StatusCode, length: Option<u64>, options: &HttpOptions) -> Result<()> {
+    if !status.is_success() {
+        bail!("HTTP {} fetching {}", status.as_u16(), url);
+    }
+    if length.is_some_and(|length| length > options.max_size as u64) {
+        bail!("Response from {} exceeds {} bytes", url, options.max_size);
+    }
+    Ok(())
+}
+
+/// Check the size of a body read up to one byte past the limit, then undo
+/// archive compression within the same limit
+fn finish_body(url: &str, body: Vec<u8>, options: &HttpOptions) -> Result<Vec<u8>> {
+    if body.len() > options.max_size {
+        bail!("Response from {} exceeds {} bytes", url, options.max_size);
+    }
+    match Compression::detect(&body) {
+        Some(compression) => compression.decompress_limited(&body, options.max_size)
+            .with_context(|| format!("Failed to decompress {}", url)),
+        None => Ok(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::gzip;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve canned responses, one per connection, returning the base URL
+    fn serve(responses: Vec<Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0u8; 4096];
+                let _ = stream.read(&mut request);
+                stream.write_all(&response).unwrap();
+            }
+        });
+        format!("http://{}", addr)
+    }
+
+    /// A complete response closing the connection after `body`
+    fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut response = format!("HTTP/1.1 {}\r\nConnection: close\r\n{}\r\n", status, headers).into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    fn chunked_gzip(text: &[u8]) -> Vec<u8> {
+        let body = gzip(text);
+        let mut chunked = format!("{:x}\r\n", 10).into_bytes();
+        chunked.extend_from_slice(&body[..10]);
+        chunked.extend_from_slice(format!("\r\n{:x}\r\n", body.len() - 10).as_bytes());
+        chunked.extend_from_slice(&body[10..]);
+        chunked.extend_from_slice(b"\r\n0\r\n\r\n");
+        response("200 OK", "Transfer-Encoding: chunked\r\nContent-Encoding: gzip\r\n", &chunked)
+    }
+
+    #[test]
+    fn test_decode_url_chunked_gzip_with_redirect() {
+        let redirect = response("302 Found", "Location: /fixtures/a.txtar\r\nContent-Length: 0\r\n", b"");
+        let base = serve(vec![redirect, chunked_gzip(b"-- a.txt --\nhello\n")]);
+
+        let archive = Decoder::new().decode_url(&format!("{}/latest", base)).unwrap();
+        assert_eq!(archive.files[0].name, "a.txt");
+        assert_eq!(&archive.files[0].data[..], b"hello");
+    }
+
+    #[tokio::test]
+    async fn test_decode_url_async() {
+        let packed = Compression::Zstd.compress(b"-- a.txt --\nhello\n").unwrap();
+        let plain = response("200 OK", &format!("Content-Length: {}\r\n", packed.len()), &packed);
+        let base = serve(vec![chunked_gzip(b"-- b.txt --\nworld\n"), plain]);
+
+        let archive = Decoder::new().decode_url_async(&base).await.unwrap();
+        assert_eq!(&archive.files[0].data[..], b"world");
+        let archive = Decoder::new().decode_url_async(&format!("{}/a.txtar.zst", base)).await.unwrap();
+        assert_eq!(&archive.files[0].data[..], b"hello");
+    }
+
+    #[test]
+    fn test_decode_url_limits_and_errors() {
+        let large = response("200 OK", "Content-Length: 100\r\n", &[b'x'; 100]);
+        let missing = response("404 Not Found", "Content-Length: 0\r\n", b"");
+        let base = serve(vec![large, missing]);
+        let options = HttpOptions { max_size: 10, ..Default::default() };
+
+        let err = Decoder::new().decode_url_with(&base, &options).unwrap_err();
+        assert!(format!("{:#}", err).contains("exceeds 10 bytes"));
+        let err = Decoder::new().decode_url(&base).unwrap_err();
+        assert!(err.to_string().contains("HTTP 404"));
+    }
+
+    #[test]
+    fn test_decode_url_limits_decompressed_size() {
+        // A small body inflating far beyond the limit, as content encoding
+        // and as a compressed archive file
+        let bomb = vec![b'x'; 1 << 20];
+        let encoded = gzip(&bomb);
+        let content_encoded = response("200 OK", &format!("Content-Encoding: gzip\r\nContent-Length: {}\r\n", encoded.len()), &encoded);
+        let archive_file = response("200 OK", &format!("Content-Length: {}\r\n", encoded.len()), &encoded);
+        let base = serve(vec![content_encoded, archive_file]);
+        let options = HttpOptions { max_size: 64 * 1024, ..Default::default() };
+
+        for _ in 0..2 {
+            let err = fetch(&base, &options).unwrap_err();
+            assert!(format!("{:#}", err).contains("exceeds 65536 bytes"), "{:#}", err);
+        }
+    }
+
+    #[test]
+    fn test_put() {
+        let created = response("201 Created", "Content-Length: 0\r\n", b"");
+        let forbidden = response("403 Forbidden", "Content-Length: 0\r\n", b"");
+        let base = serve(vec![created, forbidden]);
+
+        put(&format!("{}/a.txtar", base), b"-- a --\n", &HttpOptions::default()).unwrap();
+        let err = put(&format!("{}/a.txtar", base), b"", &HttpOptions::default()).unwrap_err();
+        assert!(err.to_string().contains("HTTP 403"));
+    }
+}
@@ -0,0 +1,122 @@
+//! Include directives for composing archives
+//!
+//! An entry named `@include <path>` stands for the files of another
+//! archive. When a resolver is set with
+//! [`Decoder::with_includes`](crate::Decoder::with_includes), decoding
+//! replaces the entry with the files of the archive the resolver returns for
+//! `<path>`, decoded the same way; its comment and the directive's own
+//! contents are ignored. Without a resolver the entry is an ordinary file.
+//!
+//! Included archives may include others; an include that leads back to an
+//! archive still being decoded is an error.
+//!
+//! ```
+//! use emx_txtar::Decoder;
+//! use std::sync::Arc;
+//!
+//! let resolver = |path: &str| match path {
+//!     "common.txtar" => Ok("-- LICENSE --\nMIT\n".to_string()),
+//!     _ => anyhow::bail!("no archive {}", path),
+//! };
+//! let decoder = Decoder::new().with_includes(Arc::new(resolver));
+//! let archive = decoder.decode("-- @include common.txtar --\n-- main.rs --\nfn main() {}\n").unwrap();
+//! assert_eq!(archive.files[0].name, "LICENSE");
+//! assert_eq!(archive.files[1].name, "main.rs");
+//! ```
+
+use alloc::string::String;
+use anyhow::Result;
+
+/// Prefix of the name of an include directive entry
+pub const INCLUDE_PREFIX: &str = "@include ";
+
+/// Source of included archives
+pub trait IncludeResolver: Send + Sync {
+    /// The text of the archive an `@include <path>` entry refers to
+    fn resolve(&self, path: &str) -> Result<String>;
+}
+
+impl<F: Fn(&str) -> Result<String> + Send + Sync> IncludeResolver for F {
+    fn resolve(&self, path: &str) -> Result<String> {
+        self(path)
+    }
+}
+
+/// Resolves include paths relative to a directory
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct DirResolver {
+    root: std::path::PathBuf,
+}
+
+#[cfg(feature = "std")]
+impl DirResolver {
+    pub fn new(root: impl Into<std::path::PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+#[cfg(feature = "std")]
+impl IncludeResolver for DirResolver {
+    fn resolve(&self, path: &str) -> Result<String> {
+        // The same containment rules as extraction: no absolute paths or `..`
+        let full = crate::archive::extract_path(&self.root, path)?;
+        std::fs::read_to_string(&full).map_err(|e| anyhow::anyhow!("Failed to read {}: {}", full.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decoder;
+    use alloc::string::ToString;
+    use alloc::sync::Arc;
+    use anyhow::bail;
+
+    fn resolver(path: &str) -> Result<String> {
+        Ok(match path {
+            "base.txtar" => "comment\n-- a.txt --\nA\n-- @include inner.txtar --\n",
+            "inner.txtar" => "-- b.txt --\nB\n",
+            "loop1.txtar" => "-- @include loop2.txtar --\n",
+            "loop2.txtar" => "-- @include loop1.txtar --\n",
+            _ => bail!("no archive {}", path),
+        }.to_string())
+    }
+
+    #[test]
+    fn test_includes_are_resolved() {
+        let decoder = Decoder::new().with_includes(Arc::new(resolver));
+        let archive = decoder.decode("top\n-- @include base.txtar --\nignored\n-- c.txt --\nC\n").unwrap();
+        assert_eq!(archive.comment, "top");
+        let names: alloc::vec::Vec<_> = archive.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a.txt", "b.txt", "c.txt"]);
+
+        // Without a resolver the directive is an ordinary entry
+        let plain = Decoder::new().decode("-- @include base.txtar --\n").unwrap();
+        assert_eq!(plain.files[0].name, "@include base.txtar");
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let decoder = Decoder::new().with_includes(Arc::new(resolver));
+        let err = decoder.decode("-- @include loop1.txtar --\n").unwrap_err();
+        assert!(err.to_string().contains("Include cycle: loop1.txtar -> loop2.txtar -> loop1.txtar"), "{}", err);
+
+        let err = decoder.decode("-- @include missing.txtar --\n").unwrap_err();
+        assert!(err.to_string().contains("no archive missing.txtar"), "{}", err);
+
+        let err = decoder.decode("-- b.txt --\n-- @include inner.txtar --\n").unwrap_err();
+        assert!(err.to_string().contains("Duplicate file"), "{}", err);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_dir_resolver() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("common.txtar"), "-- shared.txt --\nshared\n").unwrap();
+        let decoder = Decoder::new().with_includes(Arc::new(DirResolver::new(dir.path())));
+        let archive = decoder.decode("-- @include common.txtar --\n").unwrap();
+        assert_eq!(archive.files[0].data.as_ref(), b"shared");
+        assert!(decoder.decode("-- @include ../common.txtar --\n").is_err());
+    }
+}
@@ -0,0 +1,26 @@
+//! Conversion between txtar archives and other archive formats
+//!
+//! Only regular files are carried over. Snippet and edit entries are
+//! references rather than file contents and are not written out, and
+//! entries without a txtar equivalent (directories, links) are skipped on
+//! read.
+
+#[cfg(feature = "tar")]
+pub mod tar;
+#[cfg(feature = "zip")]
+pub mod zip;
+
+use crate::archive::{Archive, File};
+
+/// Files of an archive that have real contents to export
+fn exported_files(archive: &Archive) -> impl Iterator<Item = &File> {
+    archive.files.iter().filter(|f| f.snippet_ref.is_none() && f.edit_ref.is_none())
+}
+
+/// Add an imported file, letting later entries replace earlier ones as tar does
+fn insert_file(archive: &mut Archive, file: File) {
+    match archive.files.iter_mut().find(|f| f.name == file.name) {
+        Some(existing) => *existing = file,
+        None => archive.files.push(file),
+    }
+}
@@ -0,0 +1,239 @@
+//! Reading and writing ustar archives
+//!
+//! Regular files are mapped to archive entries, with permission bits and
+//! modification times carried over as [`FileMetadata`]. Long names are
+//! stored in the ustar prefix field; GNU long-name and pax `path` records
+//! are understood on read.
+
+use super::{exported_files, insert_file};
+use crate::archive::{Archive, File, FileMetadata};
+use anyhow::{bail, Context, Result};
+use std::io::{Read, Write};
+
+const BLOCK_SIZE: usize = 512;
+const NAME_LEN: usize = 100;
+const PREFIX_LEN: usize = 155;
+const DEFAULT_MODE: u32 = 0o644;
+
+impl Archive {
+    /// Read a tar stream into an archive
+    pub fn from_tar<R: Read>(reader: R) -> Result<Self> {
+        read(reader)
+    }
+
+    /// Write the regular files of the archive as a tar stream
+    pub fn to_tar<W: Write>(&self, writer: W) -> Result<()> {
+        write(self, writer)
+    }
+}
+
+/// Read a tar stream into an archive
+pub fn read<R: Read>(mut reader: R) -> Result<Archive> {
+    let mut archive = Archive::new();
+    let mut long_name: Option<String> = None;
+    let mut header = [0u8; BLOCK_SIZE];
+
+    loop {
+        if !read_block(&mut reader, &mut header)? || header.iter().all(|&b| b == 0) {
+            break;
+        }
+
+        let size = parse_octal(&header[124..136]).context("Invalid size in tar header")? as usize;
+        let mut data = vec![0u8; size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE];
+        reader.read_exact(&mut data).context("Truncated tar entry")?;
+        data.truncate(size);
+
+        match header[156] {
+            b'0' | b'\0' | b'7' => {
+                let name = long_name.take().unwrap_or_else(|| header_path(&header));
+                let name = name.trim_start_matches("./").to_string();
+                if !name.is_empty() {
+                    let metadata = header_metadata(&header)?;
+                    insert_file(&mut archive, File::new(name, data).with_metadata(metadata));
+                }
+            }
+            // GNU long name for the next entry
+            b'L' => {
+                long_name = Some(String::from_utf8_lossy(&data).trim_end_matches('\0').to_string());
+            }
+            // pax extended header for the next entry
+            b'x' => {
+                if let Some(path) = pax_path(&data) {
+                    long_name = Some(path);
+                }
+            }
+            // Directories, links and other special entries have no txtar equivalent
+            _ => long_name = None,
+        }
+    }
+
+    Ok(archive)
+}
+
+/// Write the regular files of an archive as a ustar stream
+pub fn write<W: Write>(archive: &Archive, mut writer: W) -> Result<()> {
+    for file in exported_files(archive) {
+        let file = file.load()?;
+        let data = file.content();
+        let header = build_header(&file.name, data.len(), &file.metadata)?;
+        writer.write_all(&header)?;
+        writer.write_all(&data)?;
+        let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        writer.write_all(&[0u8; BLOCK_SIZE][..padding])?;
+    }
+
+    // End of archive marker
+    writer.write_all(&[0u8; BLOCK_SIZE * 2])?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Fill a block, returning false at a clean end of stream
+fn read_block<R: Read>(reader: &mut R, block: &mut [u8; BLOCK_SIZE]) -> Result<bool> {
+    let mut filled = 0;
+    while filled < BLOCK_SIZE {
+        let n = reader.read(&mut block[filled..])?;
+        if n == 0 {
+            if filled == 0 {
+                return Ok(false);
+            }
+            bail!("Truncated tar header");
+        }
+        filled += n;
+    }
+    Ok(true)
+}
+
+fn build_header(name: &str, size: usize, metadata: &FileMetadata) -> Result<[u8; BLOCK_SIZE]> {
+    let mut header = [0u8; BLOCK_SIZE];
+
+    let (prefix, name_part) = split_name(name)
+        .with_context(|| format!("File name too long for tar: {}", name))?;
+    header[..name_part.len()].copy_from_slice(name_part.as_bytes());
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+
+    write_octal(&mut header[100..108], metadata.mode.unwrap_or(DEFAULT_MODE) as u64);
+    write_octal(&mut header[108..116], 0);
+    write_octal(&mut header[116..124], 0);
+    write_octal(&mut header[124..136], size as u64);
+    write_octal(&mut header[136..148], metadata.mtime.unwrap_or(0));
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    // The checksum is computed with its own field filled with spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    write_octal(&mut header[148..155], checksum as u64);
+    header[155] = b' ';
+
+    Ok(header)
+}
+
+/// Mode and mtime of a header, leaving out the defaults written for files without metadata
+fn header_metadata(header: &[u8; BLOCK_SIZE]) -> Result<FileMetadata> {
+    let mode = parse_octal(&header[100..108]).context("Invalid mode in tar header")? as u32 & 0o7777;
+    let mtime = parse_octal(&header[136..148]).context("Invalid mtime in tar header")?;
+    Ok(FileMetadata {
+        mode: (mode != DEFAULT_MODE).then_some(mode),
+        mtime: (mtime != 0).then_some(mtime),
+    })
+}
+
+/// Split a name into ustar prefix and name fields
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= NAME_LEN {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(i, _)| (&name[..i], &name[i + 1..]))
+        .find(|(prefix, rest)| prefix.len() <= PREFIX_LEN && rest.len() <= NAME_LEN && !rest.is_empty())
+}
+
+/// Write a zero-padded, NUL-terminated octal number filling the field
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+    field[digits.len()..].fill(0);
+}
+
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?;
+    let text = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Full path of a header, joining the ustar prefix when present
+fn header_path(header: &[u8; BLOCK_SIZE]) -> String {
+    let name = c_string(&header[..NAME_LEN]);
+    let prefix = if &header[257..262] == b"ustar" { c_string(&header[345..345 + PREFIX_LEN]) } else { String::new() };
+    if prefix.is_empty() {
+        name
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Extract the `path` record from pax extended header data
+fn pax_path(data: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .filter_map(|record| record.split_once(' ').map(|(_, kv)| kv))
+        .find_map(|kv| kv.strip_prefix("path=").map(str::to_string))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tar_round_trip() {
+        // Decoded text writes the final newline its data leaves out
+        let mut archive: Archive = "-- a.txt --\nhello\n".parse().unwrap();
+        archive.add_file(File::new("bin/data", vec![0u8, 0xFF, 1])).unwrap();
+        let long = format!("{}/{}.txt", "d".repeat(120), "f".repeat(80));
+        archive.add_file(File::new(long.as_str(), "deep")).unwrap();
+
+        let mut buffer = Vec::new();
+        write(&archive, &mut buffer).unwrap();
+        assert_eq!(buffer.len() % BLOCK_SIZE, 0);
+
+        let restored = read(buffer.as_slice()).unwrap();
+        assert_eq!(restored.files.len(), 3);
+        assert_eq!(&restored.files[0].data[..], b"hello\n");
+        assert!(restored.files[1].is_binary);
+        assert_eq!(restored.files[2].name, long);
+    }
+
+    #[test]
+    fn test_tar_metadata() {
+        let mut archive = Archive::new();
+        let metadata = FileMetadata { mode: Some(0o755), mtime: Some(1_700_000_000) };
+        archive.add_file(File::new("run.sh", "#!/bin/sh\n").with_metadata(metadata)).unwrap();
+        archive.add_file(File::new("plain.txt", "text\n")).unwrap();
+
+        let mut buffer = Vec::new();
+        archive.to_tar(&mut buffer).unwrap();
+        let restored = Archive::from_tar(buffer.as_slice()).unwrap();
+
+        assert_eq!(restored.files[0].metadata, metadata);
+        assert_eq!(restored.files[1].metadata, FileMetadata::default());
+    }
+
+    #[test]
+    fn test_tar_name_too_long() {
+        let mut archive = Archive::new();
+        archive.add_file(File::new("x".repeat(200), "data")).unwrap();
+
+        let err = write(&archive, Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("File name too long"));
+    }
+}
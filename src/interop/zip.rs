@@ -0,0 +1,332 @@
+//! Reading and writing zip archives
+//!
+//! Entries are written uncompressed (stored) with UTF-8 names, and the
+//! txtar comment becomes the zip comment. Text entries are flagged as text
+//! in the internal attributes; modes are stored as Unix external attributes
+//! and mtimes in an extended timestamp field alongside the DOS date.
+//!
+//! Stored and deflated entries can be read; other compression methods are
+//! rejected. Directory entries are skipped and Windows `\` separators are
+//! converted to `/`.
+
+use super::{exported_files, insert_file};
+use crate::archive::{Archive, File, FileMetadata};
+use crate::checksum::crc32;
+use crate::compress::inflate;
+use anyhow::{anyhow, bail, Result};
+use std::io::{Read, Write};
+
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIR_SIG: u32 = 0x0605_4b50;
+const END_OF_CENTRAL_DIR_LEN: usize = 22;
+const VERSION: u16 = 20;
+const FLAG_UTF8: u16 = 0x0800;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+/// DOS date for 1980-01-01, the earliest representable
+const DOS_EPOCH_DATE: u16 = 0x21;
+/// Unix seconds at 1980-01-01, the DOS epoch
+const DOS_EPOCH_SECS: u64 = 315_532_800;
+/// "Version made by" host byte for Unix, whose external attributes hold the mode
+const HOST_UNIX: u16 = 3;
+/// Internal attribute bit marking an entry as text
+const ATTR_TEXT: u16 = 1;
+/// Regular file type bits of a Unix mode
+const MODE_REGULAR: u32 = 0o100000;
+const DEFAULT_MODE: u32 = 0o644;
+/// Extended timestamp extra field, holding the Unix mtime
+const EXTRA_TIMESTAMP: u16 = 0x5455;
+
+impl Archive {
+    /// Read a zip archive into an archive
+    pub fn from_zip<R: Read>(reader: R) -> Result<Self> {
+        read(reader)
+    }
+
+    /// Write the regular files of the archive as a zip archive
+    pub fn to_zip<W: Write>(&self, writer: W) -> Result<()> {
+        write(self, writer)
+    }
+}
+
+/// Read a zip archive into an archive
+pub fn read<R: Read>(mut reader: R) -> Result<Archive> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+
+    let eocd = (0..=bytes.len().saturating_sub(END_OF_CENTRAL_DIR_LEN))
+        .rev()
+        .find(|&i| u32_at(&bytes, i) == Some(END_OF_CENTRAL_DIR_SIG))
+        .ok_or_else(|| anyhow!("Not a zip archive: end of central directory not found"))?;
+    let count = u16_at(&bytes, eocd + 10).unwrap_or(0) as usize;
+    let mut pos = u32_at(&bytes, eocd + 16).unwrap_or(0) as usize;
+    let comment_len = u16_at(&bytes, eocd + 20).unwrap_or(0) as usize;
+
+    let comment = bytes.get(eocd + END_OF_CENTRAL_DIR_LEN..eocd + END_OF_CENTRAL_DIR_LEN + comment_len)
+        .ok_or_else(|| anyhow!("Truncated zip comment"))?;
+    let mut archive = Archive::with_comment(String::from_utf8_lossy(comment));
+
+    for _ in 0..count {
+        let truncated = || anyhow!("Truncated zip central directory");
+        if u32_at(&bytes, pos) != Some(CENTRAL_HEADER_SIG) {
+            bail!("Invalid zip central directory entry at offset {}", pos);
+        }
+        let made_by = u16_at(&bytes, pos + 4).ok_or_else(truncated)?;
+        let method = u16_at(&bytes, pos + 10).ok_or_else(truncated)?;
+        let crc = u32_at(&bytes, pos + 16).ok_or_else(truncated)?;
+        let compressed_size = u32_at(&bytes, pos + 20).ok_or_else(truncated)? as usize;
+        let size = u32_at(&bytes, pos + 24).ok_or_else(truncated)? as usize;
+        let name_len = u16_at(&bytes, pos + 28).ok_or_else(truncated)? as usize;
+        let extra_len = u16_at(&bytes, pos + 30).ok_or_else(truncated)? as usize;
+        let comment_len = u16_at(&bytes, pos + 32).ok_or_else(truncated)? as usize;
+        let external_attrs = u32_at(&bytes, pos + 38).ok_or_else(truncated)?;
+        let offset = u32_at(&bytes, pos + 42).ok_or_else(truncated)? as usize;
+        let name = bytes.get(pos + 46..pos + 46 + name_len).ok_or_else(truncated)?;
+        let name = String::from_utf8_lossy(name).replace('\\', "/");
+        let extra = bytes.get(pos + 46 + name_len..pos + 46 + name_len + extra_len).ok_or_else(truncated)?;
+        pos += 46 + name_len + extra_len + comment_len;
+
+        // Directory entries have no txtar equivalent
+        if name.ends_with('/') {
+            continue;
+        }
+        let mode = (made_by >> 8 == HOST_UNIX).then_some((external_attrs >> 16) & 0o7777);
+        let metadata = FileMetadata {
+            mode: mode.filter(|&m| m != 0 && m != DEFAULT_MODE),
+            mtime: extra_mtime(extra),
+        };
+
+        let local_name_len = u16_at(&bytes, offset + 26).ok_or_else(truncated)? as usize;
+        let local_extra_len = u16_at(&bytes, offset + 28).ok_or_else(truncated)? as usize;
+        let start = offset + 30 + local_name_len + local_extra_len;
+        let stored = bytes.get(start..start + compressed_size)
+            .ok_or_else(|| anyhow!("Truncated zip entry: {}", name))?;
+        let data = match method {
+            METHOD_STORED => stored.to_vec(),
+            METHOD_DEFLATED => inflate(stored, size).map_err(|e| anyhow!("Invalid zip entry {}: {}", name, e))?,
+            _ => bail!("Unsupported zip compression method {} for entry: {}", method, name),
+        };
+        if crc32(&data) != crc {
+            bail!("CRC mismatch in zip entry: {}", name);
+        }
+
+        insert_file(&mut archive, File::new(name, data).with_metadata(metadata));
+    }
+
+    Ok(archive)
+}
+
+/// Write the regular files of an archive as an uncompressed zip archive
+pub fn write<W: Write>(archive: &Archive, mut writer: W) -> Result<()> {
+    let mut central = Vec::new();
+    let mut offset = 0usize;
+    let mut count = 0usize;
+
+    for file in exported_files(archive) {
+        let file = file.load()?;
+        let data = file.content();
+        let name = file.name.as_bytes();
+        let crc = crc32(&data);
+        let size = to_u32(data.len(), &file.name)?;
+        let name_len = u16::try_from(name.len()).map_err(|_| anyhow!("File name too long for zip: {}", file.name))?;
+        let (dos_time, dos_date) = file.metadata.mtime.map_or((0, DOS_EPOCH_DATE), dos_datetime);
+        let mut extra = Vec::new();
+        if let Some(mtime) = file.metadata.mtime {
+            put_u16(&mut extra, EXTRA_TIMESTAMP);
+            put_u16(&mut extra, 5);
+            extra.push(1);
+            put_u32(&mut extra, u32::try_from(mtime).unwrap_or(u32::MAX));
+        }
+        let mode = file.metadata.mode.unwrap_or(DEFAULT_MODE);
+
+        let mut local = Vec::with_capacity(30 + name.len());
+        put_u32(&mut local, LOCAL_HEADER_SIG);
+        put_u16(&mut local, VERSION);
+        put_u16(&mut local, FLAG_UTF8);
+        put_u16(&mut local, METHOD_STORED);
+        put_u16(&mut local, dos_time);
+        put_u16(&mut local, dos_date);
+        put_u32(&mut local, crc);
+        put_u32(&mut local, size);
+        put_u32(&mut local, size);
+        put_u16(&mut local, name_len);
+        put_u16(&mut local, extra.len() as u16);
+        local.extend_from_slice(name);
+        local.extend_from_slice(&extra);
+
+        put_u32(&mut central, CENTRAL_HEADER_SIG);
+        put_u16(&mut central, HOST_UNIX << 8 | VERSION);
+        put_u16(&mut central, VERSION);
+        put_u16(&mut central, FLAG_UTF8);
+        put_u16(&mut central, METHOD_STORED);
+        put_u16(&mut central, dos_time);
+        put_u16(&mut central, dos_date);
+        put_u32(&mut central, crc);
+        put_u32(&mut central, size);
+        put_u32(&mut central, size);
+        put_u16(&mut central, name_len);
+        put_u16(&mut central, extra.len() as u16);
+        put_u16(&mut central, 0);
+        put_u16(&mut central, 0);
+        put_u16(&mut central, if file.is_binary { 0 } else { ATTR_TEXT });
+        put_u32(&mut central, (MODE_REGULAR | mode) << 16);
+        put_u32(&mut central, to_u32(offset, &file.name)?);
+        central.extend_from_slice(name);
+        central.extend_from_slice(&extra);
+
+        writer.write_all(&local)?;
+        writer.write_all(&data)?;
+        offset += local.len() + data.len();
+        count += 1;
+    }
+
+    let count = u16::try_from(count).map_err(|_| anyhow!("Too many files for zip: {}", count))?;
+    let comment = archive.comment.as_bytes();
+    let comment_len = u16::try_from(comment.len()).map_err(|_| anyhow!("Archive comment too long for zip"))?;
+
+    let mut end = Vec::with_capacity(END_OF_CENTRAL_DIR_LEN + comment.len());
+    put_u32(&mut end, END_OF_CENTRAL_DIR_SIG);
+    put_u16(&mut end, 0);
+    put_u16(&mut end, 0);
+    put_u16(&mut end, count);
+    put_u16(&mut end, count);
+    put_u32(&mut end, to_u32(central.len(), "central directory")?);
+    put_u32(&mut end, to_u32(offset, "central directory")?);
+    put_u16(&mut end, comment_len);
+    end.extend_from_slice(comment);
+
+    writer.write_all(&central)?;
+    writer.write_all(&end)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Unix mtime from an extended timestamp extra field, if present
+fn extra_mtime(mut extra: &[u8]) -> Option<u64> {
+    while extra.len() >= 4 {
+        let tag = u16_at(extra, 0)?;
+        let len = u16_at(extra, 2)? as usize;
+        let data = extra.get(4..4 + len)?;
+        if tag == EXTRA_TIMESTAMP && data.first()? & 1 != 0 {
+            return u32_at(data, 1).map(u64::from);
+        }
+        extra = &extra[4 + len..];
+    }
+    None
+}
+
+/// DOS (time, date) for a Unix timestamp, clamped to the DOS epoch
+fn dos_datetime(secs: u64) -> (u16, u16) {
+    if secs < DOS_EPOCH_SECS {
+        return (0, DOS_EPOCH_DATE);
+    }
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    let date = ((year - 1980).min(127) as u16) << 9 | (month as u16) << 5 | day as u16;
+    let time = ((rem / 3600) as u16) << 11 | (((rem / 60) % 60) as u16) << 5 | ((rem % 60) / 2) as u16;
+    (time, date)
+}
+
+fn to_u32(value: usize, what: &str) -> Result<u32> {
+    u32::try_from(value).map_err(|_| anyhow!("Too large for zip: {}", what))
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn u16_at(bytes: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zip_round_trip() {
+        // Decoded text writes the final newline its data leaves out
+        let mut archive: Archive = "-- a.txt --\nhello\n".parse().unwrap();
+        archive.comment = "fixtures\n".into();
+        archive.add_file(File::new("img/logo.png", vec![0x89u8, b'P', b'N', b'G', 0])).unwrap();
+
+        let mut buffer = Vec::new();
+        write(&archive, &mut buffer).unwrap();
+
+        let restored = read(buffer.as_slice()).unwrap();
+        assert_eq!(restored.comment, "fixtures\n");
+        assert_eq!(restored.files.len(), 2);
+        assert_eq!(&restored.files[0].data[..], b"hello\n");
+        assert_eq!(restored.files[1].name, "img/logo.png");
+        assert!(restored.files[1].is_binary);
+    }
+
+    #[test]
+    fn test_zip_metadata() {
+        let mut archive = Archive::new();
+        let metadata = FileMetadata { mode: Some(0o755), mtime: Some(1_700_000_000) };
+        archive.add_file(File::new("run.sh", "#!/bin/sh\n").with_metadata(metadata)).unwrap();
+        archive.add_file(File::new("plain.txt", "text\n")).unwrap();
+
+        let mut buffer = Vec::new();
+        archive.to_zip(&mut buffer).unwrap();
+        let restored = Archive::from_zip(buffer.as_slice()).unwrap();
+
+        assert_eq!(restored.files[0].metadata, metadata);
+        assert_eq!(restored.files[1].metadata, FileMetadata::default());
+    }
+
+    #[test]
+    fn test_dos_datetime() {
+        // 2023-11-14 22:13:20 UTC
+        assert_eq!(dos_datetime(1_700_000_000), (22 << 11 | 13 << 5 | 10, 43 << 9 | 11 << 5 | 14));
+        assert_eq!(dos_datetime(0), (0, DOS_EPOCH_DATE));
+    }
+
+    #[test]
+    fn test_zip_deflated_entries() {
+        use base64::Engine;
+
+        // Written by Python's zipfile with ZIP_DEFLATED, plus a directory
+        // and a stored entry
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode("UEsDBBQAAAAIAOO0T101bJrNGwAAAJABAAAJAAAAbm90ZXMudHh0y8nMS1XIT1MoyUhVSMusKCktSuXKGRUbMDEAUEsDBBQAAAAIAOO0T10AAAAAAgAAAAAAAAAEAAAAZGlyLwMAUEsDBBQAAAAAAAAAIQAkOLI/BAAAAAQAAAAKAAAAc3RvcmVkLmJpbgABAv9QSwECFAMUAAAACADjtE9dNWyazRsAAACQAQAACQAAAAAAAAAAAAAAgAEAAAAAbm90ZXMudHh0UEsBAhQDFAAAAAgA47RPXQAAAAACAAAAAAAAAAQAAAAAAAAAAAAQAP1BQgAAAGRpci9QSwECFAMUAAAAAAAAACEAJDiyPwQAAAAEAAAACgAAAAAAAAAAAAAAgAFmAAAAc3RvcmVkLmJpblBLBQYAAAAAAwADAKEAAACSAAAAAAA=")
+            .unwrap();
+        let archive = read(bytes.as_slice()).unwrap();
+        let names: Vec<_> = archive.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["notes.txt", "stored.bin"]);
+        assert_eq!(archive.files[0].data.as_ref(), "line of the fixture\n".repeat(20).as_bytes());
+        assert_eq!(archive.files[1].data.as_ref(), [0, 1, 2, 255]);
+
+        // A corrupted entry fails its checks instead of reading garbage
+        let mut corrupt = bytes.clone();
+        corrupt[40] ^= 0xff;
+        assert!(read(corrupt.as_slice()).unwrap_err().to_string().contains("notes.txt"));
+    }
+
+    #[test]
+    fn test_zip_rejects_non_zip() {
+        let err = read(&b"not a zip"[..]).unwrap_err();
+        assert!(err.to_string().contains("Not a zip archive"));
+    }
+}
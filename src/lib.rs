@@ -25,6 +25,55 @@
 //! /9j/4AAQSkZJRgABAQEAYABgAAD/2wBD...
 //! ```
 //!
+//! ## Text Encoding Tags
+//!
+//! Text stored in a legacy encoding is transcoded to UTF-8 in the archive and
+//! tagged with its source encoding, so decoding restores the original bytes:
+//!
+//! ```text
+//! -- legacy.cfg[.enc:latin1] --
+//! name=José
+//! ```
+//!
+//! GBK, Shift_JIS and Big5 need the `encoding` feature, which transcodes them
+//! with `encoding_rs` and detects them with `chardetng`. Binary entries may
+//! carry the tag too (`[.base64][.enc:gbk]`) to record the encoding of text
+//! that could not be transcoded. Unknown labels are rejected.
+//!
+//! ## File Metadata
+//!
+//! Permissions and modification times can be recorded per file and restored
+//! on extraction:
+//!
+//! ```text
+//! -- run.sh[.mode:0755][.mtime:1700000000] --
+//! #!/bin/sh
+//! ```
+//!
+//! ## Nested Archives
+//!
+//! An entry tagged `[.txtar]` holds another archive, decoded on demand by
+//! [`File::as_archive`] and limited in depth by [`Decoder::with_max_depth`]:
+//!
+//! ```text
+//! -- suite.txtar[.base64][.txtar] --
+//! LS0gaW5wdXQudHh0IC0tCmhlbGxvCg==
+//! ```
+//!
+//! ## Archive Streams
+//!
+//! Several independent archives can share one file, each introduced by a
+//! delimiter line; see [`Decoder::decode_stream`] and [`Encoder::encode_stream`]:
+//!
+//! ```text
+//! === archive: parse-ok ===
+//! -- input.txt --
+//! ...
+//! === archive: parse-error ===
+//! -- input.txt --
+//! ...
+//! ```
+//!
 //! ## Automatic Binary Detection
 //!
 //! Files are automatically marked as binary if:
@@ -36,31 +85,142 @@
 //! The encoding detection is configurable via [`EncodingConfig`]:
 //! - Enable/disable content marker checking
 //! - Enable/disable UTF-8 validation
-//! - Future: Support for UTF-16, GBK, ShiftJIS, etc.
+//! - Opt-in binary heuristics, per-pattern overrides and Latin-1 fallback
+//! - Opt-in GBK / Shift_JIS / Big5 detection (`encoding` feature)
+//! - A custom detection callback consulted before the built-in rules
+//! - Presets via [`EncodingConfig::strict`], [`EncodingConfig::lenient`] and
+//!   [`EncodingConfig::go_compat`], adjustable with [`EncodingConfig::builder`]
+//! - Future: Support for UTF-16, etc.
 //!
 //! ## Binary Detection Rules
 //!
 //! Current detection rules (in order):
-//! 1. Content has lines like `-- name --` → Binary (ContentConflict) **[PRIMARY]**
-//! 2. Data is not valid UTF-8 → Binary (InvalidUtf8)
-//! 3. Otherwise → Text (UTF-8)
+//! 1. File name matches an [`EncodingOverride`] pattern → forced classification
+//! 2. Content has lines like `-- name --` → Binary (ContentConflict) **[PRIMARY]**
+//! 3. Data is not valid UTF-8 → Binary (InvalidUtf8)
+//! 4. Too many NUL/control bytes (opt-in) → Binary (ControlChars)
+//! 5. A line longer than the configured limit (opt-in) → Binary (LongLine)
+//! 6. Otherwise → Text (UTF-8)
 //!
 //! **Why content detection?**
 //! The real issue is file CONTENT containing txtar marker patterns.
 //! For example, a markdown file documenting txtar format would naturally
 //! contain examples like `-- file.txt --`, which would corrupt the archive
 //! structure if not encoded as binary.
+//!
+//! ## `no_std` Support
+//!
+//! With default features off, [`Archive`], [`Encoder`] and [`Decoder`] build
+//! for `no_std + alloc` targets. The `std` feature (on by default, and
+//! required by every other feature) adds filesystem helpers such as
+//! [`Archive::extract_to_dir`] and `io::Write` output.
+//!
+//! ## Tracing
+//!
+//! The `tracing` feature (which does not need `std`) runs decoding,
+//! encoding, edit application and extraction in `debug` spans under the
+//! `emx_txtar` target, with an event per file giving its name and size and,
+//! with `std`, a closing `done` event carrying `elapsed_us`.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+/// Add to a [`profiling`] counter; compiled out without the `profiling` feature
+macro_rules! profile_count {
+    ($counter:ident, $n:expr) => {
+        #[cfg(feature = "profiling")]
+        $crate::profiling::COUNTERS.$counter.fetch_add($n as u64, core::sync::atomic::Ordering::Relaxed);
+    };
+}
+
+/// Enter a timed `tracing` span for the rest of the scope; compiled out
+/// without the `tracing` feature
+macro_rules! trace_span {
+    ($name:literal $(, $($fields:tt)*)?) => {
+        #[cfg(feature = "tracing")]
+        let _span = $crate::trace::Timed::enter(tracing::debug_span!(target: "emx_txtar", $name $(, $($fields)*)?));
+    };
+}
+
+/// Emit a `tracing` debug event; compiled out without the `tracing` feature
+macro_rules! trace_event {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "emx_txtar", $($arg)*);
+    };
+}
 
 pub mod archive;
 pub mod encoder;
 pub mod decoder;
+pub mod glob;
+pub mod include;
+pub mod checksum;
+pub mod condition;
+pub mod tags;
+pub mod lines;
+pub mod frontmatter;
+pub mod position;
+pub mod borrowed;
+pub mod version;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+#[cfg(feature = "std")]
+pub mod sync;
+#[cfg(feature = "std")]
+pub mod log;
+#[cfg(feature = "std")]
+pub mod series;
+#[cfg(feature = "std")]
+pub mod template;
+pub mod diagnostics;
+pub mod lsp;
+mod b64;
+mod comment;
+#[cfg(feature = "std")]
+#[doc(hidden)]
+pub mod parallel;
+#[cfg(feature = "mime")]
+pub mod mime;
+#[cfg(feature = "compression")]
+pub mod compress;
+#[cfg(any(feature = "tar", feature = "zip"))]
+pub mod interop;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "embed")]
+pub mod embed;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "storage")]
+pub mod storage;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "profiling")]
+pub mod profiling;
+#[cfg(feature = "proptest")]
+pub mod proptest;
+#[cfg(feature = "signing")]
+pub mod signing;
+#[cfg(feature = "encryption")]
+pub mod crypt;
+#[cfg(feature = "git2")]
+mod git;
+#[cfg(feature = "tracing")]
+mod trace;
 
 pub use archive::{
-    Archive, File,
-    EncodingConfig, EncodingDetection, TextEncoding, BinaryReason,
+    Archive, ArchiveError, File, FileMetadata, FileSource, WindowsNamePolicy,
+    EncodingConfig, EncodingConfigBuilder, EncodingDetection, DetectionSampling, DetectionHook, EncodingOverride, TextEncoding, LineEnding, BinaryReason,
     Command, SnippetRef, SnippetRefError, SnippetParseError,
-    EditRef, EditBlock, EditOperation,
-    EditParseError, EditApplyError,
+    EditRef, EditBlock, EditOperation, MatchStrategy,
+    EditParseError, EditApplyError, AppliedReport,
 };
-pub use encoder::Encoder;
-pub use decoder::Decoder;
+#[cfg(feature = "std")]
+pub use archive::{ApplyOptions, DirOptions, ExtractOptions, FileReader};
+pub use condition::{Condition, Conditions};
+pub use encoder::{ArchiveWriter, Encoder};
+pub use decoder::{Decoder, DuplicatePolicy};
@@ -0,0 +1,158 @@
+//! Line access to text file contents
+//!
+//! Lines are numbered from 1, as in snippet and edit references, and split
+//! like [`str::lines`]: at `\n` with a trailing `\r` removed, with no empty
+//! line after a final line break. [`File::line_range`] looks up a single
+//! range; for repeated lookups, build a [`LineIndex`] once with
+//! [`File::line_index`]. The index is not cached on the file, since `data`
+//! can be replaced at any time.
+//!
+//! ```
+//! use emx_txtar::File;
+//!
+//! let file = File::new("main.rs", "fn main() {\n    run();\n}\n");
+//! assert_eq!(file.line_range(2..4), Some("    run();\n}"));
+//! assert_eq!(file.lines().unwrap().count(), 3);
+//! ```
+
+use crate::archive::{File, TextEncoding};
+use alloc::vec::Vec;
+use core::ops::Range;
+
+impl File {
+    /// The contents as text, if stored as UTF-8 and valid
+    ///
+    /// Text kept in another encoding returns `None`; see
+    /// [`utf8_data`](File::utf8_data) to transcode it. Files that are binary
+    /// only because of their layout (marker lines, long lines, control
+    /// characters) still have text.
+    pub fn text(&self) -> Option<&str> {
+        if self.encoding != TextEncoding::Utf8 {
+            return None;
+        }
+        core::str::from_utf8(&self.data).ok()
+    }
+
+    /// The lines of the [`text`](File::text)
+    pub fn lines(&self) -> Option<core::str::Lines<'_>> {
+        self.text().map(str::lines)
+    }
+
+    /// Lines `range.start` up to but not including `range.end`, without the
+    /// final line break
+    ///
+    /// `None` if the file has no text or the range is outside `1..=count + 1`.
+    pub fn line_range(&self, range: Range<usize>) -> Option<&str> {
+        self.line_index()?.range(range)
+    }
+
+    /// An index of where each line of the [`text`](File::text) starts
+    pub fn line_index(&self) -> Option<LineIndex<'_>> {
+        self.text().map(LineIndex::new)
+    }
+}
+
+/// Byte offsets of the lines of a text, for repeated lookups
+#[derive(Debug, Clone)]
+pub struct LineIndex<'a> {
+    text: &'a str,
+    /// Offset of the start of each line
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> Self {
+        let mut starts = Vec::new();
+        if !text.is_empty() {
+            starts.push(0);
+        }
+        starts.extend(text.match_indices('\n').map(|(i, _)| i + 1).filter(|&start| start < text.len()));
+        Self { text, starts }
+    }
+
+    /// Number of lines
+    pub fn len(&self) -> usize {
+        self.starts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.starts.is_empty()
+    }
+
+    /// Line `number`, without its line break
+    pub fn line(&self, number: usize) -> Option<&'a str> {
+        self.range(number..number + 1)
+    }
+
+    /// Lines `range.start` up to but not including `range.end`, without the
+    /// final line break
+    pub fn range(&self, range: Range<usize>) -> Option<&'a str> {
+        if range.start == 0 || range.start > range.end || range.end > self.len() + 1 {
+            return None;
+        }
+        if range.start == range.end {
+            return Some("");
+        }
+        let start = self.starts[range.start - 1];
+        Some(&self.text[start..self.line_end(range.end - 2)])
+    }
+
+    /// Number of the line containing byte `offset`
+    pub fn line_of(&self, offset: usize) -> Option<usize> {
+        if offset >= self.text.len() {
+            return None;
+        }
+        Some(self.starts.partition_point(|&start| start <= offset))
+    }
+
+    /// End of the 0-based line `index`, before its line break
+    fn line_end(&self, index: usize) -> usize {
+        let end = self.starts.get(index + 1).copied().unwrap_or(self.text.len());
+        let line = &self.text[..end];
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        line.strip_suffix('\r').unwrap_or(line).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_index() {
+        let index = LineIndex::new("one\r\ntwo\n\nfour");
+        assert_eq!(index.len(), 4);
+        assert_eq!(index.line(1), Some("one"));
+        assert_eq!(index.line(3), Some(""));
+        assert_eq!(index.line(4), Some("four"));
+        assert_eq!(index.line(5), None);
+        assert_eq!(index.range(1..3), Some("one\r\ntwo"));
+        assert_eq!(index.range(5..5), Some(""));
+        assert_eq!(index.range(0..2), None);
+        assert_eq!(index.line_of(0), Some(1));
+        assert_eq!(index.line_of(5), Some(2));
+        assert_eq!(index.line_of(9), Some(3));
+        assert_eq!(index.line_of(14), None);
+
+        assert!(LineIndex::new("").is_empty());
+        assert_eq!(LineIndex::new("a\n").len(), 1);
+    }
+
+    #[test]
+    fn test_file_lines() {
+        let file = File::new("a.txt", "a\nb\nc\n");
+        assert_eq!(file.text(), Some("a\nb\nc\n"));
+        assert_eq!(file.lines().unwrap().collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(file.line_range(2..4), Some("b\nc"));
+        assert_eq!(file.line_range(2..5), None);
+
+        let binary = File::new("b.bin", alloc::vec![0xFF, b'\n']);
+        assert_eq!(binary.text(), None);
+        assert_eq!(binary.line_range(1..2), None);
+
+        // Binary only because of a marker line, but still text
+        let conflict = File::new("c.txt", "x\n-- y --\n");
+        assert!(conflict.is_binary);
+        assert_eq!(conflict.line_range(2..3), Some("-- y --"));
+    }
+}
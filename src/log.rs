@@ -0,0 +1,150 @@
+//! Append-only archive files shared between processes
+//!
+//! An [`ArchiveLog`] appends file sections to an archive on disk, holding an
+//! advisory lock for each append and syncing the data before releasing it,
+//! so several processes can record tool output into one archive without
+//! interleaving or losing sections. The file stays an ordinary archive:
+//! [`ArchiveLog::read`] decodes it, which requires entry names to be unique
+//! (e.g. by including a timestamp or sequence number).
+//!
+//! ```no_run
+//! use emx_txtar::log::ArchiveLog;
+//! use emx_txtar::File;
+//!
+//! let log = ArchiveLog::open("captures.txtar")?;
+//! log.append(&File::new("run-1/stdout", "ok\n"))?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::archive::{Archive, File};
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use anyhow::{anyhow, Result};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// An archive file that sections are appended to under a lock
+pub struct ArchiveLog {
+    path: PathBuf,
+    encoder: Encoder,
+}
+
+impl ArchiveLog {
+    /// Use the archive at `path`, creating an empty one if it does not exist
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        open_file(&path)?;
+        Ok(Self { path, encoder: Encoder::new() })
+    }
+
+    /// Encode appended files with `encoder`
+    pub fn with_encoder(mut self, encoder: Encoder) -> Self {
+        self.encoder = encoder;
+        self
+    }
+
+    /// Path of the archive file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append one file section
+    pub fn append(&self, file: &File) -> Result<()> {
+        self.append_all(core::slice::from_ref(file))
+    }
+
+    /// Append several file sections at once, so no other append lands
+    /// between them
+    pub fn append_all(&self, files: &[File]) -> Result<()> {
+        let mut batch = Archive::new();
+        for file in files {
+            batch.add_file(file.clone())?;
+        }
+        let sections = self.encoder.encode(&batch)?;
+
+        let mut file = open_file(&self.path)?;
+        file.lock().map_err(|e| anyhow!("Failed to lock {}: {}", self.path.display(), e))?;
+        // A writer that died mid-line must not glue its tail to our marker
+        let len = file.seek(SeekFrom::End(0))?;
+        let mut last = [b'\n'];
+        if len > 0 {
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+        }
+        if last[0] != b'\n' {
+            file.write_all(b"\n")?;
+        }
+        file.write_all(sections.as_bytes())?;
+        file.sync_data()?;
+        file.unlock()?;
+        Ok(())
+    }
+
+    /// Decode the whole archive, waiting for any append in progress
+    pub fn read(&self) -> Result<Archive> {
+        let mut file = open_file(&self.path)?;
+        file.lock_shared().map_err(|e| anyhow!("Failed to lock {}: {}", self.path.display(), e))?;
+        let mut text = String::new();
+        file.read_to_string(&mut text)?;
+        file.unlock()?;
+        Decoder::new().decode(&text)
+    }
+}
+
+fn open_file(path: &Path) -> Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| anyhow!("Failed to open {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_concurrent_appends() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txtar");
+        std::fs::write(&path, "Captured output\n").unwrap();
+
+        let threads: Vec<_> = (0..4)
+            .map(|t| {
+                let log = ArchiveLog::open(&path).unwrap();
+                std::thread::spawn(move || {
+                    for i in 0..25 {
+                        let output = format!("line one\n-- not a marker? --\nrun {} {}\n", t, i);
+                        log.append(&File::new(format!("t{}/run{}", t, i), output)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let archive = ArchiveLog::open(&path).unwrap().read().unwrap();
+        assert_eq!(archive.comment, "Captured output");
+        assert_eq!(archive.files.len(), 100);
+        let file = archive.get_file("t3/run7").unwrap();
+        assert_eq!(file.data.as_ref(), b"line one\n-- not a marker? --\nrun 3 7\n");
+    }
+
+    #[test]
+    fn test_append_after_torn_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log.txtar");
+        std::fs::write(&path, "-- a --\npartial").unwrap();
+
+        let log = ArchiveLog::open(&path).unwrap();
+        log.append_all(&[File::new("b", "B"), File::new("c", "C")]).unwrap();
+        let archive = log.read().unwrap();
+        let names: Vec<_> = archive.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["a", "b", "c"]);
+        assert_eq!(archive.files[0].data.as_ref(), b"partial");
+
+        assert!(log.append_all(&[File::new("d", ""), File::new("d", "")]).is_err());
+    }
+}
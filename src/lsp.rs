@@ -0,0 +1,294 @@
+//! Export of edit entries as LSP workspace edits
+//!
+//! Edit blocks are resolved against the target content exactly as
+//! [`EditRef::apply`] does, and the resulting changes are expressed as
+//! non-overlapping line-based `TextEdit`s over the original document.
+//! Positions use UTF-16 code units, as LSP requires.
+
+use crate::archive::{find_line_block_near, Archive, EditApplyError, EditOperation, EditRef};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use anyhow::{anyhow, Result};
+
+/// Zero-based position in a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    /// Offset in UTF-16 code units
+    pub character: usize,
+}
+
+/// Replacement of the text between two positions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub start: Position,
+    pub end: Position,
+    pub new_text: String,
+}
+
+impl TextEdit {
+    fn to_json(&self) -> String {
+        format!(
+            "{{\"range\":{{\"start\":{{\"line\":{},\"character\":{}}},\"end\":{{\"line\":{},\"character\":{}}}}},\"newText\":{}}}",
+            self.start.line, self.start.character, self.end.line, self.end.character, json_string(&self.new_text),
+        )
+    }
+}
+
+impl EditRef {
+    /// Changes this edit makes to `content`, as edits over the original text
+    pub fn text_edits(&self, content: &str) -> Result<Vec<TextEdit>, EditApplyError> {
+        // Validate exactly like `apply`, so both agree on what is an error
+        self.apply(content)?;
+
+        let original: Vec<&str> = content.lines().collect();
+        let near = self.start_line.map(|line| line.saturating_sub(1));
+        let mut lines: Vec<(Option<usize>, &str)> = original.iter().enumerate().map(|(i, &line)| (Some(i), line)).collect();
+
+        for edit in &self.edits {
+            let inserted = edit.replacement.iter().map(|s| (None, s.as_str()));
+            if edit.operation == EditOperation::Insert || edit.search.is_empty() {
+                lines.splice(0..0, inserted);
+                continue;
+            }
+            let start = find_line_block_near(&lines, &edit.search, |(_, line), search| *line == search, near)
+                .ok_or_else(|| EditApplyError::SearchNotFound { search: edit.search.join("\n") })?;
+            let end = start + edit.search.len();
+            // Anchored inserts keep the SEARCH lines
+            let range = match edit.operation {
+                EditOperation::InsertAfter => end..end,
+                EditOperation::InsertBefore => start..start,
+                _ => start..end,
+            };
+            if edit.operation == EditOperation::Delete {
+                lines.drain(range);
+            } else {
+                lines.splice(range, inserted);
+            }
+        }
+
+        Ok(hunks(content, &original, &lines))
+    }
+
+    /// LSP `WorkspaceEdit` JSON applying this edit to the document at `uri`
+    pub fn to_workspace_edit(&self, uri: &str, content: &str) -> Result<String, EditApplyError> {
+        let edits = self.text_edits(content)?;
+        Ok(workspace_edit(&[document_edit(uri, &edits)]))
+    }
+}
+
+impl Archive {
+    /// LSP `WorkspaceEdit` JSON applying every edit entry to its target file
+    ///
+    /// Document URIs are `base_uri` joined with the file name. Several edit
+    /// entries for one file become consecutive document edits, each against
+    /// the content left by the previous one, as do the blocks of one entry
+    /// naming different files (see [`EditRef::split_by_file`]).
+    pub fn to_workspace_edit(&self, base_uri: &str) -> Result<String> {
+        let mut contents: Vec<(&str, String)> = Vec::new();
+        let mut changes = Vec::new();
+
+        for file in &self.files {
+            let Some(edit) = &file.edit_ref else { continue };
+            for (name, run) in edit.split_by_file(&file.name) {
+                let index = match contents.iter().position(|(target, _)| *target == name) {
+                    Some(index) => index,
+                    None => {
+                        let target = self.get_file(name)
+                            .ok_or_else(|| anyhow!("Edit target not found in archive: {}", name))?;
+                        let text = core::str::from_utf8(&target.data).map_err(|_| EditApplyError::InvalidUtf8)?;
+                        contents.push((name, String::from(text)));
+                        contents.len() - 1
+                    }
+                };
+
+                let content = &mut contents[index].1;
+                let edits = run.text_edits(content).map_err(|e| anyhow!("Failed to apply edit to {}: {}", name, e))?;
+                let uri = format!("{}/{}", base_uri.trim_end_matches('/'), name);
+                changes.push(document_edit(&uri, &edits));
+                *content = run.apply(content)?;
+            }
+        }
+
+        Ok(workspace_edit(&changes))
+    }
+}
+
+/// Turn the edited line list back into edits over the original lines
+fn hunks(content: &str, original: &[&str], lines: &[(Option<usize>, &str)]) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+    let mut next = 0;
+    let mut pending = String::new();
+
+    for &(index, text) in lines {
+        match index {
+            Some(index) => {
+                if index > next || !pending.is_empty() {
+                    edits.push(line_edit(content, original, next, index, core::mem::take(&mut pending)));
+                }
+                next = index + 1;
+            }
+            None => {
+                pending.push_str(text);
+                pending.push('\n');
+            }
+        }
+    }
+    if next < original.len() || !pending.is_empty() {
+        edits.push(line_edit(content, original, next, original.len(), pending));
+    }
+    edits
+}
+
+/// Replace whole lines `start..end` with `new_text` (newline-terminated lines)
+fn line_edit(content: &str, original: &[&str], start: usize, end: usize, mut new_text: String) -> TextEdit {
+    let mut start = Position { line: start, character: 0 };
+    let mut end = Position { line: end, character: 0 };
+
+    // The last line has no newline to consume, so end the range on it instead
+    if end.line == original.len() && !content.ends_with('\n') && !original.is_empty() {
+        let last = original.len() - 1;
+        end = Position { line: last, character: utf16_len(original[last]) };
+        if new_text.ends_with('\n') {
+            new_text.pop();
+        } else if start.line > 0 && start.line <= last {
+            let previous = start.line - 1;
+            start = Position { line: previous, character: utf16_len(original[previous]) };
+        }
+    }
+    TextEdit { start, end, new_text }
+}
+
+fn utf16_len(s: &str) -> usize {
+    s.chars().map(char::len_utf16).sum()
+}
+
+fn document_edit(uri: &str, edits: &[TextEdit]) -> String {
+    let edits: Vec<String> = edits.iter().map(TextEdit::to_json).collect();
+    format!(
+        "{{\"textDocument\":{{\"uri\":{},\"version\":null}},\"edits\":[{}]}}",
+        json_string(uri),
+        edits.join(","),
+    )
+}
+
+fn workspace_edit(changes: &[String]) -> String {
+    format!("{{\"documentChanges\":[{}]}}", changes.join(","))
+}
+
+/// Quote and escape a string as a JSON string literal
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::EditBlock;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    fn block(operation: EditOperation, search: &[&str], replacement: &[&str]) -> EditBlock {
+        EditBlock::new(
+            operation,
+            search.iter().map(|s| s.to_string()).collect(),
+            replacement.iter().map(|s| s.to_string()).collect(),
+        )
+    }
+
+    /// Apply text edits (which never overlap) to content, last first
+    fn apply_edits(content: &str, edits: &[TextEdit]) -> String {
+        let offset = |pos: Position| {
+            let line_start: usize = content.split_inclusive('\n').take(pos.line).map(str::len).sum();
+            let line = content[line_start..].split('\n').next().unwrap_or("");
+            let mut units = 0;
+            let within = line.char_indices().find(|&(_, c)| {
+                let found = units >= pos.character;
+                units += c.len_utf16();
+                found
+            }).map_or(line.len(), |(i, _)| i);
+            line_start + within
+        };
+        let mut result = content.to_string();
+        for edit in edits.iter().rev() {
+            result.replace_range(offset(edit.start)..offset(edit.end), &edit.new_text);
+        }
+        result
+    }
+
+    #[test]
+    fn test_text_edits_match_apply() {
+        let content = "a\nb\nc\nd\n";
+        let edit = EditRef {
+            command_href: None,
+            start_line: None,
+            edits: vec![
+                block(EditOperation::Replace, &["b"], &["B1", "B2"]),
+                block(EditOperation::Delete, &["d"], &[]),
+                block(EditOperation::Insert, &[], &["top"]),
+            ],
+        };
+
+        let edits = edit.text_edits(content).unwrap();
+        assert_eq!(edits.len(), 3);
+        assert_eq!(edits[0], TextEdit {
+            start: Position { line: 0, character: 0 },
+            end: Position { line: 0, character: 0 },
+            new_text: "top\n".to_string(),
+        });
+        assert_eq!(apply_edits(content, &edits), edit.apply(content).unwrap() + "\n");
+
+        // Without a trailing newline the final line is edited in place
+        let content = "x\nyé";
+        let edit = EditRef { command_href: None, start_line: None, edits: vec![block(EditOperation::Delete, &["yé"], &[])] };
+        let edits = edit.text_edits(content).unwrap();
+        assert_eq!(edits[0].start, Position { line: 0, character: 1 });
+        assert_eq!(edits[0].end, Position { line: 1, character: 2 });
+        assert_eq!(apply_edits(content, &edits), edit.apply(content).unwrap());
+
+        // Anchored inserts leave their anchor in place
+        let content = "a\nb\n";
+        let edit = EditRef {
+            command_href: None,
+            start_line: None,
+            edits: vec![block(EditOperation::InsertAfter, &["a"], &["a2"]), block(EditOperation::InsertBefore, &["a"], &["a0"])],
+        };
+        let edits = edit.text_edits(content).unwrap();
+        assert_eq!(apply_edits(content, &edits), edit.apply(content).unwrap() + "\n");
+        assert_eq!(apply_edits(content, &edits), "a0\na\na2\nb\n");
+
+        // Both pick the match nearest the start line
+        let content = "x\ny\nx\n";
+        let edit = EditRef { command_href: None, start_line: Some(3), edits: vec![block(EditOperation::Replace, &["x"], &["z"])] };
+        let edits = edit.text_edits(content).unwrap();
+        assert_eq!(edits[0].start.line, 2);
+        assert_eq!(apply_edits(content, &edits), edit.apply(content).unwrap() + "\n");
+    }
+
+    #[test]
+    fn test_archive_to_workspace_edit() {
+        let input = "-- a.txt --\nold\n-- a.txt[.edit] --\n<<<<<<< SEARCH\nold\n=======\nnew\n>>>>>>> REPLACE\n";
+        let archive = crate::Decoder::new().decode(input).unwrap();
+
+        let json = archive.to_workspace_edit("file:///work/").unwrap();
+        assert_eq!(
+            json,
+            r#"{"documentChanges":[{"textDocument":{"uri":"file:///work/a.txt","version":null},"edits":[{"range":{"start":{"line":0,"character":0},"end":{"line":0,"character":3}},"newText":"new"}]}]}"#,
+        );
+    }
+}
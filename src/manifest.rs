@@ -0,0 +1,180 @@
+//! Manifest entries for archive integrity
+//!
+//! A `-- .manifest --` entry lists every other entry in order, one per line
+//! as `<sha256> <size> <name>`, so an archive carries the means to check its
+//! own contents. A trailing [`SIGNATURE_NAME`] entry is not listed, so a
+//! manifest and a signature can be combined.
+//!
+//! ```
+//! use emx_txtar::{Archive, File};
+//!
+//! let mut archive = Archive::new();
+//! archive.add_file(File::new("a.txt", "hello")).unwrap();
+//! let mut archive = archive.with_manifest().unwrap();
+//! archive.verify_manifest().unwrap();
+//!
+//! archive.files[0] = File::new("a.txt", "changed");
+//! assert!(archive.verify_manifest().is_err());
+//! ```
+
+use crate::archive::{Archive, File, MANIFEST_NAME, SIGNATURE_NAME};
+use crate::checksum::to_hex;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use anyhow::{anyhow, bail, Result};
+use sha2::{Digest, Sha256};
+
+/// One manifest line: digest, size and name of an entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    sha256: String,
+    size: usize,
+    name: String,
+}
+
+impl Archive {
+    /// The archive with its manifest entry added, or updated in place
+    pub fn with_manifest(mut self) -> Result<Self> {
+        self.update_manifest()?;
+        Ok(self)
+    }
+
+    /// Add a manifest entry listing the other entries, or update the
+    /// existing one in place
+    pub fn update_manifest(&mut self) -> Result<()> {
+        let text: String = listed_lines(self)?.iter()
+            .map(|line| format!("{} {} {}\n", line.sha256, line.size, line.name))
+            .collect();
+        let manifest = File::new(MANIFEST_NAME, text);
+        match self.files.iter_mut().find(|f| is_manifest(f)) {
+            Some(existing) => *existing = manifest.with_metadata(existing.metadata),
+            None => self.files.push(manifest),
+        }
+        Ok(())
+    }
+
+    /// Check every entry against the manifest
+    ///
+    /// Fails if there is no manifest, or describes each entry that changed,
+    /// is missing or is not listed.
+    pub fn verify_manifest(&self) -> Result<()> {
+        let manifest = self.files.iter().find(|f| is_manifest(f))
+            .ok_or_else(|| anyhow!("Archive has no '{}' entry", MANIFEST_NAME))?;
+        let expected = parse(&manifest.data)?;
+        let actual = listed_lines(self)?;
+
+        let mut problems = Vec::new();
+        for (want, have) in expected.iter().zip(&actual) {
+            if want.name != have.name {
+                problems.push(format!("expected '{}', found '{}'", want.name, have.name));
+            } else if want != have {
+                problems.push(format!("'{}' changed", have.name));
+            }
+        }
+        for missing in expected.iter().skip(actual.len()) {
+            problems.push(format!("'{}' is missing", missing.name));
+        }
+        for unlisted in actual.iter().skip(expected.len()) {
+            problems.push(format!("'{}' is not listed", unlisted.name));
+        }
+        if !problems.is_empty() {
+            bail!("Archive does not match its manifest: {}", problems.join("; "));
+        }
+        Ok(())
+    }
+}
+
+fn is_manifest(file: &File) -> bool {
+    file.name == MANIFEST_NAME && file.snippet_ref.is_none() && file.edit_ref.is_none()
+}
+
+/// Manifest lines for the entries a manifest lists
+fn listed_lines(archive: &Archive) -> Result<Vec<Line>> {
+    let mut files = archive.files.as_slice();
+    if let [rest @ .., last] = files {
+        if last.name == SIGNATURE_NAME {
+            files = rest;
+        }
+    }
+    files.iter()
+        .filter(|f| !is_manifest(f))
+        .map(|file| {
+            #[cfg(feature = "std")]
+            let file = file.load()?;
+            let content = file.content();
+            Ok(Line { sha256: to_hex(&Sha256::digest(&content)), size: content.len(), name: file.name.clone() })
+        })
+        .collect()
+}
+
+fn parse(data: &[u8]) -> Result<Vec<Line>> {
+    let text = core::str::from_utf8(data).map_err(|_| anyhow!("Manifest is not valid UTF-8"))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.is_empty())
+        .map(|(i, line)| {
+            let mut parts = line.splitn(3, ' ');
+            let (Some(sha256), Some(size), Some(name)) = (parts.next(), parts.next(), parts.next()) else {
+                bail!("Malformed manifest line {}: '{}'", i + 1, line);
+            };
+            let size = size.parse().map_err(|_| anyhow!("Invalid size on manifest line {}: '{}'", i + 1, size))?;
+            Ok(Line { sha256: sha256.to_string(), size, name: name.to_string() })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, Encoder};
+
+    fn archive() -> Archive {
+        let mut archive = Archive::with_comment("fixtures");
+        archive.add_file(File::new("a.txt", "hello\n")).unwrap();
+        archive.add_file(File::new("dir/b c.bin", vec![0, 255])).unwrap();
+        archive
+    }
+
+    #[test]
+    fn test_manifest_lists_entries() {
+        let archive = archive().with_manifest().unwrap();
+        assert_eq!(archive.files.len(), 3);
+        assert_eq!(
+            core::str::from_utf8(&archive.files[2].data).unwrap(),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03 6 a.txt\n\
+             06eb7d6a69ee19e5fbdf749018d3d2abfa04bcbd1365db312eb86dc7169389b8 2 dir/b c.bin\n"
+        );
+
+        let decoded = Decoder::new().decode(&Encoder::new().encode(&archive).unwrap()).unwrap();
+        decoded.verify_manifest().unwrap();
+
+        // Updating keeps a single manifest in its place
+        let mut updated = archive.clone();
+        updated.files.swap(0, 2);
+        updated.update_manifest().unwrap();
+        assert_eq!(updated.files.iter().filter(|f| f.name == MANIFEST_NAME).count(), 1);
+        assert_eq!(updated.files[0].name, MANIFEST_NAME);
+        updated.verify_manifest().unwrap();
+    }
+
+    #[test]
+    fn test_verify_manifest_reports_problems() {
+        assert!(archive().verify_manifest().unwrap_err().to_string().contains("no '.manifest' entry"));
+
+        let mut archive = archive().with_manifest().unwrap();
+        archive.files[0] = File::new("a.txt", "changed\n");
+        archive.files.remove(1);
+        archive.add_file(File::new("new.txt", "")).unwrap();
+        let err = archive.verify_manifest().unwrap_err().to_string();
+        assert!(err.contains("'a.txt' changed"), "{}", err);
+        assert!(err.contains("expected 'dir/b c.bin', found 'new.txt'"), "{}", err);
+
+        archive.files.pop();
+        let err = archive.verify_manifest().unwrap_err().to_string();
+        assert!(err.contains("'dir/b c.bin' is missing"), "{}", err);
+
+        archive.files[1] = File::new(MANIFEST_NAME, "abc\n");
+        assert!(archive.verify_manifest().unwrap_err().to_string().contains("Malformed manifest line 1"));
+    }
+}
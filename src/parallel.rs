@@ -0,0 +1,50 @@
+//! Scoped worker threads over a shared work queue
+//!
+//! Used by [`Encoder::with_jobs`](crate::Encoder::with_jobs) and by the
+//! command line tool to read files; not part of the stable API.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Apply `f` to each item on up to `jobs` threads, returning results in item order
+///
+/// Items are handed out one at a time so that a large one doesn't stall a
+/// worker's share. A panic in `f` is resumed on the calling thread.
+pub fn map<T: Sync, R: Send>(items: &[T], jobs: usize, f: impl Fn(usize, &T) -> R + Sync) -> Vec<R> {
+    let jobs = jobs.min(items.len());
+    if jobs <= 1 {
+        return items.iter().enumerate().map(|(index, item)| f(index, item)).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..jobs)
+            .map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else { break };
+                    done.push((index, f(index, item)));
+                }
+                done
+            }))
+            .collect();
+        workers.into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    });
+    results.sort_unstable_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map_keeps_order() {
+        let items: Vec<usize> = (0..100).collect();
+        for jobs in [0, 1, 4, 200] {
+            assert_eq!(map(&items, jobs, |index, item| index + item), (0..100).map(|i| i * 2).collect::<Vec<_>>());
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Positions of file contents in the archive text
+//!
+//! The decoder records where each entry starts in the text it decodes, so
+//! editors working on the `.txtar` document can jump from a line of a
+//! stored file to the archive ([`Archive::locate`]) and back
+//! ([`Archive::file_at`]). Lines and columns are numbered from 1.
+//!
+//! Positions describe the text the archive was decoded from: they are not
+//! updated when the archive changes, and archives built in memory or read
+//! through an include have none. Base64 entries have no line-for-line
+//! mapping and are never located.
+//!
+//! ```
+//! use emx_txtar::position::ArchivePosition;
+//! use emx_txtar::Decoder;
+//!
+//! let archive = Decoder::new().decode("Fixtures\n-- a.txt --\none\ntwo\n").unwrap();
+//! assert_eq!(archive.locate("a.txt", 2), Some(ArchivePosition { line: 4, column: 1 }));
+//! assert_eq!(archive.file_at(4).map(|(file, line)| (file.name.as_str(), line)), Some(("a.txt", 2)));
+//! ```
+
+use crate::archive::{Archive, File};
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Line and column in the archive text, both 1-based
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ArchivePosition {
+    pub line: usize,
+    /// Column of the first character of the file's line, after any marker
+    /// escape
+    pub column: usize,
+}
+
+/// Where a decoded entry was found
+#[derive(Debug, Clone)]
+pub(crate) struct EntrySpan {
+    pub(crate) name: String,
+    /// Line of the `-- name --` marker
+    pub(crate) header: usize,
+    /// Number of lines after the marker belonging to the entry
+    pub(crate) lines: usize,
+    /// Whether the contents are base64
+    pub(crate) base64: bool,
+    /// Lines of the file written with a marker escape
+    pub(crate) escaped_lines: Vec<usize>,
+}
+
+impl Archive {
+    /// Position in the archive text of line `line` of file `file_name`
+    ///
+    /// `None` if the file has no recorded position, is stored as base64 or
+    /// has fewer lines.
+    pub fn locate(&self, file_name: &str, line: usize) -> Option<ArchivePosition> {
+        let span = self.spans.iter().find(|span| span.name == file_name)?;
+        if span.base64 || line == 0 || line > span.lines || !self.files.iter().any(|f| f.name == file_name) {
+            return None;
+        }
+        let column = if span.escaped_lines.contains(&line) { 2 } else { 1 };
+        Some(ArchivePosition { line: span.header + line, column })
+    }
+
+    /// The file whose contents include line `line` of the archive text, and
+    /// the number of that line in the file
+    pub fn file_at(&self, line: usize) -> Option<(&File, usize)> {
+        let span = self.spans.iter().find(|span| span.header < line && line <= span.header + span.lines)?;
+        if span.base64 {
+            return None;
+        }
+        let file = self.files.iter().find(|f| f.name == span.name)?;
+        Some((file, line - span.header))
+    }
+
+    /// Forget the position of the file at `index`, before removing it
+    pub(crate) fn remove_span(&mut self, index: usize) {
+        // Entries of one name are recorded in order, so the span of this
+        // one comes after those of the earlier ones
+        let name = &self.files[index].name;
+        let earlier = self.files[..index].iter().filter(|f| &f.name == name).count();
+        if let Some(span) = self.spans.iter().enumerate().filter(|(_, span)| &span.name == name).nth(earlier).map(|(i, _)| i) {
+            self.spans.remove(span);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decoder;
+
+    const INPUT: &str = "# Fixtures\n\n-- a.txt --\none\ntwo\n-- doc.md[.esc:2] --\ntext\n\\-- b --\n-- blob[.base64] --\nAA==\n-- empty --\n";
+
+    #[test]
+    fn test_locate() {
+        let archive = Decoder::new().decode(INPUT).unwrap();
+        assert_eq!(archive.locate("a.txt", 1), Some(ArchivePosition { line: 4, column: 1 }));
+        assert_eq!(archive.locate("doc.md", 2), Some(ArchivePosition { line: 8, column: 2 }));
+        assert_eq!(archive.locate("a.txt", 3), None);
+        assert_eq!(archive.locate("a.txt", 0), None);
+        assert_eq!(archive.locate("blob", 1), None);
+        assert_eq!(archive.locate("empty", 1), None);
+        assert_eq!(archive.locate("missing", 1), None);
+
+        // Positions are only known for decoded text
+        let mut built = crate::Archive::new();
+        let mut file = File::new("a.txt", "one");
+        file.trailing_newline = Some(true);
+        built.add_file(file).unwrap();
+        assert_eq!(built.locate("a.txt", 1), None);
+        // and do not take part in comparisons
+        assert_eq!(built, Decoder::new().decode("-- a.txt --\none\n").unwrap());
+    }
+
+    #[test]
+    fn test_file_at() {
+        let archive = Decoder::new().decode(INPUT).unwrap();
+        let at = |line| archive.file_at(line).map(|(file, n)| (file.name.as_str(), n));
+        assert_eq!(at(1), None);
+        assert_eq!(at(3), None);
+        assert_eq!(at(5), Some(("a.txt", 2)));
+        assert_eq!(at(8), Some(("doc.md", 2)));
+        assert_eq!(at(10), None);
+        assert_eq!(at(12), None);
+
+        for (name, line) in [("a.txt", 1), ("a.txt", 2), ("doc.md", 1), ("doc.md", 2)] {
+            let position = archive.locate(name, line).unwrap();
+            assert_eq!(at(position.line), Some((name, line)));
+        }
+    }
+}
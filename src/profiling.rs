@@ -0,0 +1,94 @@
+//! Internal counters for profiling
+//!
+//! With the `profiling` feature the decoder, encoder, detection and edit
+//! paths count their work in process-wide counters. Take a [`snapshot`]
+//! before and after an operation to see what it did.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub(crate) struct Counters {
+    pub(crate) bytes_decoded: AtomicU64,
+    pub(crate) files_decoded: AtomicU64,
+    pub(crate) base64_decoded: AtomicU64,
+    pub(crate) files_encoded: AtomicU64,
+    pub(crate) base64_encoded: AtomicU64,
+    pub(crate) detections: AtomicU64,
+    pub(crate) edits_applied: AtomicU64,
+}
+
+pub(crate) static COUNTERS: Counters = Counters {
+    bytes_decoded: AtomicU64::new(0),
+    files_decoded: AtomicU64::new(0),
+    base64_decoded: AtomicU64::new(0),
+    files_encoded: AtomicU64::new(0),
+    base64_encoded: AtomicU64::new(0),
+    detections: AtomicU64::new(0),
+    edits_applied: AtomicU64::new(0),
+};
+
+/// Counter values at one point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Bytes of archive text passed to the decoder
+    pub bytes_decoded: u64,
+    /// Entries created by the decoder
+    pub files_decoded: u64,
+    /// Bytes of base64 text decoded
+    pub base64_decoded: u64,
+    /// Entries written by the encoder
+    pub files_encoded: u64,
+    /// Bytes of binary data base64-encoded
+    pub base64_encoded: u64,
+    /// Runs of binary detection
+    pub detections: u64,
+    /// Edit blocks applied
+    pub edits_applied: u64,
+}
+
+impl Snapshot {
+    /// Counts accumulated since `earlier`
+    pub fn since(&self, earlier: &Snapshot) -> Snapshot {
+        Snapshot {
+            bytes_decoded: self.bytes_decoded - earlier.bytes_decoded,
+            files_decoded: self.files_decoded - earlier.files_decoded,
+            base64_decoded: self.base64_decoded - earlier.base64_decoded,
+            files_encoded: self.files_encoded - earlier.files_encoded,
+            base64_encoded: self.base64_encoded - earlier.base64_encoded,
+            detections: self.detections - earlier.detections,
+            edits_applied: self.edits_applied - earlier.edits_applied,
+        }
+    }
+}
+
+/// Current counter values
+pub fn snapshot() -> Snapshot {
+    let c = &COUNTERS;
+    Snapshot {
+        bytes_decoded: c.bytes_decoded.load(Ordering::Relaxed),
+        files_decoded: c.files_decoded.load(Ordering::Relaxed),
+        base64_decoded: c.base64_decoded.load(Ordering::Relaxed),
+        files_encoded: c.files_encoded.load(Ordering::Relaxed),
+        base64_encoded: c.base64_encoded.load(Ordering::Relaxed),
+        detections: c.detections.load(Ordering::Relaxed),
+        edits_applied: c.edits_applied.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counters_track_work() {
+        let before = snapshot();
+        let archive = crate::Decoder::new().decode("-- a.txt --\nA\n-- b.bin[.base64] --\n/w==\n").unwrap();
+        crate::Encoder::new().encode(&archive).unwrap();
+
+        // Other tests run concurrently, so only lower bounds are reliable
+        let delta = snapshot().since(&before);
+        assert!(delta.files_decoded >= 2);
+        assert!(delta.base64_decoded >= 4);
+        assert!(delta.files_encoded >= 2);
+        assert!(delta.base64_encoded >= 1);
+    }
+}
@@ -0,0 +1,355 @@
+//! Property-based testing and fuzzing support
+//!
+//! [`Archive`], [`File`] and [`EditBlock`] implement [`arbitrary::Arbitrary`],
+//! building values from a stream of raw bytes, so the same generators serve
+//! randomized property tests (see [`check`]), `proptest` strategies (through
+//! [`proptest::arbitrary::Arbitrary`], e.g. `any::<Archive>()`) and
+//! coverage-guided fuzzing (`cargo fuzz` targets take an `Archive` directly).
+//! Generated values lean towards the parser's edge cases: marker-like content
+//! lines, bracketed tags in names, truncated base64 and missing trailing
+//! newlines. Once the input runs out every choice takes its smallest value,
+//! so any input (including an empty one) produces a value.
+//!
+//! ```
+//! use emx_txtar::proptest::{check, check_archive_round_trip};
+//! use emx_txtar::Archive;
+//!
+//! check::<Archive>(64, |archive| check_archive_round_trip(&archive));
+//! ```
+
+use crate::archive::{Archive, EditBlock, EditOperation, File};
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use arbitrary::{Arbitrary, Result, Unstructured};
+use proptest::strategy::{BoxedStrategy, Strategy};
+
+/// Name fragments, including ones that resemble tags and markers
+const NAME_PARTS: &[&str] = &[
+    "a", "b.txt", "src", "main.rs", "data.bin", "x[.base64]", "[.edit]", "--",
+    "-- y --", "\\", "]", "[", "é", "dir.d", ".hidden", "name with space",
+];
+
+/// Whole content lines that stress the parser
+///
+/// Generated content never contains carriage returns: decoding normalizes
+/// CRLF line endings in text entries, so they cannot round-trip.
+const TRICKY_LINES: &[&str] = &[
+    "-- a.txt --", "-- a.txt[.base64] --", "--  --", "-- --", "\\-- a --",
+    "<<<<<<< SEARCH", "=======", ">>>>>>> REPLACE", "[.snippet:1]", "#!/bin/sh",
+    "SGVsbG8=", "SGVsbG", "====", "", " ", "\t",
+];
+
+/// Words for comments and ordinary content lines
+const WORDS: &[&str] = &["hello", "world", "fn", "main()", "{", "}", "=", "42", "$ run", "é", "世界"];
+
+/// Maximum number of raw bytes in a generated binary file
+const MAX_BINARY_LEN: usize = 64;
+
+impl<'a> Arbitrary<'a> for EditBlock {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let operation = match u.int_in_range(0..=4)? {
+            0 => EditOperation::Replace,
+            1 => EditOperation::Delete,
+            2 => EditOperation::InsertAfter,
+            3 => EditOperation::InsertBefore,
+            _ => EditOperation::Insert,
+        };
+        let mut lines = |present: bool| -> Result<Vec<String>> {
+            if !present {
+                return Ok(Vec::new());
+            }
+            (0..1 + u.int_in_range(0..=2)?).map(|_| Ok(text_line(u)?.trim_end().to_string())).collect()
+        };
+        let search = lines(operation != EditOperation::Insert)?;
+        let replacement = lines(operation != EditOperation::Delete)?;
+        Ok(EditBlock { search, replacement, operation, file: None })
+    }
+}
+
+impl<'a> Arbitrary<'a> for File {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let name = file_name(u)?;
+        let data = match u.int_in_range(0..=3)? {
+            0 => {
+                let len = u.int_in_range(0..=MAX_BINARY_LEN)?.min(u.len());
+                u.bytes(len)?.iter().map(|&b| if b == b'\r' { b'\n' } else { b }).collect()
+            }
+            _ => {
+                let mut text = String::new();
+                for _ in 0..u.int_in_range(0..=5)? {
+                    text += &text_line(u)?;
+                    text.push('\n');
+                }
+                if u.ratio(1, 3)? {
+                    text.pop();
+                }
+                text.into_bytes()
+            }
+        };
+        Ok(File::new(name, data))
+    }
+}
+
+impl<'a> Arbitrary<'a> for Archive {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut comment = String::new();
+        for _ in 0..u.int_in_range(0..=2)? {
+            let count = 1 + u.int_in_range(0..=3)?;
+            comment += &words(u, count)?;
+            comment.push('\n');
+        }
+        let mut archive = Archive::with_comment(comment);
+        for _ in 0..u.int_in_range(0..=5)? {
+            // Duplicate names are simply dropped
+            let _ = archive.add_file(File::arbitrary(u)?);
+        }
+        Ok(archive)
+    }
+}
+
+/// `proptest` strategies draw generator input as bytes, which proptest
+/// shrinks towards shorter and smaller inputs
+macro_rules! proptest_arbitrary {
+    ($($ty:ty),*) => {$(
+        impl proptest::arbitrary::Arbitrary for $ty {
+            type Parameters = ();
+            type Strategy = BoxedStrategy<Self>;
+
+            fn arbitrary_with(_: ()) -> Self::Strategy {
+                proptest::collection::vec(proptest::num::u8::ANY, 0..256)
+                    .prop_filter_map("generator input rejected", |input| {
+                        <$ty as Arbitrary>::arbitrary(&mut Unstructured::new(&input)).ok()
+                    })
+                    .boxed()
+            }
+        }
+    )*};
+}
+
+proptest_arbitrary!(Archive, File, EditBlock);
+
+/// A non-empty single-line file name with no surrounding whitespace
+fn file_name(u: &mut Unstructured<'_>) -> Result<String> {
+    let mut parts = Vec::new();
+    for _ in 0..1 + u.int_in_range(0..=2)? {
+        parts.push(*u.choose(NAME_PARTS)?);
+    }
+    let mut name = parts.join("/").trim().to_string();
+    if name.is_empty() {
+        name.push('f');
+    }
+    Ok(name)
+}
+
+/// One content line without its line break
+fn text_line(u: &mut Unstructured<'_>) -> Result<String> {
+    if u.ratio(1, 3)? {
+        return Ok(u.choose(TRICKY_LINES)?.to_string());
+    }
+    let count = u.int_in_range(0..=4)?;
+    words(u, count)
+}
+
+/// `count` words joined by spaces
+fn words(u: &mut Unstructured<'_>, count: usize) -> Result<String> {
+    let mut words = Vec::new();
+    for _ in 0..count {
+        words.push(*u.choose(WORDS)?);
+    }
+    Ok(words.join(" "))
+}
+
+/// Run `property` against `cases` values generated from pseudo-random input
+///
+/// Inputs are derived deterministically from the case number; a failing case
+/// panics with that number so it can be reproduced with [`case_input`].
+pub fn check<T: for<'a> Arbitrary<'a> + core::fmt::Debug>(cases: u32, mut property: impl FnMut(T)) {
+    for case in 0..cases {
+        let input = case_input(case);
+        let Ok(value) = T::arbitrary(&mut Unstructured::new(&input)) else {
+            continue;
+        };
+        let description = format!("{:?}", value);
+        if let Err(panic) = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| property(value))) {
+            eprintln!("property failed for case {} with {}", case, description);
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// The generator input used for case number `case` by [`check`]
+pub fn case_input(case: u32) -> Vec<u8> {
+    let mut state = case.wrapping_mul(2654435761) | 1;
+    (0..256)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Assert that encoding `archive` and decoding the result restores its content
+///
+/// The comment is compared without its final line break, which decoding drops.
+pub fn check_archive_round_trip(archive: &Archive) {
+    let (decoded, encoded) = encode_decode(archive);
+    let comment = archive.comment.strip_suffix('\n').unwrap_or(&archive.comment);
+    assert_eq!(comment, decoded.comment, "comment changed\n{}", encoded);
+    assert_same_files(archive, &decoded, &encoded);
+}
+
+/// Assert that text which decodes survives an encode/decode cycle unchanged
+///
+/// Input that fails to decode is ignored; this is the fuzzing entry point
+/// for `decode → encode → decode` round-tripping.
+pub fn check_text_round_trip(input: &[u8]) {
+    let text = String::from_utf8_lossy(input);
+    let Ok(first) = Decoder::new().decode(&text) else {
+        return;
+    };
+    let (second, encoded) = encode_decode(&first);
+    assert_eq!(first.comment, second.comment, "comment changed\n{}", encoded);
+    assert_same_files(&first, &second, &encoded);
+}
+
+fn encode_decode(archive: &Archive) -> (Archive, String) {
+    let encoded = Encoder::new().encode(archive).expect("archive encodes");
+    match Decoder::new().decode(&encoded) {
+        Ok(decoded) => (decoded, encoded),
+        Err(err) => panic!("encoded archive fails to decode: {}\n{}", err, encoded),
+    }
+}
+
+fn assert_same_files(expected: &Archive, actual: &Archive, encoded: &str) {
+    let names = |archive: &Archive| archive.files.iter().map(|f| f.name.clone()).collect::<Vec<_>>();
+    assert_eq!(names(expected), names(actual), "file names changed\n{}", encoded);
+    for (before, after) in expected.files.iter().zip(&actual.files) {
+        assert_eq!(written(before), after.content().as_ref(), "content of {} changed\n{}", before.name, encoded);
+        assert_eq!(before.metadata, after.metadata, "metadata of {} changed\n{}", before.name, encoded);
+    }
+}
+
+/// Contents of `file` as a reader of the encoded text sees them: built text
+/// files gain the final newline the encoder writes after them
+fn written(file: &File) -> Vec<u8> {
+    let mut content = file.content().into_owned();
+    if !file.is_binary && file.trailing_newline.is_none() && !content.is_empty() && !content.ends_with(b"\n") {
+        content.push(b'\n');
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_archives_round_trip() {
+        check::<Archive>(2000, |archive| check_archive_round_trip(&archive));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_proptest_archives_round_trip(archive: Archive) {
+            check_archive_round_trip(&archive);
+        }
+    }
+
+    #[test]
+    fn test_generated_edits_apply() {
+        check::<EditBlock>(500, |block| {
+            if block.operation == EditOperation::Insert {
+                return;
+            }
+            let original = format!("before\n{}\nafter", block.search.join("\n"));
+            let middle = match block.operation {
+                EditOperation::InsertAfter => [&block.search[..], &block.replacement].concat(),
+                EditOperation::InsertBefore => [&block.replacement[..], &block.search].concat(),
+                _ => block.replacement.clone(),
+            };
+            let expected = [&["before".to_string()][..], &middle, &["after".to_string()]].concat().join("\n");
+            let edit = crate::archive::EditRef { command_href: None, start_line: None, edits: vec![block] };
+            assert_eq!(edit.apply(&original).unwrap(), expected);
+        });
+    }
+
+    #[test]
+    fn test_generated_diffs_apply() {
+        // Few distinct lines, so that blocks need context to be found
+        const LINES: &[&str] = &["a", "b", "c", "", "}"];
+        for case in 0..1000 {
+            let input = case_input(case);
+            let mut u = Unstructured::new(&input);
+            let mut text = || (0..u.choose_index(12).unwrap()).map(|_| *u.choose(LINES).unwrap()).collect::<Vec<_>>().join("\n");
+            let (original, modified) = (text(), text());
+            let edit = crate::archive::EditRef::diff(&original, &modified);
+            // Edits see the text as lines, without a final line break
+            let expected = modified.lines().collect::<Vec<_>>().join("\n");
+            assert_eq!(edit.apply(&original).unwrap(), expected, "case {}: {:?}", case, edit);
+            let parsed = crate::archive::EditRef::parse_content(&edit.to_content()).unwrap();
+            assert_eq!(parsed, edit.edits, "case {}", case);
+        }
+    }
+
+    #[test]
+    fn test_generated_inverses_undo() {
+        const LINES: &[&str] = &["a", "b", "c", "", "}"];
+        const OPERATIONS: &[EditOperation] = &[
+            EditOperation::Replace,
+            EditOperation::Delete,
+            EditOperation::Insert,
+            EditOperation::InsertAfter,
+            EditOperation::InsertBefore,
+        ];
+        for case in 0..1000 {
+            let input = case_input(case);
+            let mut u = Unstructured::new(&input);
+            let original: Vec<&str> = (0..1 + u.choose_index(10).unwrap()).map(|_| *u.choose(LINES).unwrap()).collect();
+            let edits = (0..1 + u.choose_index(3).unwrap())
+                .map(|_| {
+                    // SEARCH lines taken from the original, so that most blocks apply
+                    let start = u.choose_index(original.len()).unwrap();
+                    let end = start + 1 + u.choose_index(original.len() - start).unwrap();
+                    let operation = u.choose(OPERATIONS).unwrap().clone();
+                    let search = if operation == EditOperation::Insert { Vec::new() } else { original[start..end].iter().map(|s| s.to_string()).collect() };
+                    let replacement = if operation == EditOperation::Delete { Vec::new() } else { (0..u.choose_index(3).unwrap()).map(|_| u.choose(LINES).unwrap().to_string()).collect() };
+                    EditBlock { search, replacement, operation, file: None }
+                })
+                .collect();
+            let edit = crate::archive::EditRef { command_href: None, start_line: None, edits };
+            let original = original.join("\n");
+            let Ok(edited) = edit.apply(&original) else { continue };
+            if edited.is_empty() || edited.ends_with('\n') {
+                // A final blank line is lost when the edited text is split again
+                continue;
+            }
+            let inverse = edit.invert(&original).unwrap();
+            let expected = original.lines().collect::<Vec<_>>().join("\n");
+            assert_eq!(inverse.apply(&edited), Ok(expected), "case {}: {:?} then {:?}", case, edit, inverse);
+        }
+    }
+
+    #[test]
+    fn test_text_round_trip_on_raw_input() {
+        for case in 0..2000 {
+            check_text_round_trip(&case_input(case));
+        }
+        check::<Archive>(500, |archive| {
+            let encoded = Encoder::new().encode(&archive).unwrap();
+            for end in 0..=encoded.len() {
+                check_text_round_trip(&encoded.as_bytes()[..end]);
+            }
+        });
+        check_text_round_trip(b"-- a --\n-- b[.base64] --\nSGVsbG\n");
+        check_text_round_trip(b"");
+    }
+
+    #[test]
+    fn test_unstructured_exhausted() {
+        let mut u = Unstructured::new(&[]);
+        assert_eq!(File::arbitrary(&mut u).unwrap().name, "a");
+        assert!(Archive::arbitrary(&mut u).unwrap().files.is_empty());
+    }
+}
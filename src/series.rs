@@ -0,0 +1,291 @@
+//! Ordered series of patch archives
+//!
+//! A [`PatchSeries`] is a queue of archives applied one after another to a
+//! directory, in the style of quilt: each patch holds edit entries (see
+//! [`Archive::apply_edits`]) for files under the directory and normal
+//! entries for files it writes whole. Which patches are applied is tracked
+//! in a state archive, [`STATE_NAME`], at the root of the directory, along
+//! with the previous contents of every file a patch touched, so
+//! [`PatchSeries::pop`] can restore them exactly.
+//!
+//! ```no_run
+//! use emx_txtar::series::PatchSeries;
+//! use std::path::Path;
+//!
+//! let series = PatchSeries::load(Path::new("patches"))?;
+//! series.apply_all(Path::new("src-tree"))?;
+//! series.pop(Path::new("src-tree"))?;
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::archive::{extract_path, Archive, File};
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use anyhow::{anyhow, bail, Result};
+use std::path::Path;
+
+/// Name of the state archive kept at the root of a patched directory
+///
+/// Each applied patch has a `[.txtar]` entry named after it, holding the
+/// files it changed as they were before, with the names of the files it
+/// created listed one per line in the comment.
+pub const STATE_NAME: &str = ".txtar-series";
+
+/// One archive in a series
+#[derive(Debug, Clone, PartialEq)]
+pub struct Patch {
+    pub name: String,
+    pub archive: Archive,
+}
+
+/// An ordered list of patches and the operations to apply and unapply them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PatchSeries {
+    patches: Vec<Patch>,
+}
+
+impl PatchSeries {
+    pub fn new(patches: Vec<Patch>) -> Self {
+        Self { patches }
+    }
+
+    /// Load a series from a directory of `*.txtar` files, ordered by file
+    /// name, or from an archive whose `[.txtar]` entries are the patches, in
+    /// order
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut patches = Vec::new();
+        if path.is_dir() {
+            let mut names = Vec::new();
+            for entry in std::fs::read_dir(path)? {
+                let name = entry?.file_name().to_string_lossy().into_owned();
+                if name.ends_with(".txtar") && name != STATE_NAME {
+                    names.push(name);
+                }
+            }
+            names.sort();
+            for name in names {
+                let text = std::fs::read_to_string(path.join(&name))?;
+                let archive = patch_decoder().decode(&text).map_err(|e| anyhow!("In patch '{}': {}", name, e))?;
+                patches.push(Patch { name, archive });
+            }
+        } else {
+            let archive: Archive = std::fs::read_to_string(path)?.parse()?;
+            for file in archive.files.iter().filter(|f| f.is_archive) {
+                patches.push(Patch { name: file.name.clone(), archive: patch_decoder().decode_nested(file)? });
+            }
+        }
+        Ok(Self { patches })
+    }
+
+    /// The patches, in order
+    pub fn patches(&self) -> &[Patch] {
+        &self.patches
+    }
+
+    /// Names of the patches applied to `root`, in order
+    ///
+    /// Fails if they are not the start of this series.
+    pub fn applied(&self, root: &Path) -> Result<Vec<String>> {
+        let names: Vec<String> = read_state(root)?.files.into_iter().map(|f| f.name).collect();
+        for (i, name) in names.iter().enumerate() {
+            if self.patches.get(i).map(|p| &p.name) != Some(name) {
+                bail!("Applied patch '{}' is not patch {} of the series", name, i + 1);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Apply the next unapplied patch to `root`, returning it, or `None` if
+    /// all are applied
+    ///
+    /// Nothing is written if any edit of the patch fails.
+    pub fn push(&self, root: &Path) -> Result<Option<&Patch>> {
+        let applied = self.applied(root)?.len();
+        let Some(patch) = self.patches.get(applied) else {
+            return Ok(None);
+        };
+
+        let mut backup = Archive::new();
+        let mut created = Vec::new();
+        let mut work = Archive::new();
+        for file in &patch.archive.files {
+            if file.snippet_ref.is_some() || work.files.iter().any(|f| f.name == file.name) {
+                continue;
+            }
+            let path = extract_path(root, &file.name)?;
+            if path.is_file() {
+                let original = File::from_disk(file.name.clone(), std::fs::read(&path)?);
+                backup.add_file(original.clone())?;
+                work.add_file(original)?;
+            } else {
+                created.push(file.name.clone());
+            }
+        }
+        for file in patch.archive.files.iter().filter(|f| f.snippet_ref.is_none()) {
+            match work.files.iter_mut().find(|f| f.name == file.name && file.edit_ref.is_none()) {
+                Some(target) => *target = file.load()?.into_owned(),
+                None => work.files.push(file.clone()),
+            }
+        }
+        work.apply_edits().map_err(|e| anyhow!("Failed to apply patch '{}': {}", patch.name, e))?;
+
+        backup.comment = created.join("\n");
+        let mut state = read_state(root)?;
+        state.add_file(File::from_archive(patch.name.clone(), &backup)?)?;
+        work.extract_to_dir(root, &Default::default())?;
+        write_state(root, &state)?;
+        Ok(Some(patch))
+    }
+
+    /// Apply every unapplied patch to `root` in order, returning how many
+    /// were applied
+    pub fn apply_all(&self, root: &Path) -> Result<usize> {
+        let mut count = 0;
+        while self.push(root)?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Unapply the last applied patch from `root`, restoring the files it
+    /// changed and removing those it created; returns its name, or `None` if
+    /// no patch is applied
+    pub fn pop(&self, root: &Path) -> Result<Option<String>> {
+        self.applied(root)?;
+        let mut state = read_state(root)?;
+        let Some(entry) = state.files.pop() else {
+            return Ok(None);
+        };
+
+        let backup = entry.as_archive()?;
+        for name in backup.comment.lines() {
+            let path = extract_path(root, name)?;
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        backup.extract_to_dir(root, &Default::default())?;
+        write_state(root, &state)?;
+        Ok(Some(entry.name))
+    }
+}
+
+/// Decoder for patches, whose edit targets are under the patched directory
+/// rather than the current one
+fn patch_decoder() -> Decoder {
+    Decoder::new().with_edit_target_check(false)
+}
+
+fn read_state(root: &Path) -> Result<Archive> {
+    let path = root.join(STATE_NAME);
+    if !path.exists() {
+        return Ok(Archive::new());
+    }
+    std::fs::read_to_string(&path)?.parse().map_err(|e| anyhow!("Invalid {}: {}", path.display(), e))
+}
+
+fn write_state(root: &Path, state: &Archive) -> Result<()> {
+    let path = root.join(STATE_NAME);
+    if state.files.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    std::fs::write(path, Encoder::new().encode(state)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RENAME: &str = "\
+-- main.rs[.edit] --
+<<<<<<< SEARCH
+fn old() {}
+=======
+fn new() {}
+>>>>>>> REPLACE
+";
+
+    const ADD_FILE: &str = "\
+-- lib.rs --
+pub fn lib() {}
+-- main.rs[.edit] --
+<<<<<<< SEARCH
+fn new() {}
+=======
+fn new() {}
+mod lib;
+>>>>>>> REPLACE
+";
+
+    fn series() -> PatchSeries {
+        let patch = |name: &str, text| Patch { name: name.into(), archive: patch_decoder().decode(text).unwrap() };
+        PatchSeries::new(vec![patch("01-rename", RENAME), patch("02-add-file", ADD_FILE)])
+    }
+
+    #[test]
+    fn test_apply_and_pop() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("main.rs");
+        let original = "fn old() {}\nfn main() {}";
+        std::fs::write(&main, original).unwrap();
+
+        let series = series();
+        assert_eq!(series.push(dir.path()).unwrap().unwrap().name, "01-rename");
+        assert_eq!(std::fs::read_to_string(&main).unwrap(), "fn new() {}\nfn main() {}");
+        assert_eq!(series.apply_all(dir.path()).unwrap(), 1);
+        assert_eq!(series.applied(dir.path()).unwrap(), ["01-rename", "02-add-file"]);
+        assert_eq!(std::fs::read_to_string(dir.path().join("lib.rs")).unwrap(), "pub fn lib() {}\n");
+        assert!(series.push(dir.path()).unwrap().is_none());
+
+        assert_eq!(series.pop(dir.path()).unwrap().as_deref(), Some("02-add-file"));
+        assert!(!dir.path().join("lib.rs").exists());
+        assert_eq!(series.pop(dir.path()).unwrap().as_deref(), Some("01-rename"));
+        assert_eq!(std::fs::read_to_string(&main).unwrap(), original);
+        assert!(!dir.path().join(STATE_NAME).exists());
+        assert!(series.pop(dir.path()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_failed_patch_writes_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn other() {}\n").unwrap();
+
+        let series = series();
+        let err = series.push(dir.path()).unwrap_err();
+        assert!(err.to_string().contains("Failed to apply patch '01-rename'"), "{}", err);
+        assert!(series.applied(dir.path()).unwrap().is_empty());
+        assert_eq!(std::fs::read_to_string(dir.path().join("main.rs")).unwrap(), "fn other() {}\n");
+
+        // State from a different series is rejected
+        std::fs::write(dir.path().join("main.rs"), "fn old() {}\n").unwrap();
+        series.push(dir.path()).unwrap();
+        let other = PatchSeries::new(vec![Patch { name: "other".into(), archive: Archive::new() }]);
+        assert!(other.applied(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("02-add-file.txtar"), ADD_FILE).unwrap();
+        std::fs::write(dir.path().join("01-rename.txtar"), RENAME).unwrap();
+        std::fs::write(dir.path().join("notes.md"), "not a patch").unwrap();
+        let from_dir = PatchSeries::load(dir.path()).unwrap();
+        let names: Vec<_> = from_dir.patches().iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["01-rename.txtar", "02-add-file.txtar"]);
+
+        let mut bundle = Archive::with_comment("Patch queue");
+        for patch in series().patches() {
+            bundle.add_file(File::from_archive(patch.name.clone(), &patch.archive).unwrap()).unwrap();
+        }
+        let path = dir.path().join("queue");
+        std::fs::write(&path, Encoder::new().encode(&bundle).unwrap()).unwrap();
+        let encode = |series: &PatchSeries| -> Vec<(String, String)> {
+            series.patches().iter().map(|p| (p.name.clone(), Encoder::new().encode(&p.archive).unwrap())).collect()
+        };
+        assert_eq!(encode(&PatchSeries::load(&path).unwrap()), encode(&series()));
+    }
+}
@@ -0,0 +1,126 @@
+//! Ed25519 signatures over archives
+//!
+//! [`Encoder::encode_signed`] appends a detached signature as a final
+//! `-- .signature[.base64] --` entry. The signature covers the canonical
+//! form of the rest of the archive: its encoding with default [`Encoder`]
+//! settings, so it survives a decode/encode round trip and does not depend
+//! on the options used to write the archive.
+//!
+//! ```
+//! use emx_txtar::signing::SigningKey;
+//! use emx_txtar::{Archive, Decoder, Encoder, File};
+//!
+//! let key = SigningKey::from_bytes(&[7; 32]);
+//! let mut archive = Archive::new();
+//! archive.add_file(File::new("a.txt", "hello")).unwrap();
+//!
+//! let signed = Encoder::new().encode_signed(&archive, &key).unwrap();
+//! let decoded = Decoder::new().decode(&signed).unwrap();
+//! decoded.verify_signature(&key.verifying_key()).unwrap();
+//! ```
+
+use crate::archive::{Archive, File};
+use crate::encoder::Encoder;
+use anyhow::{anyhow, bail, Result};
+use ed25519_dalek::{Signature, Signer};
+
+pub use crate::archive::SIGNATURE_NAME;
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+
+impl Encoder {
+    /// Encode an archive followed by a signature of its canonical form
+    ///
+    /// The signature covers the comment as written, including any
+    /// provenance. Fails if the archive already has a [`SIGNATURE_NAME`]
+    /// entry.
+    pub fn encode_signed(&self, archive: &Archive, key: &SigningKey) -> Result<String> {
+        if archive.files.iter().any(|f| f.name == SIGNATURE_NAME) {
+            bail!("Archive already contains a '{}' entry", SIGNATURE_NAME);
+        }
+        let signature = key.sign(canonical_form(&self.comment(archive), &archive.files)?.as_bytes());
+
+        let mut output = self.encode(archive)?;
+        if !output.is_empty() && !output.ends_with('\n') {
+            output.push('\n');
+        }
+        output.push_str("-- ");
+        output.push_str(SIGNATURE_NAME);
+        output.push_str("[.base64] --\n");
+        crate::b64::encode_into(&signature.to_bytes(), &mut output);
+        output.push('\n');
+        Ok(output)
+    }
+}
+
+impl Archive {
+    /// Check the trailing signature entry against `key`
+    ///
+    /// Fails if the last entry is not a signature, or if the signature does
+    /// not match the canonical form of the entries before it.
+    pub fn verify_signature(&self, key: &VerifyingKey) -> Result<()> {
+        let (signature, files) = match self.files.split_last() {
+            Some((last, files)) if last.name == SIGNATURE_NAME => (last, files),
+            _ => bail!("Archive is not signed"),
+        };
+        let bytes: &[u8; 64] = signature.data.as_ref().try_into()
+            .map_err(|_| anyhow!("Signature entry is {} bytes, expected 64", signature.data.len()))?;
+        key.verify_strict(canonical_form(&self.comment, files)?.as_bytes(), &Signature::from_bytes(bytes))
+            .map_err(|_| anyhow!("Signature does not match the archive contents"))
+    }
+}
+
+/// The text a signature covers
+fn canonical_form(comment: &str, files: &[File]) -> Result<String> {
+    // Decoding drops the comment's final line break, so leave it out here
+    let mut archive = Archive::with_comment(comment.strip_suffix('\n').unwrap_or(comment));
+    // Files are signed by their contents, whether built or decoded
+    archive.files.extend(files.iter().map(|file| {
+        let mut file = file.clone();
+        file.data = file.content().into_owned().into();
+        file.trailing_newline = None;
+        file
+    }));
+    Encoder::new().encode(&archive)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Decoder;
+
+    fn key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let mut archive = Archive::with_comment("Fixtures\n");
+        archive.add_file(File::new("a.txt", "hello\n")).unwrap();
+        archive.add_file(File::new("doc.md", "-- b.txt --\n")).unwrap();
+        archive.add_file(File::new("blob", vec![0, 159, 255])).unwrap();
+
+        let signed = Encoder::new().with_marker_escaping(true).encode_signed(&archive, &key()).unwrap();
+        assert!(signed.contains("\n-- .signature[.base64] --\n"));
+        let decoded = Decoder::new().decode(&signed).unwrap();
+        decoded.verify_signature(&key().verifying_key()).unwrap();
+
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        assert!(decoded.verify_signature(&other).is_err());
+
+        let tampered = Decoder::new().decode(&signed.replace("hello", "hullo")).unwrap();
+        assert!(tampered.verify_signature(&key().verifying_key()).is_err());
+
+        assert!(Encoder::new().encode_signed(&decoded, &key()).is_err());
+        assert!(archive.verify_signature(&key().verifying_key()).is_err());
+    }
+
+    #[cfg(feature = "proptest")]
+    #[test]
+    fn test_generated_archives_verify() {
+        crate::proptest::check::<Archive>(500, |archive| {
+            let signed = Encoder::new().encode_signed(&archive, &key()).unwrap();
+            let decoded = Decoder::new().decode(&signed).unwrap();
+            decoded.verify_signature(&key().verifying_key()).unwrap();
+        });
+    }
+}
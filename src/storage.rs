@@ -0,0 +1,266 @@
+//! Loading and storing archives in external storage
+//!
+//! [`Storage`] is a minimal key/value interface. [`DirStorage`] keeps
+//! archives under a local directory and [`MemoryStorage`] in memory. With
+//! the `s3` and `gcs` features [`ObjectStorage`] reads and writes Amazon S3
+//! and Google Cloud Storage buckets through [`object_store`], with signed
+//! requests over HTTPS. With the `http` feature [`HttpStorage`] sends plain
+//! GET/PUT requests to any HTTP server, such as a public bucket or an
+//! artifact server. Keys ending in `.gz` or `.zst` are stored compressed.
+
+use crate::archive::Archive;
+use crate::compress::Compression;
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+
+/// Key/value storage for archive files
+pub trait Storage {
+    /// Read the object stored under `key`
+    fn get(&self, key: &str) -> Result<Vec<u8>>;
+
+    /// Store `data` under `key`, replacing any existing object
+    fn put(&self, key: &str, data: &[u8]) -> Result<()>;
+}
+
+impl Archive {
+    /// Load and decode an archive from storage
+    pub fn load_from(storage: &dyn Storage, key: &str) -> Result<Self> {
+        let mut data = storage.get(key)?;
+        if let Some(compression) = Compression::detect(&data) {
+            data = compression.decompress(&data)?;
+        }
+        let text = String::from_utf8(data).with_context(|| format!("Archive {} is not valid UTF-8", key))?;
+        Decoder::new().decode(&text)
+    }
+
+    /// Encode the archive and store it, compressing when the key has a `.gz`
+    /// or `.zst` extension
+    pub fn store_to(&self, storage: &dyn Storage, key: &str) -> Result<()> {
+        let encoded = Encoder::new().encode(self)?.into_bytes();
+        let data = match Compression::from_path(Path::new(key)) {
+            Some(compression) => compression.compress(&encoded)?,
+            None => encoded,
+        };
+        storage.put(key, &data)
+    }
+}
+
+/// Objects stored as files under a root directory
+#[derive(Debug, Clone)]
+pub struct DirStorage {
+    root: PathBuf,
+}
+
+impl DirStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path for a key, rejecting keys that would escape the root
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        if key.is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            bail!("Invalid storage key: {}", key);
+        }
+        Ok(self.root.join(relative))
+    }
+}
+
+impl Storage for DirStorage {
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let path = self.path(key)?;
+        std::fs::read(&path).with_context(|| format!("Failed to read: {}", path.display()))
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&path, data).with_context(|| format!("Failed to write: {}", path.display()))
+    }
+}
+
+/// Objects kept in memory, for tests
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keys of the stored objects, in order
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap_or_else(|e| e.into_inner()).keys().cloned().collect()
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.objects.lock().unwrap_or_else(|e| e.into_inner())
+            .get(key)
+            .cloned()
+            .ok_or_else(|| anyhow!("No such object: {}", key))
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.objects.lock().unwrap_or_else(|e| e.into_inner()).insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+}
+
+/// Objects under a base URL such as `https://artifacts.example.com/fixtures`
+///
+/// Requests are not signed, so this works with plain HTTP servers, public
+/// buckets and local emulators; use [`ObjectStorage`] for private buckets.
+#[cfg(feature = "http")]
+#[derive(Debug, Clone)]
+pub struct HttpStorage {
+    base_url: String,
+    options: crate::http::HttpOptions,
+}
+
+#[cfg(feature = "http")]
+impl HttpStorage {
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self { base_url: base_url.into().trim_end_matches('/').to_string(), options: Default::default() }
+    }
+
+    /// Use custom HTTP options
+    pub fn with_options(mut self, options: crate::http::HttpOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("{}/{}", self.base_url, key.trim_start_matches('/'))
+    }
+}
+
+#[cfg(feature = "http")]
+impl Storage for HttpStorage {
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        crate::http::fetch(&self.url(key), &self.options)
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        crate::http::put(&self.url(key), data, &self.options)
+    }
+}
+
+/// Objects in a cloud bucket, or any other [`ObjectStore`](object_store::ObjectStore)
+///
+/// Requests run on a runtime owned by the storage, so its methods block and
+/// must not be called from within an async runtime.
+#[cfg(any(feature = "s3", feature = "gcs"))]
+pub struct ObjectStorage {
+    store: Box<dyn object_store::ObjectStore>,
+    runtime: tokio::runtime::Runtime,
+}
+
+#[cfg(any(feature = "s3", feature = "gcs"))]
+impl ObjectStorage {
+    /// Wrap an object store
+    pub fn new(store: impl object_store::ObjectStore) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        Ok(Self { store: Box::new(store), runtime })
+    }
+
+    /// An S3 bucket, with region and credentials from the `AWS_*`
+    /// environment variables
+    #[cfg(feature = "s3")]
+    pub fn s3(bucket: &str) -> Result<Self> {
+        let store = object_store::aws::AmazonS3Builder::from_env().with_bucket_name(bucket).build()?;
+        Self::new(store)
+    }
+
+    /// A Google Cloud Storage bucket, with credentials from the
+    /// `GOOGLE_*` environment variables
+    #[cfg(feature = "gcs")]
+    pub fn gcs(bucket: &str) -> Result<Self> {
+        let store = object_store::gcp::GoogleCloudStorageBuilder::from_env().with_bucket_name(bucket).build()?;
+        Self::new(store)
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "gcs"))]
+impl core::fmt::Debug for ObjectStorage {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ObjectStorage").field("store", &self.store.to_string()).finish()
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "gcs"))]
+impl Storage for ObjectStorage {
+    fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let path = object_store::path::Path::parse(key)?;
+        let bytes = self.runtime
+            .block_on(async { self.store.get(&path).await?.bytes().await })
+            .with_context(|| format!("Failed to read {} from {}", key, self.store))?;
+        Ok(bytes.to_vec())
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<()> {
+        let path = object_store::path::Path::parse(key)?;
+        let payload = object_store::PutPayload::from(data.to_vec());
+        self.runtime
+            .block_on(self.store.put(&path, payload))
+            .with_context(|| format!("Failed to write {} to {}", key, self.store))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::File;
+
+    fn sample() -> Archive {
+        let mut archive = Archive::with_comment("fixtures");
+        archive.add_file(File::new("a.txt", "A")).unwrap();
+        archive
+    }
+
+    #[test]
+    fn test_memory_storage_round_trip() {
+        let storage = MemoryStorage::new();
+        sample().store_to(&storage, "plain.txtar").unwrap();
+        sample().store_to(&storage, "packed.txtar.gz").unwrap();
+
+        assert!(Compression::detect(&storage.get("packed.txtar.gz").unwrap()).is_some());
+        let expected = Decoder::new().decode(&Encoder::new().encode(&sample()).unwrap()).unwrap();
+        assert_eq!(Archive::load_from(&storage, "packed.txtar.gz").unwrap(), expected);
+        assert_eq!(Archive::load_from(&storage, "plain.txtar").unwrap(), expected);
+        assert!(Archive::load_from(&storage, "missing.txtar").is_err());
+    }
+
+    #[test]
+    #[cfg(any(feature = "s3", feature = "gcs"))]
+    fn test_object_storage_round_trip() {
+        let storage = ObjectStorage::new(object_store::memory::InMemory::new()).unwrap();
+        sample().store_to(&storage, "fixtures/packed.txtar.zst").unwrap();
+
+        let expected = Decoder::new().decode(&Encoder::new().encode(&sample()).unwrap()).unwrap();
+        assert_eq!(Archive::load_from(&storage, "fixtures/packed.txtar.zst").unwrap(), expected);
+        let err = Archive::load_from(&storage, "fixtures/missing.txtar").unwrap_err();
+        assert!(err.to_string().contains("Failed to read fixtures/missing.txtar"), "{}", err);
+    }
+
+    #[test]
+    fn test_dir_storage_rejects_escaping_keys() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let storage = DirStorage::new(temp.path());
+
+        sample().store_to(&storage, "nested/a.txtar").unwrap();
+        assert!(temp.path().join("nested/a.txtar").exists());
+        assert!(storage.put("../outside.txtar", b"").is_err());
+        assert!(storage.get("/etc/passwd").is_err());
+    }
+}
@@ -0,0 +1,228 @@
+//! Keeping an archive and a directory in step
+//!
+//! [`Archive::sync_with_dir`] compares the normal entries of an archive
+//! (not snippets, edits or conditional variants) with the files under a
+//! directory, then, depending on [`SyncDirection`], updates the archive from
+//! disk, updates the disk from the archive, or only reports the drift. This
+//! lets either side be the source of truth without re-creating the archive
+//! or re-extracting every file.
+//!
+//! ```no_run
+//! use emx_txtar::sync::{SyncDirection, SyncOptions};
+//! use emx_txtar::Archive;
+//! use std::path::Path;
+//!
+//! let mut archive: Archive = std::fs::read_to_string("fixtures.txtar")?.parse()?;
+//! let options = SyncOptions { direction: SyncDirection::FromDisk, delete: true, ..Default::default() };
+//! let report = archive.sync_with_dir(Path::new("fixtures"), &options)?;
+//! println!("{} changed", report.modified.len());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+use crate::archive::{Archive, ExtractOptions, File, extract_path};
+use anyhow::Result;
+use std::path::Path;
+
+/// Which side of a sync is updated
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncDirection {
+    /// Change nothing, only report differences
+    #[default]
+    Report,
+    /// Update the archive from the files on disk
+    FromDisk,
+    /// Update the files on disk from the archive
+    ToDisk,
+}
+
+/// Options for [`Archive::sync_with_dir`]
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// Side to update
+    pub direction: SyncDirection,
+    /// Also remove what the source side lacks: entries with no file
+    /// ([`SyncDirection::FromDisk`]) or files with no entry
+    /// ([`SyncDirection::ToDisk`])
+    pub delete: bool,
+    /// How entries are written and compared; with `utf8` set, transcoded
+    /// text is compared and written as UTF-8
+    pub extract: ExtractOptions,
+}
+
+/// Differences found between an archive and a directory, by entry name
+///
+/// Describes the state before the sync was applied.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncReport {
+    /// Files on disk with no entry in the archive
+    pub only_on_disk: Vec<String>,
+    /// Entries with no file on disk
+    pub only_in_archive: Vec<String>,
+    /// Entries whose contents differ from their file
+    pub modified: Vec<String>,
+}
+
+impl SyncReport {
+    /// Whether the archive and the directory match
+    pub fn is_clean(&self) -> bool {
+        self.only_on_disk.is_empty() && self.only_in_archive.is_empty() && self.modified.is_empty()
+    }
+}
+
+impl Archive {
+    /// Compare the archive with the files under `root` and bring one side in
+    /// line with the other as `options` directs
+    ///
+    /// A missing `root` counts as an empty directory. Files written to disk
+    /// follow the rules of [`extract_to_dir`](Self::extract_to_dir).
+    pub fn sync_with_dir(&mut self, root: &Path, options: &SyncOptions) -> Result<SyncReport> {
+        let disk = if root.exists() { Archive::from_dir(root)? } else { Archive::new() };
+        let mut report = SyncReport::default();
+        // Contents of the files that are new or changed on disk
+        let mut disk_data = Vec::new();
+
+        for file in &disk.files {
+            let data = std::fs::read(extract_path(root, &file.name)?)?;
+            match self.files.iter().find(|f| f.name == file.name && is_synced(f)) {
+                None => report.only_on_disk.push(file.name.clone()),
+                Some(entry) if differs(entry, &data, &options.extract)? => report.modified.push(file.name.clone()),
+                Some(_) => continue,
+            }
+            disk_data.push((file.name.clone(), data));
+        }
+        for entry in self.files.iter().filter(|f| is_synced(f)) {
+            if !disk.files.iter().any(|f| f.name == entry.name) {
+                report.only_in_archive.push(entry.name.clone());
+            }
+        }
+
+        match options.direction {
+            SyncDirection::Report => {}
+            SyncDirection::FromDisk => self.update_from_disk(&report, disk_data, options.delete)?,
+            SyncDirection::ToDisk => self.update_disk(root, &report, options)?,
+        }
+        Ok(report)
+    }
+
+    fn update_from_disk(&mut self, report: &SyncReport, disk_data: Vec<(String, Vec<u8>)>, delete: bool) -> Result<()> {
+        for (name, data) in disk_data {
+            match self.files.iter_mut().find(|f| f.name == name && is_synced(f)) {
+                Some(entry) => *entry = File::from_disk(name, data).with_metadata(entry.metadata),
+                None => self.add_file(File::from_disk(name, data))?,
+            }
+        }
+        if delete {
+            self.files.retain(|f| !(is_synced(f) && report.only_in_archive.contains(&f.name)));
+        }
+        Ok(())
+    }
+
+    fn update_disk(&self, root: &Path, report: &SyncReport, options: &SyncOptions) -> Result<()> {
+        let mut changed = Archive::new();
+        changed.files = self.files.iter()
+            .filter(|f| is_synced(f) && (report.modified.contains(&f.name) || report.only_in_archive.contains(&f.name)))
+            .cloned()
+            .collect();
+        std::fs::create_dir_all(root)?;
+        changed.extract_to_dir(root, &options.extract)?;
+        if options.delete {
+            for name in &report.only_on_disk {
+                std::fs::remove_file(extract_path(root, name)?)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether an entry corresponds to a file on disk
+fn is_synced(file: &File) -> bool {
+    file.snippet_ref.is_none() && file.edit_ref.is_none() && file.conditions.is_empty()
+}
+
+/// Whether extracting an entry would write something other than `data`
+fn differs(file: &File, data: &[u8], options: &ExtractOptions) -> Result<bool> {
+    let file = file.load()?;
+    let bytes = if options.utf8 { file.utf8_content() } else { file.content() };
+    Ok(bytes.as_ref() != data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive() -> Archive {
+        let mut archive = Archive::new();
+        archive.add_file(File::new("same.txt", "same")).unwrap();
+        archive.add_file(File::new("dir/changed.txt", "archive")).unwrap();
+        archive.add_file(File::new("archive_only.txt", "new")).unwrap();
+        archive
+    }
+
+    fn setup() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("dir")).unwrap();
+        std::fs::write(dir.path().join("same.txt"), "same").unwrap();
+        std::fs::write(dir.path().join("dir/changed.txt"), "disk").unwrap();
+        std::fs::write(dir.path().join("disk_only.txt"), "extra").unwrap();
+        dir
+    }
+
+    fn expected_report() -> SyncReport {
+        SyncReport {
+            only_on_disk: vec!["disk_only.txt".into()],
+            only_in_archive: vec!["archive_only.txt".into()],
+            modified: vec!["dir/changed.txt".into()],
+        }
+    }
+
+    #[test]
+    fn test_sync_report_only() {
+        let dir = setup();
+        let mut archive = archive();
+        let report = archive.sync_with_dir(dir.path(), &SyncOptions::default()).unwrap();
+        assert_eq!(report, expected_report());
+        assert!(!report.is_clean());
+        assert_eq!(archive, self::archive());
+        assert!(!dir.path().join("archive_only.txt").exists());
+    }
+
+    #[test]
+    fn test_sync_from_disk() {
+        let dir = setup();
+        let mut archive = archive();
+        let options = SyncOptions { direction: SyncDirection::FromDisk, delete: true, ..Default::default() };
+        assert_eq!(archive.sync_with_dir(dir.path(), &options).unwrap(), expected_report());
+        let names: Vec<_> = archive.files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["same.txt", "dir/changed.txt", "disk_only.txt"]);
+        assert_eq!(archive.get_file("dir/changed.txt").unwrap().data.as_ref(), b"disk");
+        assert!(archive.sync_with_dir(dir.path(), &SyncOptions::default()).unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_sync_to_disk() {
+        let dir = setup();
+        let mut archive = archive();
+        let options = SyncOptions { direction: SyncDirection::ToDisk, delete: true, ..Default::default() };
+        assert_eq!(archive.sync_with_dir(dir.path(), &options).unwrap(), expected_report());
+        assert_eq!(std::fs::read_to_string(dir.path().join("dir/changed.txt")).unwrap(), "archive");
+        assert_eq!(std::fs::read_to_string(dir.path().join("archive_only.txt")).unwrap(), "new");
+        assert!(!dir.path().join("disk_only.txt").exists());
+        assert!(archive.sync_with_dir(dir.path(), &SyncOptions::default()).unwrap().is_clean());
+
+        // A missing directory is created
+        let target = dir.path().join("fresh");
+        archive.sync_with_dir(&target, &options).unwrap();
+        assert!(archive.sync_with_dir(&target, &SyncOptions::default()).unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_sync_decoded_archive_is_clean() {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in [("lf.txt", "one\ntwo\n"), ("newline.txt", "\n"), ("empty.txt", ""), ("z.txt", "no newline")] {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        let text = crate::Encoder::new().encode(&Archive::from_dir(dir.path()).unwrap()).unwrap();
+        let mut archive: Archive = text.parse().unwrap();
+        assert!(archive.sync_with_dir(dir.path(), &SyncOptions::default()).unwrap().is_clean());
+    }
+}
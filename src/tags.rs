@@ -0,0 +1,245 @@
+//! Application-defined header tags
+//!
+//! Besides the tags this crate understands, a header may carry tags of the
+//! form `[.name:value]` or `[.name]` defined by an application. Register a
+//! [`TagHandler`] for each name in a [`TagRegistry`] and pass it to
+//! [`Decoder::with_tags`](crate::Decoder::with_tags) and
+//! [`Encoder::with_tags`](crate::Encoder::with_tags): decoded tags are
+//! parsed and validated by their handler and kept in
+//! [`File::custom_tags`], and encoding serializes and validates them again.
+//! Tags with no handler are ignored by the decoder as before, and built-in
+//! tags cannot be redefined.
+//!
+//! ```
+//! use emx_txtar::tags::{TagHandler, TagRegistry};
+//! use emx_txtar::{Decoder, Encoder};
+//! use std::sync::Arc;
+//!
+//! struct Owner;
+//!
+//! impl TagHandler for Owner {
+//!     fn name(&self) -> &str {
+//!         "owner"
+//!     }
+//!
+//!     fn parse(&self, text: &str) -> anyhow::Result<String> {
+//!         anyhow::ensure!(!text.is_empty(), "missing owner");
+//!         Ok(text.to_lowercase())
+//!     }
+//! }
+//!
+//! let tags = TagRegistry::new().with(Arc::new(Owner));
+//! let archive = Decoder::new().with_tags(tags.clone()).decode("-- a.txt[.owner:Alice] --\nA\n").unwrap();
+//! assert_eq!(archive.files[0].custom_tags[0].value, "alice");
+//! assert_eq!(Encoder::new().with_tags(tags).encode(&archive).unwrap(), "-- a.txt[.owner:alice] --\nA\n");
+//! ```
+
+use crate::archive::File;
+use alloc::borrow::Cow;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use anyhow::{anyhow, bail, Result};
+
+/// An application-defined tag, `[.name:value]`, or `[.name]` if the value
+/// is empty
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CustomTag {
+    pub name: String,
+    pub value: String,
+}
+
+impl CustomTag {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self { name: name.into(), value: value.into() }
+    }
+
+    /// Split a `[.name:value]` or `[.name]` tag; `None` if it has another
+    /// form or the name is not letters, digits, `-` and `_`
+    pub fn parse(tag: &str) -> Option<Self> {
+        let inner = tag.strip_prefix("[.")?.strip_suffix(']')?;
+        let (name, value) = inner.split_once(':').unwrap_or((inner, ""));
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        valid.then(|| Self::new(name, value))
+    }
+}
+
+impl core::fmt::Display for CustomTag {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if self.value.is_empty() {
+            write!(f, "[.{}]", self.name)
+        } else {
+            write!(f, "[.{}:{}]", self.name, self.value)
+        }
+    }
+}
+
+/// Parsing, serialization and validation of one custom tag
+pub trait TagHandler: Send + Sync {
+    /// Name of the tag, `mytool` for `[.mytool:...]`
+    fn name(&self) -> &str;
+
+    /// Turn the text after the `:` (empty for `[.name]`) into the value kept
+    /// on the file; an error rejects the archive
+    fn parse(&self, text: &str) -> Result<String> {
+        Ok(text.into())
+    }
+
+    /// Turn a value kept on a file back into tag text
+    fn serialize(&self, value: &str) -> Result<String> {
+        Ok(value.into())
+    }
+
+    /// Check a value against the file carrying it, after decoding and
+    /// before encoding
+    fn validate(&self, _file: &File, _value: &str) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Handlers for custom tags, by name
+#[derive(Clone, Default)]
+pub struct TagRegistry {
+    handlers: Vec<Arc<dyn TagHandler>>,
+}
+
+impl TagRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a handler, replacing any with the same name
+    pub fn with(mut self, handler: Arc<dyn TagHandler>) -> Self {
+        self.handlers.retain(|h| h.name() != handler.name());
+        self.handlers.push(handler);
+        self
+    }
+
+    /// The handler for tags named `name`
+    pub fn get(&self, name: &str) -> Option<&dyn TagHandler> {
+        self.handlers.iter().find(|h| h.name() == name).map(|h| h.as_ref())
+    }
+
+    /// Parse a header tag with its handler; `None` if no handler is
+    /// registered for it
+    pub(crate) fn parse(&self, tag: &str) -> Option<Result<CustomTag>> {
+        let CustomTag { name, value } = CustomTag::parse(tag)?;
+        let handler = self.get(&name)?;
+        Some(handler.parse(&value).map(|value| CustomTag { name, value }))
+    }
+
+    /// Check the custom tags of a decoded file
+    pub(crate) fn validate(&self, file: &File) -> Result<()> {
+        for tag in &file.custom_tags {
+            if let Some(handler) = self.get(&tag.name) {
+                handler.validate(file, &tag.value).map_err(|e| invalid(file, tag, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The file with the values of its custom tags serialized for encoding
+    pub(crate) fn serialize<'a>(&self, file: &'a File) -> Result<Cow<'a, File>> {
+        if file.custom_tags.is_empty() {
+            return Ok(Cow::Borrowed(file));
+        }
+        let mut tags = Vec::with_capacity(file.custom_tags.len());
+        for tag in &file.custom_tags {
+            let value = match self.get(&tag.name) {
+                Some(handler) => {
+                    handler.validate(file, &tag.value).map_err(|e| invalid(file, tag, e))?;
+                    handler.serialize(&tag.value).map_err(|e| invalid(file, tag, e))?
+                }
+                None => tag.value.clone(),
+            };
+            let serialized = CustomTag::new(tag.name.clone(), value);
+            if CustomTag::parse(&serialized.to_string()).as_ref() != Some(&serialized) || serialized.value.contains(['[', ']', '\n']) {
+                bail!("Tag '{}' of file '{}' cannot be written in a header", serialized, file.name);
+            }
+            tags.push(serialized);
+        }
+        let mut file = file.clone();
+        file.custom_tags = tags;
+        Ok(Cow::Owned(file))
+    }
+}
+
+fn invalid(file: &File, tag: &CustomTag, error: anyhow::Error) -> anyhow::Error {
+    anyhow!("Invalid tag '{}' for file '{}': {}", tag, file.name, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, Encoder};
+    use alloc::vec;
+
+    /// `[.priority:N]` with N from 1 to 5, not allowed on binary files
+    struct Priority;
+
+    impl TagHandler for Priority {
+        fn name(&self) -> &str {
+            "priority"
+        }
+
+        fn parse(&self, text: &str) -> Result<String> {
+            match text.parse::<u8>() {
+                Ok(n @ 1..=5) => Ok(n.to_string()),
+                _ => bail!("expected 1 to 5"),
+            }
+        }
+
+        fn validate(&self, file: &File, _value: &str) -> Result<()> {
+            if file.is_binary {
+                bail!("binary files have no priority");
+            }
+            Ok(())
+        }
+    }
+
+    fn registry() -> TagRegistry {
+        TagRegistry::new().with(Arc::new(Priority))
+    }
+
+    #[test]
+    fn test_custom_tag_parse() {
+        assert_eq!(CustomTag::parse("[.reviewed]"), Some(CustomTag::new("reviewed", "")));
+        assert_eq!(CustomTag::parse("[.x-y:a:b]"), Some(CustomTag::new("x-y", "a:b")));
+        assert_eq!(CustomTag::parse("[.a b]"), None);
+        assert_eq!(CustomTag::parse("[base64]"), None);
+        assert_eq!(CustomTag::new("x", "1").to_string(), "[.x:1]");
+    }
+
+    #[test]
+    fn test_custom_tags_round_trip() {
+        let input = "-- a.txt[.mode:0644][.priority:03] --\nA\n";
+        let decoder = Decoder::new().with_tags(registry());
+        let archive = decoder.decode(input).unwrap();
+        assert_eq!(archive.files[0].custom_tags, [CustomTag::new("priority", "3")]);
+        let encoded = Encoder::new().with_tags(registry()).encode(&archive).unwrap();
+        assert_eq!(encoded, "-- a.txt[.mode:0644][.priority:3] --\nA\n");
+
+        // Without a handler the tag is ignored, or rejected when strict
+        assert!(Decoder::new().decode(input).unwrap().files[0].custom_tags.is_empty());
+        assert!(Decoder::new().with_strict(true).decode(input).is_err());
+        assert!(decoder.clone().with_strict(true).decode(input).is_ok());
+    }
+
+    #[test]
+    fn test_custom_tag_errors() {
+        let decoder = Decoder::new().with_tags(registry());
+        let err = decoder.decode("-- a.txt[.priority:9] --\nA\n").unwrap_err();
+        assert!(err.to_string().contains("Invalid tag '[.priority:9]' for file 'a.txt': expected 1 to 5"), "{}", err);
+        let err = decoder.decode("-- a.bin[.base64][.priority:1] --\nAA==\n").unwrap_err();
+        assert!(err.to_string().contains("binary files have no priority"), "{}", err);
+
+        let mut file = File::new("b.bin", vec![0xFF, 0x00]);
+        file.custom_tags.push(CustomTag::new("priority", "1"));
+        let mut archive = crate::Archive::new();
+        archive.add_file(file).unwrap();
+        assert!(Encoder::new().with_tags(registry()).encode(&archive).is_err());
+
+        archive.files[0].custom_tags = vec![CustomTag::new("note", "a]b")];
+        assert!(Encoder::new().encode(&archive).is_err());
+    }
+}
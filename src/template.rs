@@ -0,0 +1,165 @@
+//! `${VAR}` placeholder expansion for scaffold archives
+//!
+//! [`Archive::render`] expands placeholders in every file name and in the
+//! contents of UTF-8 text files, so one archive can describe a
+//! parameterized project. Binary files and text stored in other encodings
+//! keep their contents.
+//!
+//! Placeholders are `${NAME}`, where `NAME` is letters, digits and `_` and
+//! does not start with a digit. `$${` stands for a literal `${`, and a `$`
+//! not followed by `{` is left alone, so `$HOME` and `$$` need no escaping.
+//!
+//! ```
+//! use emx_txtar::{Archive, File};
+//! use std::collections::HashMap;
+//!
+//! let mut archive = Archive::new();
+//! archive.add_file(File::new("${crate}/Cargo.toml", "name = \"${crate}\"\n")).unwrap();
+//!
+//! let vars = HashMap::from([("crate".to_string(), "demo".to_string())]);
+//! let rendered = archive.render(&vars).unwrap();
+//! assert_eq!(rendered.files[0].name, "demo/Cargo.toml");
+//! assert_eq!(rendered.files[0].data.as_ref(), b"name = \"demo\"\n");
+//! ```
+
+use crate::archive::{Archive, File, TextEncoding};
+use std::collections::HashMap;
+
+/// Error type for placeholder expansion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateError {
+    /// A `${` on this 1-based line has no closing `}`
+    Unterminated { line: usize },
+    /// A placeholder does not hold a valid variable name
+    InvalidName { line: usize, name: String },
+    /// No value was given for a variable
+    Undefined { line: usize, name: String },
+}
+
+impl core::fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            TemplateError::Unterminated { line } => write!(f, "Unterminated '${{' on line {}", line),
+            TemplateError::InvalidName { line, name } => {
+                write!(f, "Invalid variable name '{}' on line {} (use '$${{' for a literal '${{')", name, line)
+            }
+            TemplateError::Undefined { line, name } => write!(f, "Undefined variable '{}' on line {}", name, line),
+        }
+    }
+}
+
+impl core::error::Error for TemplateError {}
+
+/// Expand the `${NAME}` placeholders in `text`
+pub fn expand(text: &str, vars: &HashMap<String, String>) -> Result<String, TemplateError> {
+    let line_at = |rest: &str| text[..text.len() - rest.len()].matches('\n').count() + 1;
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(dollar) = rest.find('$') {
+        output.push_str(&rest[..dollar]);
+        rest = &rest[dollar..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| TemplateError::Unterminated { line: line_at(rest) })?;
+            let name = &after[..end];
+            if !is_variable_name(name) {
+                return Err(TemplateError::InvalidName { line: line_at(rest), name: name.to_string() });
+            }
+            let value = vars.get(name)
+                .ok_or_else(|| TemplateError::Undefined { line: line_at(rest), name: name.to_string() })?;
+            output.push_str(value);
+            rest = &after[end + 1..];
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn is_variable_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Archive {
+    /// A copy of the archive with placeholders in file names and UTF-8 text
+    /// contents expanded from `vars`
+    ///
+    /// Entries backed by paths are read. Rendered contents are classified
+    /// again, so a value that introduces a marker line is still encoded
+    /// safely. Errors name the entry and wrap a [`TemplateError`].
+    pub fn render(&self, vars: &HashMap<String, String>) -> anyhow::Result<Archive> {
+        let mut rendered = self.clone();
+        for file in &mut rendered.files {
+            let original = file.load()?.into_owned();
+            let context = || format!("Failed to render '{}'", original.name);
+            let name = expand(&original.name, vars).map_err(|e| anyhow::Error::new(e).context(context()))?;
+
+            let text = match core::str::from_utf8(&original.data) {
+                Ok(text) if !original.is_binary && original.encoding == TextEncoding::Utf8 => text,
+                _ => {
+                    *file = File { name, ..original };
+                    continue;
+                }
+            };
+            let contents = expand(text, vars).map_err(|e| anyhow::Error::new(e).context(context()))?;
+            let mut expanded = File::new(name, contents).with_metadata(original.metadata);
+            expanded.snippet_ref = original.snippet_ref;
+            expanded.edit_ref = original.edit_ref;
+            expanded.is_archive = original.is_archive;
+            expanded.conditions = original.conditions;
+            expanded.custom_tags = original.custom_tags;
+            expanded.trailing_newline = original.trailing_newline;
+            expanded.line_ending = original.line_ending;
+            *file = expanded;
+        }
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> HashMap<String, String> {
+        HashMap::from([
+            ("name".to_string(), "demo".to_string()),
+            ("marker".to_string(), "-- x --".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_expand() {
+        assert_eq!(expand("a ${name} b${name}", &vars()).unwrap(), "a demo bdemo");
+        assert_eq!(expand("$${name} $HOME $$ $", &vars()).unwrap(), "${name} $HOME $$ $");
+        assert_eq!(expand("line\n${missing}", &vars()), Err(TemplateError::Undefined { line: 2, name: "missing".into() }));
+        assert_eq!(expand("${name", &vars()), Err(TemplateError::Unterminated { line: 1 }));
+        assert_eq!(expand("${a-b}", &vars()), Err(TemplateError::InvalidName { line: 1, name: "a-b".into() }));
+        assert_eq!(expand("${1x}", &vars()), Err(TemplateError::InvalidName { line: 1, name: "1x".into() }));
+    }
+
+    #[test]
+    fn test_render_archive() {
+        let mut archive = Archive::with_comment("Scaffold for ${name}");
+        archive.add_file(File::new("${name}/README", "# ${name}\n${marker}\n")).unwrap();
+        archive.add_file(File::new("${name}/logo.bin", vec![b'$', b'{', 0xFF])).unwrap();
+
+        let rendered = archive.render(&vars()).unwrap();
+        assert_eq!(rendered.comment, "Scaffold for ${name}");
+        assert_eq!(rendered.files[0].name, "demo/README");
+        assert_eq!(rendered.files[0].data.as_ref(), b"# demo\n-- x --\n");
+        assert!(rendered.files[0].is_binary, "marker line must be re-detected");
+        assert_eq!(rendered.files[1].name, "demo/logo.bin");
+        assert_eq!(rendered.files[1].data.as_ref(), &[b'$', b'{', 0xFF]);
+
+        let err = archive.render(&HashMap::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Failed to render '${name}/README'");
+        assert!(matches!(err.downcast_ref::<TemplateError>(), Some(TemplateError::Undefined { .. })));
+    }
+}
@@ -0,0 +1,296 @@
+//! Helpers for using txtar archives as test fixtures and snapshots
+//!
+//! ```
+//! use emx_txtar::txtar;
+//!
+//! let archive = txtar!("-- config.toml --\nname = \"demo\"\n");
+//! let dir = archive.materialize_temp().unwrap();
+//! assert!(dir.join("config.toml").exists());
+//! ```
+//!
+//! Directory-shaped output can be compared against a stored txtar snapshot
+//! with [`assert_archive_snapshot!`](crate::assert_archive_snapshot).
+//! Snapshots live in a `snapshots/` directory next to the test source as
+//! `<name>.snap.txtar`. Missing snapshots are written on first run (unless
+//! the `CI` environment variable is set), and `TXTAR_UPDATE_SNAPSHOTS=1`
+//! rewrites mismatching ones.
+//!
+//! ```ignore
+//! let output = emx_txtar::testing::archive_dir(out_dir.path())?;
+//! let redactions = Redactions::new().with_text(out_dir.path().to_str().unwrap(), "[OUT]");
+//! emx_txtar::assert_archive_snapshot!("generated", output, redactions);
+//! ```
+
+use crate::archive::{Archive, ExtractOptions, File, FileMetadata};
+use crate::encoder::Encoder;
+use crate::glob;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Environment variable that makes mismatching snapshots be rewritten
+const UPDATE_ENV: &str = "TXTAR_UPDATE_SNAPSHOTS";
+
+/// Decode a txtar fixture from a string, panicking if it is malformed
+#[macro_export]
+macro_rules! txtar {
+    ($text:expr) => {
+        <$crate::Archive as ::std::str::FromStr>::from_str($text).expect("invalid txtar fixture")
+    };
+}
+
+/// Compare an archive against a stored snapshot, panicking on mismatch
+///
+/// Takes a snapshot name, the archive and optionally [`Redactions`].
+#[macro_export]
+macro_rules! assert_archive_snapshot {
+    ($name:expr, $archive:expr) => {
+        $crate::assert_archive_snapshot!($name, $archive, $crate::testing::Redactions::new())
+    };
+    ($name:expr, $archive:expr, $redactions:expr) => {
+        $crate::testing::assert_snapshot(
+            &::std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join(file!())
+                .parent()
+                .expect("test source has a parent directory")
+                .join("snapshots"),
+            $name,
+            &$archive,
+            &$redactions,
+        )
+    };
+}
+
+/// A temporary directory holding extracted fixture files, removed on drop
+#[derive(Debug)]
+pub struct TempDirGuard {
+    dir: tempfile::TempDir,
+    files: Vec<PathBuf>,
+}
+
+impl TempDirGuard {
+    /// Root of the temporary directory
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Path of an archive entry inside the directory
+    pub fn join(&self, name: impl AsRef<Path>) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Paths of the files written
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+}
+
+impl AsRef<Path> for TempDirGuard {
+    fn as_ref(&self) -> &Path {
+        self.path()
+    }
+}
+
+impl Archive {
+    /// Extract the archive into a fresh temporary directory
+    ///
+    /// Each file is written as its [`content`](File::content), with the
+    /// final newline the archive gives it. Recorded permissions and
+    /// modification times are applied. The
+    /// directory and everything in it is deleted when the guard is dropped.
+    pub fn materialize_temp(&self) -> Result<TempDirGuard> {
+        let dir = tempfile::Builder::new()
+            .prefix("txtar-")
+            .tempdir()
+            .context("Failed to create temporary directory")?;
+        let options = ExtractOptions { preserve_permissions: true, preserve_mtimes: true, ..Default::default() };
+        let files = self.extract_to_dir(dir.path(), &options)?;
+        Ok(TempDirGuard { dir, files })
+    }
+}
+
+/// Rewrites applied to file contents before snapshotting
+#[derive(Default)]
+pub struct Redactions {
+    rules: Vec<(String, RedactFn)>,
+}
+
+type RedactFn = Box<dyn Fn(&[u8]) -> Vec<u8>>;
+
+impl Redactions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the whole content of files matching a glob
+    pub fn with_file(self, pattern: impl Into<String>, replacement: impl Into<Vec<u8>>) -> Self {
+        let replacement = replacement.into();
+        self.with_hook(pattern, move |_| replacement.clone())
+    }
+
+    /// Replace every occurrence of `needle` in all files
+    pub fn with_text(self, needle: impl Into<String>, replacement: impl Into<String>) -> Self {
+        let (needle, replacement) = (needle.into().into_bytes(), replacement.into().into_bytes());
+        self.with_hook("**", move |data| replace_bytes(data, &needle, &replacement))
+    }
+
+    /// Rewrite the content of files matching a glob with a custom function
+    pub fn with_hook(mut self, pattern: impl Into<String>, hook: impl Fn(&[u8]) -> Vec<u8> + 'static) -> Self {
+        self.rules.push((pattern.into(), Box::new(hook)));
+        self
+    }
+
+    fn apply(&self, file: &mut File) {
+        for (pattern, hook) in &self.rules {
+            if glob::matches(pattern, &file.name) {
+                file.data = hook(&file.data).into();
+            }
+        }
+    }
+}
+
+fn replace_bytes(data: &[u8], needle: &[u8], replacement: &[u8]) -> Vec<u8> {
+    if needle.is_empty() {
+        return data.to_vec();
+    }
+    let mut out = Vec::with_capacity(data.len());
+    let mut rest = data;
+    while let Some(pos) = rest.windows(needle.len()).position(|w| w == needle) {
+        out.extend_from_slice(&rest[..pos]);
+        out.extend_from_slice(replacement);
+        rest = &rest[pos + needle.len()..];
+    }
+    out.extend_from_slice(rest);
+    out
+}
+
+/// Canonical snapshot form of an archive
+///
+/// Files are sorted by name, modification times are dropped, redactions
+/// are applied, and the result is encoded.
+pub fn canonicalize(archive: &Archive, redactions: &Redactions) -> Result<String> {
+    let mut canonical = Archive::with_comment(archive.comment.clone());
+    let mut files = archive.files.clone();
+    files.sort_by(|a, b| a.name.cmp(&b.name));
+    for mut file in files {
+        redactions.apply(&mut file);
+        let metadata = FileMetadata { mtime: None, ..file.metadata };
+        let mut redacted = File::new(file.name.clone(), file.data.clone()).with_metadata(metadata);
+        redacted.snippet_ref = file.snippet_ref.take();
+        redacted.edit_ref = file.edit_ref.take();
+        redacted.is_archive = file.is_archive;
+        redacted.is_encrypted = file.is_encrypted;
+        redacted.conditions = file.conditions.clone();
+        redacted.custom_tags = file.custom_tags.clone();
+        canonical.files.push(redacted);
+    }
+    Encoder::new().encode(&canonical)
+}
+
+/// Read a directory tree into an archive, with paths relative to `dir`
+pub fn archive_dir(dir: &Path) -> Result<Archive> {
+    fn walk(archive: &mut Archive, root: &Path, dir: &Path) -> Result<()> {
+        let mut entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read directory: {}", dir.display()))?
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                walk(archive, root, &path)?;
+            } else {
+                let name = path.strip_prefix(root)?.to_string_lossy().replace('\\', "/");
+                archive.add_file(File::new(name, std::fs::read(&path)?))?;
+            }
+        }
+        Ok(())
+    }
+
+    let mut archive = Archive::new();
+    walk(&mut archive, dir, dir)?;
+    Ok(archive)
+}
+
+/// Compare an archive with `<dir>/<name>.snap.txtar`, used by [`assert_archive_snapshot!`](crate::assert_archive_snapshot)
+pub fn assert_snapshot(dir: &Path, name: &str, archive: &Archive, redactions: &Redactions) {
+    let actual = canonicalize(archive, redactions).expect("failed to encode snapshot");
+    let path = dir.join(format!("{}.snap.txtar", name));
+    let update = std::env::var_os(UPDATE_ENV).is_some_and(|v| v == "1");
+
+    let expected = match std::fs::read_to_string(&path) {
+        Ok(expected) => expected,
+        Err(_) if std::env::var_os("CI").is_some() => {
+            panic!("snapshot {} does not exist (run with {}=1 to create it)", path.display(), UPDATE_ENV)
+        }
+        Err(_) => return write_snapshot(&path, &actual),
+    };
+    if expected == actual {
+        return;
+    }
+    if update {
+        return write_snapshot(&path, &actual);
+    }
+
+    let line = expected.lines().zip(actual.lines()).position(|(e, a)| e != a)
+        .unwrap_or_else(|| expected.lines().count().min(actual.lines().count()));
+    panic!(
+        "snapshot {} does not match at line {}\n  expected: {:?}\n    actual: {:?}\n(run with {}=1 to update)",
+        path.display(),
+        line + 1,
+        expected.lines().nth(line).unwrap_or("<end>"),
+        actual.lines().nth(line).unwrap_or("<end>"),
+        UPDATE_ENV,
+    );
+}
+
+fn write_snapshot(path: &Path, content: &str) {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).expect("failed to create snapshot directory");
+    }
+    std::fs::write(path, content).expect("failed to write snapshot");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_materialize_temp() {
+        let archive = txtar!("setup\n-- a.txt --\nA\n-- sub/b.txt --\nB\n");
+        let guard = archive.materialize_temp().unwrap();
+        let root = guard.path().to_path_buf();
+
+        assert_eq!(guard.files().len(), 2);
+        assert_eq!(std::fs::read_to_string(guard.join("sub/b.txt")).unwrap(), "B\n");
+
+        drop(guard);
+        assert!(!root.exists());
+    }
+
+    #[test]
+    fn test_snapshot_canonicalizes_and_redacts() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let first = txtar!("-- b.txt --\nbuilt at /tmp/abc\n-- a.txt[.mtime:123] --\nA\n");
+        let second = txtar!("-- a.txt[.mtime:456] --\nA\n-- b.txt --\nbuilt at /tmp/xyz\n");
+        let redactions = || Redactions::new().with_file("b.txt", "[redacted]");
+
+        let path = dir.path().join("out.snap.txtar");
+        write_snapshot(&path, &canonicalize(&first, &redactions()).unwrap());
+        let stored = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(stored, "-- a.txt --\nA\n-- b.txt --\n[redacted]\n");
+        assert_snapshot(dir.path(), "out", &second, &redactions());
+
+        let changed = txtar!("-- a.txt --\nB\n");
+        let result = std::panic::catch_unwind(|| assert_snapshot(dir.path(), "out", &changed, &Redactions::new()));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_archive_dir() {
+        let archive = txtar!("-- top.txt --\nT\n-- nested/deep.txt --\nD [path]\n");
+        let guard = archive.materialize_temp().unwrap();
+
+        let read = archive_dir(guard.path()).unwrap();
+        let redactions = Redactions::new().with_text("[path]", "<p>");
+        assert_eq!(canonicalize(&read, &redactions).unwrap(), "-- nested/deep.txt --\nD <p>\n-- top.txt --\nT\n");
+    }
+}
@@ -0,0 +1,95 @@
+//! `tracing` instrumentation
+//!
+//! Decoding, encoding, applying edits and extracting each run in a
+//! `debug`-level span under the `emx_txtar` target, with a debug event per
+//! file and, with the `std` feature, a closing event giving the elapsed
+//! time in microseconds.
+
+/// A span entered for the rest of a scope, reporting its duration on exit
+pub(crate) struct Timed {
+    _span: tracing::span::EnteredSpan,
+    #[cfg(feature = "std")]
+    start: std::time::Instant,
+}
+
+impl Timed {
+    pub(crate) fn enter(span: tracing::Span) -> Self {
+        Self {
+            _span: span.entered(),
+            #[cfg(feature = "std")]
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+impl Drop for Timed {
+    fn drop(&mut self) {
+        // Emitted before the span is exited, so it is attributed to it
+        #[cfg(feature = "std")]
+        tracing::debug!(target: "emx_txtar", elapsed_us = self.start.elapsed().as_micros() as u64, "done");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Archive, Decoder, Encoder, File};
+    use std::sync::{Arc, Mutex};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Records span names and event messages as `span:NAME` and `event:MESSAGE`
+    #[derive(Default)]
+    struct Recorder {
+        names: Mutex<Vec<&'static str>>,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct Message(String);
+
+    impl tracing::field::Visit for Message {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn core::fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut names = self.names.lock().unwrap();
+            names.push(span.metadata().name());
+            Id::from_u64(names.len() as u64)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            self.log.lock().unwrap().push(format!("event:{}", message.0));
+        }
+        fn enter(&self, id: &Id) {
+            let name = self.names.lock().unwrap()[id.into_u64() as usize - 1];
+            self.log.lock().unwrap().push(format!("span:{}", name));
+        }
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_spans_and_events() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Recorder { log: log.clone(), ..Default::default() };
+        tracing::subscriber::with_default(recorder, || {
+            let mut archive = Archive::new();
+            archive.add_file(File::new("a.txt", "hello")).unwrap();
+            let encoded = Encoder::new().encode(&archive).unwrap();
+            Decoder::new().decode(&encoded).unwrap();
+        });
+        assert_eq!(*log.lock().unwrap(), [
+            "span:encode", "event:encoding file", "event:done",
+            "span:decode", "event:decoded file", "event:done",
+        ]);
+    }
+}
@@ -0,0 +1,234 @@
+//! Format versions and migration
+//!
+//! An archive may declare the version of the format extensions it uses in
+//! its [frontmatter](crate::frontmatter), as `txtar-ext: v2`. Archives
+//! without a declaration are version 1, which this crate still reads. The
+//! decoder rejects archives declaring a newer version than
+//! [`FORMAT_VERSION`], since their tags could be misread, unless
+//! [`Decoder::with_lenient_version`](crate::Decoder::with_lenient_version)
+//! is set.
+//!
+//! [`migrate`] upgrades the text of an archive to the current version one
+//! step at a time, rewriting only what changed between versions:
+//!
+//! - v1 to v2: the `[.#href:line]` snippet shorthand is spelled out as
+//!   `[.snippet#href:line]`, encoding aliases become their canonical label
+//!   (`[.enc:iso-8859-1]` to `[.enc:latin1]`), and `[.enc:...]` tags naming an
+//!   unknown encoding, which version 1 ignored, are dropped.
+//!
+//! ```
+//! use emx_txtar::version::migrate;
+//!
+//! let migration = migrate("-- a.txt[.enc:iso-8859-1] --\ncafé\n").unwrap();
+//! assert_eq!(migration.from, 1);
+//! assert_eq!(migration.text, "---\ntxtar-ext: v2\n---\n-- a.txt[.enc:latin1] --\ncafé\n");
+//! ```
+
+use crate::archive::{find_tag_start, Archive, File, TextEncoding, ENCODING_TAG_PREFIX, MARKER_PREFIX, MARKER_SUFFIX};
+use crate::frontmatter::Frontmatter;
+use alloc::borrow::Cow;
+use alloc::format;
+use alloc::string::{String, ToString};
+use anyhow::{anyhow, bail, Result};
+
+/// Frontmatter key declaring the format version
+pub const VERSION_KEY: &str = "txtar-ext";
+
+/// Newest format version this crate reads and writes
+pub const FORMAT_VERSION: u32 = 2;
+
+/// Version of archives without a declaration
+const UNDECLARED_VERSION: u32 = 1;
+
+/// Rewrites of the text of an archive from each version to the next, the
+/// first one from version 1
+const MIGRATIONS: [fn(&str) -> String; (FORMAT_VERSION - UNDECLARED_VERSION) as usize] = [v1_to_v2];
+
+/// Version declared in the frontmatter of `comment`, if any
+///
+/// Fails if the declaration is not of the form `v<N>`.
+pub fn declared_version(comment: &str) -> Result<Option<u32>> {
+    let Some(value) = Frontmatter::split(comment).0.and_then(|f| f.get(VERSION_KEY).map(String::from)) else {
+        return Ok(None);
+    };
+    match value.strip_prefix('v').and_then(|n| n.parse().ok()) {
+        Some(version) if version >= UNDECLARED_VERSION => Ok(Some(version)),
+        _ => bail!("Invalid format version '{}', expected 'v{}'", value, FORMAT_VERSION),
+    }
+}
+
+impl Archive {
+    /// Format version declared in the comment; 1 without a declaration
+    ///
+    /// `None` if the declaration is not a valid version.
+    pub fn format_version(&self) -> Option<u32> {
+        declared_version(&self.comment).ok().map(|v| v.unwrap_or(UNDECLARED_VERSION))
+    }
+
+    /// Declare `version` in the comment frontmatter
+    pub fn set_format_version(&mut self, version: u32) {
+        let mut frontmatter = self.frontmatter().unwrap_or_default();
+        frontmatter.set(VERSION_KEY, &format!("v{}", version));
+        self.set_frontmatter(&frontmatter);
+    }
+}
+
+/// Result of [`migrate`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migration {
+    /// Version the archive was in
+    pub from: u32,
+    /// Text of the archive in [`FORMAT_VERSION`], declaring it
+    pub text: String,
+}
+
+/// Upgrade the text of an archive to [`FORMAT_VERSION`]
+///
+/// Fails if the archive declares an invalid or newer version.
+pub fn migrate(text: &str) -> Result<Migration> {
+    let from = declared_version(text)?.unwrap_or(UNDECLARED_VERSION);
+    if from > FORMAT_VERSION {
+        bail!("Archive is format v{}, newer than v{}", from, FORMAT_VERSION);
+    }
+    let mut text = text.to_string();
+    for step in &MIGRATIONS[(from - UNDECLARED_VERSION) as usize..] {
+        text = step(&text);
+    }
+
+    let (frontmatter, body) = Frontmatter::split(&text);
+    let mut frontmatter = frontmatter.unwrap_or_default();
+    if frontmatter.get(VERSION_KEY) != Some(&*format!("v{}", FORMAT_VERSION)) {
+        frontmatter.set(VERSION_KEY, &format!("v{}", FORMAT_VERSION));
+        text = frontmatter.to_string() + body;
+    }
+    Ok(Migration { from, text })
+}
+
+/// Spell out snippet shorthands and canonicalize encoding labels
+fn v1_to_v2(text: &str) -> String {
+    rewrite_tags(text, |tag| {
+        if let Some(rest) = tag.strip_prefix("[.#") {
+            return Some(format!("[.snippet#{}", rest).into());
+        }
+        if let Some(label) = tag.strip_prefix(ENCODING_TAG_PREFIX).and_then(|t| t.strip_suffix(']')) {
+            return TextEncoding::from_label(label).map(|e| format!("{}{}]", ENCODING_TAG_PREFIX, e.label()).into());
+        }
+        Some(tag.into())
+    })
+}
+
+/// Apply `rewrite` to each tag of each marker line, dropping tags it maps
+/// to `None`
+fn rewrite_tags(text: &str, rewrite: impl Fn(&str) -> Option<Cow<'_, str>>) -> String {
+    let mut output = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let content = line.trim_end_matches(['\n', '\r']);
+        let trimmed = content.trim();
+        if !File::is_marker_line(content) {
+            output.push_str(line);
+            continue;
+        }
+        let name_start = content.len() - content.trim_start().len() + MARKER_PREFIX.len();
+        let end = name_start + trimmed.len() - MARKER_PREFIX.len() - MARKER_SUFFIX.len();
+        let Some(start) = find_tag_start(&content[name_start..end]).map(|i| name_start + i) else {
+            output.push_str(line);
+            continue;
+        };
+        output.push_str(&content[..start]);
+        let mut rest = &content[start..end];
+        while let Some(close) = rest.find(']').filter(|_| rest.starts_with('[')) {
+            if let Some(tag) = rewrite(&rest[..=close]) {
+                output.push_str(&tag);
+            }
+            rest = &rest[close + 1..];
+        }
+        output.push_str(rest);
+        output.push_str(&line[end..]);
+    }
+    output
+}
+
+/// Error for archives declaring a version newer than this crate reads
+pub(crate) fn unsupported(version: u32) -> anyhow::Error {
+    anyhow!(
+        "Archive is format v{}, but this version of emx-txtar reads up to v{}; upgrade it or decode leniently",
+        version, FORMAT_VERSION,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Decoder, Encoder};
+
+    #[test]
+    fn test_declared_version() {
+        assert_eq!(declared_version("Notes").unwrap(), None);
+        assert_eq!(declared_version("---\ntxtar-ext: v2\n---\nNotes").unwrap(), Some(2));
+        assert!(declared_version("---\ntxtar-ext: two\n---").is_err());
+        assert!(declared_version("---\ntxtar-ext: v0\n---").is_err());
+
+        let mut archive = Archive::with_comment("# Fixtures");
+        assert_eq!(archive.format_version(), Some(1));
+        archive.set_format_version(FORMAT_VERSION);
+        assert_eq!(archive.comment, "---\ntxtar-ext: v2\n---\n# Fixtures");
+        assert_eq!(archive.format_version(), Some(2));
+        assert_eq!(archive.title(), Some("Fixtures"));
+    }
+
+    #[test]
+    fn test_decode_version_check() {
+        let input = "---\ntxtar-ext: v3\n---\n-- a.txt[.future:x] --\nA\n";
+        let err = Decoder::new().decode(input).unwrap_err();
+        assert!(err.to_string().contains("Archive is format v3"), "{}", err);
+        // Checked before the tags of the first entry
+        assert!(Decoder::new().with_strict(true).decode(input).unwrap_err().to_string().contains("format v3"));
+        assert!(Decoder::new().decode("---\ntxtar-ext: 3\n---\n").is_err());
+
+        let archive = Decoder::new().with_lenient_version(true).decode(input).unwrap();
+        assert_eq!(archive.files[0].data.as_ref(), b"A");
+        #[cfg(feature = "std")]
+        {
+            let collector = alloc::sync::Arc::new(crate::diagnostics::Collector::default());
+            Decoder::new().with_lenient_version(true).with_diagnostics(collector.clone()).decode(input).unwrap();
+            assert!(collector.take().iter().any(|d| d.to_string().contains("v3")));
+        }
+
+        let current = "---\ntxtar-ext: v2\n---\n-- a.txt --\nA\n";
+        assert_eq!(Decoder::new().decode(current).unwrap().format_version(), Some(2));
+        assert!(Decoder::new().decode("---\ntxtar-ext: v9\n---").is_err());
+
+        #[cfg(feature = "std")]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            let options = crate::ExtractOptions::default();
+            assert!(Decoder::new().extract_reader(input.as_bytes(), dir.path(), &options).is_err());
+            assert!(!dir.path().join("a.txt").exists());
+        }
+    }
+
+    #[test]
+    fn test_migrate() {
+        let input = "---\nowner: qa\n---\nFixtures [command: rg](#search1)\n\
+            -- a.txt[.#search1:3:lang=rust] --\n\
+            -- b.txt[.enc:ISO-8859-1][.mode:0644] --\ntext\n-- c.txt[.enc:klingon] --\nC\n";
+        let migration = migrate(input).unwrap();
+        assert_eq!(migration.from, 1);
+        assert_eq!(
+            migration.text,
+            "---\nowner: qa\ntxtar-ext: v2\n---\nFixtures [command: rg](#search1)\n\
+            -- a.txt[.snippet#search1:3:lang=rust] --\n\
+            -- b.txt[.enc:latin1][.mode:0644] --\ntext\n-- c.txt --\nC\n",
+        );
+        let archive = Decoder::new().with_edit_target_check(false).decode(&migration.text).unwrap();
+        assert_eq!(archive.format_version(), Some(2));
+
+        // Already current: unchanged
+        let current = Encoder::new().encode(&archive).unwrap();
+        let again = migrate(&current).unwrap();
+        assert_eq!(again.from, 2);
+        assert_eq!(again.text, current);
+
+        assert!(migrate("---\ntxtar-ext: v3\n---\n").is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! WebAssembly bindings
+//!
+//! `wasm-bindgen` exports for web-based review tools that render and edit
+//! txtar archives client-side. Build the module and its JavaScript glue with
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown \
+//!     --no-default-features --features wasm --crate-type cdylib
+//! wasm-bindgen --target web --out-dir pkg \
+//!     target/wasm32-unknown-unknown/release/emx_txtar.wasm
+//! ```
+//!
+//! ```js
+//! import init, { decode, list, applyEdits, TxtarBuilder } from "./pkg/emx_txtar.js";
+//! await init();
+//! const archive = JSON.parse(decode(text));
+//! const builder = new TxtarBuilder("note");
+//! builder.addFile("logo.png", new Uint8Array([0x89, 0x50]));
+//! const encoded = builder.encode();
+//! ```
+//!
+//! Operations:
+//! - [`decode`]: txtar text to JSON `{"comment", "files": [{"name", "kind", "text" | "base64"}]}`
+//! - [`list`]: txtar text to JSON `[{"name", "size", "kind"}]`
+//! - [`TxtarBuilder`]: names and `Uint8Array` contents to txtar text
+//! - [`apply_edits`] (`applyEdits`): txtar text to txtar text with `[.edit]` entries applied
+//!
+//! Failures are thrown as JavaScript `Error`s.
+
+use crate::archive::{Archive, File};
+use crate::decoder::Decoder;
+use crate::encoder::Encoder;
+use crate::lsp::json_string;
+use anyhow::Result;
+use base64::Engine;
+use wasm_bindgen::prelude::*;
+
+/// Decode txtar text into JSON
+#[wasm_bindgen]
+pub fn decode(text: &str) -> Result<String, JsError> {
+    decode_json(text).map_err(js_error)
+}
+
+/// List the entries of txtar text as JSON
+#[wasm_bindgen]
+pub fn list(text: &str) -> Result<String, JsError> {
+    list_json(text).map_err(js_error)
+}
+
+/// Apply the `[.edit]` entries of txtar text and re-encode it
+#[wasm_bindgen(js_name = applyEdits)]
+pub fn apply_edits(text: &str) -> Result<String, JsError> {
+    apply_edits_text(text).map_err(js_error)
+}
+
+/// Builds txtar text from names and contents
+#[wasm_bindgen]
+pub struct TxtarBuilder {
+    archive: Archive,
+}
+
+#[wasm_bindgen]
+impl TxtarBuilder {
+    /// Start an archive with the given comment
+    #[wasm_bindgen(constructor)]
+    pub fn new(comment: &str) -> TxtarBuilder {
+        TxtarBuilder { archive: Archive::with_comment(comment) }
+    }
+
+    /// Add a file; binary contents are stored as base64
+    #[wasm_bindgen(js_name = addFile)]
+    pub fn add_file(&mut self, name: &str, content: &[u8]) -> Result<(), JsError> {
+        self.archive.add_file(File::new(name, content.to_vec())).map_err(js_error)
+    }
+
+    /// Encode the archive as txtar text
+    pub fn encode(&self) -> Result<String, JsError> {
+        Encoder::new().encode(&self.archive).map_err(js_error)
+    }
+}
+
+fn decode_json(text: &str) -> Result<String> {
+    let archive = Decoder::new().decode(text)?;
+    let files: Vec<String> = archive.files.iter().map(|file| {
+        let content = match std::str::from_utf8(&file.data) {
+            Ok(text) if !file.is_binary => format!("\"text\":{}", json_string(text)),
+            _ => format!("\"base64\":\"{}\"", base64::engine::general_purpose::STANDARD.encode(&file.data)),
+        };
+        format!("{{\"name\":{},\"kind\":\"{}\",{}}}", json_string(&file.name), kind(file), content)
+    }).collect();
+    Ok(format!("{{\"comment\":{},\"files\":[{}]}}", json_string(&archive.comment), files.join(",")))
+}
+
+fn list_json(text: &str) -> Result<String> {
+    let archive = Decoder::new().decode(text)?;
+    let entries: Vec<String> = archive.files.iter()
+        .map(|file| format!("{{\"name\":{},\"size\":{},\"kind\":\"{}\"}}", json_string(&file.name), file.data.len(), kind(file)))
+        .collect();
+    Ok(format!("[{}]", entries.join(",")))
+}
+
+fn apply_edits_text(text: &str) -> Result<String> {
+    let mut archive = Decoder::new().decode(text)?;
+    archive.apply_edits()?;
+    Encoder::new().encode(&archive)
+}
+
+fn js_error(err: impl core::fmt::Display) -> JsError {
+    JsError::new(&format!("{:#}", err))
+}
+
+fn kind(file: &File) -> &'static str {
+    if file.edit_ref.is_some() {
+        "edit"
+    } else if file.snippet_ref.is_some() {
+        "snippet"
+    } else if file.is_binary {
+        "binary"
+    } else {
+        "text"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wasm_operations() {
+        let text = "note\n-- a.txt --\nsay \"hi\"\n-- b.bin[.base64] --\nAAE=\n";
+        assert_eq!(
+            decode_json(text).unwrap(),
+            r#"{"comment":"note","files":[{"name":"a.txt","kind":"text","text":"say \"hi\""},{"name":"b.bin","kind":"binary","base64":"AAE="}]}"#,
+        );
+        assert_eq!(
+            list_json(text).unwrap(),
+            r#"[{"name":"a.txt","size":8,"kind":"text"},{"name":"b.bin","size":2,"kind":"binary"}]"#,
+        );
+        assert!(decode_json("-- a.txt[.base64] --\n!!\n").is_err());
+
+        let edit = "-- a.txt --\nold\n-- a.txt[.edit] --\n<<<<<<< SEARCH\nold\n=======\nnew\n>>>>>>> REPLACE\n";
+        assert_eq!(apply_edits_text(edit).unwrap(), "-- a.txt --\nnew\n");
+    }
+
+    #[test]
+    fn test_wasm_builder() {
+        let mut builder = TxtarBuilder::new("note");
+        builder.add_file("a.txt", b"hello\n").unwrap();
+        builder.add_file("b.bin", &[0xFF, 0]).unwrap();
+        assert_eq!(builder.encode().unwrap(), "note\n-- a.txt --\nhello\n-- b.bin[.base64] --\n/wA=\n");
+    }
+}
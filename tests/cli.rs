@@ -0,0 +1,89 @@
+//! Tests running the `emx-txtar` binary
+
+use std::path::Path;
+use std::process::Command;
+
+fn emx_txtar(args: &[&str], dir: &Path) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_emx-txtar")).args(args).current_dir(dir).output().unwrap()
+}
+
+#[test]
+fn test_extract_into_new_directory() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::write(temp.path().join("m.txtar"), "-- a.txt --\nA\n-- top/b.txt --\nB\n").unwrap();
+
+    let output = emx_txtar(&["x", "-i", "m.txtar", "-C", "new/dir"], temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(temp.path().join("new/dir/a.txt")).unwrap(), "A\n");
+    assert_eq!(std::fs::read_to_string(temp.path().join("new/dir/top/b.txt")).unwrap(), "B\n");
+
+    let output = emx_txtar(&["x", "-i", "m.txtar", "-C", "stripped", "--strip-components", "1"], temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(temp.path().join("stripped/b.txt")).unwrap(), "B\n");
+
+    let output = emx_txtar(&["x", "-i", "m.txtar", "-C", "moved", "--transform", "s#^top/#renamed/#"], temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(temp.path().join("moved/renamed/b.txt")).unwrap(), "B\n");
+}
+
+#[test]
+fn test_create_extract_keeps_contents() {
+    let temp = tempfile::TempDir::new().unwrap();
+    // Only the last section can end without a newline
+    let files: [(&str, &[u8]); 5] =
+        [("lines.txt", b"one\ntwo\n"), ("newline.txt", b"\n"), ("empty.txt", b""), ("blob.bin", b"\xFF\x00\n"), ("bare.txt", b"bare")];
+    for (name, content) in files {
+        std::fs::write(temp.path().join(name), content).unwrap();
+    }
+    let mut args = vec!["create", "-o", "m.txtar"];
+    args.extend(files.iter().map(|(name, _)| *name));
+    let output = emx_txtar(&args, temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = emx_txtar(&["x", "-i", "m.txtar", "-C", "out"], temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    for (name, content) in files {
+        assert_eq!(std::fs::read(temp.path().join("out").join(name)).unwrap(), content, "{}", name);
+    }
+}
+
+#[test]
+fn test_extract_resolves_conditions_for_host() {
+    let temp = tempfile::TempDir::new().unwrap();
+    let input = format!("-- a.txt[.if:os={}] --\nhost\n-- a.txt[.if:os=none] --\nother\n", std::env::consts::OS);
+    std::fs::write(temp.path().join("m.txtar"), input).unwrap();
+
+    let output = emx_txtar(&["x", "-i", "m.txtar", "-C", "out"], temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read_to_string(temp.path().join("out/a.txt")).unwrap(), "host\n");
+}
+
+#[test]
+fn test_create_jobs_output_is_identical() {
+    let temp = tempfile::TempDir::new().unwrap();
+    for i in 0..40 {
+        let dir = temp.path().join("src").join(format!("d{}", i % 4));
+        std::fs::create_dir_all(&dir).unwrap();
+        let content = if i % 5 == 0 { vec![0xFF; i * 100] } else { format!("file {}\n", i).repeat(i * 10).into_bytes() };
+        std::fs::write(dir.join(format!("f{}.txt", i)), content).unwrap();
+    }
+
+    let output = emx_txtar(&["create", "src", "-j", "1", "-o", "one.txtar"], temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let output = emx_txtar(&["create", "src", "-j", "8", "-o", "many.txtar"], temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(std::fs::read(temp.path().join("one.txtar")).unwrap(), std::fs::read(temp.path().join("many.txtar")).unwrap());
+}
+
+#[test]
+fn test_tree_sizes() {
+    let temp = tempfile::TempDir::new().unwrap();
+    std::fs::write(temp.path().join("m.txtar"), "-- a.txt --\nA\n-- top/b.txt --\nBB\n").unwrap();
+
+    let output = emx_txtar(&["t", "-i", "m.txtar", "--tree"], temp.path());
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        ". (2 files, 3 bytes)\n├── top/ (1 file, 2 bytes)\n│   └── b.txt (2 bytes)\n└── a.txt (1 byte)\n"
+    );
+}